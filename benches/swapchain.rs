@@ -0,0 +1,59 @@
+//! Time per swapchain creation and recreation, on a `VK_EXT_headless_surface` so no window is needed.
+//! Run with `cargo bench --bench swapchain`, skipped when there is no driver offering a headless surface.
+
+use ash::vk;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use std::time::{Duration, Instant};
+
+use pupsy_engine::vk::render_device::{DeviceConfig, VkRenderDevice};
+use pupsy_engine::vk::swap_chain::{SwapChainSupportCache, VkSpawChain};
+
+fn swapchain_benches(c: &mut Criterion) {
+    let mut render_device = match VkRenderDevice::try_new_headless(1280, 720, DeviceConfig::default()) {
+        Ok(render_device) => render_device,
+        Err(error) => {
+            eprintln!("Skipping swapchain benchmarks: {:?}", error);
+            return;
+        }
+    };
+
+    // with and without the cache, since formats and present modes are only queried again without it.
+    for &cache_enabled in [false, true].iter() {
+        render_device.swapchain_support_cache = SwapChainSupportCache::new(cache_enabled);
+        let suffix = if cache_enabled { " (support cache)" } else { "" };
+
+        c.bench_function(&format!("create_swapchain{}", suffix), |b| {
+            b.iter(|| {
+                let swapchain: VkSpawChain = render_device
+                    .create_swapchain(vk::SwapchainKHR::null())
+                    .expect("Failed to create Swapchain!");
+                swapchain.destroy_swapchain();
+            })
+        });
+
+        // a frame before every recreation, so old swapchains and their resources get retired and destroyed
+        // the way they are in the main loop. Only the recreation itself is timed.
+        c.bench_function(&format!("recreate_swapchain{}", suffix), |b| {
+            b.iter_custom(|iterations| {
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..iterations {
+                    render_device
+                        .render_frame(|render_device, image_index| render_device.command_buffers[image_index as usize])
+                        .expect("Failed to render frame");
+
+                    let start = Instant::now();
+                    render_device.recreate_swapchain();
+                    elapsed += start.elapsed();
+                }
+                elapsed
+            })
+        });
+    }
+
+    render_device.wait_idle().expect("Failed to wait device idle");
+    render_device.destroy();
+}
+
+criterion_group!(benches, swapchain_benches);
+criterion_main!(benches);
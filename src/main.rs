@@ -3,9 +3,8 @@ use pupsy_engine::imgui;
 use winit::event::{Event, VirtualKeyCode, ElementState, KeyboardInput, WindowEvent};
 use winit::event_loop::{EventLoop, ControlFlow};
 
-use ash::vk;
-use std::ptr;
 use pupsy_engine::utility::fps;
+use pupsy_engine::utility::frame_limiter;
 
 use pupsy_engine::utility::constants as global_constants;
 use pupsy_engine::rhi::window;
@@ -19,10 +18,10 @@ struct PupsyEngine {
     render_device: vk_render::VkRenderDevice,
     window: window::Window,
     fps_manager: fps::FPSManager,
+    frame_limiter: frame_limiter::FrameLimiter,
+    monitor: Option<winit::monitor::MonitorHandle>,
 
     ui_engine: imgui::pupsy_ui_engine::PupsyUiEngine,
-
-    is_marked_resized: bool,
 }
 
 impl PupsyEngine {
@@ -31,125 +30,49 @@ impl PupsyEngine {
         let render_device = vk_render::VkRenderDevice::new(&window);
         let pupsy_ui_engine = imgui::pupsy_ui_engine::PupsyUiEngine::new(&window);
 
+        let mut frame_limiter = frame_limiter::FrameLimiter::new();
+        frame_limiter.set_target_fps(global_constants::TARGET_FPS);
+        let monitor = window.current_monitor();
+
         PupsyEngine {
              render_device: render_device,
              window: window,
-             is_marked_resized: false,
              fps_manager: fps::FPSManager::new(),
+             frame_limiter: frame_limiter,
+             monitor: monitor,
              ui_engine: pupsy_ui_engine,
         }
     }
 
     fn draw_frame(&mut self) {
-        let wait_fences = [self.render_device.sync_objects.inflight_fences[self.render_device.current_frame]];
-
-        unsafe {
-            self.render_device.device
-            .wait_for_fences(&wait_fences, true, std::u64::MAX)
-            .expect("Failed to wait for Fence!");
-        }
-
-        let (image_index, _is_sub_optimal) = unsafe {
-            let acquire_result = self.render_device.swapchain.swapchain_loader
-                .acquire_next_image(
-                    self.render_device.swapchain.swapchain,
-                    std::u64::MAX,
-                    self.render_device.sync_objects.image_available_semaphores[self.render_device.current_frame],
-                    vk::Fence::null(),
-                );
-
-                match acquire_result {
-                    Ok(image_index) => {
-                        image_index
-                    },
-                    Err(vk_result) => match vk_result {
-                        vk::Result::ERROR_OUT_OF_DATE_KHR => {
-                            self.render_device.recreate_swapchain();
-                            return;
-                        },
-                        _ => panic!("Failed to acquire Swap Chain Image"),
-                    },
+        let delta_time = self.fps_manager.delta_time as f32;
+        let ui_engine = &mut self.ui_engine;
+        let window = &self.window;
+
+        let frame_result = self.render_device.render_frame(|render_device, image_index| {
+            render_device.update_uniform_buffer(image_index as usize, delta_time);
+
+            let command_buffer = render_device.command_buffers[image_index as usize];
+            ui_engine.render(window, &command_buffer);
+
+            command_buffer
+        });
+        match frame_result {
+            Ok(true) => {},
+            Ok(false) => return,
+            Err(vk_render::FrameError::SurfaceLost) => {
+                // e.g. the display got unplugged, tried again every frame until the window has a surface again.
+                if let Err(error) = self.render_device.recreate_surface(&self.window) {
+                    log::error!("Failed to recreate the lost surface: {:?}", error);
                 }
-        };
-
-        self.render_device.update_uniform_buffer(image_index as usize, self.fps_manager.delta_time as f32);
-
-        let image_available_semaphore = [self.render_device.sync_objects.image_available_semaphores[self.render_device.current_frame]];
-        let render_finished_semaphore = [self.render_device.sync_objects.render_finished_semaphores[self.render_device.current_frame]];
-
-        let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-
-        let draw_ui_data = self.ui_engine.render(&self.window, &self.render_device.command_buffers[image_index as usize]);
-
-        // vk_render::VkRenderDevice::create_command_buffers(
-        //     &self.render_device.device,
-        //     self.render_device.command_pool,
-        //     self.render_device.graphics_pipeline,
-        //     &self.render_device.swapchain.swapchain_framebuffers,
-        //     self.render_device.render_pass,
-        //     self.render_device.swapchain.swapchain_extent);
-
-        let submit_infos = [vk::SubmitInfo {
-            s_type: vk::StructureType::SUBMIT_INFO,
-            p_next: ptr::null(),
-            wait_semaphore_count: image_available_semaphore.len() as u32,
-            p_wait_semaphores: image_available_semaphore.as_ptr(),
-            p_wait_dst_stage_mask: wait_stages.as_ptr(),
-            command_buffer_count: 1,
-            p_command_buffers: &self.render_device.command_buffers[image_index as usize],
-            signal_semaphore_count: render_finished_semaphore.len() as u32,
-            p_signal_semaphores: render_finished_semaphore.as_ptr(),
-        }];
-
-        unsafe {
-            self.render_device.device
-                .reset_fences(&wait_fences)
-                .expect("Failed to reset Fence!");
-
-            self.render_device.device
-                .queue_submit(
-                    self.render_device.graphics_queue,
-                    &submit_infos,
-                    self.render_device.sync_objects.inflight_fences[self.render_device.current_frame],
-                )
-                .expect("Failed to execute queue submit.");
+                return;
+            }
         }
 
-        let swapchains = [self.render_device.swapchain.swapchain];
-
-        let present_info = vk::PresentInfoKHR {
-            s_type: vk::StructureType::PRESENT_INFO_KHR,
-            p_next: ptr::null(),
-            wait_semaphore_count: 1,
-            p_wait_semaphores: render_finished_semaphore.as_ptr(),
-            swapchain_count: swapchains.len() as u32,
-            p_swapchains: swapchains.as_ptr(),
-            p_image_indices: &image_index,
-            p_results: ptr::null_mut(),
-        };
-
-        let present_result = unsafe {
-            self.render_device.swapchain.swapchain_loader
-                .queue_present(self.render_device.present_queue, &present_info)
-        };
+        self.frame_limiter.wait();
 
         let time = SystemTime::now().duration_since(UNIX_EPOCH);
         self.fps_manager.update(time.unwrap().as_micros());
-
-        let is_resized = match present_result {
-            Ok(_) => !self.is_marked_resized,
-            Err(vk_result) => match vk_result {
-                vk::Result::ERROR_OUT_OF_DATE_KHR | vk::Result::SUBOPTIMAL_KHR => true,
-                _ => panic!("Faile to execure queue present!"),
-            },
-        };
-
-        if is_resized {
-            self.is_marked_resized = true;
-            self.render_device.recreate_swapchain();
-        }
-
-        self.render_device.current_frame = (self.render_device.current_frame + 1) % global_constants::MAX_FRAMES_IN_FLIGHT;
     }
 
     pub fn main_loop(mut self, event_loop: EventLoop<()>) {
@@ -164,6 +87,16 @@ impl PupsyEngine {
                         | WindowEvent::CloseRequested => {
                             *control_flow = ControlFlow::Exit
                         },
+                        | WindowEvent::Resized(size) => {
+                            self.render_device.mark_resized(size.width, size.height);
+                        },
+                        | WindowEvent::Moved(_) | WindowEvent::ScaleFactorChanged { .. } => {
+                            let monitor = self.window.current_monitor();
+                            if monitor != self.monitor {
+                                self.monitor = monitor;
+                                self.render_device.mark_display_changed();
+                            }
+                        },
                         | WindowEvent::KeyboardInput { input, .. } => {
                             match input {
                                 | KeyboardInput { virtual_keycode, state, .. } => {
@@ -172,6 +105,10 @@ impl PupsyEngine {
                                             dbg!();
                                             *control_flow = ControlFlow::Exit
                                         },
+                                        #[cfg(feature = "debug-overlay")]
+                                        | (Some(VirtualKeyCode::F3), ElementState::Pressed) => {
+                                            self.render_device.debug_overlay.toggle()
+                                        },
                                         | _ => {},
                                     }
                                 },
@@ -181,16 +118,20 @@ impl PupsyEngine {
                     }
                 },
                 | Event::MainEventsCleared => {
-                    self.window.request_redraw();
+                    // nothing can be rendered to a minimized window, sleep until the restore's resize event instead.
+                    if self.render_device.is_minimized() {
+                        *control_flow = ControlFlow::Wait;
+                    } else {
+                        *control_flow = ControlFlow::Poll;
+                        self.window.request_redraw();
+                    }
                 },
                 | Event::RedrawRequested(_window_id) => {
                     self.draw_frame();
                 },
                 | Event::LoopDestroyed => {
-                    unsafe {
-                        self.render_device.device.device_wait_idle()
-                            .expect("Failed to wait device idle!")
-                    };
+                    self.render_device.wait_idle()
+                        .expect("Failed to wait device idle!");
                 },
                 _ => (),
             }
@@ -201,7 +142,7 @@ impl PupsyEngine {
 
 impl Drop for PupsyEngine {
     fn drop(&mut self) {
-        self.render_device.drop();
+        self.render_device.destroy();
     }
 }
 
@@ -23,6 +23,17 @@ impl Window {
         }
     }
 
+    /// Size of the drawable area in physical pixels.
+    pub fn inner_size(&self) -> (u32, u32) {
+        let size = self.window.inner_size();
+        (size.width, size.height)
+    }
+
+    /// Display the window is on, `None` when the platform can't tell.
+    pub fn current_monitor(&self) -> Option<winit::monitor::MonitorHandle> {
+        self.window.current_monitor()
+    }
+
     pub fn request_redraw(&self) {
         self.window.request_redraw();
     }
@@ -9,4 +9,7 @@ pub const WINDOW_HEIGHT: u32 = 600;
 pub const WINDOW_TITLE: &'static str = "Pupsy Window";
 pub const ENGINE_TITLE: &'static str = "Pupsy Engine";
 
-pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
\ No newline at end of file
+pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+
+// None renders as fast as the present mode allows.
+pub const TARGET_FPS: Option<u32> = None;
\ No newline at end of file
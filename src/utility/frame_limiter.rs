@@ -0,0 +1,66 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+// thread::sleep can overshoot by a scheduler tick, so the last part of the wait is spun.
+const SPIN_THRESHOLD: Duration = Duration::from_micros(2000);
+
+pub struct FrameLimiter {
+    target_frame_time: Option<Duration>,
+    next_frame: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new() -> FrameLimiter {
+        FrameLimiter {
+            target_frame_time: None,
+            next_frame: Instant::now(),
+        }
+    }
+
+    /// `None` disables the limiter.
+    pub fn set_target_fps(&mut self, target_fps: Option<u32>) {
+        self.target_frame_time = target_fps
+            .filter(|&fps| fps > 0)
+            .map(|fps| Duration::from_secs(1) / fps);
+        self.next_frame = Instant::now();
+    }
+
+    pub fn target_fps(&self) -> Option<u32> {
+        self.target_frame_time
+            .map(|frame_time| (1f64 / frame_time.as_secs_f64()).round() as u32)
+    }
+
+    /// Blocks until the frame that started at the previous `wait` has taken the target frame time,
+    /// so time spent rendering counts against the budget.
+    pub fn wait(&mut self) {
+        let target_frame_time = match self.target_frame_time {
+            Some(target_frame_time) => target_frame_time,
+            None => return,
+        };
+
+        let deadline = self.next_frame + target_frame_time;
+        let now = Instant::now();
+
+        if deadline > now {
+            let remaining = deadline - now;
+            if remaining > SPIN_THRESHOLD {
+                thread::sleep(remaining - SPIN_THRESHOLD);
+            }
+
+            while Instant::now() < deadline {
+                std::hint::spin_loop();
+            }
+
+            self.next_frame = deadline;
+        } else {
+            // the frame ran over budget, start counting from now instead of trying to catch up.
+            self.next_frame = now;
+        }
+    }
+}
+
+impl Default for FrameLimiter {
+    fn default() -> FrameLimiter {
+        FrameLimiter::new()
+    }
+}
@@ -1,4 +1,5 @@
 pub mod constants;
 pub mod debug;
 pub mod tools;
-pub mod fps;
\ No newline at end of file
+pub mod fps;
+pub mod frame_limiter;
\ No newline at end of file
@@ -1,7 +1,11 @@
 use std::ffi::CStr;
+use std::io;
 use std::os::raw::c_char;
 use std::path::Path;
 
+/// First word of every SPIR-V module, in the module's byte order.
+pub const SPIRV_MAGIC_NUMBER: u32 = 0x0723_0203;
+
 pub fn vk_to_string(raw_string_array: &[c_char]) -> String {
     let raw_string = unsafe {
         let pointer = raw_string_array.as_ptr();
@@ -15,12 +19,95 @@ pub fn vk_to_string(raw_string_array: &[c_char]) -> String {
 }
 
 pub fn read_shader_code(shader_path: &Path) -> Vec<u8> {
-    use std::fs::File;
-    use std::io::Read;
+    read_file_bytes(shader_path).unwrap_or_else(|error| panic!("Failed to find spv file at {:?}: {}", shader_path, error))
+}
+
+/// Whole file at once, e.g. shaders, pipeline caches or texture containers.
+pub fn read_file_bytes(path: &Path) -> io::Result<Vec<u8>> {
+    std::fs::read(path)
+}
+
+/// SPIR-V module at `path` as words, ready for `vk::ShaderModuleCreateInfo::p_code`.
+pub fn read_spv(path: &Path) -> io::Result<Vec<u32>> {
+    spv_words(&read_file_bytes(path)?)
+}
+
+/// Copies `code` into words, it isn't guaranteed to be aligned for u32. Fails with `InvalidData`
+/// when the size isn't a multiple of 4 or it doesn't start with the SPIR-V magic number.
+pub fn spv_words(code: &[u8]) -> io::Result<Vec<u32>> {
+    if !code.len().is_multiple_of(4) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "SPIR-V size isn't a multiple of 4"));
+    }
+
+    let words: Vec<u32> = code
+        .chunks_exact(4)
+        .map(|word| u32::from_ne_bytes([word[0], word[1], word[2], word[3]]))
+        .collect();
+    if words.first() != Some(&SPIRV_MAGIC_NUMBER) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "SPIR-V magic number is missing"));
+    }
+
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    /// Unique per test and process, removed again on drop.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &[u8]) -> TempFile {
+            let path = std::env::temp_dir().join(format!("pupsy_tools_{}_{}", std::process::id(), name));
+            std::fs::write(&path, contents).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn spv_bytes(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_ne_bytes()).collect()
+    }
+
+    #[test]
+    fn read_file_bytes_returns_the_whole_file() {
+        let file = TempFile::new("bytes", &[1, 2, 3, 4, 5]);
+        assert_eq!(read_file_bytes(&file.0).unwrap(), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn read_file_bytes_fails_for_a_missing_file() {
+        let path = std::env::temp_dir().join(format!("pupsy_tools_{}_missing", std::process::id()));
+        assert_eq!(read_file_bytes(&path).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn read_spv_returns_words() {
+        let words = [SPIRV_MAGIC_NUMBER, 0x0001_0000, 7];
+        let file = TempFile::new("valid.spv", &spv_bytes(&words));
+        assert_eq!(read_spv(&file.0).unwrap(), words.to_vec());
+    }
 
-    let spv_file =
-        File::open(shader_path).expect(&format!("Failed to find spv file at {:?}", shader_path));
-    let bytes_code: Vec<u8> = spv_file.bytes().filter_map(|byte| byte.ok()).collect();
+    #[test]
+    fn read_spv_rejects_unaligned_size() {
+        let mut bytes = spv_bytes(&[SPIRV_MAGIC_NUMBER]);
+        bytes.push(0);
+        let file = TempFile::new("unaligned.spv", &bytes);
+        assert_eq!(read_spv(&file.0).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
 
-    bytes_code
+    #[test]
+    fn read_spv_rejects_missing_magic_number() {
+        let file = TempFile::new("not_spirv.spv", &spv_bytes(&[1, 2]));
+        assert_eq!(read_spv(&file.0).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(spv_words(&[]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
 }
@@ -0,0 +1,377 @@
+use ash::vk;
+
+use std::ptr;
+
+use crate::vk::buffer::{buffer_device_address, MappedBuffer, VkBuffer};
+use crate::vk::commands::UploadContext;
+use crate::vk::ray_tracing::align_up;
+
+/// Row major 3x4 matrix placing an instance in the top level structure.
+pub const IDENTITY_TRANSFORM: [f32; 12] = [
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 1.0, 0.0,
+];
+
+/// Acceleration structure with the buffer it lives in, needs `capabilities.acceleration_structure`.
+pub struct VkAccelerationStructure {
+    pub acceleration_structure: vk::AccelerationStructureKHR,
+    pub buffer: VkBuffer,
+    /// Referenced by top level instances and read by shaders.
+    pub device_address: vk::DeviceAddress,
+
+    /// Only used by the build, see `free_scratch`.
+    scratch_buffer: Option<VkBuffer>,
+}
+
+impl VkAccelerationStructure {
+    /// Bottom level structure over one opaque triangle mesh, built right away through `upload_context`.
+    pub fn bottom_level(
+        loader: &ash::extensions::khr::AccelerationStructure,
+        upload_context: &UploadContext,
+        vertex_buffer: &VkBuffer,
+        vertex_count: u32,
+        vertex_stride: vk::DeviceSize,
+        index_buffer: &VkBuffer,
+        index_count: u32,
+    ) -> VkAccelerationStructure {
+        let builder = AccelerationStructureBuilder::bottom_level()
+            .triangles(upload_context.device, vertex_buffer, vertex_count, vertex_stride, index_buffer, index_count);
+
+        VkAccelerationStructure::build_now(loader, upload_context, &builder)
+    }
+
+    /// Top level structure with one instance of every bottom level structure at its transform.
+    pub fn top_level(
+        loader: &ash::extensions::khr::AccelerationStructure,
+        upload_context: &UploadContext,
+        bottom_levels: &[(&VkAccelerationStructure, [f32; 12])],
+    ) -> VkAccelerationStructure {
+        let device = upload_context.device;
+
+        let instances: Vec<vk::AccelerationStructureInstanceKHR> = bottom_levels
+            .iter()
+            .enumerate()
+            .map(|(index, &(bottom_level, transform))| bottom_level.instance(transform, index as u32))
+            .collect();
+
+        let mut instance_buffer = MappedBuffer::new(
+            device,
+            std::mem::size_of_val(&instances[..]).max(1) as vk::DeviceSize,
+            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+            upload_context.device_memory_properties,
+            upload_context.non_coherent_atom_size,
+        );
+        instance_buffer.write(device, 0, &instances);
+
+        let builder = AccelerationStructureBuilder::top_level()
+            .instances(device, instance_buffer.buffer, instances.len() as u32);
+        let top_level = VkAccelerationStructure::build_now(loader, upload_context, &builder);
+
+        instance_buffer.destroy(device);
+        top_level
+    }
+
+    /// Instance of this bottom level structure for a top level instance buffer,
+    /// `custom_index` is what shaders read as `gl_InstanceCustomIndexEXT`.
+    pub fn instance(&self, transform: [f32; 12], custom_index: u32) -> vk::AccelerationStructureInstanceKHR {
+        vk::AccelerationStructureInstanceKHR {
+            transform: vk::TransformMatrixKHR { matrix: transform },
+            instance_custom_index_and_mask: vk::Packed24_8::new(custom_index, 0xff),
+            instance_shader_binding_table_record_offset_and_flags: vk::Packed24_8::new(
+                0,
+                vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() as u8),
+            acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
+                device_handle: self.device_address,
+            },
+        }
+    }
+
+    /// Frees the scratch buffer once the command buffer recording the build has finished.
+    pub fn free_scratch(&mut self, device: &ash::Device) {
+        if let Some(scratch_buffer) = self.scratch_buffer.take() {
+            scratch_buffer.destroy(device);
+        }
+    }
+
+    /// The device must not be using the structure anymore.
+    pub fn destroy(&mut self, loader: &ash::extensions::khr::AccelerationStructure, device: &ash::Device) {
+        self.free_scratch(device);
+        unsafe {
+            loader.destroy_acceleration_structure(self.acceleration_structure, None);
+        }
+        self.buffer.destroy(device);
+    }
+
+    fn build_now(
+        loader: &ash::extensions::khr::AccelerationStructure,
+        upload_context: &UploadContext,
+        builder: &AccelerationStructureBuilder,
+    ) -> VkAccelerationStructure {
+        let properties = unsafe {
+            ash::extensions::khr::AccelerationStructure::get_properties(upload_context.instance, upload_context.physical_device)
+        };
+
+        let mut acceleration_structure = None;
+        upload_context.execute(|command_buffer| {
+            acceleration_structure = Some(builder.cmd_build(
+                loader,
+                upload_context.device,
+                upload_context.device_memory_properties,
+                properties.min_acceleration_structure_scratch_offset_alignment as vk::DeviceSize,
+                command_buffer));
+        });
+
+        let mut acceleration_structure = acceleration_structure.unwrap();
+        acceleration_structure.free_scratch(upload_context.device);
+        acceleration_structure
+    }
+}
+
+/// Geometry of one bottom or top level acceleration structure, the build is recorded into the caller's command buffer.
+/// Needs `capabilities.acceleration_structure`, every geometry buffer needs
+/// `SHADER_DEVICE_ADDRESS | ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR` usage.
+pub struct AccelerationStructureBuilder {
+    structure_type: vk::AccelerationStructureTypeKHR,
+    flags: vk::BuildAccelerationStructureFlagsKHR,
+    geometries: Vec<vk::AccelerationStructureGeometryKHR>,
+    primitive_counts: Vec<u32>,
+}
+
+impl AccelerationStructureBuilder {
+    pub fn bottom_level() -> AccelerationStructureBuilder {
+        AccelerationStructureBuilder::new(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+    }
+
+    pub fn top_level() -> AccelerationStructureBuilder {
+        AccelerationStructureBuilder::new(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
+    }
+
+    /// `PREFER_FAST_TRACE` by default.
+    pub fn flags(mut self, flags: vk::BuildAccelerationStructureFlagsKHR) -> AccelerationStructureBuilder {
+        self.flags = flags;
+        self
+    }
+
+    /// Opaque triangle mesh of at least one triangle, only for bottom level structures.
+    /// Positions are `R32G32B32_SFLOAT` at the start of every `vertex_stride` bytes and indices are `u32`.
+    pub fn triangles(
+        mut self,
+        device: &ash::Device,
+        vertex_buffer: &VkBuffer,
+        vertex_count: u32,
+        vertex_stride: vk::DeviceSize,
+        index_buffer: &VkBuffer,
+        index_count: u32,
+    ) -> AccelerationStructureBuilder {
+        assert_eq!(self.structure_type, vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL, "Triangles need a bottom level structure");
+        assert!(vertex_count > 0 && index_count >= 3, "Triangles need at least one triangle");
+
+        let triangles = vk::AccelerationStructureGeometryTrianglesDataKHR {
+            s_type: vk::StructureType::ACCELERATION_STRUCTURE_GEOMETRY_TRIANGLES_DATA_KHR,
+            p_next: ptr::null(),
+            vertex_format: vk::Format::R32G32B32_SFLOAT,
+            vertex_data: vk::DeviceOrHostAddressConstKHR {
+                device_address: buffer_device_address(device, vertex_buffer.buffer),
+            },
+            vertex_stride,
+            max_vertex: vertex_count - 1,
+            index_type: vk::IndexType::UINT32,
+            index_data: vk::DeviceOrHostAddressConstKHR {
+                device_address: buffer_device_address(device, index_buffer.buffer),
+            },
+            transform_data: vk::DeviceOrHostAddressConstKHR::default(),
+        };
+
+        self.geometries.push(vk::AccelerationStructureGeometryKHR {
+            s_type: vk::StructureType::ACCELERATION_STRUCTURE_GEOMETRY_KHR,
+            p_next: ptr::null(),
+            geometry_type: vk::GeometryTypeKHR::TRIANGLES,
+            geometry: vk::AccelerationStructureGeometryDataKHR { triangles },
+            flags: vk::GeometryFlagsKHR::OPAQUE,
+        });
+        self.primitive_counts.push(index_count / 3);
+        self
+    }
+
+    /// Tightly packed `vk::AccelerationStructureInstanceKHR`s, only for top level structures.
+    pub fn instances(
+        mut self,
+        device: &ash::Device,
+        instance_buffer: vk::Buffer,
+        instance_count: u32,
+    ) -> AccelerationStructureBuilder {
+        assert_eq!(self.structure_type, vk::AccelerationStructureTypeKHR::TOP_LEVEL, "Instances need a top level structure");
+
+        self.geometries.push(vk::AccelerationStructureGeometryKHR {
+            s_type: vk::StructureType::ACCELERATION_STRUCTURE_GEOMETRY_KHR,
+            p_next: ptr::null(),
+            geometry_type: vk::GeometryTypeKHR::INSTANCES,
+            geometry: vk::AccelerationStructureGeometryDataKHR {
+                instances: vk::AccelerationStructureGeometryInstancesDataKHR {
+                    s_type: vk::StructureType::ACCELERATION_STRUCTURE_GEOMETRY_INSTANCES_DATA_KHR,
+                    p_next: ptr::null(),
+                    array_of_pointers: vk::FALSE,
+                    data: vk::DeviceOrHostAddressConstKHR {
+                        device_address: buffer_device_address(device, instance_buffer),
+                    },
+                },
+            },
+            flags: vk::GeometryFlagsKHR::OPAQUE,
+        });
+        self.primitive_counts.push(instance_count);
+        self
+    }
+
+    /// Sizes of the structure and of the scratch buffer its build needs.
+    pub fn build_sizes(
+        &self,
+        loader: &ash::extensions::khr::AccelerationStructure,
+    ) -> vk::AccelerationStructureBuildSizesInfoKHR {
+        unsafe {
+            loader.get_acceleration_structure_build_sizes(
+                vk::AccelerationStructureBuildTypeKHR::DEVICE,
+                &self.build_info(),
+                &self.primitive_counts)
+        }
+    }
+
+    /// Allocates the structure and its scratch buffer and records the build into `command_buffer`.
+    /// `scratch_offset_alignment` is `minAccelerationStructureScratchOffsetAlignment`,
+    /// see `VkRenderDevice::acceleration_structure_properties`.
+    /// Call `free_scratch` once the command buffer has finished, builds in the same command buffer
+    /// that read this structure need `cmd_build_barrier` first.
+    pub fn cmd_build(
+        &self,
+        loader: &ash::extensions::khr::AccelerationStructure,
+        device: &ash::Device,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        scratch_offset_alignment: vk::DeviceSize,
+        command_buffer: vk::CommandBuffer,
+    ) -> VkAccelerationStructure {
+        assert!(!self.geometries.is_empty(), "Acceleration structure needs geometry");
+        let build_sizes = self.build_sizes(loader);
+
+        let buffer = VkBuffer::new(
+            device,
+            build_sizes.acceleration_structure_size,
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            device_memory_properties,
+        );
+
+        let create_info = vk::AccelerationStructureCreateInfoKHR {
+            s_type: vk::StructureType::ACCELERATION_STRUCTURE_CREATE_INFO_KHR,
+            p_next: ptr::null(),
+            create_flags: vk::AccelerationStructureCreateFlagsKHR::empty(),
+            buffer: buffer.buffer,
+            offset: 0,
+            size: build_sizes.acceleration_structure_size,
+            ty: self.structure_type,
+            device_address: 0,
+        };
+        let acceleration_structure = unsafe {
+            loader
+                .create_acceleration_structure(&create_info, None)
+                .expect("Failed to create Acceleration Structure!")
+        };
+
+        // buffers are only guaranteed their memory alignment, the slack lets the address be rounded up.
+        let scratch_offset_alignment = scratch_offset_alignment.max(1);
+        let scratch_buffer = VkBuffer::new(
+            device,
+            build_sizes.build_scratch_size + scratch_offset_alignment - 1,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            device_memory_properties,
+        );
+
+        let mut build_info = self.build_info();
+        build_info.dst_acceleration_structure = acceleration_structure;
+        build_info.scratch_data = vk::DeviceOrHostAddressKHR {
+            device_address: align_up(buffer_device_address(device, scratch_buffer.buffer), scratch_offset_alignment),
+        };
+
+        let build_ranges: Vec<vk::AccelerationStructureBuildRangeInfoKHR> = self.primitive_counts
+            .iter()
+            .map(|&primitive_count| vk::AccelerationStructureBuildRangeInfoKHR {
+                primitive_count,
+                primitive_offset: 0,
+                first_vertex: 0,
+                transform_offset: 0,
+            })
+            .collect();
+
+        unsafe {
+            loader.cmd_build_acceleration_structures(
+                command_buffer,
+                std::slice::from_ref(&build_info),
+                &[&build_ranges[..]]);
+        }
+
+        let address_info = vk::AccelerationStructureDeviceAddressInfoKHR {
+            s_type: vk::StructureType::ACCELERATION_STRUCTURE_DEVICE_ADDRESS_INFO_KHR,
+            p_next: ptr::null(),
+            acceleration_structure,
+        };
+        let device_address = unsafe { loader.get_acceleration_structure_device_address(&address_info) };
+
+        VkAccelerationStructure {
+            acceleration_structure: acceleration_structure,
+            buffer: buffer,
+            device_address: device_address,
+
+            scratch_buffer: Some(scratch_buffer),
+        }
+    }
+
+    fn new(structure_type: vk::AccelerationStructureTypeKHR) -> AccelerationStructureBuilder {
+        AccelerationStructureBuilder {
+            structure_type: structure_type,
+            flags: vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE,
+            geometries: vec![],
+            primitive_counts: vec![],
+        }
+    }
+
+    /// Points into `geometries`, only valid as long as the builder is not changed.
+    fn build_info(&self) -> vk::AccelerationStructureBuildGeometryInfoKHR {
+        vk::AccelerationStructureBuildGeometryInfoKHR {
+            s_type: vk::StructureType::ACCELERATION_STRUCTURE_BUILD_GEOMETRY_INFO_KHR,
+            p_next: ptr::null(),
+            ty: self.structure_type,
+            flags: self.flags,
+            mode: vk::BuildAccelerationStructureModeKHR::BUILD,
+            src_acceleration_structure: vk::AccelerationStructureKHR::null(),
+            dst_acceleration_structure: vk::AccelerationStructureKHR::null(),
+            geometry_count: self.geometries.len() as u32,
+            p_geometries: self.geometries.as_ptr(),
+            pp_geometries: ptr::null(),
+            scratch_data: vk::DeviceOrHostAddressKHR::default(),
+        }
+    }
+}
+
+/// Makes finished builds visible to `dst_stage_mask`, e.g. `ACCELERATION_STRUCTURE_BUILD_KHR` between bottom and top level builds
+/// or the shader stages doing ray queries.
+pub fn cmd_build_barrier(device: &ash::Device, command_buffer: vk::CommandBuffer, dst_stage_mask: vk::PipelineStageFlags) {
+    let memory_barrier = vk::MemoryBarrier {
+        s_type: vk::StructureType::MEMORY_BARRIER,
+        p_next: ptr::null(),
+        src_access_mask: vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+        dst_access_mask: vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR,
+    };
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+            dst_stage_mask,
+            vk::DependencyFlags::empty(),
+            &[memory_barrier],
+            &[],
+            &[],
+        );
+    }
+}
@@ -0,0 +1,343 @@
+use ash::vk;
+
+use std::ptr;
+
+use std::os::raw::c_void;
+
+use crate::vk::capabilities::DeviceCapabilities;
+use crate::vk::lifecycle::{self, TrackedObject};
+use crate::vk::render_device::VkRenderDevice;
+
+/// Host visible buffer that stays mapped for its whole lifetime.
+/// Prefers `HOST_COHERENT` memory, otherwise every write is flushed to the device.
+pub struct MappedBuffer {
+    pub buffer: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+    pub size: vk::DeviceSize,
+    pub is_coherent: bool,
+
+    allocation_size: vk::DeviceSize,
+    non_coherent_atom_size: vk::DeviceSize,
+    mapped: *mut u8,
+}
+
+/// Grows `offset..offset + size` to `non_coherent_atom_size` boundaries, without going past the allocation.
+pub fn align_mapped_range(
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    non_coherent_atom_size: vk::DeviceSize,
+    allocation_size: vk::DeviceSize,
+) -> (vk::DeviceSize, vk::DeviceSize) {
+    let atom_size = non_coherent_atom_size.max(1);
+    let aligned_offset = offset / atom_size * atom_size;
+    let aligned_end = ((offset + size).div_ceil(atom_size) * atom_size).min(allocation_size);
+
+    (aligned_offset, aligned_end - aligned_offset)
+}
+
+/// Makes host writes to `offset..offset + size` of mapped `memory` visible to the device.
+pub fn flush_mapped_range(
+    device: &ash::Device,
+    memory: vk::DeviceMemory,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    non_coherent_atom_size: vk::DeviceSize,
+    allocation_size: vk::DeviceSize,
+) {
+    let ranges = [mapped_memory_range(memory, offset, size, non_coherent_atom_size, allocation_size)];
+
+    unsafe {
+        device
+            .flush_mapped_memory_ranges(&ranges)
+            .expect("Failed to flush mapped memory");
+    }
+}
+
+/// Makes device writes to `offset..offset + size` of mapped `memory` visible to the host.
+pub fn invalidate_mapped_range(
+    device: &ash::Device,
+    memory: vk::DeviceMemory,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    non_coherent_atom_size: vk::DeviceSize,
+    allocation_size: vk::DeviceSize,
+) {
+    let ranges = [mapped_memory_range(memory, offset, size, non_coherent_atom_size, allocation_size)];
+
+    unsafe {
+        device
+            .invalidate_mapped_memory_ranges(&ranges)
+            .expect("Failed to invalidate mapped memory");
+    }
+}
+
+/// Buffers with `SHADER_DEVICE_ADDRESS` usage need memory allocated with the `DEVICE_ADDRESS` flag.
+fn allocate_buffer_memory(
+    device: &ash::Device,
+    allocation_size: vk::DeviceSize,
+    memory_type_index: u32,
+    usage: vk::BufferUsageFlags,
+) -> vk::DeviceMemory {
+    let allocate_flags_info = vk::MemoryAllocateFlagsInfo {
+        s_type: vk::StructureType::MEMORY_ALLOCATE_FLAGS_INFO,
+        p_next: ptr::null(),
+        flags: vk::MemoryAllocateFlags::DEVICE_ADDRESS,
+        device_mask: 0,
+    };
+
+    let allocate_info = vk::MemoryAllocateInfo {
+        s_type: vk::StructureType::MEMORY_ALLOCATE_INFO,
+        p_next: if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
+            &allocate_flags_info as *const _ as *const c_void
+        } else {
+            ptr::null()
+        },
+        allocation_size,
+        memory_type_index,
+    };
+
+    unsafe {
+        device
+            .allocate_memory(&allocate_info, None)
+            .expect("Failed to allocate buffer memory!")
+    }
+}
+
+fn mapped_memory_range(
+    memory: vk::DeviceMemory,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    non_coherent_atom_size: vk::DeviceSize,
+    allocation_size: vk::DeviceSize,
+) -> vk::MappedMemoryRange {
+    let (offset, size) = align_mapped_range(offset, size, non_coherent_atom_size, allocation_size);
+
+    vk::MappedMemoryRange {
+        s_type: vk::StructureType::MAPPED_MEMORY_RANGE,
+        p_next: ptr::null(),
+        memory,
+        offset,
+        size,
+    }
+}
+
+impl MappedBuffer {
+    pub fn new(
+        device: &ash::Device,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        non_coherent_atom_size: vk::DeviceSize,
+    ) -> MappedBuffer {
+        let buffer_create_info = vk::BufferCreateInfo {
+            s_type: vk::StructureType::BUFFER_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::BufferCreateFlags::empty(),
+            size,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            queue_family_index_count: 0,
+            p_queue_family_indices: ptr::null(),
+        };
+
+        let buffer = unsafe {
+            device
+                .create_buffer(&buffer_create_info, None)
+                .expect("Failed to create Mapped Buffer")
+        };
+        lifecycle::on_create(TrackedObject::Buffer, buffer);
+
+        let mem_requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+        let coherent_memory_type = VkRenderDevice::try_find_memory_type(
+            mem_requirements.memory_type_bits,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            device_memory_properties,
+        );
+        let is_coherent = coherent_memory_type.is_some();
+        let memory_type = coherent_memory_type.unwrap_or_else(|| {
+            VkRenderDevice::find_memory_type(
+                mem_requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::HOST_VISIBLE,
+                device_memory_properties,
+            )
+        });
+
+        let memory = allocate_buffer_memory(device, mem_requirements.size, memory_type, usage);
+
+        let mapped = unsafe {
+            device
+                .bind_buffer_memory(buffer, memory, 0)
+                .expect("Failed to bind Buffer");
+
+            device
+                .map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+                .expect("Failed to Map Memory") as *mut u8
+        };
+
+        MappedBuffer {
+            buffer: buffer,
+            memory: memory,
+            size: size,
+            is_coherent: is_coherent,
+
+            allocation_size: mem_requirements.size,
+            non_coherent_atom_size: non_coherent_atom_size,
+            mapped: mapped,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.mapped, self.size as usize) }
+    }
+
+    /// Writes through this slice are only seen by the device after `flush_range` on non-coherent memory,
+    /// and device writes only after `invalidate_range`.
+    pub fn as_slice_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.mapped, self.size as usize) }
+    }
+
+    /// Copies `data` to `offset` bytes into the buffer and makes it visible to the device.
+    pub fn write<T: Copy>(&mut self, device: &ash::Device, offset: vk::DeviceSize, data: &[T]) {
+        let byte_size = std::mem::size_of_val(data) as vk::DeviceSize;
+        assert!(offset + byte_size <= self.size, "Mapped buffer write out of bounds");
+
+        unsafe {
+            let data_ptr = self.mapped.add(offset as usize) as *mut T;
+            data_ptr.copy_from_nonoverlapping(data.as_ptr(), data.len());
+        }
+
+        self.flush_range(device, offset, byte_size);
+    }
+
+    /// No-op on coherent memory.
+    pub fn flush_range(&self, device: &ash::Device, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        if !self.is_coherent {
+            flush_mapped_range(device, self.memory, offset, size, self.non_coherent_atom_size, self.allocation_size);
+        }
+    }
+
+    /// No-op on coherent memory.
+    pub fn invalidate_range(&self, device: &ash::Device, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        if !self.is_coherent {
+            invalidate_mapped_range(device, self.memory, offset, size, self.non_coherent_atom_size, self.allocation_size);
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.unmap_memory(self.memory);
+            lifecycle::on_destroy(TrackedObject::Buffer, self.buffer);
+            device.destroy_buffer(self.buffer, None);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// Buffer with its own memory of `memory_properties`, usually `DEVICE_LOCAL` and filled through transfers.
+pub struct VkBuffer {
+    pub buffer: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+    pub size: vk::DeviceSize,
+    pub usage: vk::BufferUsageFlags,
+}
+
+impl VkBuffer {
+    pub fn new(
+        device: &ash::Device,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        memory_properties: vk::MemoryPropertyFlags,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> VkBuffer {
+        let buffer_create_info = vk::BufferCreateInfo {
+            s_type: vk::StructureType::BUFFER_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::BufferCreateFlags::empty(),
+            size,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            queue_family_index_count: 0,
+            p_queue_family_indices: ptr::null(),
+        };
+
+        let buffer = unsafe {
+            device
+                .create_buffer(&buffer_create_info, None)
+                .expect("Failed to create Buffer")
+        };
+        lifecycle::on_create(TrackedObject::Buffer, buffer);
+
+        let mem_requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+        let memory_type = VkRenderDevice::find_memory_type(
+            mem_requirements.memory_type_bits,
+            memory_properties,
+            device_memory_properties,
+        );
+        let memory = allocate_buffer_memory(device, mem_requirements.size, memory_type, usage);
+
+        unsafe {
+            device
+                .bind_buffer_memory(buffer, memory, 0)
+                .expect("Failed to bind Buffer");
+        }
+
+        VkBuffer {
+            buffer: buffer,
+            memory: memory,
+            size: size,
+            usage: usage,
+        }
+    }
+
+    /// Address for reading the buffer through pointers in shaders, e.g. for bindless buffers.
+    /// Needs `capabilities.buffer_device_address` and `SHADER_DEVICE_ADDRESS` usage.
+    pub fn device_address(&self, device: &ash::Device, capabilities: &DeviceCapabilities) -> vk::DeviceAddress {
+        assert!(capabilities.buffer_device_address, "Buffer device address is not enabled on the device");
+        assert!(
+            self.usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS),
+            "Buffer was not created with SHADER_DEVICE_ADDRESS usage");
+
+        buffer_device_address(device, self.buffer)
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            lifecycle::on_destroy(TrackedObject::Buffer, self.buffer);
+            device.destroy_buffer(self.buffer, None);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// Unchecked `vkGetBufferDeviceAddress`, prefer `VkBuffer::device_address`.
+pub fn buffer_device_address(device: &ash::Device, buffer: vk::Buffer) -> vk::DeviceAddress {
+    let address_info = vk::BufferDeviceAddressInfo {
+        s_type: vk::StructureType::BUFFER_DEVICE_ADDRESS_INFO,
+        p_next: ptr::null(),
+        buffer,
+    };
+
+    unsafe { device.get_buffer_device_address(&address_info) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapped_range_is_aligned_to_atom_size() {
+        assert_eq!(align_mapped_range(0, 16, 64, 256), (0, 64));
+        assert_eq!(align_mapped_range(70, 10, 64, 256), (64, 64));
+        assert_eq!(align_mapped_range(60, 10, 64, 256), (0, 128));
+        // the end is clamped to the allocation instead of rounded past it.
+        assert_eq!(align_mapped_range(200, 20, 64, 240), (192, 48));
+    }
+
+    #[test]
+    fn range_on_atom_boundaries_is_unchanged() {
+        assert_eq!(align_mapped_range(64, 128, 64, 256), (64, 128));
+        // a zero atom size, as for coherent memory, must not divide by zero.
+        assert_eq!(align_mapped_range(70, 10, 0, 256), (70, 10));
+        assert_eq!(align_mapped_range(70, 10, 1, 256), (70, 10));
+    }
+}
@@ -0,0 +1,471 @@
+use ash::vk;
+
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::fmt;
+
+use crate::utility::tools;
+
+/// Features, extensions and limits that are enabled on the logical device.
+/// Queried once before device creation, then handed out next to the `ash::Device`.
+#[derive(Clone, Copy, Debug)]
+pub struct DeviceCapabilities {
+    pub api_version: u32,
+
+    pub dynamic_rendering: bool,
+    pub synchronization2: bool,
+    pub timeline_semaphore: bool,
+    pub bindless: bool,
+    /// `bufferDeviceAddress`, buffers created with `SHADER_DEVICE_ADDRESS` usage can be read through pointers.
+    pub buffer_device_address: bool,
+    /// `VK_KHR_present_id`, every present gets tagged with an increasing id.
+    pub present_id: bool,
+    /// `VK_KHR_present_wait`, only with `present_id` since it waits on those ids.
+    pub present_wait: bool,
+    /// `VK_EXT_swapchain_maintenance1`, present modes can be switched per present without recreating the swapchain.
+    /// Also needs `VK_EXT_surface_maintenance1` on the instance, see `VkRenderDevice::supports_surface_maintenance1`.
+    pub swapchain_maintenance1: bool,
+    /// `VK_KHR_acceleration_structure`, only together with `buffer_device_address`.
+    /// Enough for ray queries, see `AccelerationStructureBuilder`.
+    pub acceleration_structure: bool,
+    /// `VK_KHR_ray_tracing_pipeline`, only together with `acceleration_structure`, see `ray_tracing`.
+    pub ray_tracing: bool,
+    /// `VK_EXT_mesh_shader`, graphics pipelines can be built from mesh shaders, see `GraphicsPipelineBuilder::mesh_shader`.
+    pub mesh_shader: bool,
+    /// Task shaders in front of mesh shaders, only together with `mesh_shader`.
+    pub task_shader: bool,
+    /// `VK_KHR_fragment_shading_rate` with `pipelineFragmentShadingRate`, shading rates per pipeline or per draw,
+    /// see `GraphicsPipelineBuilder::fragment_shading_rate`.
+    pub fragment_shading_rate: bool,
+    /// `VK_EXT_conditional_rendering`, draws can be skipped by a value in a buffer,
+    /// see `VkRenderDevice::cmd_begin_conditional_rendering`.
+    pub conditional_rendering: bool,
+    /// `VK_EXT_transform_feedback`, vertex outputs can be captured to a buffer, see `TransformFeedbackCapture`.
+    pub transform_feedback: bool,
+    /// `VK_GOOGLE_display_timing`, presents can be scheduled for a time and report when they actually happened,
+    /// see `VkRenderDevice::present_at`.
+    pub display_timing: bool,
+    /// `VK_EXT_hdr_metadata`, the display is told the luminance range of the content, see `VkRenderDevice::set_hdr_metadata`.
+    pub hdr_metadata: bool,
+    /// `multiview` (Vulkan 1.1), one draw renders to several array layers, see `RenderPassBuilder::multiview`.
+    pub multiview: bool,
+    /// `VK_EXT_depth_clip_control`, pipelines can take OpenGL's -1 to 1 depth range,
+    /// see `GraphicsPipelineBuilder::negative_one_to_one_depth`.
+    pub depth_clip_control: bool,
+    /// `VK_EXT_device_fault` with `deviceFault`, a lost device can tell what faulted, see `VkRenderDevice::device_fault`.
+    pub device_fault: bool,
+    /// `deviceFaultVendorBinary`, the fault comes with a vendor specific crash dump for the vendor's tools.
+    pub device_fault_vendor_binary: bool,
+    /// `protectedMemory` (Vulkan 1.1) on a graphics family with protected queues, only enabled with
+    /// `DeviceConfig::protected_memory`. Needed for protected swapchains, see `SwapChainConfig::protected`.
+    pub protected_memory: bool,
+    /// `VK_EXT_memory_budget`, heaps report how much of them is used and available to the process,
+    /// see `VkRenderDevice::memory_budget`.
+    pub memory_budget: bool,
+    /// Physical devices behind the logical device, more than one only with `DeviceConfig::use_device_group`.
+    pub device_group_size: u32,
+
+    pub sampler_anisotropy: bool,
+    pub geometry_shader: bool,
+    pub tessellation_shader: bool,
+    pub multi_viewport: bool,
+    pub depth_bias_clamp: bool,
+    /// Needed for cubemap array views, see `VkTexture::from_compressed_file`.
+    pub image_cube_array: bool,
+    /// `sparseBinding` and `sparseResidencyImage2D` with a graphics queue family that can bind sparse memory,
+    /// see `VkSparseImage`. The device also requires it of the family `graphics_queue` is from.
+    pub sparse_residency_image_2d: bool,
+
+    pub max_sample_count: vk::SampleCountFlags,
+    pub max_sampler_anisotropy: f32,
+    pub max_viewports: u32,
+    pub max_image_dimension_2d: u32,
+    pub max_image_dimension_3d: u32,
+    pub max_image_dimension_cube: u32,
+    pub max_image_array_layers: u32,
+    pub max_push_constants_size: u32,
+    pub max_bound_descriptor_sets: u32,
+    pub non_coherent_atom_size: vk::DeviceSize,
+    pub timestamp_period: f32,
+}
+
+impl DeviceCapabilities {
+    /// `instance_api_version` is the version `instance` was created with.
+    pub fn query(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        instance_api_version: u32
+    ) -> DeviceCapabilities {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let limits = properties.limits;
+        // the device can't go past the version the instance was created with.
+        let api_version = properties.api_version.min(instance_api_version);
+
+        let extensions = DeviceCapabilities::supported_extensions(instance, physical_device);
+        let is_vulkan_1_2 = api_version >= vk::API_VERSION_1_2;
+        let has_extension = |name: &CStr| extensions.contains(name.to_str().unwrap());
+
+        let has_dynamic_rendering_ext = is_vulkan_1_2 && has_extension(ash::extensions::khr::DynamicRendering::name());
+        let has_synchronization2_ext = is_vulkan_1_2 && has_extension(ash::extensions::khr::Synchronization2::name());
+        let has_present_id_ext = is_vulkan_1_2 && has_extension(vk::KhrPresentIdFn::name());
+        let has_present_wait_ext = has_present_id_ext && has_extension(ash::extensions::khr::PresentWait::name());
+        let has_swapchain_maintenance1_ext = api_version >= vk::API_VERSION_1_1
+            && has_extension(vk::ExtSwapchainMaintenance1Fn::name());
+        let has_acceleration_structure_ext = is_vulkan_1_2
+            && has_extension(ash::extensions::khr::AccelerationStructure::name())
+            && has_extension(ash::extensions::khr::DeferredHostOperations::name());
+        let has_ray_tracing_ext = has_acceleration_structure_ext
+            && has_extension(ash::extensions::khr::RayTracingPipeline::name());
+        let has_mesh_shader_ext = is_vulkan_1_2 && has_extension(ash::extensions::ext::MeshShader::name());
+        let has_fragment_shading_rate_ext = is_vulkan_1_2 && has_extension(vk::KhrFragmentShadingRateFn::name());
+        let has_conditional_rendering_ext = api_version >= vk::API_VERSION_1_1
+            && has_extension(vk::ExtConditionalRenderingFn::name());
+        let has_transform_feedback_ext = api_version >= vk::API_VERSION_1_1
+            && has_extension(vk::ExtTransformFeedbackFn::name());
+        let has_depth_clip_control_ext = api_version >= vk::API_VERSION_1_1
+            && has_extension(vk::ExtDepthClipControlFn::name());
+        let has_device_fault_ext = api_version >= vk::API_VERSION_1_1
+            && has_extension(vk::ExtDeviceFaultFn::name());
+
+        let mut vulkan12_features = vk::PhysicalDeviceVulkan12Features::default();
+        let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+        let mut synchronization2_features = vk::PhysicalDeviceSynchronization2Features::default();
+        let mut present_id_features = vk::PhysicalDevicePresentIdFeaturesKHR::default();
+        let mut present_wait_features = vk::PhysicalDevicePresentWaitFeaturesKHR::default();
+        let mut swapchain_maintenance1_features = vk::PhysicalDeviceSwapchainMaintenance1FeaturesEXT::default();
+        let mut acceleration_structure_features = vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default();
+        let mut ray_tracing_pipeline_features = vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::default();
+        let mut mesh_shader_features = vk::PhysicalDeviceMeshShaderFeaturesEXT::default();
+        let mut fragment_shading_rate_features = vk::PhysicalDeviceFragmentShadingRateFeaturesKHR::default();
+        let mut conditional_rendering_features = vk::PhysicalDeviceConditionalRenderingFeaturesEXT::default();
+        let mut transform_feedback_features = vk::PhysicalDeviceTransformFeedbackFeaturesEXT::default();
+        let mut multiview_features = vk::PhysicalDeviceMultiviewFeatures::default();
+        let mut depth_clip_control_features = vk::PhysicalDeviceDepthClipControlFeaturesEXT::default();
+        let mut device_fault_features = vk::PhysicalDeviceFaultFeaturesEXT::default();
+        let mut protected_memory_features = vk::PhysicalDeviceProtectedMemoryFeatures::default();
+
+        let mut feature_chain: Vec<*mut vk::BaseOutStructure> = vec![];
+        if is_vulkan_1_2 {
+            feature_chain.push(&mut vulkan12_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if has_dynamic_rendering_ext {
+            feature_chain.push(&mut dynamic_rendering_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if has_synchronization2_ext {
+            feature_chain.push(&mut synchronization2_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if has_present_id_ext {
+            feature_chain.push(&mut present_id_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if has_present_wait_ext {
+            feature_chain.push(&mut present_wait_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if has_swapchain_maintenance1_ext {
+            feature_chain.push(&mut swapchain_maintenance1_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if has_acceleration_structure_ext {
+            feature_chain.push(&mut acceleration_structure_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if has_ray_tracing_ext {
+            feature_chain.push(&mut ray_tracing_pipeline_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if has_mesh_shader_ext {
+            feature_chain.push(&mut mesh_shader_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if has_fragment_shading_rate_ext {
+            feature_chain.push(&mut fragment_shading_rate_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if has_conditional_rendering_ext {
+            feature_chain.push(&mut conditional_rendering_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if has_transform_feedback_ext {
+            feature_chain.push(&mut transform_feedback_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if api_version >= vk::API_VERSION_1_1 {
+            feature_chain.push(&mut multiview_features as *mut _ as *mut vk::BaseOutStructure);
+            feature_chain.push(&mut protected_memory_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if has_depth_clip_control_ext {
+            feature_chain.push(&mut depth_clip_control_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if has_device_fault_ext {
+            feature_chain.push(&mut device_fault_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+
+        let features = if api_version >= vk::API_VERSION_1_1 {
+            let mut features2 = vk::PhysicalDeviceFeatures2::default();
+            unsafe {
+                link_p_next(&mut features2 as *mut _ as *mut vk::BaseOutStructure, &feature_chain);
+                instance.get_physical_device_features2(physical_device, &mut features2);
+            }
+            features2.features
+        } else {
+            unsafe { instance.get_physical_device_features(physical_device) }
+        };
+
+        let queue_families = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+        let has_sparse_graphics_queue = queue_families.iter().any(|queue_family| {
+            queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::SPARSE_BINDING)
+        });
+
+        let bindless = vulkan12_features.descriptor_indexing == vk::TRUE
+            && vulkan12_features.runtime_descriptor_array == vk::TRUE
+            && vulkan12_features.descriptor_binding_partially_bound == vk::TRUE
+            && vulkan12_features.descriptor_binding_variable_descriptor_count == vk::TRUE
+            && vulkan12_features.descriptor_binding_sampled_image_update_after_bind == vk::TRUE
+            && vulkan12_features.shader_sampled_image_array_non_uniform_indexing == vk::TRUE;
+        let buffer_device_address = vulkan12_features.buffer_device_address == vk::TRUE;
+        let acceleration_structure = buffer_device_address
+            && acceleration_structure_features.acceleration_structure == vk::TRUE;
+        let mesh_shader = mesh_shader_features.mesh_shader == vk::TRUE;
+
+        DeviceCapabilities {
+            api_version: api_version,
+
+            dynamic_rendering: dynamic_rendering_features.dynamic_rendering == vk::TRUE,
+            synchronization2: synchronization2_features.synchronization2 == vk::TRUE,
+            timeline_semaphore: vulkan12_features.timeline_semaphore == vk::TRUE,
+            bindless: bindless,
+            buffer_device_address: buffer_device_address,
+            present_id: present_id_features.present_id == vk::TRUE,
+            present_wait: present_id_features.present_id == vk::TRUE
+                && present_wait_features.present_wait == vk::TRUE,
+            swapchain_maintenance1: swapchain_maintenance1_features.swapchain_maintenance1 == vk::TRUE,
+            acceleration_structure: acceleration_structure,
+            ray_tracing: acceleration_structure
+                && ray_tracing_pipeline_features.ray_tracing_pipeline == vk::TRUE,
+            mesh_shader: mesh_shader,
+            task_shader: mesh_shader && mesh_shader_features.task_shader == vk::TRUE,
+            fragment_shading_rate: fragment_shading_rate_features.pipeline_fragment_shading_rate == vk::TRUE,
+            conditional_rendering: conditional_rendering_features.conditional_rendering == vk::TRUE,
+            transform_feedback: transform_feedback_features.transform_feedback == vk::TRUE,
+            multiview: multiview_features.multiview == vk::TRUE,
+            depth_clip_control: depth_clip_control_features.depth_clip_control == vk::TRUE,
+            device_fault: device_fault_features.device_fault == vk::TRUE,
+            device_fault_vendor_binary: device_fault_features.device_fault == vk::TRUE
+                && device_fault_features.device_fault_vendor_binary == vk::TRUE,
+            protected_memory: protected_memory_features.protected_memory == vk::TRUE,
+            display_timing: has_extension(vk::GoogleDisplayTimingFn::name()),
+            hdr_metadata: has_extension(vk::ExtHdrMetadataFn::name()),
+            // read through vkGetPhysicalDeviceMemoryProperties2, which is core in 1.1.
+            memory_budget: api_version >= vk::API_VERSION_1_1 && has_extension(vk::ExtMemoryBudgetFn::name()),
+            device_group_size: 1,
+
+            sampler_anisotropy: features.sampler_anisotropy == vk::TRUE,
+            geometry_shader: features.geometry_shader == vk::TRUE,
+            tessellation_shader: features.tessellation_shader == vk::TRUE,
+            multi_viewport: features.multi_viewport == vk::TRUE,
+            depth_bias_clamp: features.depth_bias_clamp == vk::TRUE,
+            image_cube_array: features.image_cube_array == vk::TRUE,
+            sparse_residency_image_2d: features.sparse_binding == vk::TRUE
+                && features.sparse_residency_image2_d == vk::TRUE
+                && has_sparse_graphics_queue,
+
+            max_sample_count: DeviceCapabilities::highest_sample_count(
+                limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts),
+            max_sampler_anisotropy: limits.max_sampler_anisotropy,
+            max_viewports: limits.max_viewports,
+            max_image_dimension_2d: limits.max_image_dimension2_d,
+            max_image_dimension_3d: limits.max_image_dimension3_d,
+            max_image_dimension_cube: limits.max_image_dimension_cube,
+            max_image_array_layers: limits.max_image_array_layers,
+            max_push_constants_size: limits.max_push_constants_size,
+            max_bound_descriptor_sets: limits.max_bound_descriptor_sets,
+            non_coherent_atom_size: limits.non_coherent_atom_size,
+            timestamp_period: limits.timestamp_period,
+        }
+    }
+
+    /// Device extensions that have to be enabled for the capabilities above.
+    pub fn enabled_extension_names(&self) -> Vec<&'static CStr> {
+        let mut names = vec![];
+
+        if self.dynamic_rendering {
+            names.push(ash::extensions::khr::DynamicRendering::name());
+        }
+        if self.synchronization2 {
+            names.push(ash::extensions::khr::Synchronization2::name());
+        }
+        if self.present_id {
+            names.push(vk::KhrPresentIdFn::name());
+        }
+        if self.present_wait {
+            names.push(ash::extensions::khr::PresentWait::name());
+        }
+        if self.swapchain_maintenance1 {
+            names.push(vk::ExtSwapchainMaintenance1Fn::name());
+        }
+        if self.acceleration_structure {
+            names.push(ash::extensions::khr::AccelerationStructure::name());
+            names.push(ash::extensions::khr::DeferredHostOperations::name());
+        }
+        if self.ray_tracing {
+            names.push(ash::extensions::khr::RayTracingPipeline::name());
+        }
+        if self.mesh_shader {
+            names.push(ash::extensions::ext::MeshShader::name());
+        }
+        if self.fragment_shading_rate {
+            names.push(vk::KhrFragmentShadingRateFn::name());
+        }
+        if self.conditional_rendering {
+            names.push(vk::ExtConditionalRenderingFn::name());
+        }
+        if self.transform_feedback {
+            names.push(vk::ExtTransformFeedbackFn::name());
+        }
+        if self.display_timing {
+            names.push(vk::GoogleDisplayTimingFn::name());
+        }
+        if self.hdr_metadata {
+            names.push(vk::ExtHdrMetadataFn::name());
+        }
+        if self.depth_clip_control {
+            names.push(vk::ExtDepthClipControlFn::name());
+        }
+        if self.device_fault {
+            names.push(vk::ExtDeviceFaultFn::name());
+        }
+        if self.memory_budget {
+            names.push(vk::ExtMemoryBudgetFn::name());
+        }
+        if self.device_group_size > 1 {
+            names.push(ash::extensions::khr::DeviceGroup::name());
+        }
+
+        names
+    }
+
+    pub fn supports_sample_count(&self, samples: vk::SampleCountFlags) -> bool {
+        samples.as_raw() <= self.max_sample_count.as_raw()
+    }
+
+    fn supported_extensions(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice
+    ) -> HashSet<String> {
+        let available_extensions = unsafe {
+            instance
+                .enumerate_device_extension_properties(physical_device)
+                .expect("Failed to get device extension properties.")
+        };
+
+        available_extensions
+            .iter()
+            .map(|extension| tools::vk_to_string(&extension.extension_name))
+            .collect()
+    }
+
+    fn highest_sample_count(counts: vk::SampleCountFlags) -> vk::SampleCountFlags {
+        let candidates = [
+            vk::SampleCountFlags::TYPE_64,
+            vk::SampleCountFlags::TYPE_32,
+            vk::SampleCountFlags::TYPE_16,
+            vk::SampleCountFlags::TYPE_8,
+            vk::SampleCountFlags::TYPE_4,
+            vk::SampleCountFlags::TYPE_2,
+        ];
+
+        for &candidate in candidates.iter() {
+            if counts.contains(candidate) {
+                return candidate;
+            }
+        }
+
+        vk::SampleCountFlags::TYPE_1
+    }
+}
+
+/// Chains `structures` one after another behind `head` through their `p_next` fields.
+///
+/// # Safety
+///
+/// `head` and every pointer in `structures` must point to a live Vulkan structure starting with
+/// `s_type` and `p_next`, and stay valid for as long as the chain is used.
+pub unsafe fn link_p_next(head: *mut vk::BaseOutStructure, structures: &[*mut vk::BaseOutStructure]) {
+    let mut last = head;
+    for &structure in structures.iter() {
+        (*last).p_next = structure;
+        last = structure;
+    }
+}
+
+/// A memory type allocations can be made from, `index` is what `memoryTypeIndex` takes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryTypeInfo {
+    pub index: u32,
+    pub property_flags: vk::MemoryPropertyFlags,
+}
+
+/// One memory heap of the physical device with the memory types allocating from it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeapInfo {
+    pub index: u32,
+    /// Total bytes of the heap, not what is still free, see `VkRenderDevice::memory_budget` for that.
+    pub size: vk::DeviceSize,
+    pub flags: vk::MemoryHeapFlags,
+    pub memory_types: Vec<MemoryTypeInfo>,
+}
+
+impl HeapInfo {
+    pub fn is_device_local(&self) -> bool {
+        self.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL)
+    }
+}
+
+impl fmt::Display for HeapInfo {
+    /// E.g. `Heap 0: 8.00 GiB (8589934592 bytes) DEVICE_LOCAL, types 1 DEVICE_LOCAL, 2 DEVICE_LOCAL | HOST_VISIBLE`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Heap {}: {} ({} bytes) {:?}", self.index, format_bytes(self.size), self.size, self.flags)?;
+
+        let memory_types: Vec<String> = self.memory_types
+            .iter()
+            .map(|memory_type| format!("{} {:?}", memory_type.index, memory_type.property_flags))
+            .collect();
+        write!(f, ", types {}", memory_types.join(", "))
+    }
+}
+
+/// Every memory heap of `physical_device` in heap index order, e.g. for logging the VRAM at startup or
+/// deciding how much an allocator may use. The total VRAM is the size summed over the device local heaps.
+pub fn memory_heaps(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> Vec<HeapInfo> {
+    let memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+    let memory_types = &memory_properties.memory_types[..memory_properties.memory_type_count as usize];
+
+    memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+        .iter()
+        .enumerate()
+        .map(|(heap_index, heap)| HeapInfo {
+            index: heap_index as u32,
+            size: heap.size,
+            flags: heap.flags,
+            memory_types: memory_types
+                .iter()
+                .enumerate()
+                .filter(|(_, memory_type)| memory_type.heap_index == heap_index as u32)
+                .map(|(type_index, memory_type)| MemoryTypeInfo {
+                    index: type_index as u32,
+                    property_flags: memory_type.property_flags,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Bytes in the largest binary unit they fill at least once, e.g. `1.50 GiB`.
+pub fn format_bytes(bytes: vk::DeviceSize) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.2} {}", value, UNITS[unit])
+    }
+}
@@ -0,0 +1,114 @@
+use ash::vk;
+
+use std::ptr;
+
+use crate::vk::sampler::SamplerCache;
+
+/// Everything needed to upload data to device local resources outside of the frame loop.
+pub struct UploadContext<'a> {
+    pub instance: &'a ash::Instance,
+    pub physical_device: vk::PhysicalDevice,
+    pub device: &'a ash::Device,
+    pub device_memory_properties: &'a vk::PhysicalDeviceMemoryProperties,
+    pub non_coherent_atom_size: vk::DeviceSize,
+    pub command_pool: vk::CommandPool,
+    pub queue: vk::Queue,
+    pub sampler_cache: &'a SamplerCache,
+}
+
+impl<'a> UploadContext<'a> {
+    pub fn execute<F: FnOnce(vk::CommandBuffer)>(&self, record: F) {
+        execute_one_time_commands(self.device, self.command_pool, self.queue, record);
+    }
+}
+
+/// Records a one time command buffer with `record`, submits it and waits until `queue` is idle.
+pub fn execute_one_time_commands<F: FnOnce(vk::CommandBuffer)>(
+    device: &ash::Device,
+    command_pool: vk::CommandPool,
+    queue: vk::Queue,
+    record: F,
+) {
+    submit_one_time_commands(device, command_pool, queue, None, record);
+}
+
+/// Same as `execute_one_time_commands`, but the commands wait on `wait_semaphore` at `wait_stage` first,
+/// e.g. to read back an image a frame submission is still rendering to.
+pub fn execute_one_time_commands_after<F: FnOnce(vk::CommandBuffer)>(
+    device: &ash::Device,
+    command_pool: vk::CommandPool,
+    queue: vk::Queue,
+    wait_semaphore: vk::Semaphore,
+    wait_stage: vk::PipelineStageFlags,
+    record: F,
+) {
+    submit_one_time_commands(device, command_pool, queue, Some((wait_semaphore, wait_stage)), record);
+}
+
+fn submit_one_time_commands<F: FnOnce(vk::CommandBuffer)>(
+    device: &ash::Device,
+    command_pool: vk::CommandPool,
+    queue: vk::Queue,
+    wait: Option<(vk::Semaphore, vk::PipelineStageFlags)>,
+    record: F,
+) {
+    let allocate_info = vk::CommandBufferAllocateInfo {
+        s_type: vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
+        p_next: ptr::null(),
+        command_buffer_count: 1,
+        command_pool,
+        level: vk::CommandBufferLevel::PRIMARY,
+    };
+
+    let command_buffers = unsafe {
+        device
+            .allocate_command_buffers(&allocate_info)
+            .expect("Failed to allocate Command Buffer")
+    };
+    let command_buffer = command_buffers[0];
+
+    let begin_info = vk::CommandBufferBeginInfo {
+        s_type: vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
+        p_next: ptr::null(),
+        flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+        p_inheritance_info: ptr::null(),
+    };
+
+    unsafe {
+        device
+            .begin_command_buffer(command_buffer, &begin_info)
+            .expect("Failed to begin Command Buffer");
+    }
+
+    record(command_buffer);
+
+    unsafe {
+        device
+            .end_command_buffer(command_buffer)
+            .expect("Failed to end Command Buffer");
+    }
+
+    let (wait_semaphores, wait_stages): (Vec<vk::Semaphore>, Vec<vk::PipelineStageFlags>) = wait.into_iter().unzip();
+    let submit_info = [vk::SubmitInfo {
+        s_type: vk::StructureType::SUBMIT_INFO,
+        p_next: ptr::null(),
+        wait_semaphore_count: wait_semaphores.len() as u32,
+        p_wait_semaphores: wait_semaphores.as_ptr(),
+        p_wait_dst_stage_mask: wait_stages.as_ptr(),
+        command_buffer_count: 1,
+        p_command_buffers: &command_buffer,
+        signal_semaphore_count: 0,
+        p_signal_semaphores: ptr::null(),
+    }];
+
+    unsafe {
+        device
+            .queue_submit(queue, &submit_info, vk::Fence::null())
+            .expect("Failed to Submit Queue.");
+        device
+            .queue_wait_idle(queue)
+            .expect("Failed to wait Queue idle");
+
+        device.free_command_buffers(command_pool, &command_buffers);
+    }
+}
@@ -0,0 +1,315 @@
+use ash::vk;
+
+use std::convert::TryInto;
+use std::path::Path;
+
+/// Pre-compressed texture read from a `.ktx2` or `.dds` container, uploaded without any runtime compression.
+pub struct CompressedImage {
+    pub format: vk::Format,
+    pub extent: vk::Extent3D,
+    pub array_layers: u32,
+    pub is_cubemap: bool,
+    pub levels: Vec<CompressedLevel>,
+    pub data: Vec<u8>,
+}
+
+/// Byte range of one mip level of one array layer (or cube face) inside `CompressedImage::data`.
+pub struct CompressedLevel {
+    pub mip_level: u32,
+    pub array_layer: u32,
+    pub offset: usize,
+    pub size: usize,
+}
+
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+
+/// Width, height and byte size of a texel block of `format`, `None` for formats the loader doesn't know.
+pub fn format_block_info(format: vk::Format) -> Option<(u32, u32, u32)> {
+    let block_info = match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK => (4, 4, 8),
+        vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC5_SNORM_BLOCK
+        | vk::Format::BC6H_UFLOAT_BLOCK
+        | vk::Format::BC6H_SFLOAT_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK => (4, 4, 16),
+        vk::Format::ASTC_4X4_UNORM_BLOCK | vk::Format::ASTC_4X4_SRGB_BLOCK => (4, 4, 16),
+        vk::Format::ASTC_5X5_UNORM_BLOCK | vk::Format::ASTC_5X5_SRGB_BLOCK => (5, 5, 16),
+        vk::Format::ASTC_6X6_UNORM_BLOCK | vk::Format::ASTC_6X6_SRGB_BLOCK => (6, 6, 16),
+        vk::Format::ASTC_8X8_UNORM_BLOCK | vk::Format::ASTC_8X8_SRGB_BLOCK => (8, 8, 16),
+        vk::Format::ASTC_10X10_UNORM_BLOCK | vk::Format::ASTC_10X10_SRGB_BLOCK => (10, 10, 16),
+        vk::Format::ASTC_12X12_UNORM_BLOCK | vk::Format::ASTC_12X12_SRGB_BLOCK => (12, 12, 16),
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB => (1, 1, 4),
+        vk::Format::R16G16B16A16_SFLOAT => (1, 1, 8),
+        vk::Format::R32G32B32A32_SFLOAT => (1, 1, 16),
+        _ => return None,
+    };
+
+    Some(block_info)
+}
+
+/// Bytes of one `width x height x depth` image in `format`, partial blocks on the edges count as full ones.
+pub fn image_byte_size(format: vk::Format, width: u32, height: u32, depth: u32) -> Option<usize> {
+    let (block_width, block_height, block_size) = format_block_info(format)?;
+    let blocks_x = width.div_ceil(block_width);
+    let blocks_y = height.div_ceil(block_height);
+
+    Some(blocks_x as usize * blocks_y as usize * depth as usize * block_size as usize)
+}
+
+impl CompressedImage {
+    pub fn load(path: &Path) -> Result<CompressedImage, String> {
+        let data = std::fs::read(path)
+            .map_err(|error| format!("Failed to read {}: {}", path.display(), error))?;
+
+        if data.starts_with(&KTX2_IDENTIFIER) {
+            CompressedImage::parse_ktx2(data)
+        } else if data.starts_with(DDS_MAGIC) {
+            CompressedImage::parse_dds(data)
+        } else {
+            Err(format!("{} is neither a KTX2 nor a DDS file", path.display()))
+        }
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.levels.iter().map(|level| level.mip_level + 1).max().unwrap_or(1)
+    }
+
+    /// KTX2 stores the levels from the smallest mip up, inside a level each layer and face is contiguous.
+    fn parse_ktx2(data: Vec<u8>) -> Result<CompressedImage, String> {
+        let vk_format = read_u32(&data, 12)?;
+        let width = read_u32(&data, 20)?;
+        let height = read_u32(&data, 24)?.max(1);
+        let depth = read_u32(&data, 28)?.max(1);
+        let layer_count = read_u32(&data, 32)?.max(1);
+        let face_count = read_u32(&data, 36)?;
+        let level_count = read_u32(&data, 40)?.max(1);
+        let supercompression_scheme = read_u32(&data, 44)?;
+
+        if supercompression_scheme != 0 {
+            return Err(String::from("Supercompressed KTX2 files are not supported"));
+        }
+        if face_count != 1 && face_count != 6 {
+            return Err(format!("Invalid KTX2 face count {}", face_count));
+        }
+
+        let format = vk::Format::from_raw(vk_format as i32);
+        if format == vk::Format::UNDEFINED {
+            return Err(String::from("KTX2 files without a Vulkan format (e.g. Basis Universal) are not supported"));
+        }
+        let images_per_level = (layer_count * face_count) as usize;
+
+        // the level index follows the 80 byte header and section index.
+        let mut levels = vec![];
+        for mip_level in 0..level_count {
+            let index_offset = 80 + mip_level as usize * 24;
+            let level_offset = read_u64(&data, index_offset)? as usize;
+            let level_size = read_u64(&data, index_offset + 8)? as usize;
+
+            let is_in_bounds = level_offset
+                .checked_add(level_size)
+                .map_or(false, |level_end| level_end <= data.len());
+            if !is_in_bounds {
+                return Err(format!("KTX2 level {} is out of bounds", mip_level));
+            }
+
+            let image_size = level_size / images_per_level;
+            for image in 0..images_per_level {
+                levels.push(CompressedLevel {
+                    mip_level,
+                    array_layer: image as u32,
+                    offset: level_offset + image * image_size,
+                    size: image_size,
+                });
+            }
+        }
+
+        Ok(CompressedImage {
+            format,
+            extent: vk::Extent3D { width, height, depth },
+            array_layers: layer_count * face_count,
+            is_cubemap: face_count == 6,
+            levels,
+            data,
+        })
+    }
+
+    /// DDS stores every mip chain of a layer after the other, level sizes follow from the format.
+    fn parse_dds(data: Vec<u8>) -> Result<CompressedImage, String> {
+        const HEADER_OFFSET: usize = 4;
+        const DDSCAPS2_CUBEMAP: u32 = 0x200;
+
+        let height = read_u32(&data, HEADER_OFFSET + 8)?;
+        let width = read_u32(&data, HEADER_OFFSET + 12)?;
+        let mip_count = read_u32(&data, HEADER_OFFSET + 24)?.max(1);
+        let four_cc = data
+            .get(HEADER_OFFSET + 80..HEADER_OFFSET + 84)
+            .ok_or_else(|| String::from("Unexpected end of texture file"))?;
+        let caps2 = read_u32(&data, HEADER_OFFSET + 108)?;
+
+        let (format, mut array_layers, data_offset) = if four_cc == b"DX10" {
+            let dxgi_format = read_u32(&data, HEADER_OFFSET + 124)?;
+            let array_size = read_u32(&data, HEADER_OFFSET + 136)?.max(1);
+            (dxgi_to_vk_format(dxgi_format)?, array_size, HEADER_OFFSET + 124 + 20)
+        } else {
+            (four_cc_to_vk_format(four_cc)?, 1, HEADER_OFFSET + 124)
+        };
+
+        let is_cubemap = caps2 & DDSCAPS2_CUBEMAP != 0;
+        if is_cubemap {
+            array_layers *= 6;
+        }
+
+        let mut levels = vec![];
+        let mut offset = data_offset;
+        for array_layer in 0..array_layers {
+            for mip_level in 0..mip_count {
+                let size = image_byte_size(
+                    format,
+                    (width >> mip_level).max(1),
+                    (height >> mip_level).max(1),
+                    1)
+                    .ok_or_else(|| format!("Unsupported DDS format {:?}", format))?;
+
+                if offset + size > data.len() {
+                    return Err(format!("DDS layer {} mip {} is out of bounds", array_layer, mip_level));
+                }
+
+                levels.push(CompressedLevel {
+                    mip_level,
+                    array_layer,
+                    offset,
+                    size,
+                });
+                offset += size;
+            }
+        }
+
+        Ok(CompressedImage {
+            format,
+            extent: vk::Extent3D { width, height, depth: 1 },
+            array_layers,
+            is_cubemap,
+            levels,
+            data,
+        })
+    }
+}
+
+fn four_cc_to_vk_format(four_cc: &[u8]) -> Result<vk::Format, String> {
+    match four_cc {
+        b"DXT1" => Ok(vk::Format::BC1_RGBA_UNORM_BLOCK),
+        b"DXT3" => Ok(vk::Format::BC2_UNORM_BLOCK),
+        b"DXT5" => Ok(vk::Format::BC3_UNORM_BLOCK),
+        b"ATI1" | b"BC4U" => Ok(vk::Format::BC4_UNORM_BLOCK),
+        b"ATI2" | b"BC5U" => Ok(vk::Format::BC5_UNORM_BLOCK),
+        _ => Err(format!("Unsupported DDS FourCC {:?}", String::from_utf8_lossy(four_cc))),
+    }
+}
+
+fn dxgi_to_vk_format(dxgi_format: u32) -> Result<vk::Format, String> {
+    match dxgi_format {
+        71 => Ok(vk::Format::BC1_RGBA_UNORM_BLOCK),
+        72 => Ok(vk::Format::BC1_RGBA_SRGB_BLOCK),
+        74 => Ok(vk::Format::BC2_UNORM_BLOCK),
+        75 => Ok(vk::Format::BC2_SRGB_BLOCK),
+        77 => Ok(vk::Format::BC3_UNORM_BLOCK),
+        78 => Ok(vk::Format::BC3_SRGB_BLOCK),
+        80 => Ok(vk::Format::BC4_UNORM_BLOCK),
+        81 => Ok(vk::Format::BC4_SNORM_BLOCK),
+        83 => Ok(vk::Format::BC5_UNORM_BLOCK),
+        84 => Ok(vk::Format::BC5_SNORM_BLOCK),
+        95 => Ok(vk::Format::BC6H_UFLOAT_BLOCK),
+        96 => Ok(vk::Format::BC6H_SFLOAT_BLOCK),
+        98 => Ok(vk::Format::BC7_UNORM_BLOCK),
+        99 => Ok(vk::Format::BC7_SRGB_BLOCK),
+        _ => Err(format!("Unsupported DXGI format {}", dxgi_format)),
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| String::from("Unexpected end of texture file"))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, String> {
+    data.get(offset..offset + 8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| String::from("Unexpected end of texture file"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// KTX2 header and level index of a 2D image with one layer and face, `levels` are offset and size by mip level.
+    /// The level data goes after the 80 + 24 * `levels.len()` bytes returned.
+    fn ktx2_header(format: vk::Format, width: u32, height: u32, levels: &[(u64, u64)]) -> Vec<u8> {
+        let mut data = KTX2_IDENTIFIER.to_vec();
+        for value in [format.as_raw() as u32, 1, width, height, 0, 0, 1, levels.len() as u32, 0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        // data format descriptor, key/value and supercompression sections, all empty.
+        data.resize(80, 0);
+        for &(offset, size) in levels.iter() {
+            data.extend_from_slice(&offset.to_le_bytes());
+            data.extend_from_slice(&size.to_le_bytes());
+            data.extend_from_slice(&size.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn ktx2_levels_point_into_the_data() {
+        let level_offset = 80 + 2 * 24;
+        let mut data = ktx2_header(
+            vk::Format::BC1_RGBA_UNORM_BLOCK,
+            8,
+            8,
+            &[(level_offset as u64 + 8, 32), (level_offset as u64, 8)]);
+        data.resize(level_offset + 40, 0);
+
+        let image = CompressedImage::parse_ktx2(data).unwrap();
+
+        assert_eq!(image.format, vk::Format::BC1_RGBA_UNORM_BLOCK);
+        assert_eq!((image.extent.width, image.extent.height, image.extent.depth), (8, 8, 1));
+        assert_eq!(image.mip_levels(), 2);
+        assert!(!image.is_cubemap);
+        let ranges: Vec<(u32, usize, usize)> = image.levels
+            .iter()
+            .map(|level| (level.mip_level, level.offset, level.size))
+            .collect();
+        assert_eq!(ranges, vec![(0, level_offset + 8, 32), (1, level_offset, 8)]);
+    }
+
+    #[test]
+    fn truncated_ktx2_is_an_error() {
+        let data = ktx2_header(vk::Format::BC1_RGBA_UNORM_BLOCK, 4, 4, &[(104, 8)]);
+
+        assert!(CompressedImage::parse_ktx2(data[..40].to_vec()).is_err());
+        // the header is complete, the level data isn't.
+        assert!(CompressedImage::parse_ktx2(data).is_err());
+    }
+
+    #[test]
+    fn overflowing_ktx2_level_size_is_an_error() {
+        let mut data = ktx2_header(vk::Format::BC1_RGBA_UNORM_BLOCK, 4, 4, &[(104, u64::MAX)]);
+        data.resize(112, 0);
+
+        assert!(CompressedImage::parse_ktx2(data).is_err());
+    }
+}
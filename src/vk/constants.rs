@@ -2,7 +2,8 @@ use ash::vk;
 use crate::vk::debug;
 use std::ffi::CStr;
 
-pub const API_VERSION: u32 = vk::make_api_version(0, 1, 0, 0);
+/// Highest version the engine makes use of, instances are created with the lower of this and the loader's version.
+pub const API_VERSION: u32 = vk::make_api_version(0, 1, 2, 0);
 
 pub const VALIDATION: debug::ValidationInfo = debug::ValidationInfo {
     is_enable: true,
@@ -1,4 +1,5 @@
 use ash::vk;
+use std::ffi::CString;
 use std::ptr;
 
 use crate::vk::constants;
@@ -10,14 +11,60 @@ pub struct ValidationInfo {
     pub required_validation_layers: [&'static str; 1],
 }
 
-pub fn setup_debug_utils(entry: &ash::Entry, instance: &ash::Instance) -> (ash::extensions::ext::DebugUtils, vk::DebugUtilsMessengerEXT){
+/// Which validation messages reach the callback, filtered by the layers before they're even formatted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DebugMessengerConfig {
+    pub message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_types: vk::DebugUtilsMessageTypeFlagsEXT,
+}
+
+impl Default for DebugMessengerConfig {
+    /// Warnings and errors of every type.
+    fn default() -> DebugMessengerConfig {
+        DebugMessengerConfig {
+            message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            message_types: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+        }
+    }
+}
+
+impl DebugMessengerConfig {
+    /// `min_severity` and everything more severe, e.g. INFO for info, warning and error messages.
+    pub fn severity_threshold(mut self, min_severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> DebugMessengerConfig {
+        self.message_severity = [
+            vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+            vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+            vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+        ]
+            .iter()
+            .filter(|severity| severity.as_raw() >= min_severity.as_raw())
+            .fold(vk::DebugUtilsMessageSeverityFlagsEXT::empty(), |flags, &severity| flags | severity);
+        self
+    }
+
+    /// E.g. just VALIDATION to drop general and performance messages.
+    pub fn message_types(mut self, message_types: vk::DebugUtilsMessageTypeFlagsEXT) -> DebugMessengerConfig {
+        self.message_types = message_types;
+        self
+    }
+}
+
+pub fn setup_debug_utils(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    config: &DebugMessengerConfig
+) -> (ash::extensions::ext::DebugUtils, vk::DebugUtilsMessengerEXT) {
     let debug_units_loader = ash::extensions::ext::DebugUtils::new(entry, instance);
 
     if constants::VALIDATION.is_enable == false {
         (debug_units_loader, ash::vk::DebugUtilsMessengerEXT::null())
     }
     else {
-        let messanger_ci = debug_messanger_create_info();
+        let messanger_ci = debug_messanger_create_info(config);
 
         let debug_utils_messanger = unsafe {
             debug_units_loader
@@ -29,45 +76,140 @@ pub fn setup_debug_utils(entry: &ash::Entry, instance: &ash::Instance) -> (ash::
     }
 }
 
-fn debug_messanger_create_info() -> vk::DebugUtilsMessengerCreateInfoEXT {
+fn debug_messanger_create_info(config: &DebugMessengerConfig) -> vk::DebugUtilsMessengerCreateInfoEXT {
     vk::DebugUtilsMessengerCreateInfoEXT {
         s_type: vk::StructureType::DEBUG_UTILS_MESSENGER_CREATE_INFO_EXT,
         p_next: ptr::null(),
         flags: vk::DebugUtilsMessengerCreateFlagsEXT::empty(),
-        message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING |
-            // vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE |
-            // vk::DebugUtilsMessageSeverityFlagsEXT::INFO |
-            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
-        message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-            | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
-            | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+        message_severity: config.message_severity,
+        message_type: config.message_types,
         pfn_user_callback: Some(debug::vulkan_debug_utils_callback),
         p_user_data: ptr::null_mut()
     }
 }
 
-pub fn check_validation_layer_support(
-    entry: &ash::Entry
-) -> bool {
+/// Validation layer when `constants::VALIDATION.is_enable`, then the layers named in `PUPSY_INSTANCE_LAYERS`,
+/// separated by commas, so extra layers can be turned on without recompiling.
+pub fn default_instance_layers() -> Vec<String> {
+    let mut layers: Vec<String> = vec![];
+    if constants::VALIDATION.is_enable {
+        layers.extend(constants::VALIDATION.required_validation_layers.iter().map(|layer| layer.to_string()));
+    }
+
+    if let Ok(extra_layers) = std::env::var("PUPSY_INSTANCE_LAYERS") {
+        for layer in extra_layers.split(',').map(str::trim).filter(|layer| !layer.is_empty()) {
+            if !layers.iter().any(|existing| existing == layer) {
+                layers.push(layer.to_string());
+            }
+        }
+    }
+
+    layers
+}
+
+/// The installed ones of `requested`, in order. Missing layers are logged and skipped
+/// since an instance can't be created with them, but the engine runs fine without.
+pub fn available_instance_layers(entry: &ash::Entry, requested: &[String]) -> Vec<CString> {
     let layer_properties = entry
         .enumerate_instance_layer_properties()
         .expect("Failed to enumerate Instance Layers Properties");
+    let installed_layers: Vec<String> = layer_properties
+        .iter()
+        .map(|layer_property| tools::vk_to_string(&layer_property.layer_name))
+        .collect();
 
-    for required_layer_name in constants::VALIDATION.required_validation_layers.iter() {
-        let mut is_layer_found = false;
-
-        for layer_property in layer_properties.iter() {
-            let test_layer_name = tools::vk_to_string(&layer_property.layer_name);
-            if (*required_layer_name) == test_layer_name {
-                is_layer_found = true;
-                break;
+    requested
+        .iter()
+        .filter(|&layer| {
+            let is_installed = installed_layers.contains(layer);
+            if !is_installed {
+                log::warn!("Instance layer {} is not installed, skipping it", layer);
             }
+            is_installed
+        })
+        .map(|layer| CString::new(layer.as_str()).expect("Layer name contains a nul byte"))
+        .collect()
+}
+
+/// What `VK_EXT_device_fault` reported for a lost device, see `VkRenderDevice::device_fault`.
+#[derive(Clone, Debug)]
+pub struct DeviceFaultReport {
+    pub description: String,
+    /// GPU virtual addresses involved in the fault, e.g. an invalid read or the faulting instruction.
+    pub addresses: Vec<vk::DeviceFaultAddressInfoEXT>,
+    /// Description, fault code and fault data of each vendor specific fault.
+    pub vendor_infos: Vec<(String, u64, u64)>,
+    /// Crash dump for the vendor's tools, empty without `capabilities.device_fault_vendor_binary`.
+    pub vendor_binary: Vec<u8>,
+}
+
+impl DeviceFaultReport {
+    /// Counts first, then the data, as `vkGetDeviceFaultInfoEXT` has to be called. Only valid after `ERROR_DEVICE_LOST`.
+    pub fn query(device_fault_fn: &vk::ExtDeviceFaultFn, device: vk::Device) -> Result<DeviceFaultReport, vk::Result> {
+        let mut fault_counts = vk::DeviceFaultCountsEXT::default();
+        unsafe {
+            (device_fault_fn.get_device_fault_info_ext)(device, &mut fault_counts, ptr::null_mut()).result()?;
         }
 
-        if is_layer_found == false {
-            return false;
+        let mut addresses = vec![vk::DeviceFaultAddressInfoEXT::default(); fault_counts.address_info_count as usize];
+        let mut vendor_infos = vec![vk::DeviceFaultVendorInfoEXT::default(); fault_counts.vendor_info_count as usize];
+        let mut vendor_binary = vec![0u8; fault_counts.vendor_binary_size as usize];
+        let mut fault_info = vk::DeviceFaultInfoEXT {
+            p_address_infos: addresses.as_mut_ptr(),
+            p_vendor_infos: vendor_infos.as_mut_ptr(),
+            p_vendor_binary_data: if vendor_binary.is_empty() {
+                ptr::null_mut()
+            } else {
+                vendor_binary.as_mut_ptr() as *mut std::os::raw::c_void
+            },
+            ..Default::default()
+        };
+        unsafe {
+            // INCOMPLETE when the fault grew since the counts were queried, what fit is still worth reporting.
+            match (device_fault_fn.get_device_fault_info_ext)(device, &mut fault_counts, &mut fault_info) {
+                vk::Result::SUCCESS | vk::Result::INCOMPLETE => {}
+                error => return Err(error),
+            }
         }
+
+        addresses.truncate(fault_counts.address_info_count as usize);
+        vendor_infos.truncate(fault_counts.vendor_info_count as usize);
+        vendor_binary.truncate(fault_counts.vendor_binary_size as usize);
+
+        Ok(DeviceFaultReport {
+            description: tools::vk_to_string(&fault_info.description),
+            addresses: addresses,
+            vendor_infos: vendor_infos
+                .iter()
+                .map(|vendor_info| {
+                    (tools::vk_to_string(&vendor_info.description), vendor_info.vendor_fault_code, vendor_info.vendor_fault_data)
+                })
+                .collect(),
+            vendor_binary: vendor_binary,
+        })
     }
 
-    layer_properties.len() > 0
-}
\ No newline at end of file
+    /// Logs everything as errors, the vendor binary is written to `dump_dir` when there is one.
+    pub fn log(&self, dump_dir: &std::path::Path) {
+        log::error!("Device lost: {}", self.description);
+        for address in self.addresses.iter() {
+            log::error!(
+                "  {:?} at 0x{:x} (+/- 0x{:x})",
+                address.address_type,
+                address.reported_address,
+                address.address_precision);
+        }
+        for (description, code, data) in self.vendor_infos.iter() {
+            log::error!("  {} (code 0x{:x}, data 0x{:x})", description, code, data);
+        }
+
+        if self.vendor_binary.is_empty() {
+            return;
+        }
+        let dump_path = dump_dir.join("device_fault.bin");
+        match std::fs::write(&dump_path, &self.vendor_binary) {
+            Ok(()) => log::error!("  vendor crash dump written to {}", dump_path.display()),
+            Err(error) => log::error!("  failed to write vendor crash dump to {}: {}", dump_path.display(), error),
+        }
+    }
+}
@@ -0,0 +1,541 @@
+use ash::vk;
+use memoffset::offset_of;
+
+use std::path::Path;
+use std::ptr;
+use std::time::Instant;
+
+use crate::utility::tools;
+use crate::vk::buffer::MappedBuffer;
+use crate::vk::commands::UploadContext;
+use crate::vk::deletion_queue::DeletionQueue;
+use crate::vk::descriptor::{DescriptorAllocator, DescriptorPoolConfig, DescriptorSetLayoutBuilder};
+use crate::vk::draw::DrawCommand;
+use crate::vk::lifecycle::{self, TrackedObject};
+use crate::vk::pipeline::{BlendMode, CullMode, GraphicsPipelineBuilder};
+use crate::vk::sampler::SamplerConfig;
+use crate::vk::texture::VkTexture;
+use crate::vk::vertex::{AttributeDescriptions, BindingDescriptions};
+
+/// Characters the overlay can draw at once, over all lines. Spaces don't count.
+pub const MAX_OVERLAY_CHARS: usize = 128;
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+/// Glyphs sit in cells one texel larger on each axis, so nearest sampling never picks up a neighbour.
+const CELL_WIDTH: u32 = GLYPH_WIDTH + 1;
+const CELL_HEIGHT: u32 = GLYPH_HEIGHT + 1;
+/// Screen pixels per font texel.
+const SCALE: f32 = 2.0;
+/// Distance of the text from the top left corner, in screen pixels.
+const MARGIN: f32 = 8.0;
+
+/// The background quad plus one quad per character, two triangles each.
+const VERTEX_COUNT: usize = (MAX_OVERLAY_CHARS + 1) * 6;
+
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const BACKGROUND_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+
+/// Weight of the latest frame in the smoothed frame time.
+const FRAME_TIME_SMOOTHING: f32 = 0.1;
+
+/// 5x7 bitmaps, one row per byte from the top, bit 4 is the leftmost column. Lowercase is drawn as uppercase.
+const GLYPHS: [(char, [u8; 7]); 42] = [
+    ('0', [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E]),
+    ('1', [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('2', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F]),
+    ('3', [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E]),
+    ('4', [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02]),
+    ('5', [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E]),
+    ('6', [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E]),
+    ('7', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E]),
+    ('9', [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C]),
+    ('A', [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+    ('B', [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E]),
+    ('C', [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E]),
+    ('D', [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C]),
+    ('E', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F]),
+    ('F', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10]),
+    ('G', [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F]),
+    ('H', [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+    ('I', [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('J', [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C]),
+    ('K', [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11]),
+    ('L', [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F]),
+    ('M', [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11]),
+    ('N', [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11]),
+    ('O', [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('P', [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10]),
+    ('Q', [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D]),
+    ('R', [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11]),
+    ('S', [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E]),
+    ('T', [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04]),
+    ('U', [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('V', [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04]),
+    ('W', [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A]),
+    ('X', [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11]),
+    ('Y', [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04]),
+    ('Z', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C]),
+    (':', [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00]),
+    ('/', [0x01, 0x01, 0x02, 0x04, 0x08, 0x10, 0x10]),
+    ('%', [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03]),
+    ('-', [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00]),
+    ('+', [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00]),
+];
+
+/// Cell after the glyphs, filled completely, what the background quad samples.
+const SOLID_CELL: usize = GLYPHS.len();
+const ATLAS_WIDTH: u32 = CELL_WIDTH * (SOLID_CELL as u32 + 1);
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OverlayVertex {
+    pub pos: [f32; 2],
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl BindingDescriptions for OverlayVertex {
+    fn get_binding_descriptions() -> Vec<vk::VertexInputBindingDescription> {
+        vec![vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<Self>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }]
+    }
+}
+
+impl AttributeDescriptions for OverlayVertex {
+    fn get_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: offset_of!(Self, pos) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 1,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: offset_of!(Self, uv) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 2,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(Self, color) as u32,
+            },
+        ]
+    }
+}
+
+/// Frame time, FPS and VRAM usage drawn as bitmap font text in the top left corner, on top of whatever
+/// the render pass drew before. Toggled at runtime with `set_enabled` or `toggle`, starts out enabled.
+///
+/// Every swapchain image has its own vertex buffer of a fixed size which `update` rewrites, so the draw
+/// recorded by `cmd_draw` never changes and works from pre-recorded command buffers; unused quads and the
+/// whole overlay while disabled are written as degenerate triangles.
+/// `rebuild` has to follow every change of the render pass, extent or swapchain image count.
+pub struct DebugOverlay {
+    is_enabled: bool,
+
+    font: VkTexture,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_allocator: DescriptorAllocator,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+
+    vertex_buffers: Vec<MappedBuffer>,
+    extent: vk::Extent2D,
+    device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    non_coherent_atom_size: vk::DeviceSize,
+
+    last_frame: Option<Instant>,
+    /// Smoothed, in seconds.
+    frame_time: f32,
+}
+
+impl DebugOverlay {
+    /// `render_pass` has to have a color attachment at subpass 0 to blend the text onto.
+    pub fn new(
+        upload_context: &UploadContext,
+        render_pass: vk::RenderPass,
+        extent: vk::Extent2D,
+        image_count: usize
+    ) -> DebugOverlay {
+        let device = upload_context.device;
+
+        let (atlas_width, atlas_height, atlas) = font_atlas();
+        let font = VkTexture::new_2d(upload_context, atlas_width, atlas_height, vk::Format::R8_UNORM, &atlas);
+        // texel sharp at integer scales, linear filtering would blur the glyphs into their padding.
+        let sampler = upload_context.sampler_cache.get_or_create(&SamplerConfig {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            ..SamplerConfig::linear(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        });
+
+        let descriptor_set_layout = DescriptorSetLayoutBuilder::new()
+            .binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1, vk::ShaderStageFlags::FRAGMENT)
+            .build(device);
+        let mut descriptor_allocator = DescriptorAllocator::with_config(DescriptorPoolConfig {
+            ratios: vec![(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1.0)],
+            ..DescriptorPoolConfig::new(1)
+        });
+        let descriptor_set = descriptor_allocator.allocate(device, descriptor_set_layout);
+        DebugOverlay::write_descriptor_set(device, descriptor_set, &font, sampler);
+
+        let set_layouts = [descriptor_set_layout];
+        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo {
+            s_type: vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineLayoutCreateFlags::empty(),
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: 0,
+            p_push_constant_ranges: ptr::null(),
+        };
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_create_info, None)
+                .expect("Failed to create debug overlay pipeline layout!")
+        };
+
+        let mut overlay = DebugOverlay {
+            is_enabled: true,
+
+            font: font,
+            descriptor_set_layout: descriptor_set_layout,
+            descriptor_allocator: descriptor_allocator,
+            descriptor_set: descriptor_set,
+            pipeline_layout: pipeline_layout,
+            pipeline: vk::Pipeline::null(),
+
+            vertex_buffers: vec![],
+            extent: extent,
+            device_memory_properties: *upload_context.device_memory_properties,
+            non_coherent_atom_size: upload_context.non_coherent_atom_size,
+
+            last_frame: None,
+            frame_time: 0.0,
+        };
+        overlay.pipeline = overlay.create_pipeline(device, render_pass, extent);
+        overlay.vertex_buffers = overlay.create_vertex_buffers(device, image_count);
+        overlay
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.is_enabled
+    }
+
+    /// Takes effect with the next `update` of each swapchain image.
+    pub fn set_enabled(&mut self, is_enabled: bool) {
+        self.is_enabled = is_enabled;
+    }
+
+    pub fn toggle(&mut self) {
+        self.is_enabled = !self.is_enabled;
+    }
+
+    /// Frame time smoothed over the last frames, in seconds. Measured even while disabled.
+    pub fn frame_time(&self) -> f32 {
+        self.frame_time
+    }
+
+    /// Recreates the pipeline and the vertex buffers for a new swapchain, the old ones are destroyed
+    /// through `deletion_queue` since frames in flight may still use them.
+    pub fn rebuild(
+        &mut self,
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        extent: vk::Extent2D,
+        image_count: usize,
+        deletion_queue: &mut DeletionQueue
+    ) {
+        deletion_queue.enqueue_pipeline(self.pipeline);
+        let vertex_buffers = std::mem::take(&mut self.vertex_buffers);
+        deletion_queue.enqueue(move |device| {
+            for vertex_buffer in vertex_buffers.iter() {
+                vertex_buffer.destroy(device);
+            }
+        });
+
+        self.extent = extent;
+        self.pipeline = self.create_pipeline(device, render_pass, extent);
+        self.vertex_buffers = self.create_vertex_buffers(device, image_count);
+    }
+
+    /// Has to be called once per frame after acquiring `image_index`, before the image's command buffer
+    /// is submitted. Takes the frame time since the last call and rewrites the image's text.
+    /// `memory_budget` is the device local usage and budget in bytes, see `VkRenderDevice::memory_budget`.
+    pub fn update(&mut self, device: &ash::Device, image_index: u32, memory_budget: Option<(vk::DeviceSize, vk::DeviceSize)>) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame {
+            let delta_time = (now - last_frame).as_secs_f32();
+            self.frame_time = if self.frame_time == 0.0 {
+                delta_time
+            } else {
+                self.frame_time + (delta_time - self.frame_time) * FRAME_TIME_SMOOTHING
+            };
+        }
+        self.last_frame = Some(now);
+
+        let vertices = if self.is_enabled {
+            layout_text(&overlay_lines(self.frame_time, memory_budget), self.extent)
+        } else {
+            vec![OverlayVertex::default(); VERTEX_COUNT]
+        };
+        self.vertex_buffers[image_index as usize].write(device, 0, &vertices);
+    }
+
+    /// Has to be recorded inside the render pass `rebuild` or `new` got, at subpass 0.
+    pub fn cmd_draw(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, image_index: usize) {
+        DrawCommand::new(device, command_buffer)
+            .pipeline(self.pipeline, self.pipeline_layout)
+            .bind_vertex(self.vertex_buffers[image_index].buffer)
+            .descriptor_set(0, self.descriptor_set)
+            .draw(VERTEX_COUNT as u32);
+    }
+
+    /// The device must be idle. The sampler belongs to the `SamplerCache` and stays.
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for vertex_buffer in self.vertex_buffers.drain(..) {
+            vertex_buffer.destroy(device);
+        }
+        self.font.destroy(device);
+        self.descriptor_allocator.destroy(device);
+
+        unsafe {
+            lifecycle::on_destroy(TrackedObject::Pipeline, self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+
+    fn write_descriptor_set(
+        device: &ash::Device,
+        descriptor_set: vk::DescriptorSet,
+        font: &VkTexture,
+        sampler: vk::Sampler
+    ) {
+        let image_infos = [vk::DescriptorImageInfo {
+            sampler: sampler,
+            image_view: font.image_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+
+        let descriptor_writes = [vk::WriteDescriptorSet {
+            s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+            p_next: ptr::null(),
+            dst_set: descriptor_set,
+            dst_binding: 0,
+            dst_array_element: 0,
+            descriptor_count: image_infos.len() as u32,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            p_image_info: image_infos.as_ptr(),
+            p_buffer_info: ptr::null(),
+            p_texel_buffer_view: ptr::null(),
+        }];
+
+        unsafe {
+            device.update_descriptor_sets(&descriptor_writes, &[]);
+        }
+    }
+
+    fn create_pipeline(&self, device: &ash::Device, render_pass: vk::RenderPass, extent: vk::Extent2D) -> vk::Pipeline {
+        let vert_shader_module = create_shader_module(device, Path::new("shaders/spv/debug-overlay.vert.spv"));
+        let frag_shader_module = create_shader_module(device, Path::new("shaders/spv/debug-overlay.frag.spv"));
+
+        let binding_description = OverlayVertex::get_binding_descriptions();
+        let attribute_description = OverlayVertex::get_attribute_descriptions();
+
+        let pipeline = GraphicsPipelineBuilder::new(extent)
+            .shader_stage(vk::ShaderStageFlags::VERTEX, vert_shader_module)
+            .shader_stage(vk::ShaderStageFlags::FRAGMENT, frag_shader_module)
+            .vertex_input(&binding_description, &attribute_description)
+            .cull_mode(CullMode::None)
+            .blend_mode(BlendMode::AlphaBlend)
+            .build(device, self.pipeline_layout, render_pass, 0);
+
+        unsafe {
+            device.destroy_shader_module(vert_shader_module, None);
+            device.destroy_shader_module(frag_shader_module, None);
+        }
+
+        pipeline
+    }
+
+    fn create_vertex_buffers(&self, device: &ash::Device, image_count: usize) -> Vec<MappedBuffer> {
+        let buffer_size = (std::mem::size_of::<OverlayVertex>() * VERTEX_COUNT) as vk::DeviceSize;
+
+        (0..image_count)
+            .map(|_| {
+                let mut vertex_buffer = MappedBuffer::new(
+                    device,
+                    buffer_size,
+                    vk::BufferUsageFlags::VERTEX_BUFFER,
+                    &self.device_memory_properties,
+                    self.non_coherent_atom_size);
+                // nothing is drawn until the image's first update.
+                vertex_buffer.write(device, 0, &[OverlayVertex::default(); VERTEX_COUNT]);
+                vertex_buffer
+            })
+            .collect()
+    }
+}
+
+fn create_shader_module(device: &ash::Device, path: &Path) -> vk::ShaderModule {
+    let code = tools::read_spv(path).expect("Failed to read debug overlay shader");
+
+    let shader_module_create_info = vk::ShaderModuleCreateInfo {
+        s_type: vk::StructureType::SHADER_MODULE_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::ShaderModuleCreateFlags::empty(),
+        code_size: code.len() * std::mem::size_of::<u32>(),
+        p_code: code.as_ptr(),
+    };
+
+    unsafe {
+        device
+            .create_shader_module(&shader_module_create_info, None)
+            .expect("Failed to create debug overlay shader module")
+    }
+}
+
+/// `R8_UNORM` texels of every glyph cell side by side, then the solid cell. Returns width, height and texels.
+fn font_atlas() -> (u32, u32, Vec<u8>) {
+    let width = ATLAS_WIDTH as usize;
+    let mut texels = vec![0u8; width * CELL_HEIGHT as usize];
+
+    for (cell, (_, rows)) in GLYPHS.iter().enumerate() {
+        for (y, row) in rows.iter().enumerate() {
+            for x in 0..GLYPH_WIDTH {
+                if row & (1 << (GLYPH_WIDTH - 1 - x)) != 0 {
+                    texels[y * width + cell * CELL_WIDTH as usize + x as usize] = 0xFF;
+                }
+            }
+        }
+    }
+    for y in 0..CELL_HEIGHT as usize {
+        for x in 0..CELL_WIDTH as usize {
+            texels[y * width + SOLID_CELL * CELL_WIDTH as usize + x] = 0xFF;
+        }
+    }
+
+    (ATLAS_WIDTH, CELL_HEIGHT, texels)
+}
+
+fn glyph_cell(character: char) -> Option<usize> {
+    let character = character.to_ascii_uppercase();
+    GLYPHS.iter().position(|&(glyph, _)| glyph == character)
+}
+
+fn overlay_lines(frame_time: f32, memory_budget: Option<(vk::DeviceSize, vk::DeviceSize)>) -> Vec<String> {
+    const MIB: vk::DeviceSize = 1024 * 1024;
+
+    let fps = if frame_time > 0.0 { 1.0 / frame_time } else { 0.0 };
+    let mut lines = vec![
+        format!("FRAME {:.2} MS", frame_time * 1000.0),
+        format!("FPS {:.0}", fps),
+    ];
+    if let Some((usage, budget)) = memory_budget {
+        lines.push(format!("VRAM {}/{} MB", usage / MIB, budget / MIB));
+    }
+
+    lines
+}
+
+/// Quads in clip space for `lines` from the top left corner of `extent`, behind them a background quad
+/// covering all of them. Always `VERTEX_COUNT` vertices, characters past `MAX_OVERLAY_CHARS` are dropped
+/// and characters without a glyph are left as a gap.
+fn layout_text(lines: &[String], extent: vk::Extent2D) -> Vec<OverlayVertex> {
+    let to_clip = |x: f32, y: f32| [x / extent.width as f32 * 2.0 - 1.0, y / extent.height as f32 * 2.0 - 1.0];
+
+    let mut vertices = Vec::with_capacity(VERTEX_COUNT);
+    let push_quad = |vertices: &mut Vec<OverlayVertex>, min: [f32; 2], max: [f32; 2], cell: usize, glyph_size: [u32; 2], color: [f32; 4]| {
+        let u0 = (cell as u32 * CELL_WIDTH) as f32 / ATLAS_WIDTH as f32;
+        let u1 = (cell as u32 * CELL_WIDTH + glyph_size[0]) as f32 / ATLAS_WIDTH as f32;
+        let v1 = glyph_size[1] as f32 / CELL_HEIGHT as f32;
+
+        let corners = [
+            (to_clip(min[0], min[1]), [u0, 0.0]),
+            (to_clip(max[0], min[1]), [u1, 0.0]),
+            (to_clip(max[0], max[1]), [u1, v1]),
+            (to_clip(min[0], max[1]), [u0, v1]),
+        ];
+        for &corner in [0, 1, 2, 2, 3, 0].iter() {
+            let (pos, uv) = corners[corner];
+            vertices.push(OverlayVertex { pos, uv, color });
+        }
+    };
+
+    let advance = CELL_WIDTH as f32 * SCALE;
+    let line_height = (CELL_HEIGHT + 1) as f32 * SCALE;
+    let longest_line = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+    if longest_line > 0 {
+        let padding = SCALE * 2.0;
+        push_quad(
+            &mut vertices,
+            [MARGIN - padding, MARGIN - padding],
+            [MARGIN + longest_line as f32 * advance + padding, MARGIN + lines.len() as f32 * line_height + padding],
+            SOLID_CELL,
+            [CELL_WIDTH, CELL_HEIGHT],
+            BACKGROUND_COLOR);
+    }
+
+    let mut char_count = 0;
+    'lines: for (row, line) in lines.iter().enumerate() {
+        let y = MARGIN + row as f32 * line_height;
+        for (column, character) in line.chars().enumerate() {
+            if char_count == MAX_OVERLAY_CHARS {
+                break 'lines;
+            }
+            let cell = match glyph_cell(character) {
+                Some(cell) => cell,
+                None => continue,
+            };
+
+            let x = MARGIN + column as f32 * advance;
+            push_quad(
+                &mut vertices,
+                [x, y],
+                [x + GLYPH_WIDTH as f32 * SCALE, y + GLYPH_HEIGHT as f32 * SCALE],
+                cell,
+                [GLYPH_WIDTH, GLYPH_HEIGHT],
+                TEXT_COLOR);
+            char_count += 1;
+        }
+    }
+
+    vertices.resize(VERTEX_COUNT, OverlayVertex::default());
+    vertices
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_pads_to_the_fixed_vertex_count_and_skips_unknown_characters() {
+        let extent = vk::Extent2D { width: 640, height: 480 };
+
+        let vertices = layout_text(&["FPS 60".to_string()], extent);
+        assert_eq!(vertices.len(), VERTEX_COUNT);
+        // background plus 5 glyphs, the space has none; everything after is degenerate.
+        let drawn = vertices.iter().filter(|vertex| vertex.color[3] != 0.0).count();
+        assert_eq!(drawn, 6 * 6);
+        assert_eq!(vertices[0].color, BACKGROUND_COLOR);
+        assert_eq!(vertices[0].pos, [(MARGIN - 2.0 * SCALE) / 320.0 - 1.0, (MARGIN - 2.0 * SCALE) / 240.0 - 1.0]);
+
+        let long_line = "8".repeat(MAX_OVERLAY_CHARS + 10);
+        let vertices = layout_text(&[long_line], extent);
+        assert_eq!(vertices.len(), VERTEX_COUNT);
+        assert!(vertices.iter().all(|vertex| vertex.color[3] != 0.0));
+    }
+}
@@ -0,0 +1,119 @@
+use ash::vk;
+
+use std::collections::VecDeque;
+
+use crate::utility::constants::MAX_FRAMES_IN_FLIGHT;
+use crate::vk::lifecycle::{self, TrackedObject};
+
+/// Destroys whatever was retired with it, gets the device the queue was created with.
+pub type DeletionFn = Box<dyn FnOnce(&ash::Device)>;
+
+/// Delays destroying objects the device may still be using until the frames that could use them are done.
+/// Everything enqueued during frame `n` is destroyed in `begin_frame` of frame `n + MAX_FRAMES_IN_FLIGHT`,
+/// which is when that frame's in flight fence has been waited on again.
+/// Keeps a clone of the device which has to outlive it, `flush` has to run before the device is destroyed.
+pub struct DeletionQueue {
+    device: ash::Device,
+    frame_index: u64,
+    pending: VecDeque<(u64, DeletionFn)>,
+}
+
+impl DeletionQueue {
+    pub fn new(device: &ash::Device) -> DeletionQueue {
+        DeletionQueue {
+            device: device.clone(),
+            frame_index: 0,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Frames begun so far, what enqueued objects are tagged with.
+    pub fn frame_index(&self) -> u64 {
+        self.frame_index
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn enqueue<F>(&mut self, destroy: F)
+    where
+        F: FnOnce(&ash::Device) + 'static,
+    {
+        self.pending.push_back((self.frame_index, Box::new(destroy)));
+    }
+
+    pub fn enqueue_pipeline(&mut self, pipeline: vk::Pipeline) {
+        self.enqueue(move |device| {
+            lifecycle::on_destroy(TrackedObject::Pipeline, pipeline);
+            unsafe {
+                device.destroy_pipeline(pipeline, None);
+            }
+        });
+    }
+
+    pub fn enqueue_framebuffers(&mut self, framebuffers: Vec<vk::Framebuffer>) {
+        self.enqueue(move |device| unsafe {
+            for framebuffer in framebuffers {
+                device.destroy_framebuffer(framebuffer, None);
+            }
+        });
+    }
+
+    pub fn enqueue_image_views(&mut self, image_views: Vec<vk::ImageView>) {
+        self.enqueue(move |device| unsafe {
+            for image_view in image_views {
+                device.destroy_image_view(image_view, None);
+            }
+        });
+    }
+
+    /// Has to be called once per submitted frame, after waiting on the frame's in flight fence and before recording it.
+    /// Not for frames that are skipped without a submit, the frame slots would stop lining up with `frame_index`.
+    /// Destroys what was retired `MAX_FRAMES_IN_FLIGHT` or more frames ago.
+    pub fn begin_frame(&mut self) {
+        self.frame_index += 1;
+
+        // entries are pushed in frame order, so the expired ones are all at the front.
+        while let Some(&(retired_frame, _)) = self.pending.front() {
+            if !is_expired(retired_frame, self.frame_index) {
+                break;
+            }
+
+            let (_, destroy) = self.pending.pop_front().unwrap();
+            destroy(&self.device);
+        }
+    }
+
+    /// Destroys everything right away, the device must be idle.
+    pub fn flush(&mut self) {
+        for (_, destroy) in self.pending.drain(..) {
+            destroy(&self.device);
+        }
+    }
+}
+
+/// Frame `retired_frame` ran on the slot whose fence `begin_frame` of frame `retired_frame + MAX_FRAMES_IN_FLIGHT`
+/// waits on, earlier frames on the other slots were waited on before that.
+fn is_expired(retired_frame: u64, frame_index: u64) -> bool {
+    retired_frame + MAX_FRAMES_IN_FLIGHT as u64 <= frame_index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_expire_once_their_frame_slot_is_waited_on_again() {
+        let retired_frame = 7;
+        let retired_slot = retired_frame % MAX_FRAMES_IN_FLIGHT as u64;
+
+        for frame_index in retired_frame..retired_frame + MAX_FRAMES_IN_FLIGHT as u64 {
+            assert!(!is_expired(retired_frame, frame_index), "Destroyed while frame {} may still run", retired_frame);
+        }
+
+        let expiring_frame = retired_frame + MAX_FRAMES_IN_FLIGHT as u64;
+        assert_eq!(expiring_frame % MAX_FRAMES_IN_FLIGHT as u64, retired_slot);
+        assert!(is_expired(retired_frame, expiring_frame));
+    }
+}
@@ -0,0 +1,216 @@
+use ash::vk;
+use cgmath::{Matrix4, Rad};
+
+use std::ptr;
+
+use crate::vk::image;
+use crate::vk::lifecycle::{self, TrackedObject};
+
+/// Depth (and stencil) attachment with views for every way a pass can use it:
+/// the combined view for the attachment, and depth-only / stencil-only views for sampling.
+pub struct VkDepthImage {
+    pub image: vk::Image,
+    pub image_memory: vk::DeviceMemory,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+
+    /// All aspects of the format, used as the render pass attachment.
+    pub image_view: vk::ImageView,
+    pub depth_view: vk::ImageView,
+    /// `None` when the format has no stencil component.
+    pub stencil_view: Option<vk::ImageView>,
+}
+
+impl VkDepthImage {
+    pub const FORMAT_CANDIDATES: [vk::Format; 3] = [
+        vk::Format::D32_SFLOAT_S8_UINT,
+        vk::Format::D24_UNORM_S8_UINT,
+        vk::Format::D32_SFLOAT,
+    ];
+
+    pub fn find_depth_format(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> vk::Format {
+        image::find_supported_format(
+            instance,
+            physical_device,
+            &VkDepthImage::FORMAT_CANDIDATES,
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+        )
+        .expect("Failed to find supported depth format!")
+    }
+
+    /// `sampled` also allows reading the depth/stencil views from shaders.
+    /// `api_version` decides whether the per-aspect views can be restricted to `SAMPLED` usage (Vulkan 1.1).
+    /// More than one of `array_layers` makes every view a `TYPE_2D_ARRAY` one, e.g. for a multiview render pass.
+    pub fn new(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &ash::Device,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        sampled: bool,
+        array_layers: u32,
+        api_version: u32,
+    ) -> VkDepthImage {
+        let mut usage = vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT;
+        if sampled {
+            usage |= vk::ImageUsageFlags::SAMPLED;
+        }
+        let tiling = image::choose_tiling(instance, physical_device, format, usage);
+
+        let image_create_info = vk::ImageCreateInfo {
+            s_type: vk::StructureType::IMAGE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::ImageCreateFlags::empty(),
+            image_type: vk::ImageType::TYPE_2D,
+            format,
+            extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            mip_levels: 1,
+            array_layers: array_layers,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            queue_family_index_count: 0,
+            p_queue_family_indices: ptr::null(),
+            initial_layout: vk::ImageLayout::UNDEFINED,
+        };
+
+        let (image, image_memory) = image::create_image(
+            device,
+            &image_create_info,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            device_memory_properties);
+
+        let has_stencil = image::has_stencil_component(format);
+        let aspect_mask = VkDepthImage::aspect_mask(format);
+        let view_type = if array_layers > 1 {
+            vk::ImageViewType::TYPE_2D_ARRAY
+        } else {
+            vk::ImageViewType::TYPE_2D
+        };
+
+        let image_view = image::create_image_view(
+            device,
+            image,
+            view_type,
+            format,
+            aspect_mask);
+
+        let view_usage = if sampled && api_version >= vk::API_VERSION_1_1 {
+            Some(vk::ImageUsageFlags::SAMPLED)
+        } else {
+            None
+        };
+
+        let depth_view = image::create_image_view_with_usage(
+            device,
+            image,
+            view_type,
+            format,
+            vk::ImageAspectFlags::DEPTH,
+            view_usage);
+
+        let stencil_view = if has_stencil {
+            Some(image::create_image_view_with_usage(
+                device,
+                image,
+                view_type,
+                format,
+                vk::ImageAspectFlags::STENCIL,
+                view_usage))
+        } else {
+            None
+        };
+
+        VkDepthImage {
+            image: image,
+            image_memory: image_memory,
+            format: format,
+            extent: extent,
+
+            image_view: image_view,
+            depth_view: depth_view,
+            stencil_view: stencil_view,
+        }
+    }
+
+    pub fn aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+        let mut aspect_mask = vk::ImageAspectFlags::empty();
+        if image::has_depth_component(format) {
+            aspect_mask |= vk::ImageAspectFlags::DEPTH;
+        }
+        if image::has_stencil_component(format) {
+            aspect_mask |= vk::ImageAspectFlags::STENCIL;
+        }
+
+        aspect_mask
+    }
+
+    pub fn has_stencil(&self) -> bool {
+        self.stencil_view.is_some()
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            if let Some(stencil_view) = self.stencil_view {
+                device.destroy_image_view(stencil_view, None);
+            }
+            device.destroy_image_view(self.depth_view, None);
+            device.destroy_image_view(self.image_view, None);
+            lifecycle::on_destroy(TrackedObject::Image, self.image);
+            device.destroy_image(self.image, None);
+            device.free_memory(self.image_memory, None);
+        }
+    }
+}
+
+/// Which way depth goes, one value to keep the projection, the compare op and the depth clear value in agreement.
+/// `ReverseZ` maps the near plane to 1.0 and the far plane to 0.0, which spreads float precision
+/// far more evenly over the distance than `Standard`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DepthMode {
+    #[default]
+    Standard,
+    ReverseZ,
+}
+
+impl DepthMode {
+    /// What the depth attachment is cleared to, the farthest depth.
+    pub fn clear_depth(self) -> f32 {
+        match self {
+            DepthMode::Standard => 1.0,
+            DepthMode::ReverseZ => 0.0,
+        }
+    }
+
+    /// For `GraphicsPipelineBuilder::depth_test`, see `GraphicsPipelineBuilder::depth_mode`.
+    pub fn compare_op(self) -> vk::CompareOp {
+        match self {
+            DepthMode::Standard => vk::CompareOp::LESS_OR_EQUAL,
+            DepthMode::ReverseZ => vk::CompareOp::GREATER_OR_EQUAL,
+        }
+    }
+
+    /// Right handed perspective projection onto Vulkan's 0 to 1 depth range, unlike `cgmath::perspective`.
+    /// Y isn't flipped, same as `cgmath::perspective`.
+    pub fn perspective(self, fovy: Rad<f32>, aspect: f32, near: f32, far: f32) -> Matrix4<f32> {
+        let focal_length = 1.0 / (fovy.0 / 2.0).tan();
+        let (depth_scale, depth_offset) = match self {
+            DepthMode::Standard => (far / (near - far), near * far / (near - far)),
+            DepthMode::ReverseZ => (near / (far - near), near * far / (far - near)),
+        };
+
+        Matrix4::new(
+            focal_length / aspect, 0.0, 0.0, 0.0,
+            0.0, focal_length, 0.0, 0.0,
+            0.0, 0.0, depth_scale, -1.0,
+            0.0, 0.0, depth_offset, 0.0,
+        )
+    }
+}
@@ -0,0 +1,430 @@
+use ash::vk;
+
+use std::ptr;
+
+use crate::vk::render_pass::RenderPassBuilder;
+
+/// Collects bindings, then creates a `vk::DescriptorSetLayout` from them.
+#[derive(Default)]
+pub struct DescriptorSetLayoutBuilder {
+    bindings: Vec<vk::DescriptorSetLayoutBinding>,
+    /// Indexed like `bindings`, the binding pointers are only filled in by `build`
+    /// so the arrays stay where the pointers point to while the layout is created.
+    immutable_samplers: Vec<Vec<vk::Sampler>>,
+}
+
+impl DescriptorSetLayoutBuilder {
+    pub fn new() -> DescriptorSetLayoutBuilder {
+        DescriptorSetLayoutBuilder::default()
+    }
+
+    pub fn binding(
+        mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        descriptor_count: u32,
+        stage_flags: vk::ShaderStageFlags
+    ) -> DescriptorSetLayoutBuilder {
+        self.bindings.push(vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type,
+            descriptor_count,
+            stage_flags,
+            p_immutable_samplers: ptr::null(),
+        });
+        self.immutable_samplers.push(vec![]);
+        self
+    }
+
+    /// `SAMPLER` or `COMBINED_IMAGE_SAMPLER` binding whose samplers are baked into the layout,
+    /// so descriptor writes leave the sampler out. One descriptor per sampler,
+    /// the samplers must not be destroyed while the layout or sets allocated from it are in use.
+    pub fn immutable_samplers(
+        mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        stage_flags: vk::ShaderStageFlags,
+        samplers: &[vk::Sampler]
+    ) -> DescriptorSetLayoutBuilder {
+        assert!(
+            descriptor_type == vk::DescriptorType::SAMPLER
+                || descriptor_type == vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            "Immutable samplers need a SAMPLER or COMBINED_IMAGE_SAMPLER binding"
+        );
+
+        self.bindings.push(vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type,
+            descriptor_count: samplers.len() as u32,
+            stage_flags,
+            p_immutable_samplers: ptr::null(),
+        });
+        self.immutable_samplers.push(samplers.to_vec());
+        self
+    }
+
+    /// `subpassInput` binding, input attachments can only be read from fragment shaders.
+    pub fn input_attachment(self, binding: u32) -> DescriptorSetLayoutBuilder {
+        self.binding(binding, vk::DescriptorType::INPUT_ATTACHMENT, 1, vk::ShaderStageFlags::FRAGMENT)
+    }
+
+    /// Checks there's one `input_attachment` binding per input of `subpass`, starting at `first_binding`
+    /// in `input_attachment_index` order, like `write_input_attachments` fills them.
+    pub fn validate_input_attachments(&self, first_binding: u32, render_pass: &RenderPassBuilder, subpass: u32) {
+        let subpasses = render_pass.subpasses();
+        let input_count = subpasses
+            .get(subpass as usize)
+            .unwrap_or_else(|| panic!("Render pass has no subpass {}", subpass))
+            .input_attachments()
+            .len() as u32;
+
+        for binding in first_binding..first_binding + input_count {
+            let layout_binding = self.bindings
+                .iter()
+                .find(|layout_binding| layout_binding.binding == binding)
+                .unwrap_or_else(|| panic!("Subpass {} reads an input attachment without binding {}", subpass, binding));
+            assert_eq!(
+                layout_binding.descriptor_type,
+                vk::DescriptorType::INPUT_ATTACHMENT,
+                "Binding {} is read as an input attachment by subpass {}", binding, subpass
+            );
+        }
+
+        let input_bindings = self.bindings
+            .iter()
+            .filter(|layout_binding| layout_binding.descriptor_type == vk::DescriptorType::INPUT_ATTACHMENT)
+            .count() as u32;
+        assert_eq!(
+            input_bindings, input_count,
+            "Layout has {} input attachment bindings, subpass {} declares {} input attachments",
+            input_bindings, subpass, input_count
+        );
+    }
+
+    pub fn build(&self, device: &ash::Device) -> vk::DescriptorSetLayout {
+        let bindings: Vec<vk::DescriptorSetLayoutBinding> = self.bindings
+            .iter()
+            .zip(self.immutable_samplers.iter())
+            .map(|(&binding, samplers)| vk::DescriptorSetLayoutBinding {
+                p_immutable_samplers: if samplers.is_empty() { ptr::null() } else { samplers.as_ptr() },
+                ..binding
+            })
+            .collect();
+
+        let layout_create_info = vk::DescriptorSetLayoutCreateInfo {
+            s_type: vk::StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DescriptorSetLayoutCreateFlags::empty(),
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+        };
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_create_info, None)
+                .expect("Failed to create Descriptor Set Layout!")
+        }
+    }
+}
+
+/// Points the bindings from `first_binding` on at the input attachments of `subpass`, one binding per input
+/// in `input_attachment_index` order. `attachment_views` are the framebuffer attachments the pass is begun with.
+pub fn write_input_attachments(
+    device: &ash::Device,
+    descriptor_set: vk::DescriptorSet,
+    first_binding: u32,
+    render_pass: &RenderPassBuilder,
+    subpass: u32,
+    attachment_views: &[vk::ImageView]
+) {
+    let subpasses = render_pass.subpasses();
+    let input_refs = render_pass.input_attachment_refs(
+        subpasses
+            .get(subpass as usize)
+            .unwrap_or_else(|| panic!("Render pass has no subpass {}", subpass)));
+    assert!(!input_refs.is_empty(), "Subpass {} declares no input attachments", subpass);
+
+    let image_infos: Vec<vk::DescriptorImageInfo> = input_refs
+        .iter()
+        .map(|input_ref| vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: *attachment_views
+                .get(input_ref.attachment as usize)
+                .unwrap_or_else(|| panic!("No image view for input attachment {}", input_ref.attachment)),
+            image_layout: input_ref.layout,
+        })
+        .collect();
+
+    let descriptor_writes: Vec<vk::WriteDescriptorSet> = image_infos
+        .iter()
+        .enumerate()
+        .map(|(input_index, image_info)| vk::WriteDescriptorSet {
+            s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+            p_next: ptr::null(),
+            dst_set: descriptor_set,
+            dst_binding: first_binding + input_index as u32,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::INPUT_ATTACHMENT,
+            p_image_info: image_info,
+            p_buffer_info: ptr::null(),
+            p_texel_buffer_view: ptr::null(),
+        })
+        .collect();
+
+    unsafe {
+        device.update_descriptor_sets(&descriptor_writes, &[]);
+    }
+}
+
+/// Descriptors of each type per set a new pool makes room for, unless configured otherwise.
+const POOL_SIZE_RATIOS: [(vk::DescriptorType, f32); 7] = [
+    (vk::DescriptorType::UNIFORM_BUFFER, 2.0),
+    (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1.0),
+    (vk::DescriptorType::STORAGE_BUFFER, 2.0),
+    (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 4.0),
+    (vk::DescriptorType::STORAGE_IMAGE, 1.0),
+    (vk::DescriptorType::SAMPLER, 1.0),
+    (vk::DescriptorType::INPUT_ATTACHMENT, 1.0),
+];
+
+/// Sizes of the pools a `DescriptorAllocator` creates. Each pool holds `max_sets` sets and `ratio * max_sets`
+/// descriptors of every type, e.g. a ratio of 4.0 for uniform buffers when sets use 4 of them on average.
+#[derive(Clone, Debug)]
+pub struct DescriptorPoolConfig {
+    /// Sets the first pool makes room for.
+    pub max_sets: u32,
+    /// Descriptors of each type per set, types left out can't be allocated from the pools.
+    pub ratios: Vec<(vk::DescriptorType, f32)>,
+    /// Every new pool holds this many times the sets of the previous one, 1.0 keeps them all the same size.
+    pub growth_factor: f32,
+    /// Grown pools stop at this many sets.
+    pub max_sets_limit: u32,
+}
+
+impl DescriptorPoolConfig {
+    /// The default ratios, doubling the pool size up to 4096 sets.
+    pub fn new(max_sets: u32) -> DescriptorPoolConfig {
+        DescriptorPoolConfig {
+            max_sets: max_sets,
+            ratios: POOL_SIZE_RATIOS.to_vec(),
+            growth_factor: 2.0,
+            max_sets_limit: 4096,
+        }
+    }
+
+    /// Sets the ratio of `descriptor_type`, replacing the default one if there is any.
+    pub fn ratio(mut self, descriptor_type: vk::DescriptorType, ratio: f32) -> DescriptorPoolConfig {
+        match self.ratios.iter_mut().find(|(ty, _)| *ty == descriptor_type) {
+            Some(entry) => entry.1 = ratio,
+            None => self.ratios.push((descriptor_type, ratio)),
+        }
+        self
+    }
+
+    /// At least one descriptor of every configured type, a pool size of 0 isn't valid.
+    fn pool_sizes(&self, max_sets: u32) -> Vec<vk::DescriptorPoolSize> {
+        self.ratios
+            .iter()
+            .map(|&(ty, ratio)| vk::DescriptorPoolSize {
+                ty,
+                descriptor_count: ((ratio * max_sets as f32).ceil() as u32).max(1),
+            })
+            .collect()
+    }
+
+    /// Set count of the pool created after one of `max_sets`.
+    fn grown_max_sets(&self, max_sets: u32) -> u32 {
+        let grown = (max_sets as f32 * self.growth_factor.max(1.0)) as u32;
+        grown.min(self.max_sets_limit).max(max_sets)
+    }
+}
+
+/// Hands out descriptor sets from a growing list of pools.
+/// A new pool is created whenever the current one runs out or is fragmented, each one larger than the last
+/// as set by `DescriptorPoolConfig::growth_factor`, so the first size doesn't have to be right.
+/// `reset` frees every set at once and keeps the pools for reuse, e.g. for per-frame transient sets.
+pub struct DescriptorAllocator {
+    config: DescriptorPoolConfig,
+    /// Sets the next newly created pool holds.
+    next_max_sets: u32,
+
+    current_pool: Option<vk::DescriptorPool>,
+    used_pools: Vec<vk::DescriptorPool>,
+    free_pools: Vec<vk::DescriptorPool>,
+}
+
+impl DescriptorAllocator {
+    /// Default `DescriptorPoolConfig` with a first pool of `sets_per_pool` sets.
+    pub fn new(sets_per_pool: u32) -> DescriptorAllocator {
+        DescriptorAllocator::with_config(DescriptorPoolConfig::new(sets_per_pool))
+    }
+
+    pub fn with_config(config: DescriptorPoolConfig) -> DescriptorAllocator {
+        DescriptorAllocator {
+            next_max_sets: config.max_sets,
+            config: config,
+
+            current_pool: None,
+            used_pools: vec![],
+            free_pools: vec![],
+        }
+    }
+
+    pub fn allocate(&mut self, device: &ash::Device, layout: vk::DescriptorSetLayout) -> vk::DescriptorSet {
+        let pool = match self.current_pool {
+            Some(pool) => pool,
+            None => self.grab_pool(device),
+        };
+
+        match DescriptorAllocator::allocate_from(device, pool, layout) {
+            Ok(descriptor_set) => descriptor_set,
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {
+                let pool = self.grab_pool(device);
+                DescriptorAllocator::allocate_from(device, pool, layout)
+                    .expect("Failed to allocate descriptor set from a fresh pool!")
+            }
+            Err(error) => panic!("Failed to allocate descriptor set: {:?}", error),
+        }
+    }
+
+    /// Frees all sets handed out so far, they must not be in use by the device anymore.
+    pub fn reset(&mut self, device: &ash::Device) {
+        for &pool in self.used_pools.iter() {
+            unsafe {
+                device
+                    .reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())
+                    .expect("Failed to reset Descriptor Pool!");
+            }
+        }
+
+        self.free_pools.append(&mut self.used_pools);
+        self.current_pool = None;
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for &pool in self.used_pools.iter().chain(self.free_pools.iter()) {
+            unsafe {
+                device.destroy_descriptor_pool(pool, None);
+            }
+        }
+
+        self.used_pools.clear();
+        self.free_pools.clear();
+        self.current_pool = None;
+    }
+
+    /// Makes a free or a new pool the current one.
+    fn grab_pool(&mut self, device: &ash::Device) -> vk::DescriptorPool {
+        let pool = match self.free_pools.pop() {
+            Some(pool) => pool,
+            None => {
+                let pool = DescriptorAllocator::create_pool(device, &self.config, self.next_max_sets);
+                self.next_max_sets = self.config.grown_max_sets(self.next_max_sets);
+                pool
+            }
+        };
+
+        self.used_pools.push(pool);
+        self.current_pool = Some(pool);
+        pool
+    }
+
+    fn allocate_from(
+        device: &ash::Device,
+        pool: vk::DescriptorPool,
+        layout: vk::DescriptorSetLayout
+    ) -> Result<vk::DescriptorSet, vk::Result> {
+        let layouts = [layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo {
+            s_type: vk::StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+            p_next: ptr::null(),
+            descriptor_pool: pool,
+            descriptor_set_count: layouts.len() as u32,
+            p_set_layouts: layouts.as_ptr(),
+        };
+
+        unsafe { device.allocate_descriptor_sets(&allocate_info) }
+            .map(|descriptor_sets| descriptor_sets[0])
+    }
+
+    fn create_pool(device: &ash::Device, config: &DescriptorPoolConfig, max_sets: u32) -> vk::DescriptorPool {
+        let pool_sizes = config.pool_sizes(max_sets);
+
+        let pool_create_info = vk::DescriptorPoolCreateInfo {
+            s_type: vk::StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DescriptorPoolCreateFlags::empty(),
+            max_sets: max_sets,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+        };
+
+        unsafe {
+            device
+                .create_descriptor_pool(&pool_create_info, None)
+                .expect("Failed to create Descriptor Pool!")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor_counts(pool_sizes: &[vk::DescriptorPoolSize]) -> Vec<(vk::DescriptorType, u32)> {
+        pool_sizes.iter().map(|pool_size| (pool_size.ty, pool_size.descriptor_count)).collect()
+    }
+
+    #[test]
+    fn pool_sizes_round_up_and_keep_at_least_one_descriptor() {
+        let config = DescriptorPoolConfig {
+            max_sets: 10,
+            ratios: vec![
+                (vk::DescriptorType::UNIFORM_BUFFER, 4.0),
+                (vk::DescriptorType::STORAGE_BUFFER, 0.25),
+                (vk::DescriptorType::SAMPLER, 0.0),
+            ],
+            growth_factor: 2.0,
+            max_sets_limit: 4096,
+        };
+
+        assert_eq!(
+            descriptor_counts(&config.pool_sizes(10)),
+            vec![
+                (vk::DescriptorType::UNIFORM_BUFFER, 40),
+                (vk::DescriptorType::STORAGE_BUFFER, 3),
+                (vk::DescriptorType::SAMPLER, 1),
+            ]);
+    }
+
+    #[test]
+    fn ratio_replaces_the_default_or_adds_the_type() {
+        let config = DescriptorPoolConfig::new(8)
+            .ratio(vk::DescriptorType::UNIFORM_BUFFER, 3.0)
+            .ratio(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR, 0.5);
+        let counts = descriptor_counts(&config.pool_sizes(8));
+
+        assert_eq!(config.ratios.len(), POOL_SIZE_RATIOS.len() + 1);
+        assert!(counts.contains(&(vk::DescriptorType::UNIFORM_BUFFER, 24)));
+        assert_eq!(counts.last(), Some(&(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR, 4)));
+    }
+
+    #[test]
+    fn grown_max_sets_stops_at_the_limit() {
+        let config = DescriptorPoolConfig {
+            max_sets_limit: 100,
+            ..DescriptorPoolConfig::new(16)
+        };
+
+        assert_eq!(config.grown_max_sets(16), 32);
+        assert_eq!(config.grown_max_sets(64), 100);
+        // pools never shrink, not even past the limit or with a growth factor below 1.0.
+        assert_eq!(config.grown_max_sets(200), 200);
+        let shrinking = DescriptorPoolConfig {
+            growth_factor: 0.5,
+            ..DescriptorPoolConfig::new(16)
+        };
+        assert_eq!(shrinking.grown_max_sets(16), 16);
+    }
+}
@@ -0,0 +1,180 @@
+use ash::vk;
+
+use std::ptr;
+
+use crate::utility::tools;
+use crate::vk::render_device::VkSurface;
+use crate::vk::swap_chain::VkSpawChain;
+
+/// Alternate frame rendering across the physical devices of a device group.
+/// Every frame is acquired, rendered and presented by a single device, which rotates each present.
+pub struct VkDeviceGroup {
+    pub device_group_loader: ash::extensions::khr::DeviceGroup,
+    pub physical_devices: Vec<vk::PhysicalDevice>,
+
+    /// Devices that can present their own swapchain image instances (`LOCAL` mode).
+    present_device_indices: Vec<u32>,
+    current: usize,
+
+    device_index: u32,
+    device_mask: u32,
+}
+
+/// Returns the physical devices of the group `physical_device` belongs to,
+/// if that group has more than one device and supports `VK_KHR_device_group`.
+pub fn find_device_group(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice
+) -> Option<Vec<vk::PhysicalDevice>> {
+    let groups = unsafe {
+        let group_count = instance
+            .enumerate_physical_device_groups_len()
+            .expect("Failed to enumerate physical device groups");
+        let mut groups = vec![vk::PhysicalDeviceGroupProperties::default(); group_count];
+        instance
+            .enumerate_physical_device_groups(&mut groups)
+            .expect("Failed to enumerate physical device groups");
+        groups
+    };
+
+    let is_extension_supported = unsafe {
+        instance
+            .enumerate_device_extension_properties(physical_device)
+            .expect("Failed to get device extension properties.")
+            .iter()
+            .any(|extension| {
+                tools::vk_to_string(&extension.extension_name)
+                    == ash::extensions::khr::DeviceGroup::name().to_str().unwrap()
+            })
+    };
+
+    if !is_extension_supported {
+        return None;
+    }
+
+    for group in groups.iter() {
+        let devices = &group.physical_devices[..group.physical_device_count as usize];
+        if devices.len() > 1 && devices.contains(&physical_device) {
+            return Some(devices.to_vec());
+        }
+    }
+
+    None
+}
+
+/// Goes in `DeviceCreateInfo.p_next`, `physical_devices` must outlive device creation.
+pub fn device_create_info(physical_devices: &[vk::PhysicalDevice]) -> vk::DeviceGroupDeviceCreateInfo {
+    vk::DeviceGroupDeviceCreateInfo {
+        s_type: vk::StructureType::DEVICE_GROUP_DEVICE_CREATE_INFO,
+        p_next: ptr::null(),
+        physical_device_count: physical_devices.len() as u32,
+        p_physical_devices: physical_devices.as_ptr(),
+    }
+}
+
+impl VkDeviceGroup {
+    /// Returns `None` when no device of the group can present locally,
+    /// in which case the group is driven like a single device.
+    pub fn new(
+        instance: &ash::Instance,
+        device: &ash::Device,
+        physical_devices: Vec<vk::PhysicalDevice>,
+        surface: &VkSurface
+    ) -> Option<VkDeviceGroup> {
+        let device_group_loader = ash::extensions::khr::DeviceGroup::new(instance, device);
+
+        let mut present_capabilities = vk::DeviceGroupPresentCapabilitiesKHR::default();
+        let surface_modes = unsafe {
+            device_group_loader
+                .get_device_group_present_capabilities(&mut present_capabilities)
+                .expect("Failed to get device group present capabilities");
+            device_group_loader
+                .get_device_group_surface_present_modes(surface.surface)
+                .expect("Failed to get device group surface present modes")
+        };
+
+        if !present_capabilities.modes.contains(vk::DeviceGroupPresentModeFlagsKHR::LOCAL)
+            || !surface_modes.contains(vk::DeviceGroupPresentModeFlagsKHR::LOCAL) {
+            log::warn!("Device group doesn't support LOCAL present, falling back to a single device.");
+            return None;
+        }
+
+        let present_device_indices: Vec<u32> = (0..physical_devices.len() as u32)
+            .filter(|&i| present_capabilities.present_mask[i as usize] & (1 << i) != 0)
+            .collect();
+
+        if present_device_indices.is_empty() {
+            log::warn!("No device in the group can present, falling back to a single device.");
+            return None;
+        }
+
+        let device_index = present_device_indices[0];
+
+        Some(VkDeviceGroup {
+            device_group_loader: device_group_loader,
+            physical_devices: physical_devices,
+            present_device_indices: present_device_indices,
+            current: 0,
+            device_index: device_index,
+            device_mask: 1 << device_index,
+        })
+    }
+
+    pub fn device_count(&self) -> usize {
+        self.present_device_indices.len()
+    }
+
+    pub fn current_device_index(&self) -> u32 {
+        self.device_index
+    }
+
+    pub fn current_device_mask(&self) -> u32 {
+        self.device_mask
+    }
+
+    /// Goes in `SwapchainCreateInfoKHR.p_next`.
+    pub fn swapchain_create_info(&self) -> vk::DeviceGroupSwapchainCreateInfoKHR {
+        vk::DeviceGroupSwapchainCreateInfoKHR {
+            s_type: vk::StructureType::DEVICE_GROUP_SWAPCHAIN_CREATE_INFO_KHR,
+            p_next: ptr::null(),
+            modes: vk::DeviceGroupPresentModeFlagsKHR::LOCAL,
+        }
+    }
+
+    pub fn acquire_next_image(
+        &self,
+        swapchain: &VkSpawChain,
+        semaphore: vk::Semaphore
+    ) -> Result<(u32, bool), vk::Result> {
+        let acquire_info = vk::AcquireNextImageInfoKHR {
+            s_type: vk::StructureType::ACQUIRE_NEXT_IMAGE_INFO_KHR,
+            p_next: ptr::null(),
+            swapchain: swapchain.swapchain,
+            timeout: u64::MAX,
+            semaphore,
+            fence: vk::Fence::null(),
+            device_mask: self.device_mask,
+        };
+
+        unsafe { self.device_group_loader.acquire_next_image2(&acquire_info) }
+    }
+
+    /// Goes in `PresentInfoKHR.p_next` for a single swapchain.
+    /// Points into `self`, so it must not outlive the borrow.
+    pub fn present_info(&self) -> vk::DeviceGroupPresentInfoKHR {
+        vk::DeviceGroupPresentInfoKHR {
+            s_type: vk::StructureType::DEVICE_GROUP_PRESENT_INFO_KHR,
+            p_next: ptr::null(),
+            swapchain_count: 1,
+            p_device_masks: &self.device_mask,
+            mode: vk::DeviceGroupPresentModeFlagsKHR::LOCAL,
+        }
+    }
+
+    /// Hands the next frame to the next presenting device.
+    pub fn advance(&mut self) {
+        self.current = (self.current + 1) % self.present_device_indices.len();
+        self.device_index = self.present_device_indices[self.current];
+        self.device_mask = 1 << self.device_index;
+    }
+}
@@ -0,0 +1,137 @@
+use ash::vk;
+
+use std::os::raw::c_void;
+
+/// Records a draw into `command_buffer` one call at a time, checking the order the raw `cmd_*` calls need:
+/// no descriptor set, push constant or draw before `pipeline`, and `indexed` needs `bind_index` first.
+/// The command buffer has to be inside a render pass or dynamic rendering, which isn't checked.
+///
+/// `DrawCommand::new(device, command_buffer).pipeline(pipeline, layout).bind_vertex(vertex_buffer)
+///     .bind_index(index_buffer, vk::IndexType::UINT32).descriptor_set(0, set).indexed(index_count)`
+pub struct DrawCommand<'a> {
+    device: &'a ash::Device,
+    command_buffer: vk::CommandBuffer,
+
+    pipeline_layout: Option<vk::PipelineLayout>,
+    is_index_buffer_bound: bool,
+    instance_count: u32,
+    first_instance: u32,
+}
+
+impl<'a> DrawCommand<'a> {
+    pub fn new(device: &'a ash::Device, command_buffer: vk::CommandBuffer) -> DrawCommand<'a> {
+        DrawCommand {
+            device: device,
+            command_buffer: command_buffer,
+
+            pipeline_layout: None,
+            is_index_buffer_bound: false,
+            instance_count: 1,
+            first_instance: 0,
+        }
+    }
+
+    /// Binds a graphics pipeline, `pipeline_layout` is what it was built with and what sets and push constants use.
+    pub fn pipeline(mut self, pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout) -> DrawCommand<'a> {
+        unsafe {
+            self.device.cmd_bind_pipeline(self.command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+        }
+        self.pipeline_layout = Some(pipeline_layout);
+        self
+    }
+
+    /// Whole `buffer` at vertex binding 0.
+    pub fn bind_vertex(self, buffer: vk::Buffer) -> DrawCommand<'a> {
+        self.bind_vertex_buffers(0, &[buffer], &[0])
+    }
+
+    /// One buffer per binding from `first_binding` on, each read from its offset in `offsets`.
+    pub fn bind_vertex_buffers(
+        self,
+        first_binding: u32,
+        buffers: &[vk::Buffer],
+        offsets: &[vk::DeviceSize]
+    ) -> DrawCommand<'a> {
+        assert_eq!(buffers.len(), offsets.len(), "Every vertex buffer needs an offset");
+
+        unsafe {
+            self.device.cmd_bind_vertex_buffers(self.command_buffer, first_binding, buffers, offsets);
+        }
+        self
+    }
+
+    pub fn bind_index(mut self, buffer: vk::Buffer, index_type: vk::IndexType) -> DrawCommand<'a> {
+        unsafe {
+            self.device.cmd_bind_index_buffer(self.command_buffer, buffer, 0, index_type);
+        }
+        self.is_index_buffer_bound = true;
+        self
+    }
+
+    pub fn descriptor_set(self, set: u32, descriptor_set: vk::DescriptorSet) -> DrawCommand<'a> {
+        self.descriptor_set_with_offsets(set, descriptor_set, &[])
+    }
+
+    /// `dynamic_offsets` has one entry per dynamic uniform or storage buffer of the set, in binding order.
+    pub fn descriptor_set_with_offsets(
+        self,
+        set: u32,
+        descriptor_set: vk::DescriptorSet,
+        dynamic_offsets: &[u32]
+    ) -> DrawCommand<'a> {
+        let pipeline_layout = self.pipeline_layout();
+
+        unsafe {
+            self.device.cmd_bind_descriptor_sets(
+                self.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                set,
+                &[descriptor_set],
+                dynamic_offsets,
+            );
+        }
+        self
+    }
+
+    /// Copies `data` to offset 0 of the push constants, `stages` have to be the ones of the layout's range.
+    pub fn push_constants<T: Copy>(self, stages: vk::ShaderStageFlags, data: &T) -> DrawCommand<'a> {
+        let pipeline_layout = self.pipeline_layout();
+        let bytes = unsafe {
+            std::slice::from_raw_parts(data as *const T as *const c_void as *const u8, std::mem::size_of::<T>())
+        };
+
+        unsafe {
+            self.device.cmd_push_constants(self.command_buffer, pipeline_layout, stages, 0, bytes);
+        }
+        self
+    }
+
+    /// Instance count and first instance of the draw, one instance starting at 0 by default.
+    pub fn instances(mut self, instance_count: u32, first_instance: u32) -> DrawCommand<'a> {
+        self.instance_count = instance_count;
+        self.first_instance = first_instance;
+        self
+    }
+
+    pub fn draw(self, vertex_count: u32) {
+        self.pipeline_layout();
+
+        unsafe {
+            self.device.cmd_draw(self.command_buffer, vertex_count, self.instance_count, 0, self.first_instance);
+        }
+    }
+
+    pub fn indexed(self, index_count: u32) {
+        self.pipeline_layout();
+        assert!(self.is_index_buffer_bound, "Indexed draw without an index buffer, call bind_index first!");
+
+        unsafe {
+            self.device.cmd_draw_indexed(self.command_buffer, index_count, self.instance_count, 0, 0, self.first_instance);
+        }
+    }
+
+    fn pipeline_layout(&self) -> vk::PipelineLayout {
+        self.pipeline_layout.expect("No pipeline bound, call pipeline first!")
+    }
+}
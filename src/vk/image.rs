@@ -0,0 +1,452 @@
+use ash::vk;
+
+use std::ptr;
+use std::os::raw::c_void;
+
+use crate::vk::render_device::VkRenderDevice;
+use crate::vk::lifecycle::{self, TrackedObject};
+
+pub fn full_subresource_range(aspect_mask: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask,
+        base_mip_level: 0,
+        level_count: vk::REMAINING_MIP_LEVELS,
+        base_array_layer: 0,
+        layer_count: vk::REMAINING_ARRAY_LAYERS,
+    }
+}
+
+/// Mip levels and array layers a view covers, the default covers all of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageSubresources {
+    pub base_mip_level: u32,
+    /// `vk::REMAINING_MIP_LEVELS` for every level from `base_mip_level` on.
+    pub level_count: u32,
+    pub base_array_layer: u32,
+    /// `vk::REMAINING_ARRAY_LAYERS` for every layer from `base_array_layer` on.
+    pub layer_count: u32,
+}
+
+impl Default for ImageSubresources {
+    fn default() -> ImageSubresources {
+        ImageSubresources {
+            base_mip_level: 0,
+            level_count: vk::REMAINING_MIP_LEVELS,
+            base_array_layer: 0,
+            layer_count: vk::REMAINING_ARRAY_LAYERS,
+        }
+    }
+}
+
+impl ImageSubresources {
+    /// Only `mip_level`, on every layer.
+    pub fn mip(mip_level: u32) -> ImageSubresources {
+        ImageSubresources {
+            base_mip_level: mip_level,
+            level_count: 1,
+            ..Default::default()
+        }
+    }
+
+    /// `layer_count` layers from `base_array_layer`, on every mip.
+    pub fn layers(base_array_layer: u32, layer_count: u32) -> ImageSubresources {
+        ImageSubresources {
+            base_array_layer,
+            layer_count,
+            ..Default::default()
+        }
+    }
+
+    pub fn range(&self, aspect_mask: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: self.base_mip_level,
+            level_count: self.level_count,
+            base_array_layer: self.base_array_layer,
+            layer_count: self.layer_count,
+        }
+    }
+
+    pub fn validate(&self, image_mip_levels: u32, image_array_layers: u32) -> Result<(), String> {
+        let level_count = if self.level_count == vk::REMAINING_MIP_LEVELS {
+            image_mip_levels.saturating_sub(self.base_mip_level)
+        } else {
+            self.level_count
+        };
+        let layer_count = if self.layer_count == vk::REMAINING_ARRAY_LAYERS {
+            image_array_layers.saturating_sub(self.base_array_layer)
+        } else {
+            self.layer_count
+        };
+
+        if level_count == 0 || self.base_mip_level as u64 + level_count as u64 > image_mip_levels as u64 {
+            return Err(format!(
+                "mips {}..{} out of the image's {} mip levels",
+                self.base_mip_level, self.base_mip_level as u64 + level_count as u64, image_mip_levels));
+        }
+        if layer_count == 0 || self.base_array_layer as u64 + layer_count as u64 > image_array_layers as u64 {
+            return Err(format!(
+                "layers {}..{} out of the image's {} array layers",
+                self.base_array_layer, self.base_array_layer as u64 + layer_count as u64, image_array_layers));
+        }
+
+        Ok(())
+    }
+}
+
+/// Size of `mip_level` for an image of `extent`, every dimension stops at 1.
+pub fn mip_extent(extent: vk::Extent3D, mip_level: u32) -> vk::Extent3D {
+    vk::Extent3D {
+        width: (extent.width >> mip_level).max(1),
+        height: (extent.height >> mip_level).max(1),
+        depth: (extent.depth >> mip_level).max(1),
+    }
+}
+
+/// Access mask and pipeline stage that touch an image while it sits in `layout`.
+pub fn layout_access_and_stage(layout: vk::ImageLayout) -> (vk::AccessFlags, vk::PipelineStageFlags) {
+    match layout {
+        vk::ImageLayout::UNDEFINED => (vk::AccessFlags::empty(), vk::PipelineStageFlags::TOP_OF_PIPE),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (vk::AccessFlags::TRANSFER_WRITE, vk::PipelineStageFlags::TRANSFER),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (vk::AccessFlags::TRANSFER_READ, vk::PipelineStageFlags::TRANSFER),
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::FRAGMENT_SHADER),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        ),
+        vk::ImageLayout::PRESENT_SRC_KHR => (vk::AccessFlags::empty(), vk::PipelineStageFlags::BOTTOM_OF_PIPE),
+        _ => (
+            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+        ),
+    }
+}
+
+/// Records a pipeline barrier moving `image` from `old_layout` to `new_layout`.
+/// Stage and access masks are derived from the two layouts.
+pub fn cmd_transition_image_layout(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    subresource_range: vk::ImageSubresourceRange,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) {
+    let (src_access_mask, src_stage_mask) = layout_access_and_stage(old_layout);
+    let (dst_access_mask, dst_stage_mask) = layout_access_and_stage(new_layout);
+
+    let image_barriers = [vk::ImageMemoryBarrier {
+        s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
+        p_next: ptr::null(),
+        src_access_mask,
+        dst_access_mask,
+        old_layout,
+        new_layout,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        image,
+        subresource_range,
+    }];
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage_mask,
+            dst_stage_mask,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &image_barriers,
+        );
+    }
+}
+
+/// Region between a tightly packed buffer at `buffer_offset` and `layer_count` layers of `mip_level`,
+/// `width` and `height` are the size of that mip.
+pub fn buffer_image_copy(
+    buffer_offset: vk::DeviceSize,
+    aspect_mask: vk::ImageAspectFlags,
+    mip_level: u32,
+    base_array_layer: u32,
+    layer_count: u32,
+    width: u32,
+    height: u32,
+) -> vk::BufferImageCopy {
+    vk::BufferImageCopy {
+        buffer_offset,
+        // 0 means rows and layers follow each other without padding.
+        buffer_row_length: 0,
+        buffer_image_height: 0,
+        image_subresource: vk::ImageSubresourceLayers {
+            aspect_mask,
+            mip_level,
+            base_array_layer,
+            layer_count,
+        },
+        image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+        image_extent: vk::Extent3D { width, height, depth: 1 },
+    }
+}
+
+/// Copies `layer_count` layers of `width` x `height` texels from the start of `buffer` into `mip_level`,
+/// which has to be in `TRANSFER_DST_OPTIMAL`.
+pub fn cmd_copy_buffer_to_image(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    buffer: vk::Buffer,
+    image: vk::Image,
+    aspect_mask: vk::ImageAspectFlags,
+    mip_level: u32,
+    width: u32,
+    height: u32,
+    layer_count: u32,
+) {
+    let regions = [buffer_image_copy(0, aspect_mask, mip_level, 0, layer_count, width, height)];
+
+    unsafe {
+        device.cmd_copy_buffer_to_image(
+            command_buffer,
+            buffer,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &regions,
+        );
+    }
+}
+
+/// Reads `layer_count` layers of `mip_level` back into the start of `buffer`, tightly packed,
+/// e.g. for screenshots. The image has to be in `TRANSFER_SRC_OPTIMAL`.
+pub fn cmd_copy_image_to_buffer(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    buffer: vk::Buffer,
+    aspect_mask: vk::ImageAspectFlags,
+    mip_level: u32,
+    width: u32,
+    height: u32,
+    layer_count: u32,
+) {
+    let regions = [buffer_image_copy(0, aspect_mask, mip_level, 0, layer_count, width, height)];
+
+    unsafe {
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            buffer,
+            &regions,
+        );
+    }
+}
+
+/// Creates an image from `image_create_info` and binds freshly allocated memory to it.
+pub fn create_image(
+    device: &ash::Device,
+    image_create_info: &vk::ImageCreateInfo,
+    required_memory_properties: vk::MemoryPropertyFlags,
+    device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+) -> (vk::Image, vk::DeviceMemory) {
+    let image = unsafe {
+        device
+            .create_image(image_create_info, None)
+            .expect("Failed to create Image!")
+    };
+    lifecycle::on_create(TrackedObject::Image, image);
+
+    let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
+    let memory_type = VkRenderDevice::find_memory_type(
+        mem_requirements.memory_type_bits,
+        required_memory_properties,
+        device_memory_properties,
+    );
+
+    let allocate_info = vk::MemoryAllocateInfo {
+        s_type: vk::StructureType::MEMORY_ALLOCATE_INFO,
+        p_next: ptr::null(),
+        allocation_size: mem_requirements.size,
+        memory_type_index: memory_type,
+    };
+
+    let image_memory = unsafe {
+        device
+            .allocate_memory(&allocate_info, None)
+            .expect("Failed to allocate Image memory!")
+    };
+
+    unsafe {
+        device
+            .bind_image_memory(image, image_memory, 0)
+            .expect("Failed to bind Image Memory!");
+    }
+
+    (image, image_memory)
+}
+
+pub fn create_image_view(
+    device: &ash::Device,
+    image: vk::Image,
+    view_type: vk::ImageViewType,
+    format: vk::Format,
+    aspect_mask: vk::ImageAspectFlags,
+) -> vk::ImageView {
+    create_image_view_with_usage(device, image, view_type, format, aspect_mask, None)
+}
+
+/// Like `create_image_view`, but `usage` restricts what the view is used for
+/// (`VkImageViewUsageCreateInfo`, Vulkan 1.1), e.g. a stencil-only view of a depth attachment that is only sampled.
+pub fn create_image_view_with_usage(
+    device: &ash::Device,
+    image: vk::Image,
+    view_type: vk::ImageViewType,
+    format: vk::Format,
+    aspect_mask: vk::ImageAspectFlags,
+    usage: Option<vk::ImageUsageFlags>,
+) -> vk::ImageView {
+    create_image_view_for_range(device, image, view_type, format, full_subresource_range(aspect_mask), usage)
+}
+
+/// View into `subresources` of an image created with `image_mip_levels` mips and `image_array_layers` layers,
+/// e.g. a single mip for mipmap generation or a subset of the layers of an array texture.
+pub fn create_subresource_image_view(
+    device: &ash::Device,
+    image: vk::Image,
+    view_type: vk::ImageViewType,
+    format: vk::Format,
+    aspect_mask: vk::ImageAspectFlags,
+    subresources: ImageSubresources,
+    image_mip_levels: u32,
+    image_array_layers: u32,
+) -> vk::ImageView {
+    if let Err(error) = subresources.validate(image_mip_levels, image_array_layers) {
+        panic!("Invalid image view range: {}", error);
+    }
+
+    create_image_view_for_range(device, image, view_type, format, subresources.range(aspect_mask), None)
+}
+
+fn create_image_view_for_range(
+    device: &ash::Device,
+    image: vk::Image,
+    view_type: vk::ImageViewType,
+    format: vk::Format,
+    subresource_range: vk::ImageSubresourceRange,
+    usage: Option<vk::ImageUsageFlags>,
+) -> vk::ImageView {
+    let usage_create_info = usage.map(|usage| vk::ImageViewUsageCreateInfo {
+        s_type: vk::StructureType::IMAGE_VIEW_USAGE_CREATE_INFO,
+        p_next: ptr::null(),
+        usage,
+    });
+
+    let image_view_create_info = vk::ImageViewCreateInfo {
+        s_type: vk::StructureType::IMAGE_VIEW_CREATE_INFO,
+        p_next: match &usage_create_info {
+            Some(usage_create_info) => usage_create_info as *const _ as *const c_void,
+            None => ptr::null(),
+        },
+        flags: vk::ImageViewCreateFlags::empty(),
+        view_type,
+        format,
+        components: vk::ComponentMapping {
+            r: vk::ComponentSwizzle::IDENTITY,
+            g: vk::ComponentSwizzle::IDENTITY,
+            b: vk::ComponentSwizzle::IDENTITY,
+            a: vk::ComponentSwizzle::IDENTITY,
+        },
+        subresource_range,
+        image,
+    };
+
+    unsafe {
+        device
+            .create_image_view(&image_view_create_info, None)
+            .expect("Failed to create Image View!")
+    }
+}
+
+pub fn has_stencil_component(format: vk::Format) -> bool {
+    format == vk::Format::D16_UNORM_S8_UINT
+        || format == vk::Format::D24_UNORM_S8_UINT
+        || format == vk::Format::D32_SFLOAT_S8_UINT
+        || format == vk::Format::S8_UINT
+}
+
+pub fn has_depth_component(format: vk::Format) -> bool {
+    format == vk::Format::D16_UNORM
+        || format == vk::Format::X8_D24_UNORM_PACK32
+        || format == vk::Format::D32_SFLOAT
+        || format == vk::Format::D16_UNORM_S8_UINT
+        || format == vk::Format::D24_UNORM_S8_UINT
+        || format == vk::Format::D32_SFLOAT_S8_UINT
+}
+
+/// First format of `candidates` that supports `features` with `tiling`.
+pub fn find_supported_format(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    candidates: &[vk::Format],
+    tiling: vk::ImageTiling,
+    features: vk::FormatFeatureFlags,
+) -> Option<vk::Format> {
+    candidates
+        .iter()
+        .copied()
+        .find(|&format| supports_format_feature(instance, physical_device, format, tiling, features))
+}
+
+pub fn supports_format_feature(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    format: vk::Format,
+    tiling: vk::ImageTiling,
+    features: vk::FormatFeatureFlags,
+) -> bool {
+    let format_properties = unsafe {
+        instance.get_physical_device_format_properties(physical_device, format)
+    };
+
+    match tiling {
+        vk::ImageTiling::LINEAR => format_properties.linear_tiling_features.contains(features),
+        vk::ImageTiling::OPTIMAL => format_properties.optimal_tiling_features.contains(features),
+        _ => false,
+    }
+}
+
+/// Format features an image needs for every one of its `usage` flags.
+pub fn format_features_for_usage(usage: vk::ImageUsageFlags) -> vk::FormatFeatureFlags {
+    let usage_features = [
+        (vk::ImageUsageFlags::SAMPLED, vk::FormatFeatureFlags::SAMPLED_IMAGE),
+        (vk::ImageUsageFlags::STORAGE, vk::FormatFeatureFlags::STORAGE_IMAGE),
+        (vk::ImageUsageFlags::COLOR_ATTACHMENT, vk::FormatFeatureFlags::COLOR_ATTACHMENT),
+        (vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT, vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT),
+        (vk::ImageUsageFlags::TRANSFER_SRC, vk::FormatFeatureFlags::TRANSFER_SRC),
+        (vk::ImageUsageFlags::TRANSFER_DST, vk::FormatFeatureFlags::TRANSFER_DST),
+    ];
+
+    usage_features
+        .iter()
+        .filter(|&&(usage_flag, _)| usage.contains(usage_flag))
+        .fold(vk::FormatFeatureFlags::empty(), |features, &(_, feature)| features | feature)
+}
+
+/// OPTIMAL unless `format` lacks a feature `usage` needs with it, LINEAR only as the fallback.
+/// LINEAR images are limited to a single mip and layer of a 2D image on most devices, keep that in mind for the fallback.
+pub fn choose_tiling(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+) -> vk::ImageTiling {
+    let features = format_features_for_usage(usage);
+
+    [vk::ImageTiling::OPTIMAL, vk::ImageTiling::LINEAR]
+        .iter()
+        .copied()
+        .find(|&tiling| supports_format_feature(instance, physical_device, format, tiling, features))
+        .unwrap_or_else(|| panic!("{:?} doesn't support {:?} with any tiling!", format, usage))
+}
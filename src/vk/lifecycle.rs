@@ -0,0 +1,76 @@
+use ash::vk::Handle;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Vulkan objects whose creation and destruction is counted, to spot leaks without validation layers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackedObject {
+    Swapchain,
+    Image,
+    Buffer,
+    Pipeline,
+}
+
+impl TrackedObject {
+    pub const ALL: [TrackedObject; 4] = [
+        TrackedObject::Swapchain,
+        TrackedObject::Image,
+        TrackedObject::Buffer,
+        TrackedObject::Pipeline,
+    ];
+
+    fn live_counter(self) -> &'static AtomicUsize {
+        match self {
+            TrackedObject::Swapchain => &LIVE_SWAPCHAINS,
+            TrackedObject::Image => &LIVE_IMAGES,
+            TrackedObject::Buffer => &LIVE_BUFFERS,
+            TrackedObject::Pipeline => &LIVE_PIPELINES,
+        }
+    }
+}
+
+static LIVE_SWAPCHAINS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_IMAGES: AtomicUsize = AtomicUsize::new(0);
+static LIVE_BUFFERS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_PIPELINES: AtomicUsize = AtomicUsize::new(0);
+
+/// Every create and destroy is logged at trace level, counting happens either way, it's only a relaxed atomic add.
+pub fn on_create<T: Handle>(object: TrackedObject, handle: T) {
+    let live_count = object.live_counter().fetch_add(1, Ordering::Relaxed) + 1;
+    log::trace!("create {:?} {:#x}, {} live", object, handle.as_raw(), live_count);
+}
+
+/// Null handles are ignored, destroying them is a no-op in Vulkan as well.
+pub fn on_destroy<T: Handle>(object: TrackedObject, handle: T) {
+    let raw_handle = handle.as_raw();
+    if raw_handle == 0 {
+        return;
+    }
+
+    // saturating so a destroy of an untracked object doesn't wrap the count around.
+    let previous_count = object
+        .live_counter()
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| Some(count.saturating_sub(1)))
+        .unwrap();
+    log::trace!("destroy {:?} {:#x}, {} live", object, raw_handle, previous_count.saturating_sub(1));
+}
+
+pub fn live_count(object: TrackedObject) -> usize {
+    object.live_counter().load(Ordering::Relaxed)
+}
+
+/// Warns about the tracked objects that are still alive, called once everything should have been destroyed.
+/// Returns whether anything leaked.
+pub fn report_leaks() -> bool {
+    let mut has_leaks = false;
+    for object in TrackedObject::ALL {
+        let count = live_count(object);
+        if count > 0 {
+            log::warn!("{} {:?} object(s) still alive on shutdown, likely leaked", count, object);
+            has_leaks = true;
+        }
+    }
+
+    has_leaks
+}
+
@@ -1,6 +1,43 @@
 pub mod render_device;
+pub mod render_context;
 pub mod debug;
 pub mod constants;
 pub mod swap_chain;
 pub mod platforms;
-pub mod vertex;
\ No newline at end of file
+pub mod vertex;
+pub mod capabilities;
+pub mod image;
+pub mod render_target;
+pub mod device_group;
+pub mod submit;
+pub mod buffer;
+pub mod depth_image;
+pub mod sparse_image;
+pub mod offscreen_framebuffer;
+pub mod commands;
+pub mod texture;
+pub mod compressed_texture;
+pub mod descriptor;
+pub mod sync;
+pub mod render_pass;
+pub mod pipeline;
+pub mod draw;
+pub mod acceleration_structure;
+pub mod ray_tracing;
+pub mod transform_feedback;
+pub mod sampler;
+pub mod deletion_queue;
+pub mod resource_manager;
+pub mod lifecycle;
+#[cfg(feature = "reflection")]
+pub mod reflection;
+#[cfg(feature = "hot_reload")]
+pub mod shader_reload;
+#[cfg(feature = "renderdoc")]
+pub mod renderdoc;
+#[cfg(feature = "debug-overlay")]
+pub mod debug_overlay;
+#[cfg(feature = "profiler")]
+pub mod profiler;
+#[cfg(test)]
+pub mod test_device;
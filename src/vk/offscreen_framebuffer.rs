@@ -0,0 +1,328 @@
+use ash::vk;
+use cgmath::{Matrix4, Rad};
+
+use std::ptr;
+
+use crate::vk::image;
+use crate::vk::lifecycle::{self, TrackedObject};
+use crate::vk::depth_image::{DepthMode, VkDepthImage};
+use crate::vk::render_pass::RenderPassBuilder;
+use crate::vk::sampler::{SamplerCache, SamplerConfig};
+
+/// Color format of an offscreen target, picked explicitly since it decides who applies the gamma curve.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OffscreenColorFormat {
+    /// `R8G8B8A8_SRGB`, the hardware encodes gamma on write and decodes it when sampling.
+    Srgb,
+    /// `R16G16B16A16_SFLOAT`, linear values above 1.0 survive, e.g. for bloom before tonemapping.
+    LinearHdr,
+    Custom(vk::Format),
+}
+
+impl OffscreenColorFormat {
+    pub fn format(&self) -> vk::Format {
+        match self {
+            OffscreenColorFormat::Srgb => vk::Format::R8G8B8A8_SRGB,
+            OffscreenColorFormat::LinearHdr => vk::Format::R16G16B16A16_SFLOAT,
+            OffscreenColorFormat::Custom(format) => *format,
+        }
+    }
+
+    /// Whether the format can be rendered to and then sampled with OPTIMAL tiling.
+    pub fn is_supported(&self, instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> bool {
+        image::supports_format_feature(
+            instance,
+            physical_device,
+            self.format(),
+            vk::ImageTiling::OPTIMAL,
+            // the shared sampler filters linearly.
+            vk::FormatFeatureFlags::COLOR_ATTACHMENT
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
+    }
+}
+
+/// Single color image (plus an optional depth image) rendered to by its own render pass,
+/// then sampled by later passes, e.g. shadow maps, reflections or HDR intermediates.
+pub struct OffscreenFramebuffer {
+    pub color_image: vk::Image,
+    pub color_image_memory: vk::DeviceMemory,
+    pub color_view: vk::ImageView,
+    pub color_format: vk::Format,
+    /// Array layers of the color and depth images, one per view of the multiview render pass if more than one.
+    pub view_count: u32,
+
+    pub depth: Option<VkDepthImage>,
+    /// Which way depth goes in the passes rendering to it, `Standard` by default. `begin` clears depth to
+    /// its `clear_depth`, pipelines and projections should come from `depth_compare_op` and `perspective`.
+    pub depth_mode: DepthMode,
+
+    pub render_pass: vk::RenderPass,
+    pub framebuffer: vk::Framebuffer,
+    /// Samples `color_view` once the render pass ended, owned by the `SamplerCache`.
+    pub sampler: vk::Sampler,
+
+    pub extent: vk::Extent2D,
+}
+
+impl OffscreenFramebuffer {
+    /// `depth_format` adds a depth attachment, `VkDepthImage::find_depth_format` picks a supported one.
+    /// Panics when `color_format` isn't `is_supported`, check it first to fall back to another format.
+    pub fn new(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &ash::Device,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        sampler_cache: &SamplerCache,
+        color_format: OffscreenColorFormat,
+        depth_format: Option<vk::Format>,
+        extent: vk::Extent2D,
+        api_version: u32,
+    ) -> OffscreenFramebuffer {
+        OffscreenFramebuffer::new_multiview(
+            instance,
+            physical_device,
+            device,
+            device_memory_properties,
+            sampler_cache,
+            color_format,
+            depth_format,
+            extent,
+            1,
+            api_version)
+    }
+
+    /// Same as `new`, with `view_count` > 1 the images get a layer per view and the render pass renders to all
+    /// of them in one go, e.g. both eyes of a stereo view or every shadow cascade. Needs `capabilities.multiview` then.
+    /// The views are sampled as a `sampler2DArray`. All views are correlated, split them up with `RenderPassBuilder` if that's wrong.
+    pub fn new_multiview(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &ash::Device,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        sampler_cache: &SamplerCache,
+        color_format: OffscreenColorFormat,
+        depth_format: Option<vk::Format>,
+        extent: vk::Extent2D,
+        view_count: u32,
+        api_version: u32,
+    ) -> OffscreenFramebuffer {
+        // every device with multiview supports at least 6 views, the mask can't hold more than 32.
+        assert!(
+            (1..=32).contains(&view_count),
+            "Offscreen framebuffers need 1 to 32 views, not {}", view_count);
+        assert!(
+            color_format.is_supported(instance, physical_device),
+            "{:?} can't be used as a sampled color attachment on this device", color_format);
+        let color_format = color_format.format();
+
+        let image_create_info = vk::ImageCreateInfo {
+            s_type: vk::StructureType::IMAGE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::ImageCreateFlags::empty(),
+            image_type: vk::ImageType::TYPE_2D,
+            format: color_format,
+            extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            mip_levels: 1,
+            array_layers: view_count,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            queue_family_index_count: 0,
+            p_queue_family_indices: ptr::null(),
+            initial_layout: vk::ImageLayout::UNDEFINED,
+        };
+
+        let (color_image, color_image_memory) = image::create_image(
+            device,
+            &image_create_info,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            device_memory_properties);
+
+        let view_type = if view_count > 1 {
+            vk::ImageViewType::TYPE_2D_ARRAY
+        } else {
+            vk::ImageViewType::TYPE_2D
+        };
+        let color_view = image::create_image_view(
+            device,
+            color_image,
+            view_type,
+            color_format,
+            vk::ImageAspectFlags::COLOR);
+
+        let depth = depth_format.map(|depth_format| {
+            VkDepthImage::new(instance, physical_device, device, device_memory_properties, depth_format, extent, false, view_count, api_version)
+        });
+
+        let render_pass = OffscreenFramebuffer::create_render_pass(device, color_format, depth_format, view_count);
+
+        let mut attachments = vec![color_view];
+        if let Some(depth) = &depth {
+            attachments.push(depth.image_view);
+        }
+
+        let framebuffer_create_info = vk::FramebufferCreateInfo {
+            s_type: vk::StructureType::FRAMEBUFFER_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::FramebufferCreateFlags::empty(),
+            render_pass,
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            width: extent.width,
+            height: extent.height,
+            layers: 1,
+        };
+
+        let framebuffer = unsafe {
+            device
+                .create_framebuffer(&framebuffer_create_info, None)
+                .expect("Failed to create Framebuffer!")
+        };
+
+        let sampler = sampler_cache.get_or_create(&SamplerConfig::linear(vk::SamplerAddressMode::CLAMP_TO_EDGE));
+
+        OffscreenFramebuffer {
+            color_image: color_image,
+            color_image_memory: color_image_memory,
+            color_view: color_view,
+            color_format: color_format,
+            view_count: view_count,
+
+            depth: depth,
+            depth_mode: DepthMode::default(),
+
+            render_pass: render_pass,
+            framebuffer: framebuffer,
+            sampler: sampler,
+
+            extent: extent,
+        }
+    }
+
+    /// For `GraphicsPipelineBuilder::depth_test` of the pipelines drawing into it, see `depth_mode`.
+    pub fn depth_compare_op(&self) -> vk::CompareOp {
+        self.depth_mode.compare_op()
+    }
+
+    /// Projection for `depth_mode` at the aspect ratio of `extent`.
+    pub fn perspective(&self, fovy: Rad<f32>, near: f32, far: f32) -> Matrix4<f32> {
+        let aspect = self.extent.width as f32 / self.extent.height as f32;
+        self.depth_mode.perspective(fovy, aspect, near, far)
+    }
+
+    /// Begins the render pass, clearing color to `clear_color` and depth to `depth_mode`'s `clear_depth`.
+    pub fn begin(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, clear_color: [f32; 4]) {
+        let mut clear_values = vec![vk::ClearValue {
+            color: vk::ClearColorValue { float32: clear_color },
+        }];
+        if self.depth.is_some() {
+            clear_values.push(vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth: self.depth_mode.clear_depth(), stencil: 0 },
+            });
+        }
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo {
+            s_type: vk::StructureType::RENDER_PASS_BEGIN_INFO,
+            p_next: ptr::null(),
+            render_pass: self.render_pass,
+            framebuffer: self.framebuffer,
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            },
+            clear_value_count: clear_values.len() as u32,
+            p_clear_values: clear_values.as_ptr(),
+        };
+
+        unsafe {
+            device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+        }
+    }
+
+    /// Ends the render pass, `color_view` is then in `SHADER_READ_ONLY_OPTIMAL` and ready to be sampled.
+    pub fn end(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            device.cmd_end_render_pass(command_buffer);
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_framebuffer(self.framebuffer, None);
+            device.destroy_render_pass(self.render_pass, None);
+
+            if let Some(depth) = &self.depth {
+                depth.destroy(device);
+            }
+
+            device.destroy_image_view(self.color_view, None);
+            lifecycle::on_destroy(TrackedObject::Image, self.color_image);
+            device.destroy_image(self.color_image, None);
+            device.free_memory(self.color_image_memory, None);
+        }
+    }
+
+    fn create_render_pass(
+        device: &ash::Device,
+        color_format: vk::Format,
+        depth_format: Option<vk::Format>,
+        view_count: u32,
+    ) -> vk::RenderPass {
+        let mut builder = RenderPassBuilder::new()
+            .color_attachment(
+                color_format,
+                vk::AttachmentLoadOp::CLEAR,
+                vk::AttachmentStoreOp::STORE,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        if let Some(depth_format) = depth_format {
+            builder = builder.depth_stencil_attachment(
+                depth_format,
+                vk::AttachmentLoadOp::CLEAR,
+                vk::AttachmentStoreOp::DONT_CARE,
+                vk::AttachmentLoadOp::CLEAR,
+                vk::AttachmentStoreOp::DONT_CARE,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+        }
+
+        if view_count > 1 {
+            let view_mask = u32::MAX >> (32 - view_count);
+            builder = builder.multiview(view_mask, view_mask);
+        }
+
+        // earlier reads of the color image and depth writes of the previous use have to finish
+        // before it is rendered to again, and later passes may only sample it once it has been written.
+        builder
+            .dependency(vk::SubpassDependency {
+                src_subpass: vk::SUBPASS_EXTERNAL,
+                dst_subpass: 0,
+                src_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                src_access_mask: vk::AccessFlags::SHADER_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                dependency_flags: vk::DependencyFlags::BY_REGION,
+            })
+            .dependency(vk::SubpassDependency {
+                src_subpass: 0,
+                dst_subpass: vk::SUBPASS_EXTERNAL,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
+                dependency_flags: vk::DependencyFlags::BY_REGION,
+            })
+            .build(device)
+    }
+}
@@ -0,0 +1,968 @@
+use ash::vk;
+
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::ptr;
+
+use std::os::raw::c_void;
+
+use crate::vk::capabilities::DeviceCapabilities;
+use crate::vk::depth_image::DepthMode;
+use crate::vk::lifecycle::{self, TrackedObject};
+#[cfg(feature = "reflection")]
+use crate::vk::reflection::{PipelineLayoutReflection, ReflectionError, ShaderReflection};
+
+/// Stencil test and update of one face, see `GraphicsPipelineBuilder::stencil_test`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StencilFaceConfig {
+    pub fail_op: vk::StencilOp,
+    pub pass_op: vk::StencilOp,
+    pub depth_fail_op: vk::StencilOp,
+    pub compare_op: vk::CompareOp,
+    pub compare_mask: u32,
+    pub write_mask: u32,
+    pub reference: u32,
+}
+
+impl StencilFaceConfig {
+    /// Writes `reference` wherever something is drawn, e.g. to mark an object for an outline.
+    pub fn replace(reference: u32) -> StencilFaceConfig {
+        StencilFaceConfig {
+            fail_op: vk::StencilOp::KEEP,
+            pass_op: vk::StencilOp::REPLACE,
+            depth_fail_op: vk::StencilOp::KEEP,
+            compare_op: vk::CompareOp::ALWAYS,
+            compare_mask: 0xff,
+            write_mask: 0xff,
+            reference,
+        }
+    }
+
+    /// Only draws where the stencil value isn't `reference` and leaves it untouched,
+    /// e.g. the outline around an object marked with `replace`.
+    pub fn not_equal(reference: u32) -> StencilFaceConfig {
+        StencilFaceConfig {
+            fail_op: vk::StencilOp::KEEP,
+            pass_op: vk::StencilOp::KEEP,
+            depth_fail_op: vk::StencilOp::KEEP,
+            compare_op: vk::CompareOp::NOT_EQUAL,
+            compare_mask: 0xff,
+            write_mask: 0x00,
+            reference,
+        }
+    }
+
+    pub fn to_vk(&self) -> vk::StencilOpState {
+        vk::StencilOpState {
+            fail_op: self.fail_op,
+            pass_op: self.pass_op,
+            depth_fail_op: self.depth_fail_op,
+            compare_op: self.compare_op,
+            compare_mask: self.compare_mask,
+            write_mask: self.write_mask,
+            reference: self.reference,
+        }
+    }
+}
+
+impl Default for StencilFaceConfig {
+    /// Passes everything and never writes, same as a disabled stencil test.
+    fn default() -> StencilFaceConfig {
+        StencilFaceConfig {
+            fail_op: vk::StencilOp::KEEP,
+            pass_op: vk::StencilOp::KEEP,
+            depth_fail_op: vk::StencilOp::KEEP,
+            compare_op: vk::CompareOp::ALWAYS,
+            compare_mask: 0,
+            write_mask: 0,
+            reference: 0,
+        }
+    }
+}
+
+/// Constant id to value map baked into a shader stage at pipeline creation, e.g. a light count or a feature toggle.
+/// SPIR-V bools, ints and floats are all 4 bytes wide, so every value is stored as its 32 bits.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpecializationConstants {
+    values: BTreeMap<u32, u32>,
+}
+
+impl SpecializationConstants {
+    pub fn new() -> SpecializationConstants {
+        SpecializationConstants::default()
+    }
+
+    pub fn set_u32(mut self, constant_id: u32, value: u32) -> SpecializationConstants {
+        self.values.insert(constant_id, value);
+        self
+    }
+
+    pub fn set_i32(self, constant_id: u32, value: i32) -> SpecializationConstants {
+        self.set_u32(constant_id, value as u32)
+    }
+
+    pub fn set_f32(self, constant_id: u32, value: f32) -> SpecializationConstants {
+        self.set_u32(constant_id, value.to_bits())
+    }
+
+    pub fn set_bool(self, constant_id: u32, value: bool) -> SpecializationConstants {
+        self.set_u32(constant_id, value as vk::Bool32)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Map entries and the data buffer they point into, ordered by constant id.
+    pub fn map_entries_and_data(&self) -> (Vec<vk::SpecializationMapEntry>, Vec<u8>) {
+        let value_size = std::mem::size_of::<u32>();
+
+        let map_entries = self.values
+            .keys()
+            .enumerate()
+            .map(|(index, &constant_id)| vk::SpecializationMapEntry {
+                constant_id,
+                offset: (index * value_size) as u32,
+                size: value_size,
+            })
+            .collect();
+        let data = self.values
+            .values()
+            .flat_map(|value| value.to_ne_bytes())
+            .collect();
+
+        (map_entries, data)
+    }
+}
+
+/// Strip and fan topologies, the only ones primitive restart is allowed with in core Vulkan.
+pub fn supports_primitive_restart(topology: vk::PrimitiveTopology) -> bool {
+    match topology {
+        vk::PrimitiveTopology::LINE_STRIP
+        | vk::PrimitiveTopology::TRIANGLE_STRIP
+        | vk::PrimitiveTopology::TRIANGLE_FAN
+        | vk::PrimitiveTopology::LINE_STRIP_WITH_ADJACENCY
+        | vk::PrimitiveTopology::TRIANGLE_STRIP_WITH_ADJACENCY => true,
+        _ => false,
+    }
+}
+
+/// Polygon offset, `constant_factor + slope_factor * max_depth_slope` clamped to `clamp`, added to each fragment's depth.
+/// Pushes shadow casters or decals away from the surface they would z-fight with.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DepthBias {
+    pub constant_factor: f32,
+    /// 0.0 disables clamping, anything else needs `capabilities.depth_bias_clamp`.
+    pub clamp: f32,
+    pub slope_factor: f32,
+}
+
+impl DepthBias {
+    /// Only for pipelines built with `GraphicsPipelineBuilder::dynamic_depth_bias`.
+    pub fn cmd_set(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            device.cmd_set_depth_bias(command_buffer, self.constant_factor, self.clamp, self.slope_factor);
+        }
+    }
+}
+
+/// Sets viewports from 0 on, for pipelines with `DynamicState::VIEWPORT`, as many as the pipeline was built with.
+pub fn cmd_set_viewports(device: &ash::Device, command_buffer: vk::CommandBuffer, viewports: &[vk::Viewport]) {
+    unsafe {
+        device.cmd_set_viewport(command_buffer, 0, viewports);
+    }
+}
+
+/// Same as `cmd_set_viewports` for `DynamicState::SCISSOR`.
+pub fn cmd_set_scissors(device: &ash::Device, command_buffer: vk::CommandBuffer, scissors: &[vk::Rect2D]) {
+    unsafe {
+        device.cmd_set_scissor(command_buffer, 0, scissors);
+    }
+}
+
+/// Only the pipeline or per draw rate counts, primitive and attachment rates are ignored.
+pub const FRAGMENT_SHADING_RATE_KEEP_COMBINER_OPS: [vk::FragmentShadingRateCombinerOpKHR; 2] = [
+    vk::FragmentShadingRateCombinerOpKHR::KEEP,
+    vk::FragmentShadingRateCombinerOpKHR::KEEP,
+];
+
+/// Which faces are discarded before rasterization.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CullMode {
+    None,
+    Front,
+    #[default]
+    Back,
+    FrontAndBack,
+}
+
+impl CullMode {
+    pub fn to_vk(&self) -> vk::CullModeFlags {
+        match *self {
+            CullMode::None => vk::CullModeFlags::NONE,
+            CullMode::Front => vk::CullModeFlags::FRONT,
+            CullMode::Back => vk::CullModeFlags::BACK,
+            CullMode::FrontAndBack => vk::CullModeFlags::FRONT_AND_BACK,
+        }
+    }
+}
+
+/// Winding order of front facing triangles in framebuffer space, has to match the convention of the mesh importer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FrontFace {
+    #[default]
+    Clockwise,
+    CounterClockwise,
+}
+
+impl FrontFace {
+    pub fn to_vk(&self) -> vk::FrontFace {
+        match *self {
+            FrontFace::Clockwise => vk::FrontFace::CLOCKWISE,
+            FrontFace::CounterClockwise => vk::FrontFace::COUNTER_CLOCKWISE,
+        }
+    }
+}
+
+/// Color blending of one attachment.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum BlendMode {
+    /// Overwrites the attachment, no blending.
+    #[default]
+    Opaque,
+    /// `src * src_alpha + dst * (1 - src_alpha)`, for straight alpha e.g. UI.
+    AlphaBlend,
+    /// `src * src_alpha + dst`, e.g. particles and glows.
+    Additive,
+    /// `src + dst * (1 - src_alpha)`, for colors already multiplied by their alpha.
+    PremultipliedAlpha,
+    Custom(vk::PipelineColorBlendAttachmentState),
+}
+
+impl BlendMode {
+    pub fn to_vk(&self) -> vk::PipelineColorBlendAttachmentState {
+        let blended = |src_color_blend_factor, dst_color_blend_factor| vk::PipelineColorBlendAttachmentState {
+            blend_enable: vk::TRUE,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+            src_color_blend_factor,
+            dst_color_blend_factor,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            alpha_blend_op: vk::BlendOp::ADD,
+        };
+
+        match *self {
+            BlendMode::Opaque => vk::PipelineColorBlendAttachmentState {
+                blend_enable: vk::FALSE,
+                color_write_mask: vk::ColorComponentFlags::RGBA,
+                src_color_blend_factor: vk::BlendFactor::ONE,
+                dst_color_blend_factor: vk::BlendFactor::ZERO,
+                color_blend_op: vk::BlendOp::ADD,
+                src_alpha_blend_factor: vk::BlendFactor::ONE,
+                dst_alpha_blend_factor: vk::BlendFactor::ZERO,
+                alpha_blend_op: vk::BlendOp::ADD,
+            },
+            BlendMode::AlphaBlend => blended(vk::BlendFactor::SRC_ALPHA, vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+            BlendMode::Additive => vk::PipelineColorBlendAttachmentState {
+                dst_alpha_blend_factor: vk::BlendFactor::ONE,
+                ..blended(vk::BlendFactor::SRC_ALPHA, vk::BlendFactor::ONE)
+            },
+            BlendMode::PremultipliedAlpha => blended(vk::BlendFactor::ONE, vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+            BlendMode::Custom(state) => state,
+        }
+    }
+}
+
+/// Fixed function state and shaders of a graphics pipeline with a static viewport covering `extent`, unless set through `viewports`.
+/// Defaults to back face culling, no depth or stencil test and a single opaque color attachment.
+pub struct GraphicsPipelineBuilder {
+    shader_stages: Vec<(vk::ShaderStageFlags, vk::ShaderModule)>,
+    /// Indexed like `shader_stages`.
+    specializations: Vec<SpecializationConstants>,
+    /// Of the stages added through `reflected_shader_stage`, each with the stage it's used in.
+    #[cfg(feature = "reflection")]
+    reflections: Vec<ShaderReflection>,
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    topology: vk::PrimitiveTopology,
+    primitive_restart_enable: bool,
+    /// Vertices per patch, `Some` only with tessellation shaders.
+    patch_control_points: Option<u32>,
+
+    /// Same count as `scissors`, more than one needs `capabilities.multi_viewport`.
+    viewports: Vec<vk::Viewport>,
+    scissors: Vec<vk::Rect2D>,
+    cull_mode: CullMode,
+    front_face: FrontFace,
+
+    depth_test_enable: bool,
+    depth_write_enable: bool,
+    depth_compare_op: vk::CompareOp,
+    /// Clip space depth from -1 to 1 like OpenGL instead of 0 to 1.
+    negative_one_to_one_depth: bool,
+    /// Front and back face, `None` disables the stencil test.
+    stencil: Option<(StencilFaceConfig, StencilFaceConfig)>,
+    depth_bias: Option<DepthBias>,
+    /// Fragment size shaded at once, `None` shades every pixel.
+    fragment_shading_rate: Option<vk::Extent2D>,
+
+    dynamic_states: Vec<vk::DynamicState>,
+    /// One per color attachment of the subpass.
+    blend_modes: Vec<BlendMode>,
+}
+
+impl GraphicsPipelineBuilder {
+    pub fn new(extent: vk::Extent2D) -> GraphicsPipelineBuilder {
+        GraphicsPipelineBuilder {
+            shader_stages: vec![],
+            specializations: vec![],
+            #[cfg(feature = "reflection")]
+            reflections: vec![],
+            vertex_bindings: vec![],
+            vertex_attributes: vec![],
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart_enable: false,
+            patch_control_points: None,
+
+            viewports: vec![vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: extent.width as f32,
+                height: extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }],
+            scissors: vec![vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: extent,
+            }],
+            cull_mode: CullMode::default(),
+            front_face: FrontFace::default(),
+
+            depth_test_enable: false,
+            depth_write_enable: false,
+            depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+            negative_one_to_one_depth: false,
+            stencil: None,
+            depth_bias: None,
+            fragment_shading_rate: None,
+
+            dynamic_states: vec![],
+            blend_modes: vec![BlendMode::Opaque],
+        }
+    }
+
+    /// `module` needs a `main` entry point, it has to stay alive until `build` returned.
+    pub fn shader_stage(self, stage: vk::ShaderStageFlags, module: vk::ShaderModule) -> GraphicsPipelineBuilder {
+        self.shader_stage_specialized(stage, module, SpecializationConstants::new())
+    }
+
+    /// Same as `shader_stage` with the spec constants of the module set to `constants`, missing ids keep their defaults.
+    pub fn shader_stage_specialized(
+        mut self,
+        stage: vk::ShaderStageFlags,
+        module: vk::ShaderModule,
+        constants: SpecializationConstants
+    ) -> GraphicsPipelineBuilder {
+        self.shader_stages.push((stage, module));
+        self.specializations.push(constants);
+        self
+    }
+
+    /// Same as `shader_stage`, `reflection` is what `reflection::reflect` returned for the module's code.
+    /// `reflected_layout` and `build_reflected` derive the pipeline layout from the reflected stages.
+    #[cfg(feature = "reflection")]
+    pub fn reflected_shader_stage(
+        mut self,
+        stage: vk::ShaderStageFlags,
+        module: vk::ShaderModule,
+        reflection: ShaderReflection
+    ) -> GraphicsPipelineBuilder {
+        self.reflections.push(ShaderReflection {
+            stage: stage,
+            ..reflection
+        });
+        self.shader_stage(stage, module)
+    }
+
+    /// Set layouts and push constant ranges every `reflected_shader_stage` declares, merged across the stages.
+    #[cfg(feature = "reflection")]
+    pub fn reflected_layout(&self) -> Result<PipelineLayoutReflection, ReflectionError> {
+        PipelineLayoutReflection::new(&self.reflections)
+    }
+
+    /// `build` with a pipeline layout created from `reflected_layout`. Returns the pipeline, its layout and
+    /// the set layouts indexed by set number, the caller owns and destroys all of them.
+    #[cfg(feature = "reflection")]
+    pub fn build_reflected(
+        &self,
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        subpass: u32
+    ) -> Result<(vk::Pipeline, vk::PipelineLayout, Vec<vk::DescriptorSetLayout>), ReflectionError> {
+        let (set_layouts, pipeline_layout) = self.reflected_layout()?.build(device);
+        let pipeline = self.build(device, pipeline_layout, render_pass, subpass);
+
+        Ok((pipeline, pipeline_layout, set_layouts))
+    }
+
+    /// Needs `capabilities.geometry_shader`.
+    pub fn geometry_shader(self, module: vk::ShaderModule) -> GraphicsPipelineBuilder {
+        self.shader_stage(vk::ShaderStageFlags::GEOMETRY, module)
+    }
+
+    /// Needs `capabilities.tessellation_shader`, switches the topology to patches of `patch_control_points` vertices.
+    pub fn tessellation_shaders(
+        mut self,
+        control_module: vk::ShaderModule,
+        evaluation_module: vk::ShaderModule,
+        patch_control_points: u32,
+    ) -> GraphicsPipelineBuilder {
+        // 32 is the smallest maxTessellationPatchSize any device has.
+        assert!(
+            (1..=32).contains(&patch_control_points),
+            "Patches need 1 to 32 control points, not {}",
+            patch_control_points
+        );
+
+        self.topology = vk::PrimitiveTopology::PATCH_LIST;
+        self.primitive_restart_enable = false;
+        self.patch_control_points = Some(patch_control_points);
+        self.shader_stage(vk::ShaderStageFlags::TESSELLATION_CONTROL, control_module)
+            .shader_stage(vk::ShaderStageFlags::TESSELLATION_EVALUATION, evaluation_module)
+    }
+
+    /// Needs `capabilities.task_shader`, only in front of a `mesh_shader`.
+    pub fn task_shader(self, module: vk::ShaderModule) -> GraphicsPipelineBuilder {
+        self.shader_stage(vk::ShaderStageFlags::TASK_EXT, module)
+    }
+
+    /// Needs `capabilities.mesh_shader`, replaces the vertex stages so vertex input and topology are ignored.
+    /// Draw with `VkRenderDevice::cmd_draw_mesh_tasks`.
+    pub fn mesh_shader(self, module: vk::ShaderModule) -> GraphicsPipelineBuilder {
+        self.shader_stage(vk::ShaderStageFlags::MESH_EXT, module)
+    }
+
+    pub fn is_mesh_pipeline(&self) -> bool {
+        self.shader_stages.iter().any(|&(stage, _)| stage == vk::ShaderStageFlags::MESH_EXT)
+    }
+
+    pub fn vertex_input(
+        mut self,
+        bindings: &[vk::VertexInputBindingDescription],
+        attributes: &[vk::VertexInputAttributeDescription]
+    ) -> GraphicsPipelineBuilder {
+        self.vertex_bindings = bindings.to_vec();
+        self.vertex_attributes = attributes.to_vec();
+        self
+    }
+
+    /// `primitive_restart` starts a new strip at the special index 0xFFFF / 0xFFFFFFFF
+    /// of indexed draws, only valid with strip and fan topologies.
+    pub fn topology(mut self, topology: vk::PrimitiveTopology, primitive_restart: bool) -> GraphicsPipelineBuilder {
+        assert!(
+            !primitive_restart || supports_primitive_restart(topology),
+            "Primitive restart can't be used with {:?}, only with strip and fan topologies",
+            topology
+        );
+
+        self.topology = topology;
+        self.primitive_restart_enable = primitive_restart;
+        self
+    }
+
+    /// One scissor per viewport, e.g. for split screen or one viewport per shadow cascade.
+    /// Primitives go to the viewport the last vertex stage writes to `gl_ViewportIndex`, the first one by default.
+    /// More than one needs `capabilities.multi_viewport` and at most `capabilities.max_viewports`.
+    /// With dynamic viewport or scissor state only the count matters, set them with `cmd_set_viewports` and `cmd_set_scissors`.
+    pub fn viewports(mut self, viewports: &[vk::Viewport], scissors: &[vk::Rect2D]) -> GraphicsPipelineBuilder {
+        assert!(!viewports.is_empty(), "Pipeline needs at least one viewport");
+        assert_eq!(
+            viewports.len(),
+            scissors.len(),
+            "Pipeline needs one scissor per viewport");
+
+        self.viewports = viewports.to_vec();
+        self.scissors = scissors.to_vec();
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: CullMode) -> GraphicsPipelineBuilder {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn front_face(mut self, front_face: FrontFace) -> GraphicsPipelineBuilder {
+        self.front_face = front_face;
+        self
+    }
+
+    /// Needs a depth attachment in the render pass.
+    pub fn depth_test(mut self, write_enable: bool, compare_op: vk::CompareOp) -> GraphicsPipelineBuilder {
+        self.depth_test_enable = true;
+        self.depth_write_enable = write_enable;
+        self.depth_compare_op = compare_op;
+        self
+    }
+
+    pub fn depth_test_enable(mut self, enable: bool) -> GraphicsPipelineBuilder {
+        self.depth_test_enable = enable;
+        self
+    }
+
+    /// Off while keeping the test on is what transparent geometry drawn after the opaque pass needs.
+    pub fn depth_write_enable(mut self, enable: bool) -> GraphicsPipelineBuilder {
+        self.depth_write_enable = enable;
+        self
+    }
+
+    pub fn depth_compare_op(mut self, compare_op: vk::CompareOp) -> GraphicsPipelineBuilder {
+        self.depth_compare_op = compare_op;
+        self
+    }
+
+    /// Depth test and write with GREATER_OR_EQUAL, for a projection mapping the near plane to 1.0.
+    /// The depth attachment has to be cleared to 0.0 then, e.g. by an `OffscreenFramebuffer` with `DepthMode::ReverseZ`.
+    pub fn reverse_z(self, write_enable: bool) -> GraphicsPipelineBuilder {
+        self.depth_mode(DepthMode::ReverseZ, write_enable)
+    }
+
+    /// Depth test with the compare op of `depth_mode`, which the projection and depth clear value have to match.
+    pub fn depth_mode(self, depth_mode: DepthMode, write_enable: bool) -> GraphicsPipelineBuilder {
+        self.depth_test(write_enable, depth_mode.compare_op())
+    }
+
+    /// Needs `capabilities.depth_clip_control`. Takes projections made for OpenGL, e.g. `cgmath::perspective`,
+    /// without losing the half of the depth range Vulkan clips away otherwise.
+    pub fn negative_one_to_one_depth(mut self) -> GraphicsPipelineBuilder {
+        self.negative_one_to_one_depth = true;
+        self
+    }
+
+    /// Needs a depth-stencil attachment with a stencil component in the render pass.
+    pub fn stencil_test(mut self, front: StencilFaceConfig, back: StencilFaceConfig) -> GraphicsPipelineBuilder {
+        self.stencil = Some((front, back));
+        self
+    }
+
+    /// Bias baked into the pipeline.
+    pub fn depth_bias(mut self, depth_bias: DepthBias) -> GraphicsPipelineBuilder {
+        self.depth_bias = Some(depth_bias);
+        self
+    }
+
+    /// Enables depth bias with the values set per draw through `DepthBias::cmd_set`,
+    /// which has to happen before the first draw with the pipeline.
+    pub fn dynamic_depth_bias(mut self) -> GraphicsPipelineBuilder {
+        self.depth_bias = Some(self.depth_bias.unwrap_or_default());
+        self.dynamic_state(vk::DynamicState::DEPTH_BIAS)
+    }
+
+    /// Needs `capabilities.fragment_shading_rate`, `fragment_size` should be one of
+    /// `VkRenderDevice::fragment_shading_rates`, e.g. 2x2 for distant or blurred geometry.
+    pub fn fragment_shading_rate(mut self, fragment_size: vk::Extent2D) -> GraphicsPipelineBuilder {
+        self.fragment_shading_rate = Some(fragment_size);
+        self
+    }
+
+    /// Shading rate set per draw through `VkRenderDevice::cmd_set_fragment_shading_rate`,
+    /// which has to happen before the first draw with the pipeline.
+    pub fn dynamic_fragment_shading_rate(mut self) -> GraphicsPipelineBuilder {
+        self.fragment_shading_rate = Some(self.fragment_shading_rate.unwrap_or(vk::Extent2D { width: 1, height: 1 }));
+        self.dynamic_state(vk::DynamicState::FRAGMENT_SHADING_RATE_KHR)
+    }
+
+    pub fn dynamic_state(mut self, dynamic_state: vk::DynamicState) -> GraphicsPipelineBuilder {
+        if !self.dynamic_states.contains(&dynamic_state) {
+            self.dynamic_states.push(dynamic_state);
+        }
+        self
+    }
+
+    /// Same blending for a subpass with a single color attachment.
+    pub fn blend_mode(mut self, blend_mode: BlendMode) -> GraphicsPipelineBuilder {
+        self.blend_modes = vec![blend_mode];
+        self
+    }
+
+    /// Blending per color attachment, in attachment order.
+    pub fn attachment_blend_modes(mut self, blend_modes: &[BlendMode]) -> GraphicsPipelineBuilder {
+        self.blend_modes = blend_modes.to_vec();
+        self
+    }
+
+    /// Device features the stages and state of the pipeline need, empty when `capabilities` covers all of them.
+    pub fn missing_features(&self, capabilities: &DeviceCapabilities) -> Vec<&'static str> {
+        let has_stage = |stage: vk::ShaderStageFlags| self.shader_stages.iter().any(|&(shader_stage, _)| shader_stage == stage);
+        let requirements = [
+            (has_stage(vk::ShaderStageFlags::GEOMETRY), capabilities.geometry_shader, "geometryShader"),
+            (self.patch_control_points.is_some(), capabilities.tessellation_shader, "tessellationShader"),
+            (has_stage(vk::ShaderStageFlags::MESH_EXT), capabilities.mesh_shader, "meshShader"),
+            (has_stage(vk::ShaderStageFlags::TASK_EXT), capabilities.task_shader, "taskShader"),
+            (self.fragment_shading_rate.is_some(), capabilities.fragment_shading_rate, "pipelineFragmentShadingRate"),
+            (self.depth_bias.map_or(false, |depth_bias| depth_bias.clamp != 0.0), capabilities.depth_bias_clamp, "depthBiasClamp"),
+            (self.viewports.len() > 1, capabilities.multi_viewport, "multiViewport"),
+            (self.negative_one_to_one_depth, capabilities.depth_clip_control, "depthClipControl"),
+            (self.viewports.len() > 1, self.viewports.len() as u32 <= capabilities.max_viewports, "maxViewports"),
+        ];
+
+        requirements
+            .iter()
+            .filter(|&&(is_used, is_enabled, _)| is_used && !is_enabled)
+            .map(|&(_, _, feature)| feature)
+            .collect()
+    }
+
+    pub fn depth_stencil_state(&self) -> vk::PipelineDepthStencilStateCreateInfo {
+        let (front, back) = self.stencil.unwrap_or_default();
+
+        vk::PipelineDepthStencilStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_DEPTH_STENCIL_STATE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineDepthStencilStateCreateFlags::empty(),
+            depth_test_enable: self.depth_test_enable as vk::Bool32,
+            depth_write_enable: self.depth_write_enable as vk::Bool32,
+            depth_compare_op: self.depth_compare_op,
+            depth_bounds_test_enable: vk::FALSE,
+            stencil_test_enable: self.stencil.is_some() as vk::Bool32,
+            front: front.to_vk(),
+            back: back.to_vk(),
+            max_depth_bounds: 1.0,
+            min_depth_bounds: 0.0,
+        }
+    }
+
+    /// `subpass` is the index of the `render_pass` subpass the pipeline is used in,
+    /// it needs one blend mode per color attachment of that subpass.
+    pub fn build(
+        &self,
+        device: &ash::Device,
+        pipeline_layout: vk::PipelineLayout,
+        render_pass: vk::RenderPass,
+        subpass: u32
+    ) -> vk::Pipeline {
+        let main_function_name = CString::new("main").unwrap();
+        let is_mesh_pipeline = self.is_mesh_pipeline();
+        assert!(
+            !is_mesh_pipeline || !self.shader_stages.iter().any(|&(stage, _)| stage == vk::ShaderStageFlags::VERTEX),
+            "Mesh shader pipelines can't have a vertex shader");
+
+        // the entries and data have to stay where they are until the pipeline is created,
+        // so they are all collected before any pointer into them is taken.
+        let specialization_data: Vec<(Vec<vk::SpecializationMapEntry>, Vec<u8>)> = self.specializations
+            .iter()
+            .map(|constants| constants.map_entries_and_data())
+            .collect();
+        let specialization_infos: Vec<vk::SpecializationInfo> = specialization_data
+            .iter()
+            .map(|(map_entries, data)| vk::SpecializationInfo {
+                map_entry_count: map_entries.len() as u32,
+                p_map_entries: map_entries.as_ptr(),
+                data_size: data.len(),
+                p_data: data.as_ptr() as *const c_void,
+            })
+            .collect();
+
+        let shader_stages: Vec<vk::PipelineShaderStageCreateInfo> = self.shader_stages
+            .iter()
+            .zip(self.specializations.iter().zip(specialization_infos.iter()))
+            .map(|(&(stage, module), (constants, specialization_info))| vk::PipelineShaderStageCreateInfo {
+                s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
+                p_next: ptr::null(),
+                flags: vk::PipelineShaderStageCreateFlags::empty(),
+                module,
+                p_name: main_function_name.as_ptr(),
+                p_specialization_info: if constants.is_empty() { ptr::null() } else { specialization_info },
+                stage,
+            })
+            .collect();
+
+        let vertex_input_state_create_info = vk::PipelineVertexInputStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_VERTEX_INPUT_STATE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineVertexInputStateCreateFlags::empty(),
+            vertex_attribute_description_count: self.vertex_attributes.len() as u32,
+            p_vertex_attribute_descriptions: self.vertex_attributes.as_ptr(),
+            vertex_binding_description_count: self.vertex_bindings.len() as u32,
+            p_vertex_binding_descriptions: self.vertex_bindings.as_ptr(),
+        };
+
+        let vertex_input_assembly_state_create_info = vk::PipelineInputAssemblyStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_INPUT_ASSEMBLY_STATE_CREATE_INFO,
+            flags: vk::PipelineInputAssemblyStateCreateFlags::empty(),
+            p_next: ptr::null(),
+            primitive_restart_enable: self.primitive_restart_enable as vk::Bool32,
+            topology: self.topology,
+        };
+
+        let tessellation_state_create_info = vk::PipelineTessellationStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_TESSELLATION_STATE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineTessellationStateCreateFlags::empty(),
+            patch_control_points: self.patch_control_points.unwrap_or(0),
+        };
+
+        let depth_clip_control_info = vk::PipelineViewportDepthClipControlCreateInfoEXT {
+            s_type: vk::StructureType::PIPELINE_VIEWPORT_DEPTH_CLIP_CONTROL_CREATE_INFO_EXT,
+            p_next: ptr::null(),
+            negative_one_to_one: vk::TRUE,
+        };
+        let viewport_state_create_info = vk::PipelineViewportStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_VIEWPORT_STATE_CREATE_INFO,
+            p_next: if self.negative_one_to_one_depth {
+                &depth_clip_control_info as *const _ as *const c_void
+            } else {
+                ptr::null()
+            },
+            flags: vk::PipelineViewportStateCreateFlags::empty(),
+            scissor_count: self.scissors.len() as u32,
+            p_scissors: self.scissors.as_ptr(),
+            viewport_count: self.viewports.len() as u32,
+            p_viewports: self.viewports.as_ptr(),
+        };
+
+        let depth_bias = self.depth_bias.unwrap_or_default();
+        let rasterization_state_create_info = vk::PipelineRasterizationStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_RASTERIZATION_STATE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineRasterizationStateCreateFlags::empty(),
+            depth_clamp_enable: vk::FALSE,
+            cull_mode: self.cull_mode.to_vk(),
+            front_face: self.front_face.to_vk(),
+            line_width: 1.0,
+            polygon_mode: vk::PolygonMode::FILL,
+            rasterizer_discard_enable: vk::FALSE,
+            depth_bias_clamp: depth_bias.clamp,
+            depth_bias_constant_factor: depth_bias.constant_factor,
+            depth_bias_enable: self.depth_bias.is_some() as vk::Bool32,
+            depth_bias_slope_factor: depth_bias.slope_factor,
+        };
+
+        let multisample_state_create_info = vk::PipelineMultisampleStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_MULTISAMPLE_STATE_CREATE_INFO,
+            flags: vk::PipelineMultisampleStateCreateFlags::empty(),
+            p_next: ptr::null(),
+            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+            sample_shading_enable: vk::FALSE,
+            min_sample_shading: 0.0,
+            p_sample_mask: ptr::null(),
+            alpha_to_one_enable: vk::FALSE,
+            alpha_to_coverage_enable: vk::FALSE,
+        };
+
+        let depth_stencil_state_create_info = self.depth_stencil_state();
+
+        let color_blend_attachment_states: Vec<vk::PipelineColorBlendAttachmentState> = self.blend_modes
+            .iter()
+            .map(|blend_mode| blend_mode.to_vk())
+            .collect();
+
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineColorBlendStateCreateFlags::empty(),
+            logic_op_enable: vk::FALSE,
+            logic_op: vk::LogicOp::COPY,
+            attachment_count: color_blend_attachment_states.len() as u32,
+            p_attachments: color_blend_attachment_states.as_ptr(),
+            blend_constants: [0.0, 0.0, 0.0, 0.0],
+        };
+
+        let dynamic_state_create_info = vk::PipelineDynamicStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_DYNAMIC_STATE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineDynamicStateCreateFlags::empty(),
+            dynamic_state_count: self.dynamic_states.len() as u32,
+            p_dynamic_states: self.dynamic_states.as_ptr(),
+        };
+
+        let fragment_shading_rate_state = self.fragment_shading_rate.map(|fragment_size| {
+            vk::PipelineFragmentShadingRateStateCreateInfoKHR {
+                s_type: vk::StructureType::PIPELINE_FRAGMENT_SHADING_RATE_STATE_CREATE_INFO_KHR,
+                p_next: ptr::null(),
+                fragment_size,
+                combiner_ops: FRAGMENT_SHADING_RATE_KEEP_COMBINER_OPS,
+            }
+        });
+
+        let graphic_pipeline_create_infos = [vk::GraphicsPipelineCreateInfo {
+            s_type: vk::StructureType::GRAPHICS_PIPELINE_CREATE_INFO,
+            p_next: match &fragment_shading_rate_state {
+                Some(state) => state as *const _ as *const c_void,
+                None => ptr::null(),
+            },
+            flags: vk::PipelineCreateFlags::empty(),
+            stage_count: shader_stages.len() as u32,
+            p_stages: shader_stages.as_ptr(),
+            // mesh shaders generate their own primitives.
+            p_vertex_input_state: if is_mesh_pipeline { ptr::null() } else { &vertex_input_state_create_info },
+            p_input_assembly_state: if is_mesh_pipeline { ptr::null() } else { &vertex_input_assembly_state_create_info },
+            p_tessellation_state: if self.patch_control_points.is_some() {
+                &tessellation_state_create_info
+            } else {
+                ptr::null()
+            },
+            p_viewport_state: &viewport_state_create_info,
+            p_rasterization_state: &rasterization_state_create_info,
+            p_multisample_state: &multisample_state_create_info,
+            p_depth_stencil_state: &depth_stencil_state_create_info,
+            p_color_blend_state: &color_blend_state,
+            p_dynamic_state: if self.dynamic_states.is_empty() {
+                ptr::null()
+            } else {
+                &dynamic_state_create_info
+            },
+            layout: pipeline_layout,
+            render_pass: render_pass,
+            subpass: subpass,
+            base_pipeline_handle: vk::Pipeline::null(),
+            base_pipeline_index: -1,
+        }];
+
+        let graphics_pipelines = unsafe {
+            device
+                .create_graphics_pipelines(vk::PipelineCache::null(), &graphic_pipeline_create_infos, None)
+                .expect("Failed to create graphics pipeline")
+        };
+        lifecycle::on_create(TrackedObject::Pipeline, graphics_pipelines[0]);
+
+        graphics_pipelines[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "reflection")]
+    #[test]
+    fn reflected_layout_merges_the_stages_they_are_used_in() {
+        use crate::vk::reflection::ReflectedBinding;
+
+        let camera = ShaderReflection {
+            stage: vk::ShaderStageFlags::ALL_GRAPHICS,
+            bindings: vec![ReflectedBinding {
+                set: 0,
+                binding: 0,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: 1,
+            }],
+            push_constant_size: Some(16),
+        };
+        let builder = GraphicsPipelineBuilder::new(vk::Extent2D { width: 1, height: 1 })
+            .reflected_shader_stage(vk::ShaderStageFlags::VERTEX, vk::ShaderModule::null(), camera.clone())
+            .reflected_shader_stage(vk::ShaderStageFlags::FRAGMENT, vk::ShaderModule::null(), camera);
+
+        let layout = builder.reflected_layout().unwrap();
+        assert_eq!(layout.set_layouts.len(), 1);
+        assert_eq!(layout.push_constant_ranges.len(), 1);
+        assert_eq!(layout.push_constant_ranges[0].stage_flags, vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+    }
+
+    #[test]
+    fn alpha_blend_preset_factors() {
+        let state = BlendMode::AlphaBlend.to_vk();
+
+        assert_eq!(state.blend_enable, vk::TRUE);
+        assert_eq!(state.color_write_mask, vk::ColorComponentFlags::RGBA);
+        assert_eq!(state.src_color_blend_factor, vk::BlendFactor::SRC_ALPHA);
+        assert_eq!(state.dst_color_blend_factor, vk::BlendFactor::ONE_MINUS_SRC_ALPHA);
+        assert_eq!(state.color_blend_op, vk::BlendOp::ADD);
+        assert_eq!(state.src_alpha_blend_factor, vk::BlendFactor::ONE);
+        assert_eq!(state.dst_alpha_blend_factor, vk::BlendFactor::ONE_MINUS_SRC_ALPHA);
+        assert_eq!(state.alpha_blend_op, vk::BlendOp::ADD);
+    }
+
+    #[test]
+    fn specialization_data_follows_constant_ids() {
+        let constants = SpecializationConstants::new()
+            .set_u32(3, 8)
+            .set_bool(1, true);
+        let (map_entries, data) = constants.map_entries_and_data();
+
+        assert_eq!(map_entries.len(), 2);
+        assert_eq!((map_entries[0].constant_id, map_entries[0].offset, map_entries[0].size), (1, 0, 4));
+        assert_eq!((map_entries[1].constant_id, map_entries[1].offset, map_entries[1].size), (3, 4, 4));
+        assert_eq!(data, [1_u32.to_ne_bytes(), 8_u32.to_ne_bytes()].concat());
+    }
+
+    #[test]
+    fn toggled_constant_only_changes_the_data() {
+        let (enabled_map_entries, enabled_data) = SpecializationConstants::new().set_bool(0, true).map_entries_and_data();
+        let (disabled_map_entries, disabled_data) = SpecializationConstants::new().set_bool(0, false).map_entries_and_data();
+
+        assert_eq!(enabled_map_entries.len(), 1);
+        assert_eq!(
+            (enabled_map_entries[0].constant_id, enabled_map_entries[0].offset, enabled_map_entries[0].size),
+            (disabled_map_entries[0].constant_id, disabled_map_entries[0].offset, disabled_map_entries[0].size));
+        assert_eq!(enabled_data, vk::TRUE.to_ne_bytes());
+        assert_eq!(disabled_data, vk::FALSE.to_ne_bytes());
+    }
+
+    #[test]
+    fn builder_defaults_to_opaque() {
+        let builder = GraphicsPipelineBuilder::new(vk::Extent2D { width: 1, height: 1 });
+
+        assert_eq!(builder.blend_modes.len(), 1);
+        assert_eq!(builder.blend_modes[0].to_vk().blend_enable, vk::FALSE);
+    }
+
+    #[test]
+    fn viewports_replace_extent_viewport() {
+        let extent = vk::Extent2D { width: 64, height: 32 };
+        let builder = GraphicsPipelineBuilder::new(extent);
+        assert_eq!(builder.viewports.len(), 1);
+        assert_eq!(builder.viewports[0].width, 64.0);
+        assert_eq!(builder.scissors[0].extent, extent);
+
+        let half = vk::Extent2D { width: 32, height: 32 };
+        let viewports = [
+            vk::Viewport { x: 0.0, y: 0.0, width: 32.0, height: 32.0, min_depth: 0.0, max_depth: 1.0 },
+            vk::Viewport { x: 32.0, y: 0.0, width: 32.0, height: 32.0, min_depth: 0.0, max_depth: 1.0 },
+        ];
+        let scissors = [
+            vk::Rect2D { offset: vk::Offset2D { x: 0, y: 0 }, extent: half },
+            vk::Rect2D { offset: vk::Offset2D { x: 32, y: 0 }, extent: half },
+        ];
+        let builder = builder.viewports(&viewports, &scissors);
+        assert_eq!(builder.viewports.len(), 2);
+        assert_eq!(builder.scissors[1].offset.x, 32);
+    }
+
+    #[test]
+    fn depth_toggles_set_depth_stencil_state() {
+        let extent = vk::Extent2D { width: 1, height: 1 };
+        let state = GraphicsPipelineBuilder::new(extent).depth_stencil_state();
+        assert_eq!(state.depth_test_enable, vk::FALSE);
+        assert_eq!(state.depth_write_enable, vk::FALSE);
+
+        let state = GraphicsPipelineBuilder::new(extent)
+            .depth_test(true, vk::CompareOp::LESS)
+            .depth_write_enable(false)
+            .depth_stencil_state();
+        assert_eq!(state.depth_test_enable, vk::TRUE);
+        assert_eq!(state.depth_write_enable, vk::FALSE);
+        assert_eq!(state.depth_compare_op, vk::CompareOp::LESS);
+
+        let state = GraphicsPipelineBuilder::new(extent).reverse_z(true).depth_stencil_state();
+        assert_eq!(state.depth_test_enable, vk::TRUE);
+        assert_eq!(state.depth_write_enable, vk::TRUE);
+        assert_eq!(state.depth_compare_op, vk::CompareOp::GREATER_OR_EQUAL);
+    }
+
+    #[test]
+    fn depth_mode_projection_maps_near_and_far_planes() {
+        let depth_at = |depth_mode: DepthMode, distance: f32| {
+            let projection = depth_mode.perspective(cgmath::Rad(1.0), 1.5, 0.1, 100.0);
+            let clip = projection * cgmath::Vector4::new(0.0, 0.0, -distance, 1.0);
+            clip.z / clip.w
+        };
+
+        assert!(depth_at(DepthMode::Standard, 0.1).abs() < 1e-5);
+        assert!((depth_at(DepthMode::Standard, 100.0) - 1.0).abs() < 1e-5);
+        assert!((depth_at(DepthMode::ReverseZ, 0.1) - 1.0).abs() < 1e-5);
+        assert!(depth_at(DepthMode::ReverseZ, 100.0).abs() < 1e-5);
+        assert_eq!(DepthMode::ReverseZ.clear_depth(), 0.0);
+    }
+}
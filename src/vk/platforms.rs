@@ -1,26 +1,191 @@
 use ash::vk;
 
-#[cfg(target_os = "windows")]
-use ash::extensions::khr::Win32Surface;
-use ash::extensions::ext::DebugUtils;
+use ash::extensions::khr::{Win32Surface, WaylandSurface, XcbSurface, XlibSurface};
+use ash::extensions::ext::{DebugUtils, HeadlessSurface};
 use ash::extensions::khr::Surface;
 
+use std::ffi::CStr;
+
 use crate::rhi::window;
 
-#[cfg(all(windows))]
-pub fn required_extension_names() -> Vec<*const i8> {
-    vec![
-        Surface::name().as_ptr(),
-        Win32Surface::name().as_ptr(),
-        DebugUtils::name().as_ptr(),
-    ]
+/// Window system a surface is created through, see `DeviceConfig::surface_backend`.
+/// Forcing one matters where several are available, e.g. X11 apps under XWayland or Xlib vs XCB on X11,
+/// when the default one causes present failures with some drivers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SurfaceBackend {
+    /// Whatever the window was opened with: Win32 on Windows, Wayland or else Xlib on Linux.
+    #[default]
+    Auto,
+    Win32,
+    Xlib,
+    /// X11 through the XCB connection behind the window's Xlib display.
+    Xcb,
+    Wayland,
+    /// `VK_EXT_headless_surface`, nothing is shown, see `VkRenderDevice::try_new_headless`.
+    Headless,
 }
 
-#[cfg(target_os = "windows")]
+impl SurfaceBackend {
+    /// `Auto` replaced by the backend of `window`, any other backend as it is.
+    pub fn resolve(self, window: &window::Window) -> SurfaceBackend {
+        if self != SurfaceBackend::Auto {
+            return self;
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            use winit::platform::unix::WindowExtUnix;
+
+            if window.window.wayland_surface().is_some() {
+                SurfaceBackend::Wayland
+            } else {
+                SurfaceBackend::Xlib
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = window;
+            SurfaceBackend::Win32
+        }
+    }
+
+    /// Instance extension of the backend, `None` for `Auto` which has to be resolved first.
+    pub fn extension_name(self) -> Option<&'static CStr> {
+        match self {
+            SurfaceBackend::Auto => None,
+            SurfaceBackend::Win32 => Some(Win32Surface::name()),
+            SurfaceBackend::Xlib => Some(XlibSurface::name()),
+            SurfaceBackend::Xcb => Some(XcbSurface::name()),
+            SurfaceBackend::Wayland => Some(WaylandSurface::name()),
+            SurfaceBackend::Headless => Some(HeadlessSurface::name()),
+        }
+    }
+}
+
+/// Instance extensions for presenting through `backend`, which has to be resolved already.
+pub fn required_extension_names(backend: SurfaceBackend) -> Vec<*const i8> {
+    let mut names = vec![Surface::name().as_ptr()];
+    if let Some(name) = backend.extension_name() {
+        names.push(name.as_ptr());
+    }
+    names.push(DebugUtils::name().as_ptr());
+    names
+}
+
+/// `backend` has to be resolved and its extension enabled on `instance`.
+/// `ERROR_EXTENSION_NOT_PRESENT` when the window doesn't have handles for `backend` on this platform,
+/// e.g. Wayland forced for a window opened through X11.
 pub unsafe fn create_surface(
     entry: &ash::Entry,
     instance: &ash::Instance,
     window: &window::Window,
+    backend: SurfaceBackend,
+) -> Result<vk::SurfaceKHR, vk::Result> {
+    match backend {
+        SurfaceBackend::Win32 => create_win32_surface(entry, instance, window),
+        SurfaceBackend::Xlib | SurfaceBackend::Xcb | SurfaceBackend::Wayland => {
+            create_unix_surface(entry, instance, window, backend)
+        }
+        SurfaceBackend::Headless => create_headless_surface(entry, instance),
+        SurfaceBackend::Auto => create_surface(entry, instance, window, backend.resolve(window)),
+    }
+}
+
+/// Surface of no window, for `SurfaceBackend::Headless`.
+///
+/// # Safety
+///
+/// The `SurfaceBackend::Headless` extension has to be enabled on `instance`.
+pub unsafe fn create_headless_surface(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+) -> Result<vk::SurfaceKHR, vk::Result> {
+    let headless_create_info = vk::HeadlessSurfaceCreateInfoEXT {
+        s_type: vk::StructureType::HEADLESS_SURFACE_CREATE_INFO_EXT,
+        p_next: std::ptr::null(),
+        flags: Default::default(),
+    };
+    HeadlessSurface::new(entry, instance).create_headless_surface(&headless_create_info, None)
+}
+
+#[cfg(not(target_os = "windows"))]
+unsafe fn create_win32_surface(
+    _entry: &ash::Entry,
+    _instance: &ash::Instance,
+    _window: &window::Window,
+) -> Result<vk::SurfaceKHR, vk::Result> {
+    Err(vk::Result::ERROR_EXTENSION_NOT_PRESENT)
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn create_unix_surface(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    window: &window::Window,
+    backend: SurfaceBackend,
+) -> Result<vk::SurfaceKHR, vk::Result> {
+    use std::ptr;
+    use winit::platform::unix::WindowExtUnix;
+
+    let window = &window.window;
+    match backend {
+        SurfaceBackend::Xlib => {
+            let (display, x11_window) = window.xlib_display().zip(window.xlib_window())
+                .ok_or(vk::Result::ERROR_EXTENSION_NOT_PRESENT)?;
+            let xlib_create_info = vk::XlibSurfaceCreateInfoKHR {
+                s_type: vk::StructureType::XLIB_SURFACE_CREATE_INFO_KHR,
+                p_next: ptr::null(),
+                flags: Default::default(),
+                dpy: display as *mut vk::Display,
+                window: x11_window as vk::Window,
+            };
+            XlibSurface::new(entry, instance).create_xlib_surface(&xlib_create_info, None)
+        }
+        SurfaceBackend::Xcb => {
+            let (connection, x11_window) = window.xcb_connection().zip(window.xlib_window())
+                .ok_or(vk::Result::ERROR_EXTENSION_NOT_PRESENT)?;
+            let xcb_create_info = vk::XcbSurfaceCreateInfoKHR {
+                s_type: vk::StructureType::XCB_SURFACE_CREATE_INFO_KHR,
+                p_next: ptr::null(),
+                flags: Default::default(),
+                connection: connection as *mut vk::xcb_connection_t,
+                window: x11_window as vk::xcb_window_t,
+            };
+            XcbSurface::new(entry, instance).create_xcb_surface(&xcb_create_info, None)
+        }
+        SurfaceBackend::Wayland => {
+            let (display, surface) = window.wayland_display().zip(window.wayland_surface())
+                .ok_or(vk::Result::ERROR_EXTENSION_NOT_PRESENT)?;
+            let wayland_create_info = vk::WaylandSurfaceCreateInfoKHR {
+                s_type: vk::StructureType::WAYLAND_SURFACE_CREATE_INFO_KHR,
+                p_next: ptr::null(),
+                flags: Default::default(),
+                display: display as *mut vk::wl_display,
+                surface: surface as *mut vk::wl_surface,
+            };
+            WaylandSurface::new(entry, instance).create_wayland_surface(&wayland_create_info, None)
+        }
+        SurfaceBackend::Win32 | SurfaceBackend::Headless | SurfaceBackend::Auto => {
+            Err(vk::Result::ERROR_EXTENSION_NOT_PRESENT)
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+unsafe fn create_unix_surface(
+    _entry: &ash::Entry,
+    _instance: &ash::Instance,
+    _window: &window::Window,
+    _backend: SurfaceBackend,
+) -> Result<vk::SurfaceKHR, vk::Result> {
+    Err(vk::Result::ERROR_EXTENSION_NOT_PRESENT)
+}
+
+#[cfg(target_os = "windows")]
+unsafe fn create_win32_surface(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    window: &window::Window,
 ) -> Result<vk::SurfaceKHR, vk::Result> {
     use std::os::raw::c_void;
     use std::ptr;
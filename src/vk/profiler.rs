@@ -0,0 +1,509 @@
+use ash::extensions::ext::DebugUtils;
+use ash::vk;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::ffi::CString;
+use std::path::Path;
+use std::ptr;
+use std::time::Instant;
+
+use crate::utility::constants::MAX_FRAMES_IN_FLIGHT;
+
+/// GPU scopes per frame, each takes two timestamp queries. Scopes past it are labeled and timed on the CPU only.
+pub const MAX_GPU_SCOPES: u32 = 64;
+/// Trace events kept for `ProfileData::to_chrome_trace`, the oldest are dropped first.
+pub const MAX_TRACE_EVENTS: usize = 64 * 1024;
+
+/// Where a scope was timed, CPU scopes and GPU scopes are separate threads in the Chrome trace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Timeline {
+    Cpu,
+    Gpu,
+}
+
+impl Timeline {
+    fn name(&self) -> &'static str {
+        match self {
+            Timeline::Cpu => "cpu",
+            Timeline::Gpu => "gpu",
+        }
+    }
+
+    fn thread_id(&self) -> u32 {
+        match self {
+            Timeline::Cpu => 1,
+            Timeline::Gpu => 2,
+        }
+    }
+}
+
+/// Durations of one named scope over every frame it was recorded in, in microseconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScopeStats {
+    pub count: u64,
+    pub total_us: f64,
+    pub min_us: f64,
+    pub max_us: f64,
+    pub last_us: f64,
+}
+
+impl ScopeStats {
+    fn new(duration_us: f64) -> ScopeStats {
+        ScopeStats {
+            count: 1,
+            total_us: duration_us,
+            min_us: duration_us,
+            max_us: duration_us,
+            last_us: duration_us,
+        }
+    }
+
+    fn add(&mut self, duration_us: f64) {
+        self.count += 1;
+        self.total_us += duration_us;
+        self.min_us = self.min_us.min(duration_us);
+        self.max_us = self.max_us.max(duration_us);
+        self.last_us = duration_us;
+    }
+
+    pub fn mean_us(&self) -> f64 {
+        self.total_us / self.count as f64
+    }
+}
+
+/// One timed scope, `start_us` is relative to when the `ProfileData` was created.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEvent {
+    pub name: String,
+    pub timeline: Timeline,
+    pub start_us: f64,
+    pub duration_us: f64,
+}
+
+/// Scope timings aggregated across frames, plus the latest `MAX_TRACE_EVENTS` of them for a trace.
+pub struct ProfileData {
+    stats: BTreeMap<(Timeline, String), ScopeStats>,
+    events: VecDeque<TraceEvent>,
+}
+
+impl ProfileData {
+    pub fn new() -> ProfileData {
+        ProfileData {
+            stats: BTreeMap::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, name: &str, timeline: Timeline, start_us: f64, duration_us: f64) {
+        match self.stats.get_mut(&(timeline, name.to_string())) {
+            Some(stats) => stats.add(duration_us),
+            None => {
+                self.stats.insert((timeline, name.to_string()), ScopeStats::new(duration_us));
+            }
+        }
+
+        if self.events.len() == MAX_TRACE_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(TraceEvent {
+            name: name.to_string(),
+            timeline: timeline,
+            start_us: start_us,
+            duration_us: duration_us,
+        });
+    }
+
+    pub fn stats(&self, name: &str, timeline: Timeline) -> Option<&ScopeStats> {
+        self.stats.get(&(timeline, name.to_string()))
+    }
+
+    pub fn events(&self) -> &VecDeque<TraceEvent> {
+        &self.events
+    }
+
+    pub fn clear(&mut self) {
+        self.stats.clear();
+        self.events.clear();
+    }
+
+    /// `{"scopes": [...]}` with count, mean, min, max and last duration of every scope, CPU scopes first.
+    pub fn to_json(&self) -> String {
+        let scopes: Vec<String> = self.stats
+            .iter()
+            .map(|((timeline, name), stats)| format!(
+                "{{\"name\":{},\"timeline\":\"{}\",\"count\":{},\"mean_us\":{:.3},\"min_us\":{:.3},\"max_us\":{:.3},\"last_us\":{:.3}}}",
+                json_string(name),
+                timeline.name(),
+                stats.count,
+                stats.mean_us(),
+                stats.min_us,
+                stats.max_us,
+                stats.last_us))
+            .collect();
+
+        format!("{{\"scopes\":[{}]}}", scopes.join(","))
+    }
+
+    /// Trace Event Format as loaded by `chrome://tracing` and Perfetto, one complete event per recorded scope.
+    pub fn to_chrome_trace(&self) -> String {
+        let mut trace_events: Vec<String> = [Timeline::Cpu, Timeline::Gpu]
+            .iter()
+            .map(|timeline| format!(
+                "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{},\"args\":{{\"name\":\"{}\"}}}}",
+                timeline.thread_id(),
+                timeline.name().to_uppercase()))
+            .collect();
+
+        trace_events.extend(self.events.iter().map(|event| format!(
+            "{{\"name\":{},\"cat\":\"{}\",\"ph\":\"X\",\"pid\":1,\"tid\":{},\"ts\":{:.3},\"dur\":{:.3}}}",
+            json_string(&event.name),
+            event.timeline.name(),
+            event.timeline.thread_id(),
+            event.start_us,
+            event.duration_us)));
+
+        format!("{{\"displayTimeUnit\":\"ms\",\"traceEvents\":[{}]}}", trace_events.join(","))
+    }
+
+    pub fn write_json(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_json())
+    }
+
+    pub fn write_chrome_trace(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_chrome_trace())
+    }
+}
+
+impl Default for ProfileData {
+    fn default() -> ProfileData {
+        ProfileData::new()
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for character in value.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            character if (character as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", character as u32)),
+            character => escaped.push(character),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+struct RecordedScope {
+    name: String,
+    /// First of the scope's two timestamp queries, `None` when the frame ran out of them.
+    query: Option<u32>,
+    cpu_begin: Instant,
+}
+
+struct FrameQueries {
+    query_pool: vk::QueryPool,
+    scopes: Vec<RecordedScope>,
+    next_query: u32,
+    /// CPU time of the submit, what the frame's GPU scopes are placed after in the trace.
+    submitted_at: Option<Instant>,
+}
+
+/// Named scopes timed on the GPU with timestamp queries and on the CPU while recording, aggregated into
+/// `ProfileData`. Every scope is also a debug utils label, so wrapping each render pass in `begin_scope` and
+/// `end_scope` names it in RenderDoc and the validation messages too.
+/// There is one query pool per frame in flight, a frame's results are read back by the `begin_frame` of the
+/// next frame using the same slot, after its fence was waited on. The scopes have to be recorded every frame,
+/// into command buffers that are re-recorded, since the queries are reset at each `begin_frame`.
+/// Without timestamp support on the queue only the CPU side and the labels are recorded.
+/// Keeps a clone of the device which has to outlive it, the query pools are destroyed on drop.
+pub struct Profiler {
+    device: ash::Device,
+    debug_utils: Option<DebugUtils>,
+    /// Nanoseconds per timestamp tick, `limits.timestampPeriod`.
+    timestamp_period: f32,
+    timestamp_mask: u64,
+
+    frames: Vec<FrameQueries>,
+    current_frame: Option<usize>,
+    open_scopes: Vec<usize>,
+
+    start: Instant,
+    data: ProfileData,
+}
+
+impl Profiler {
+    /// `timestamp_valid_bits` of the queue family the scopes are recorded for, no GPU timings when it's 0,
+    /// see `VkRenderDevice::timestamp_valid_bits`. `debug_utils` adds the labels.
+    pub fn new(
+        device: &ash::Device,
+        debug_utils: Option<&DebugUtils>,
+        timestamp_period: f32,
+        timestamp_valid_bits: u32,
+    ) -> Profiler {
+        let has_timestamps = timestamp_valid_bits > 0 && timestamp_period > 0.0;
+
+        let frames = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|_| FrameQueries {
+                query_pool: if has_timestamps {
+                    Profiler::create_query_pool(device)
+                } else {
+                    vk::QueryPool::null()
+                },
+                scopes: vec![],
+                next_query: 0,
+                submitted_at: None,
+            })
+            .collect();
+
+        Profiler {
+            device: device.clone(),
+            debug_utils: debug_utils.cloned(),
+            timestamp_period: timestamp_period,
+            timestamp_mask: if timestamp_valid_bits >= 64 { u64::MAX } else { (1u64 << timestamp_valid_bits) - 1 },
+
+            frames: frames,
+            current_frame: None,
+            open_scopes: vec![],
+
+            start: Instant::now(),
+            data: ProfileData::new(),
+        }
+    }
+
+    pub fn has_gpu_timings(&self) -> bool {
+        self.frames[0].query_pool != vk::QueryPool::null()
+    }
+
+    pub fn data(&self) -> &ProfileData {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut ProfileData {
+        &mut self.data
+    }
+
+    /// Reads back what `frame` recorded last time and resets its queries, recorded into `command_buffer`
+    /// before any scope and outside of a render pass. `frame` is the frame in flight slot, `AcquiredFrame::frame`,
+    /// and its fence has to be waited on already.
+    pub fn begin_frame(&mut self, command_buffer: vk::CommandBuffer, frame: usize) {
+        assert!(self.open_scopes.is_empty(), "begin_frame with scopes still open, every begin_scope needs an end_scope!");
+
+        self.collect(frame);
+
+        let query_pool = self.frames[frame].query_pool;
+        if query_pool != vk::QueryPool::null() {
+            unsafe {
+                self.device.cmd_reset_query_pool(command_buffer, query_pool, 0, MAX_GPU_SCOPES * 2);
+            }
+        }
+        self.current_frame = Some(frame);
+    }
+
+    /// Opens a scope, they nest and are closed in reverse order by `end_scope`.
+    pub fn begin_scope(&mut self, command_buffer: vk::CommandBuffer, name: &str) {
+        let frame_index = self.current_frame.expect("begin_scope before begin_frame!");
+        let frame = &mut self.frames[frame_index];
+
+        let query = if frame.query_pool != vk::QueryPool::null() && frame.next_query < MAX_GPU_SCOPES * 2 {
+            let query = frame.next_query;
+            frame.next_query += 2;
+            unsafe {
+                self.device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    frame.query_pool,
+                    query);
+            }
+            Some(query)
+        } else {
+            None
+        };
+
+        if let Some(debug_utils) = &self.debug_utils {
+            let label_name = CString::new(name).unwrap_or_default();
+            let label = vk::DebugUtilsLabelEXT {
+                s_type: vk::StructureType::DEBUG_UTILS_LABEL_EXT,
+                p_next: ptr::null(),
+                p_label_name: label_name.as_ptr(),
+                color: [0.0; 4],
+            };
+            unsafe {
+                debug_utils.cmd_begin_debug_utils_label(command_buffer, &label);
+            }
+        }
+
+        frame.scopes.push(RecordedScope {
+            name: name.to_string(),
+            query: query,
+            cpu_begin: Instant::now(),
+        });
+        self.open_scopes.push(frame.scopes.len() - 1);
+    }
+
+    /// Closes the innermost open scope, the CPU part of it is recorded right away.
+    pub fn end_scope(&mut self, command_buffer: vk::CommandBuffer) {
+        let frame_index = self.current_frame.expect("end_scope before begin_frame!");
+        let scope_index = self.open_scopes.pop().expect("end_scope without an open scope!");
+        let frame = &self.frames[frame_index];
+        let scope = &frame.scopes[scope_index];
+
+        if let Some(query) = scope.query {
+            unsafe {
+                self.device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    frame.query_pool,
+                    query + 1);
+            }
+        }
+        if let Some(debug_utils) = &self.debug_utils {
+            unsafe {
+                debug_utils.cmd_end_debug_utils_label(command_buffer);
+            }
+        }
+
+        let start_us = self.elapsed_us(scope.cpu_begin);
+        let duration_us = scope.cpu_begin.elapsed().as_secs_f64() * 1_000_000.0;
+        let name = scope.name.clone();
+        self.data.record(&name, Timeline::Cpu, start_us, duration_us);
+    }
+
+    /// CPU only scope from `start` until now, e.g. around culling or the submit itself.
+    pub fn record_cpu_scope(&mut self, name: &str, start: Instant) {
+        let start_us = self.elapsed_us(start);
+        let duration_us = start.elapsed().as_secs_f64() * 1_000_000.0;
+        self.data.record(name, Timeline::Cpu, start_us, duration_us);
+    }
+
+    /// Has to be called right after submitting the frame's command buffers, only submitted frames are read back.
+    pub fn mark_submitted(&mut self) {
+        let frame_index = self.current_frame.take().expect("mark_submitted before begin_frame!");
+        assert!(self.open_scopes.is_empty(), "Frame submitted with scopes still open!");
+
+        self.frames[frame_index].submitted_at = Some(Instant::now());
+    }
+
+    /// GPU durations of the frame's scopes, placed on the trace relative to the CPU submit, offset by how far
+    /// each scope began after the frame's first one. The gap between submit and the GPU starting isn't measured.
+    fn collect(&mut self, frame_index: usize) {
+        let frame = &mut self.frames[frame_index];
+        let scopes = std::mem::take(&mut frame.scopes);
+        let query_count = frame.next_query;
+        frame.next_query = 0;
+
+        let submitted_at = match frame.submitted_at.take() {
+            Some(submitted_at) => submitted_at,
+            None => return,
+        };
+        if query_count == 0 {
+            return;
+        }
+
+        let mut timestamps = vec![0u64; query_count as usize];
+        let result = unsafe {
+            self.device.get_query_pool_results(
+                frame.query_pool,
+                0,
+                query_count,
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT)
+        };
+        if let Err(error) = result {
+            log::error!("Failed to read profiler timestamps: {:?}", error);
+            return;
+        }
+
+        let ticks_to_us = self.timestamp_period as f64 / 1000.0;
+        let mask = self.timestamp_mask;
+        let first_timestamp = timestamps[0] & mask;
+        let submitted_us = submitted_at.duration_since(self.start).as_secs_f64() * 1_000_000.0;
+
+        for scope in scopes.iter() {
+            let query = match scope.query {
+                Some(query) => query as usize,
+                None => continue,
+            };
+            let begin = timestamps[query] & mask;
+            let end = timestamps[query + 1] & mask;
+
+            let start_us = submitted_us + begin.wrapping_sub(first_timestamp) as f64 * ticks_to_us;
+            let duration_us = end.saturating_sub(begin) as f64 * ticks_to_us;
+            self.data.record(&scope.name, Timeline::Gpu, start_us, duration_us);
+        }
+    }
+
+    fn elapsed_us(&self, instant: Instant) -> f64 {
+        instant.saturating_duration_since(self.start).as_secs_f64() * 1_000_000.0
+    }
+
+    fn create_query_pool(device: &ash::Device) -> vk::QueryPool {
+        let query_pool_create_info = vk::QueryPoolCreateInfo {
+            s_type: vk::StructureType::QUERY_POOL_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::QueryPoolCreateFlags::empty(),
+            query_type: vk::QueryType::TIMESTAMP,
+            query_count: MAX_GPU_SCOPES * 2,
+            pipeline_statistics: vk::QueryPipelineStatisticFlags::empty(),
+        };
+
+        unsafe {
+            device
+                .create_query_pool(&query_pool_create_info, None)
+                .expect("Failed to create timestamp Query Pool!")
+        }
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        for frame in self.frames.iter() {
+            if frame.query_pool != vk::QueryPool::null() {
+                unsafe {
+                    self.device.destroy_query_pool(frame.query_pool, None);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_aggregates_per_scope_and_timeline() {
+        let mut data = ProfileData::new();
+        data.record("shadows", Timeline::Gpu, 0.0, 2.0);
+        data.record("shadows", Timeline::Gpu, 10.0, 4.0);
+        data.record("shadows", Timeline::Cpu, 0.0, 1.0);
+
+        let gpu_stats = data.stats("shadows", Timeline::Gpu).unwrap();
+        assert_eq!(gpu_stats.count, 2);
+        assert_eq!(gpu_stats.mean_us(), 3.0);
+        assert_eq!(gpu_stats.min_us, 2.0);
+        assert_eq!(gpu_stats.max_us, 4.0);
+        assert_eq!(gpu_stats.last_us, 4.0);
+        assert_eq!(data.stats("shadows", Timeline::Cpu).unwrap().count, 1);
+        assert_eq!(data.events().len(), 3);
+    }
+
+    #[test]
+    fn chrome_trace_has_one_complete_event_per_scope() {
+        let mut data = ProfileData::new();
+        data.record("main \"pass\"", Timeline::Gpu, 1.5, 2.25);
+
+        assert_eq!(
+            data.to_chrome_trace(),
+            concat!(
+                "{\"displayTimeUnit\":\"ms\",\"traceEvents\":[",
+                "{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":1,\"args\":{\"name\":\"CPU\"}},",
+                "{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":2,\"args\":{\"name\":\"GPU\"}},",
+                "{\"name\":\"main \\\"pass\\\"\",\"cat\":\"gpu\",\"ph\":\"X\",\"pid\":1,\"tid\":2,\"ts\":1.500,\"dur\":2.250}",
+                "]}"));
+    }
+}
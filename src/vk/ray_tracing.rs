@@ -0,0 +1,259 @@
+use ash::vk;
+
+use std::ffi::CString;
+use std::ptr;
+
+use crate::vk::buffer::{buffer_device_address, MappedBuffer};
+use crate::vk::lifecycle::{self, TrackedObject};
+
+/// Ray tracing pipeline with one raygen shader, needs `capabilities.ray_tracing`.
+/// Shader groups are ordered raygen, misses, then hits, which is the order `ShaderBindingTable` expects.
+pub struct RayTracingPipelineBuilder {
+    raygen: Option<vk::ShaderModule>,
+    misses: Vec<vk::ShaderModule>,
+    closest_hits: Vec<vk::ShaderModule>,
+    max_recursion_depth: u32,
+}
+
+impl RayTracingPipelineBuilder {
+    pub fn new() -> RayTracingPipelineBuilder {
+        RayTracingPipelineBuilder {
+            raygen: None,
+            misses: vec![],
+            closest_hits: vec![],
+            max_recursion_depth: 1,
+        }
+    }
+
+    /// Every `module` needs a `main` entry point, it has to stay alive until `build` returned.
+    pub fn raygen(mut self, module: vk::ShaderModule) -> RayTracingPipelineBuilder {
+        self.raygen = Some(module);
+        self
+    }
+
+    pub fn miss(mut self, module: vk::ShaderModule) -> RayTracingPipelineBuilder {
+        self.misses.push(module);
+        self
+    }
+
+    /// One triangles hit group per closest hit shader.
+    pub fn closest_hit(mut self, module: vk::ShaderModule) -> RayTracingPipelineBuilder {
+        self.closest_hits.push(module);
+        self
+    }
+
+    /// Has to stay within `max_ray_recursion_depth` of the device.
+    pub fn max_recursion_depth(mut self, depth: u32) -> RayTracingPipelineBuilder {
+        self.max_recursion_depth = depth;
+        self
+    }
+
+    pub fn miss_count(&self) -> u32 {
+        self.misses.len() as u32
+    }
+
+    pub fn hit_count(&self) -> u32 {
+        self.closest_hits.len() as u32
+    }
+
+    pub fn build(
+        &self,
+        loader: &ash::extensions::khr::RayTracingPipeline,
+        pipeline_layout: vk::PipelineLayout,
+    ) -> vk::Pipeline {
+        let raygen = self.raygen.expect("Ray tracing pipeline needs a raygen shader!");
+        let main_function_name = CString::new("main").unwrap();
+
+        let stages: Vec<(vk::ShaderStageFlags, vk::ShaderModule)> = std::iter::once((vk::ShaderStageFlags::RAYGEN_KHR, raygen))
+            .chain(self.misses.iter().map(|&module| (vk::ShaderStageFlags::MISS_KHR, module)))
+            .chain(self.closest_hits.iter().map(|&module| (vk::ShaderStageFlags::CLOSEST_HIT_KHR, module)))
+            .collect();
+
+        let shader_stages: Vec<vk::PipelineShaderStageCreateInfo> = stages
+            .iter()
+            .map(|&(stage, module)| vk::PipelineShaderStageCreateInfo {
+                s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
+                p_next: ptr::null(),
+                flags: vk::PipelineShaderStageCreateFlags::empty(),
+                module,
+                p_name: main_function_name.as_ptr(),
+                p_specialization_info: ptr::null(),
+                stage,
+            })
+            .collect();
+
+        // raygen and miss shaders are general groups, closest hits get a triangles group each.
+        let shader_groups: Vec<vk::RayTracingShaderGroupCreateInfoKHR> = stages
+            .iter()
+            .enumerate()
+            .map(|(index, &(stage, _))| {
+                let is_hit = stage == vk::ShaderStageFlags::CLOSEST_HIT_KHR;
+
+                vk::RayTracingShaderGroupCreateInfoKHR {
+                    s_type: vk::StructureType::RAY_TRACING_SHADER_GROUP_CREATE_INFO_KHR,
+                    p_next: ptr::null(),
+                    ty: if is_hit {
+                        vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP
+                    } else {
+                        vk::RayTracingShaderGroupTypeKHR::GENERAL
+                    },
+                    general_shader: if is_hit { vk::SHADER_UNUSED_KHR } else { index as u32 },
+                    closest_hit_shader: if is_hit { index as u32 } else { vk::SHADER_UNUSED_KHR },
+                    any_hit_shader: vk::SHADER_UNUSED_KHR,
+                    intersection_shader: vk::SHADER_UNUSED_KHR,
+                    p_shader_group_capture_replay_handle: ptr::null(),
+                }
+            })
+            .collect();
+
+        let pipeline_create_info = vk::RayTracingPipelineCreateInfoKHR {
+            s_type: vk::StructureType::RAY_TRACING_PIPELINE_CREATE_INFO_KHR,
+            p_next: ptr::null(),
+            flags: vk::PipelineCreateFlags::empty(),
+            stage_count: shader_stages.len() as u32,
+            p_stages: shader_stages.as_ptr(),
+            group_count: shader_groups.len() as u32,
+            p_groups: shader_groups.as_ptr(),
+            max_pipeline_ray_recursion_depth: self.max_recursion_depth,
+            p_library_info: ptr::null(),
+            p_library_interface: ptr::null(),
+            p_dynamic_state: ptr::null(),
+            layout: pipeline_layout,
+            base_pipeline_handle: vk::Pipeline::null(),
+            base_pipeline_index: -1,
+        };
+
+        let pipelines = unsafe {
+            loader
+                .create_ray_tracing_pipelines(
+                    vk::DeferredOperationKHR::null(),
+                    vk::PipelineCache::null(),
+                    &[pipeline_create_info],
+                    None,
+                )
+                .expect("Failed to create Ray Tracing Pipeline!")
+        };
+        lifecycle::on_create(TrackedObject::Pipeline, pipelines[0]);
+
+        pipelines[0]
+    }
+}
+
+impl Default for RayTracingPipelineBuilder {
+    fn default() -> RayTracingPipelineBuilder {
+        RayTracingPipelineBuilder::new()
+    }
+}
+
+/// Shader group handles of a `RayTracingPipelineBuilder` pipeline, laid out in raygen, miss and hit regions.
+pub struct ShaderBindingTable {
+    pub buffer: MappedBuffer,
+    pub raygen_region: vk::StridedDeviceAddressRegionKHR,
+    pub miss_region: vk::StridedDeviceAddressRegionKHR,
+    pub hit_region: vk::StridedDeviceAddressRegionKHR,
+    pub callable_region: vk::StridedDeviceAddressRegionKHR,
+}
+
+impl ShaderBindingTable {
+    pub fn new(
+        loader: &ash::extensions::khr::RayTracingPipeline,
+        properties: &vk::PhysicalDeviceRayTracingPipelinePropertiesKHR,
+        device: &ash::Device,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        non_coherent_atom_size: vk::DeviceSize,
+        pipeline: vk::Pipeline,
+        miss_count: u32,
+        hit_count: u32,
+    ) -> ShaderBindingTable {
+        let handle_size = properties.shader_group_handle_size as vk::DeviceSize;
+        let handle_stride = align_up(handle_size, properties.shader_group_handle_alignment as vk::DeviceSize);
+        let base_alignment = properties.shader_group_base_alignment as vk::DeviceSize;
+        let group_count = 1 + miss_count + hit_count;
+
+        let handles = unsafe {
+            loader
+                .get_ray_tracing_shader_group_handles(
+                    pipeline,
+                    0,
+                    group_count,
+                    (group_count as vk::DeviceSize * handle_size) as usize)
+                .expect("Failed to get Shader Group Handles!")
+        };
+
+        // every region starts at a base aligned offset, raygen has to be exactly one handle big.
+        let region_counts = [1, miss_count, hit_count];
+        let region_sizes: Vec<vk::DeviceSize> = region_counts
+            .iter()
+            .map(|&count| align_up(count as vk::DeviceSize * handle_stride, base_alignment))
+            .collect();
+        let table_size: vk::DeviceSize = region_sizes.iter().sum();
+
+        // the buffer address itself is only guaranteed to be aligned to something smaller, so keep room to shift it.
+        let mut buffer = MappedBuffer::new(
+            device,
+            table_size + base_alignment,
+            vk::BufferUsageFlags::SHADER_BINDING_TABLE_KHR | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            device_memory_properties,
+            non_coherent_atom_size,
+        );
+        let buffer_address = buffer_device_address(device, buffer.buffer);
+        let base_address = align_up(buffer_address, base_alignment);
+
+        let mut regions = [vk::StridedDeviceAddressRegionKHR::default(); 3];
+        let mut region_offset = base_address - buffer_address;
+        let mut group_index = 0;
+        for (region_index, &count) in region_counts.iter().enumerate() {
+            for handle_index in 0..count as vk::DeviceSize {
+                let handle_start = group_index * handle_size as usize;
+                let handle = &handles[handle_start..handle_start + handle_size as usize];
+                buffer.write(device, region_offset + handle_index * handle_stride, handle);
+                group_index += 1;
+            }
+
+            regions[region_index] = vk::StridedDeviceAddressRegionKHR {
+                device_address: if count > 0 { buffer_address + region_offset } else { 0 },
+                stride: if region_index == 0 { region_sizes[0] } else { handle_stride },
+                size: if count > 0 { region_sizes[region_index] } else { 0 },
+            };
+            region_offset += region_sizes[region_index];
+        }
+
+        ShaderBindingTable {
+            buffer: buffer,
+            raygen_region: regions[0],
+            miss_region: regions[1],
+            hit_region: regions[2],
+            callable_region: vk::StridedDeviceAddressRegionKHR::default(),
+        }
+    }
+
+    /// Traces one ray per pixel of `width` x `height`, the ray tracing pipeline has to be bound.
+    pub fn cmd_trace_rays(
+        &self,
+        loader: &ash::extensions::khr::RayTracingPipeline,
+        command_buffer: vk::CommandBuffer,
+        width: u32,
+        height: u32,
+    ) {
+        unsafe {
+            loader.cmd_trace_rays(
+                command_buffer,
+                &self.raygen_region,
+                &self.miss_region,
+                &self.hit_region,
+                &self.callable_region,
+                width,
+                height,
+                1);
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        self.buffer.destroy(device);
+    }
+}
+
+pub(crate) fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    let alignment = alignment.max(1);
+    value.div_ceil(alignment) * alignment
+}
@@ -0,0 +1,534 @@
+use ash::vk;
+use rspirv::dr::{Instruction, Operand};
+use rspirv::spirv;
+
+use std::collections::HashMap;
+use std::ptr;
+
+use crate::vk::descriptor::DescriptorSetLayoutBuilder;
+
+/// Why a module couldn't be reflected or its layouts merged.
+#[derive(Debug)]
+pub enum ReflectionError {
+    /// `rspirv` couldn't parse the module.
+    InvalidSpirv(rspirv::binary::ParseState),
+    UnsupportedExecutionModel(spirv::ExecutionModel),
+    /// A resource or block member of a type without a descriptor type or size, with the type's SPIR-V id.
+    UnsupportedType(u32),
+    /// An array length that isn't a plain constant, e.g. a specialization constant, with the length's SPIR-V id.
+    NonConstantArrayLength(u32),
+    /// The same set and binding is a different descriptor type in two stages.
+    MismatchedBinding {
+        set: u32,
+        binding: u32,
+        descriptor_types: (vk::DescriptorType, vk::DescriptorType),
+    },
+}
+
+/// One descriptor binding a shader declares.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    /// Runtime sized arrays count as one descriptor, bindless sets are still laid out by hand.
+    pub descriptor_count: u32,
+}
+
+/// Resource interface of one SPIR-V module, see `reflect`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderReflection {
+    /// Stages of all entry points in the module.
+    pub stage: vk::ShaderStageFlags,
+    pub bindings: Vec<ReflectedBinding>,
+    /// Bytes of the push constant block, `None` without one.
+    pub push_constant_size: Option<u32>,
+}
+
+/// Types of the module, only what descriptor types and block sizes are derived from.
+#[derive(Clone, Debug)]
+enum SpirvType {
+    Scalar { width: u32 },
+    Vector { component_type: u32, component_count: u32 },
+    Matrix { column_type: u32, column_count: u32 },
+    Image { dim: spirv::Dim, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element_type: u32, length_id: u32 },
+    RuntimeArray { element_type: u32 },
+    Struct { member_types: Vec<u32> },
+    Pointer { pointee_type: u32 },
+    AccelerationStructure,
+}
+
+/// Descriptor bindings and push constant block of a SPIR-V module, `code` is the module as `vk::ShaderModuleCreateInfo` takes it.
+pub fn reflect(code: &[u32]) -> Result<ShaderReflection, ReflectionError> {
+    let parsed = rspirv::dr::load_words(code).map_err(ReflectionError::InvalidSpirv)?;
+
+    let mut stage = vk::ShaderStageFlags::empty();
+    for entry_point in parsed.entry_points.iter() {
+        stage |= execution_model_stage(entry_point.operands[0].unwrap_execution_model())?;
+    }
+
+    let mut module = SpirvModule {
+        types: HashMap::new(),
+        constants: HashMap::new(),
+        decorations: HashMap::new(),
+        member_decorations: HashMap::new(),
+    };
+    // (result id, pointer type, storage class)
+    let mut variables: Vec<(u32, u32, spirv::StorageClass)> = vec![];
+
+    for instruction in parsed.types_global_values.iter() {
+        let result_id = instruction.result_id.unwrap_or(0);
+        let id = |index: usize| instruction.operands[index].unwrap_id_ref();
+        let literal = |index: usize| instruction.operands[index].unwrap_literal_bit32();
+
+        let parsed_type = match instruction.class.opcode {
+            spirv::Op::TypeInt | spirv::Op::TypeFloat => SpirvType::Scalar { width: literal(0) },
+            spirv::Op::TypeVector => SpirvType::Vector { component_type: id(0), component_count: literal(1) },
+            spirv::Op::TypeMatrix => SpirvType::Matrix { column_type: id(0), column_count: literal(1) },
+            spirv::Op::TypeImage => SpirvType::Image { dim: instruction.operands[1].unwrap_dim(), sampled: literal(5) },
+            spirv::Op::TypeSampler => SpirvType::Sampler,
+            spirv::Op::TypeSampledImage => SpirvType::SampledImage,
+            spirv::Op::TypeArray => SpirvType::Array { element_type: id(0), length_id: id(1) },
+            spirv::Op::TypeRuntimeArray => SpirvType::RuntimeArray { element_type: id(0) },
+            spirv::Op::TypeStruct => SpirvType::Struct {
+                member_types: instruction.operands.iter().map(|operand| operand.unwrap_id_ref()).collect(),
+            },
+            spirv::Op::TypePointer => SpirvType::Pointer { pointee_type: id(1) },
+            spirv::Op::TypeAccelerationStructureKHR => SpirvType::AccelerationStructure,
+            spirv::Op::Constant => {
+                // array lengths are the only constants needed, they never need more than 32 bits.
+                if let Some(&Operand::LiteralBit32(value)) = instruction.operands.first() {
+                    module.constants.insert(result_id, value);
+                }
+                continue;
+            }
+            spirv::Op::Variable => {
+                variables.push((result_id, instruction.result_type.unwrap_or(0), instruction.operands[0].unwrap_storage_class()));
+                continue;
+            }
+            _ => continue,
+        };
+        module.types.insert(result_id, parsed_type);
+    }
+
+    for instruction in parsed.annotations.iter() {
+        match instruction.class.opcode {
+            spirv::Op::Decorate => {
+                let target = instruction.operands[0].unwrap_id_ref();
+                module.decorations.insert((target, instruction.operands[1].unwrap_decoration()), decoration_literal(instruction, 2));
+            }
+            spirv::Op::MemberDecorate => {
+                let target = instruction.operands[0].unwrap_id_ref();
+                let member = instruction.operands[1].unwrap_literal_bit32();
+                module.member_decorations.insert(
+                    (target, member, instruction.operands[2].unwrap_decoration()),
+                    decoration_literal(instruction, 3));
+            }
+            _ => {}
+        }
+    }
+
+    let mut bindings = vec![];
+    let mut push_constant_size = None;
+    for &(variable_id, pointer_type, storage_class) in variables.iter() {
+        let pointee_type = match module.types.get(&pointer_type) {
+            Some(&SpirvType::Pointer { pointee_type }) => pointee_type,
+            _ => return Err(ReflectionError::UnsupportedType(pointer_type)),
+        };
+
+        match storage_class {
+            spirv::StorageClass::PushConstant => {
+                push_constant_size = Some(module.type_size(pointee_type)?);
+            }
+            spirv::StorageClass::UniformConstant | spirv::StorageClass::Uniform | spirv::StorageClass::StorageBuffer => {
+                let (element_type, descriptor_count) = module.array_element(pointee_type)?;
+                let set = module.decorations.get(&(variable_id, spirv::Decoration::DescriptorSet)).copied();
+                let binding = module.decorations.get(&(variable_id, spirv::Decoration::Binding)).copied();
+
+                if let (Some(set), Some(binding)) = (set, binding) {
+                    bindings.push(ReflectedBinding {
+                        set,
+                        binding,
+                        descriptor_type: module.descriptor_type(element_type, storage_class)?,
+                        descriptor_count,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    bindings.sort_by_key(|binding| (binding.set, binding.binding));
+
+    Ok(ShaderReflection {
+        stage: stage,
+        bindings: bindings,
+        push_constant_size: push_constant_size,
+    })
+}
+
+/// Descriptor set layouts and push constant ranges of a pipeline built from the shaders behind `reflections`.
+/// Bindings declared by more than one stage are merged, the types have to match.
+/// `GraphicsPipelineBuilder::reflected_layout` derives one from the builder's shaders.
+pub struct PipelineLayoutReflection {
+    /// Indexed by set number, sets no shader uses stay empty.
+    pub set_layouts: Vec<DescriptorSetLayoutBuilder>,
+    pub push_constant_ranges: Vec<vk::PushConstantRange>,
+}
+
+impl PipelineLayoutReflection {
+    pub fn new(reflections: &[ShaderReflection]) -> Result<PipelineLayoutReflection, ReflectionError> {
+        let mut merged_bindings: Vec<(ReflectedBinding, vk::ShaderStageFlags)> = vec![];
+
+        for reflection in reflections.iter() {
+            for binding in reflection.bindings.iter() {
+                let merged = merged_bindings
+                    .iter_mut()
+                    .find(|(merged, _)| merged.set == binding.set && merged.binding == binding.binding);
+
+                match merged {
+                    Some((merged, stage_flags)) => {
+                        if merged.descriptor_type != binding.descriptor_type {
+                            return Err(ReflectionError::MismatchedBinding {
+                                set: binding.set,
+                                binding: binding.binding,
+                                descriptor_types: (merged.descriptor_type, binding.descriptor_type),
+                            });
+                        }
+                        merged.descriptor_count = merged.descriptor_count.max(binding.descriptor_count);
+                        *stage_flags |= reflection.stage;
+                    }
+                    None => merged_bindings.push((*binding, reflection.stage)),
+                }
+            }
+        }
+        let set_count = merged_bindings.iter().map(|(binding, _)| binding.set + 1).max().unwrap_or(0);
+        let mut set_layouts: Vec<DescriptorSetLayoutBuilder> = (0..set_count).map(|_| DescriptorSetLayoutBuilder::new()).collect();
+        for (binding, stage_flags) in merged_bindings {
+            let set_layout = std::mem::take(&mut set_layouts[binding.set as usize]);
+            set_layouts[binding.set as usize] =
+                set_layout.binding(binding.binding, binding.descriptor_type, binding.descriptor_count, stage_flags);
+        }
+
+        // one range over the largest block, visible to every stage that declares one.
+        let push_constant_stages = reflections
+            .iter()
+            .filter(|reflection| reflection.push_constant_size.is_some())
+            .fold(vk::ShaderStageFlags::empty(), |stages, reflection| stages | reflection.stage);
+        let push_constant_size = reflections.iter().filter_map(|reflection| reflection.push_constant_size).max();
+        let push_constant_ranges = push_constant_size
+            .map(|size| vk::PushConstantRange {
+                stage_flags: push_constant_stages,
+                offset: 0,
+                size,
+            })
+            .into_iter()
+            .collect();
+
+        Ok(PipelineLayoutReflection {
+            set_layouts: set_layouts,
+            push_constant_ranges: push_constant_ranges,
+        })
+    }
+
+    /// Creates the set layouts and a pipeline layout over them, the caller owns and destroys all of them.
+    pub fn build(&self, device: &ash::Device) -> (Vec<vk::DescriptorSetLayout>, vk::PipelineLayout) {
+        let set_layouts: Vec<vk::DescriptorSetLayout> = self.set_layouts
+            .iter()
+            .map(|set_layout| set_layout.build(device))
+            .collect();
+
+        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo {
+            s_type: vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineLayoutCreateFlags::empty(),
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: self.push_constant_ranges.len() as u32,
+            p_push_constant_ranges: self.push_constant_ranges.as_ptr(),
+        };
+
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_create_info, None)
+                .expect("Failed to create pipeline layout!")
+        };
+
+        (set_layouts, pipeline_layout)
+    }
+}
+
+struct SpirvModule {
+    types: HashMap<u32, SpirvType>,
+    constants: HashMap<u32, u32>,
+    /// First literal of each decoration, 0 for the ones without, e.g. `Block`.
+    decorations: HashMap<(u32, spirv::Decoration), u32>,
+    member_decorations: HashMap<(u32, u32, spirv::Decoration), u32>,
+}
+
+impl SpirvModule {
+    fn get_type(&self, type_id: u32) -> Result<&SpirvType, ReflectionError> {
+        self.types
+            .get(&type_id)
+            .ok_or(ReflectionError::UnsupportedType(type_id))
+    }
+
+    /// Element type and descriptor count of a binding, which is an array for descriptor arrays.
+    fn array_element(&self, type_id: u32) -> Result<(u32, u32), ReflectionError> {
+        match *self.get_type(type_id)? {
+            SpirvType::Array { element_type, length_id } => {
+                let length = self.constants
+                    .get(&length_id)
+                    .copied()
+                    .ok_or(ReflectionError::NonConstantArrayLength(length_id))?;
+                Ok((element_type, length))
+            }
+            SpirvType::RuntimeArray { element_type } => Ok((element_type, 1)),
+            _ => Ok((type_id, 1)),
+        }
+    }
+
+    fn descriptor_type(&self, type_id: u32, storage_class: spirv::StorageClass) -> Result<vk::DescriptorType, ReflectionError> {
+        let has_decoration = |decoration: spirv::Decoration| self.decorations.contains_key(&(type_id, decoration));
+
+        match *self.get_type(type_id)? {
+            SpirvType::Sampler => Ok(vk::DescriptorType::SAMPLER),
+            SpirvType::SampledImage => Ok(vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+            SpirvType::Image { dim: spirv::Dim::DimSubpassData, .. } => Ok(vk::DescriptorType::INPUT_ATTACHMENT),
+            SpirvType::Image { dim: spirv::Dim::DimBuffer, sampled: 2 } => Ok(vk::DescriptorType::STORAGE_TEXEL_BUFFER),
+            SpirvType::Image { dim: spirv::Dim::DimBuffer, .. } => Ok(vk::DescriptorType::UNIFORM_TEXEL_BUFFER),
+            SpirvType::Image { sampled: 2, .. } => Ok(vk::DescriptorType::STORAGE_IMAGE),
+            SpirvType::Image { .. } => Ok(vk::DescriptorType::SAMPLED_IMAGE),
+            SpirvType::AccelerationStructure => Ok(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR),
+            SpirvType::Struct { .. } if storage_class == spirv::StorageClass::StorageBuffer => Ok(vk::DescriptorType::STORAGE_BUFFER),
+            // older compilers mark storage buffers as uniform buffer blocks.
+            SpirvType::Struct { .. } if has_decoration(spirv::Decoration::BufferBlock) => Ok(vk::DescriptorType::STORAGE_BUFFER),
+            SpirvType::Struct { .. } if has_decoration(spirv::Decoration::Block) => Ok(vk::DescriptorType::UNIFORM_BUFFER),
+            _ => Err(ReflectionError::UnsupportedType(type_id)),
+        }
+    }
+
+    /// Bytes of a block member type, laid out by the offsets and strides the compiler decorated it with.
+    fn type_size(&self, type_id: u32) -> Result<u32, ReflectionError> {
+        match *self.get_type(type_id)? {
+            SpirvType::Scalar { width } => Ok(width / 8),
+            SpirvType::Vector { component_type, component_count } => Ok(self.type_size(component_type)? * component_count),
+            SpirvType::Matrix { column_type, column_count } => Ok(self.type_size(column_type)? * column_count),
+            SpirvType::Array { element_type, length_id } => {
+                let length = self.constants.get(&length_id).copied().unwrap_or(1);
+                let stride = match self.decorations.get(&(type_id, spirv::Decoration::ArrayStride)) {
+                    Some(&stride) => stride,
+                    None => self.type_size(element_type)?,
+                };
+                Ok(stride * length)
+            }
+            SpirvType::Struct { ref member_types } => {
+                let mut size = 0;
+                for (member, &member_type) in member_types.iter().enumerate() {
+                    let member = member as u32;
+                    let member_offset = self.member_decorations
+                        .get(&(type_id, member, spirv::Decoration::Offset))
+                        .copied()
+                        .unwrap_or(size);
+                    size = size.max(member_offset + self.member_size(type_id, member, member_type)?);
+                }
+                Ok(size)
+            }
+            _ => Err(ReflectionError::UnsupportedType(type_id)),
+        }
+    }
+
+    /// Matrices are padded to their `MatrixStride`, per column or per row when the member is `RowMajor`,
+    /// e.g. a column major mat3 in std140 takes 48 bytes and a row major mat4x3 (4 columns, 3 rows) in std430 too.
+    fn member_size(&self, struct_type: u32, member: u32, member_type: u32) -> Result<u32, ReflectionError> {
+        let matrix_stride = self.member_decorations.get(&(struct_type, member, spirv::Decoration::MatrixStride));
+
+        match (self.get_type(member_type)?, matrix_stride) {
+            (&SpirvType::Matrix { column_type, column_count }, Some(&matrix_stride)) => {
+                let is_row_major = self.member_decorations.contains_key(&(struct_type, member, spirv::Decoration::RowMajor));
+                let strided_vectors = if is_row_major {
+                    match *self.get_type(column_type)? {
+                        SpirvType::Vector { component_count, .. } => component_count,
+                        _ => return Err(ReflectionError::UnsupportedType(column_type)),
+                    }
+                } else {
+                    column_count
+                };
+                Ok(matrix_stride * strided_vectors)
+            }
+            _ => self.type_size(member_type),
+        }
+    }
+}
+
+/// Literal operand `index` of a decoration instruction, 0 when it has none.
+fn decoration_literal(instruction: &Instruction, index: usize) -> u32 {
+    match instruction.operands.get(index) {
+        Some(&Operand::LiteralBit32(value)) => value,
+        _ => 0,
+    }
+}
+
+fn execution_model_stage(execution_model: spirv::ExecutionModel) -> Result<vk::ShaderStageFlags, ReflectionError> {
+    match execution_model {
+        spirv::ExecutionModel::Vertex => Ok(vk::ShaderStageFlags::VERTEX),
+        spirv::ExecutionModel::TessellationControl => Ok(vk::ShaderStageFlags::TESSELLATION_CONTROL),
+        spirv::ExecutionModel::TessellationEvaluation => Ok(vk::ShaderStageFlags::TESSELLATION_EVALUATION),
+        spirv::ExecutionModel::Geometry => Ok(vk::ShaderStageFlags::GEOMETRY),
+        spirv::ExecutionModel::Fragment => Ok(vk::ShaderStageFlags::FRAGMENT),
+        spirv::ExecutionModel::GLCompute => Ok(vk::ShaderStageFlags::COMPUTE),
+        spirv::ExecutionModel::RayGenerationKHR => Ok(vk::ShaderStageFlags::RAYGEN_KHR),
+        spirv::ExecutionModel::IntersectionKHR => Ok(vk::ShaderStageFlags::INTERSECTION_KHR),
+        spirv::ExecutionModel::AnyHitKHR => Ok(vk::ShaderStageFlags::ANY_HIT_KHR),
+        spirv::ExecutionModel::ClosestHitKHR => Ok(vk::ShaderStageFlags::CLOSEST_HIT_KHR),
+        spirv::ExecutionModel::MissKHR => Ok(vk::ShaderStageFlags::MISS_KHR),
+        spirv::ExecutionModel::CallableKHR => Ok(vk::ShaderStageFlags::CALLABLE_KHR),
+        spirv::ExecutionModel::TaskEXT => Ok(vk::ShaderStageFlags::TASK_EXT),
+        spirv::ExecutionModel::MeshEXT => Ok(vk::ShaderStageFlags::MESH_EXT),
+        _ => Err(ReflectionError::UnsupportedExecutionModel(execution_model)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rspirv::binary::Assemble;
+    use rspirv::dr::Builder;
+
+    /// Fragment shader with `layout(set = 1, binding = 2) uniform sampler2D textures[4];`,
+    /// `layout(set = 0, binding = 0) uniform Camera { mat4 view; }` and `layout(push_constant) uniform Push { vec4 color; uint id; }`.
+    const REFLECTED_FRAGMENT_SHADER: [u32; 141] = [
+        0x07230203, 0x00010000, 0, 30, 0,
+        (2 << 16) | 17, 1,                                  // OpCapability Shader
+        (3 << 16) | 14, 0, 1,                               // OpMemoryModel Logical GLSL450
+        (5 << 16) | 15, 4, 1, 0x6E69616D, 0,                // OpEntryPoint Fragment %1 "main"
+        (4 << 16) | 71, 10, 34, 1,                          // OpDecorate %10 DescriptorSet 1
+        (4 << 16) | 71, 10, 33, 2,                          // OpDecorate %10 Binding 2
+        (3 << 16) | 71, 13, 2,                              // OpDecorate %13 Block
+        (5 << 16) | 72, 13, 0, 35, 0,                       // OpMemberDecorate %13 0 Offset 0
+        (4 << 16) | 72, 13, 0, 5,                           // OpMemberDecorate %13 0 ColMajor
+        (5 << 16) | 72, 13, 0, 7, 16,                       // OpMemberDecorate %13 0 MatrixStride 16
+        (4 << 16) | 71, 15, 34, 0,                          // OpDecorate %15 DescriptorSet 0
+        (4 << 16) | 71, 15, 33, 0,                          // OpDecorate %15 Binding 0
+        (3 << 16) | 71, 17, 2,                              // OpDecorate %17 Block
+        (5 << 16) | 72, 17, 0, 35, 0,                       // OpMemberDecorate %17 0 Offset 0
+        (5 << 16) | 72, 17, 1, 35, 16,                      // OpMemberDecorate %17 1 Offset 16
+        (3 << 16) | 22, 2, 32,                              // %2 = OpTypeFloat 32
+        (9 << 16) | 25, 3, 2, 1, 0, 0, 0, 1, 0,             // %3 = OpTypeImage %2 2D 0 0 0 1 Unknown
+        (3 << 16) | 27, 5, 3,                               // %5 = OpTypeSampledImage %3
+        (4 << 16) | 21, 6, 32, 0,                           // %6 = OpTypeInt 32 0
+        (4 << 16) | 43, 6, 7, 4,                            // %7 = OpConstant %6 4
+        (4 << 16) | 28, 8, 5, 7,                            // %8 = OpTypeArray %5 %7
+        (4 << 16) | 32, 9, 0, 8,                            // %9 = OpTypePointer UniformConstant %8
+        (4 << 16) | 59, 9, 10, 0,                           // %10 = OpVariable %9 UniformConstant
+        (4 << 16) | 23, 11, 2, 4,                           // %11 = OpTypeVector %2 4
+        (4 << 16) | 24, 12, 11, 4,                          // %12 = OpTypeMatrix %11 4
+        (3 << 16) | 30, 13, 12,                             // %13 = OpTypeStruct %12
+        (4 << 16) | 32, 14, 2, 13,                          // %14 = OpTypePointer Uniform %13
+        (4 << 16) | 59, 14, 15, 2,                          // %15 = OpVariable %14 Uniform
+        (2 << 16) | 19, 16,                                 // %16 = OpTypeVoid
+        (4 << 16) | 30, 17, 11, 6,                          // %17 = OpTypeStruct %11 %6
+        (4 << 16) | 32, 18, 9, 17,                          // %18 = OpTypePointer PushConstant %17
+        (4 << 16) | 59, 18, 19, 9,                          // %19 = OpVariable %18 PushConstant
+        (3 << 16) | 33, 20, 16,                             // %20 = OpTypeFunction %16
+        (5 << 16) | 54, 16, 1, 0, 20,                       // %1 = OpFunction %16 None %20
+        (2 << 16) | 248, 21,                                // %21 = OpLabel
+        (1 << 16) | 253,                                    // OpReturn
+        (1 << 16) | 56,                                     // OpFunctionEnd
+    ];
+
+    #[test]
+    fn reflects_bindings_and_push_constants() {
+        let reflection = reflect(&REFLECTED_FRAGMENT_SHADER).unwrap();
+
+        assert_eq!(reflection.stage, vk::ShaderStageFlags::FRAGMENT);
+        assert_eq!(reflection.bindings, vec![
+            ReflectedBinding {
+                set: 0,
+                binding: 0,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: 1,
+            },
+            ReflectedBinding {
+                set: 1,
+                binding: 2,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 4,
+            },
+        ]);
+        // vec4 at 0 and uint at 16.
+        assert_eq!(reflection.push_constant_size, Some(20));
+    }
+
+    #[test]
+    fn merges_stages_into_layout() {
+        let fragment = reflect(&REFLECTED_FRAGMENT_SHADER).unwrap();
+        let vertex = ShaderReflection {
+            stage: vk::ShaderStageFlags::VERTEX,
+            ..fragment.clone()
+        };
+        let layout = PipelineLayoutReflection::new(&[vertex, fragment]).unwrap();
+
+        assert_eq!(layout.set_layouts.len(), 2);
+        assert_eq!(layout.push_constant_ranges.len(), 1);
+        assert_eq!(layout.push_constant_ranges[0].stage_flags, vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+        assert_eq!(layout.push_constant_ranges[0].size, 20);
+    }
+
+    #[test]
+    fn rejects_mismatched_binding_types() {
+        let fragment = reflect(&REFLECTED_FRAGMENT_SHADER).unwrap();
+        let mut vertex = fragment.clone();
+        vertex.stage = vk::ShaderStageFlags::VERTEX;
+        vertex.bindings[0].descriptor_type = vk::DescriptorType::STORAGE_BUFFER;
+
+        assert!(PipelineLayoutReflection::new(&[vertex, fragment]).is_err());
+    }
+
+    #[test]
+    fn rejects_non_spirv() {
+        assert!(matches!(reflect(&[0, 1, 2, 3, 4]), Err(ReflectionError::InvalidSpirv(_))));
+    }
+
+    /// Vertex shader with `layout(push_constant) uniform Push { mat4x3 transform; }`, 4 columns of vec3.
+    fn matrix_push_constant_shader(majorness: spirv::Decoration) -> Vec<u32> {
+        let mut builder = Builder::new();
+        builder.capability(spirv::Capability::Shader);
+        builder.memory_model(spirv::AddressingModel::Logical, spirv::MemoryModel::GLSL450);
+
+        let float = builder.type_float(32);
+        let column = builder.type_vector(float, 3);
+        let matrix = builder.type_matrix(column, 4);
+        let block = builder.type_struct([matrix]);
+        builder.decorate(block, spirv::Decoration::Block, []);
+        builder.member_decorate(block, 0, spirv::Decoration::Offset, [Operand::LiteralBit32(0)]);
+        builder.member_decorate(block, 0, spirv::Decoration::MatrixStride, [Operand::LiteralBit32(16)]);
+        builder.member_decorate(block, 0, majorness, []);
+        let pointer = builder.type_pointer(None, spirv::StorageClass::PushConstant, block);
+        builder.variable(pointer, None, spirv::StorageClass::PushConstant, None);
+
+        let main = builder.id();
+        builder.entry_point(spirv::ExecutionModel::Vertex, main, "main", []);
+        builder.module().assemble()
+    }
+
+    #[test]
+    fn column_major_matrices_are_strided_per_column() {
+        let reflection = reflect(&matrix_push_constant_shader(spirv::Decoration::ColMajor)).unwrap();
+
+        assert_eq!(reflection.stage, vk::ShaderStageFlags::VERTEX);
+        // 4 columns padded to 16 bytes.
+        assert_eq!(reflection.push_constant_size, Some(64));
+    }
+
+    #[test]
+    fn row_major_matrices_are_strided_per_row() {
+        let reflection = reflect(&matrix_push_constant_shader(spirv::Decoration::RowMajor)).unwrap();
+
+        // 3 rows of 4 floats.
+        assert_eq!(reflection.push_constant_size, Some(48));
+    }
+}
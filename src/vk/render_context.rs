@@ -0,0 +1,45 @@
+use ash::vk;
+
+use std::ops::{Deref, DerefMut};
+
+use crate::rhi::window;
+use crate::vk::render_device::{DeviceConfig, VkRenderDevice};
+
+/// One call bring-up of everything rendering to a window needs: instance, debug messenger, surface,
+/// physical and logical device, queues and swapchain, with `DeviceConfig::default()` unless `with_config` is used.
+/// Derefs to the `VkRenderDevice` owning all of them, `instance()`, `physical_device()` and the others expose them.
+/// `VkRenderDevice::create_instance`, `pick_physical_device` and `create_device` stay available for wiring them by hand.
+pub struct RenderContext {
+    render_device: VkRenderDevice,
+}
+
+impl RenderContext {
+    pub fn new(window: &window::Window) -> Result<RenderContext, vk::Result> {
+        RenderContext::with_config(window, DeviceConfig::default())
+    }
+
+    /// See `VkRenderDevice::try_new_with_config` for the errors.
+    pub fn with_config(window: &window::Window, config: DeviceConfig) -> Result<RenderContext, vk::Result> {
+        Ok(RenderContext {
+            render_device: VkRenderDevice::try_new_with_config(window, config)?,
+        })
+    }
+
+    pub fn into_render_device(self) -> VkRenderDevice {
+        self.render_device
+    }
+}
+
+impl Deref for RenderContext {
+    type Target = VkRenderDevice;
+
+    fn deref(&self) -> &VkRenderDevice {
+        &self.render_device
+    }
+}
+
+impl DerefMut for RenderContext {
+    fn deref_mut(&mut self) -> &mut VkRenderDevice {
+        &mut self.render_device
+    }
+}
@@ -3,12 +3,14 @@ use ash::vk::ValidationCacheCreateInfoEXT;
 use ash;
 use cgmath::SquareMatrix;
 
+use std::cell::Cell;
 use std::ffi::CString;
 use std::path::Path;
 use std::ptr;
+use std::time::{Duration, Instant};
 use std::collections::HashSet;
 
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_void};
 
 use crate::vk::vertex::AttributeDescriptions;
 use crate::vk::vertex::BindingDescriptions;
@@ -22,8 +24,28 @@ use crate::rhi::render_device;
 use crate::rhi::window;
 
 use crate::vk::swap_chain;
+use crate::vk::capabilities;
+use crate::vk::render_target;
+use crate::vk::device_group;
+use crate::vk::commands;
+use crate::vk::image;
+use crate::vk::submit::SubmitBatch;
+use crate::vk::buffer::MappedBuffer;
+use crate::vk::descriptor::DescriptorSetLayoutBuilder;
+use crate::vk::render_pass::{AttachmentLoad, RenderPassBuilder};
+use crate::vk::draw::DrawCommand;
+use crate::vk::pipeline::{GraphicsPipelineBuilder, FRAGMENT_SHADING_RATE_KEEP_COMBINER_OPS};
+use crate::vk::lifecycle::{self, TrackedObject};
+use crate::vk::deletion_queue::DeletionQueue;
+use crate::vk::sync::{VkSemaphore, VkFence};
+use crate::vk::sampler::SamplerCache;
+#[cfg(feature = "debug-overlay")]
+use crate::vk::debug_overlay::DebugOverlay;
 
 use super::swap_chain::VkSpawChain;
+use super::capabilities::DeviceCapabilities;
+use super::render_target::RenderTarget;
+use super::device_group::VkDeviceGroup;
 
 use crate::vk::vertex::{Vertex};
 
@@ -42,10 +64,107 @@ pub struct QueueFamilyIndices {
     pub present_family: Option<u32>,
 }
 
+pub struct DeviceConfig {
+    /// Create the device over every GPU of a device group and alternate frames between them.
+    /// Ignored when the GPU isn't part of a group or `VK_KHR_device_group` is missing.
+    pub use_device_group: bool,
+
+    /// Queues requested from the graphics family, e.g. one per submitting thread.
+    /// Clamped to the family's `queue_count`.
+    pub graphics_queue_count: u32,
+
+    /// Priority of each graphics queue in order, from 0.0 to 1.0, e.g. lower for a queue submitting background work.
+    /// Queues past the end get 1.0. Only relative to the other queues of the device,
+    /// and drivers with `discreteQueuePriorities` of 2 round to 0.0 or 1.0.
+    pub graphics_queue_priorities: Vec<f32>,
+
+    /// Priority of the present queue when it's a different family than graphics, from 0.0 to 1.0.
+    pub present_queue_priority: f32,
+
+    /// See `SwapChainConfig::force_exclusive_sharing`, can still be changed later through `swapchain_config`.
+    pub force_exclusive_sharing: bool,
+
+    /// Benchmark both sharing modes at startup and keep the faster one, see `VkRenderDevice::tune_sharing_mode`.
+    /// Skipped when `force_exclusive_sharing` already decided it or graphics and present are the same family.
+    pub sharing_mode_tuning: Option<swap_chain::SharingModeTuning>,
+
+    /// See `SwapChainConfig::extensions`, already used for the first swapchain.
+    pub swapchain_extensions: swap_chain::SwapChainExtensions,
+
+    /// See `SwapChainConfig::transparent`, can be switched later through `set_transparent`.
+    pub transparent: bool,
+
+    /// See `SwapChainConfig::present_mode_policy`, the hints are set through `set_power_saving` and `set_frame_rate_cap`.
+    pub present_mode_policy: swap_chain::PresentModePolicy,
+
+    /// Instance layers to enable, e.g. `VK_LAYER_LUNARG_api_dump` or an overlay. Layers that aren't installed
+    /// are logged and skipped. Defaults to the validation layer when `constants::VALIDATION.is_enable`,
+    /// followed by the comma separated names in the `PUPSY_INSTANCE_LAYERS` environment variable.
+    pub instance_layers: Vec<String>,
+
+    /// Severities and types of validation messages printed, warnings and errors of every type by default.
+    pub debug_messenger: debug::DebugMessengerConfig,
+
+    /// Enable `protectedMemory` and create the graphics queues protected capable, for protected swapchains.
+    /// Ignored when unsupported, `capabilities.protected_memory` tells whether it's enabled.
+    pub protected_memory: bool,
+
+    /// Window system the surface is created through, the one the window was opened with by default.
+    /// Picks e.g. XCB over Xlib, fails with `ERROR_EXTENSION_NOT_PRESENT` when the window has no handles for it.
+    pub surface_backend: platforms::SurfaceBackend,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> DeviceConfig {
+        DeviceConfig {
+            use_device_group: false,
+            graphics_queue_count: 1,
+            graphics_queue_priorities: vec![],
+            present_queue_priority: 1.0,
+            force_exclusive_sharing: false,
+            sharing_mode_tuning: None,
+            swapchain_extensions: swap_chain::SwapChainExtensions::new(),
+            transparent: false,
+            present_mode_policy: swap_chain::PresentModePolicy::Fixed,
+            instance_layers: debug::default_instance_layers(),
+            debug_messenger: debug::DebugMessengerConfig::default(),
+            protected_memory: false,
+            surface_backend: platforms::SurfaceBackend::Auto,
+        }
+    }
+}
+
 pub struct SyncObjects {
-    pub image_available_semaphores: Vec<vk::Semaphore>,
-    pub render_finished_semaphores: Vec<vk::Semaphore>,
-    pub inflight_fences: Vec<vk::Fence>,
+    pub image_available_semaphores: Vec<VkSemaphore>,
+    pub render_finished_semaphores: Vec<VkSemaphore>,
+    pub inflight_fences: Vec<VkFence>,
+}
+
+/// What the device's surface is created for.
+#[derive(Clone, Copy)]
+enum SurfaceTarget<'a> {
+    Window(&'a window::Window),
+    /// Headless surface of this size, see `VkRenderDevice::try_new_headless`.
+    Headless(u32, u32),
+}
+
+/// Why a frame couldn't be rendered, other than being skipped for a minimized window or an out of date swapchain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// Acquire or present returned `ERROR_SURFACE_LOST_KHR`, e.g. after the display the window was on got
+    /// unplugged. Every frame fails with this until `VkRenderDevice::recreate_surface` succeeded.
+    SurfaceLost,
+}
+
+/// A swapchain image acquired by `acquire_frame` ahead of recording, has to be handed to `submit_frame`
+/// which waits on its acquire semaphore with the first submit drawing to it.
+#[must_use = "an acquired frame has to be submitted, its acquire semaphore stays signaled otherwise"]
+pub struct AcquiredFrame {
+    pub image_index: u32,
+    /// Frame in flight slot, what `current_frame` was at the acquire.
+    pub frame: usize,
+    is_suboptimal: bool,
+    image_available_semaphore: vk::Semaphore,
 }
 
 const VERTICES_DATA: [Vertex; 4] = [
@@ -76,8 +195,9 @@ impl QueueFamilyIndices {
         }
     }
 
+    /// Both families were found, a device without a family presenting to the surface can't be used.
     pub fn is_complete(&self) -> bool {
-        self.graphics_family.is_some()
+        self.graphics_family.is_some() && self.present_family.is_some()
     }
 }
 pub struct VkRenderDevice {
@@ -85,19 +205,48 @@ pub struct VkRenderDevice {
     entry: ash::Entry,
 
     surface: VkSurface,
+    /// `DeviceConfig::surface_backend` resolved for the window, or `Headless`; what `recreate_surface` uses again.
+    surface_backend: platforms::SurfaceBackend,
 
     debug_utils_loader: ash::extensions::ext::DebugUtils,
     debug_messager: vk::DebugUtilsMessengerEXT,
 
     physical_device: vk::PhysicalDevice,
     pub device: ash::Device,
+    pub capabilities: DeviceCapabilities,
 
+    /// Where `submit_draw` and `submit_batch` go.
     pub graphics_queue: vk::Queue,
+    /// Where `present` goes, the same queue as `graphics_queue` unless `has_separate_present_queue`.
+    /// A present waits on the semaphore the graphics submit signaled, plus the ownership acquire
+    /// of `present_ownership_transfer` in between when the images are EXCLUSIVE.
     pub present_queue: vk::Queue,
+    /// All queues created on the graphics family, `graphics_queue` is the first one.
+    pub graphics_queues: Vec<vk::Queue>,
 
     indices: QueueFamilyIndices,
 
+    pub device_group: Option<VkDeviceGroup>,
+    present_wait_loader: Option<ash::extensions::khr::PresentWait>,
+    synchronization2_loader: Option<ash::extensions::khr::Synchronization2>,
+    swapchain_maintenance1_fn: Option<vk::ExtSwapchainMaintenance1Fn>,
+    acceleration_structure_loader: Option<ash::extensions::khr::AccelerationStructure>,
+    ray_tracing_pipeline_loader: Option<ash::extensions::khr::RayTracingPipeline>,
+    mesh_shader_loader: Option<ash::extensions::ext::MeshShader>,
+    fragment_shading_rate_fn: Option<vk::KhrFragmentShadingRateFn>,
+    conditional_rendering_fn: Option<vk::ExtConditionalRenderingFn>,
+    transform_feedback_fn: Option<vk::ExtTransformFeedbackFn>,
+    display_timing_fn: Option<vk::GoogleDisplayTimingFn>,
+    hdr_metadata_fn: Option<vk::ExtHdrMetadataFn>,
+    device_fault_fn: Option<vk::ExtDeviceFaultFn>,
+
     pub swapchain: swap_chain::VkSpawChain,
+    /// Old swapchains still in use by the presentation engine, destroyed once their present fences signal.
+    retired_swapchains: Vec<swap_chain::RetiredSwapchain>,
+    free_present_fences: Vec<VkFence>,
+    present_ownership_transfer: Option<swap_chain::PresentOwnershipTransfer>,
+    pub swapchain_support_cache: swap_chain::SwapChainSupportCache,
+    pub swapchain_config: swap_chain::SwapChainConfig,
 
     pub render_pass: vk::RenderPass,
     ubo_layout: vk::DescriptorSetLayout,
@@ -111,17 +260,37 @@ pub struct VkRenderDevice {
     index_buffer_memory: vk::DeviceMemory,
 
     uniform_transform: UniformBufferObject,
-    uniform_buffers: Vec<vk::Buffer>,
-    uniform_buffers_memory: Vec<vk::DeviceMemory>,
+    uniform_buffers: Vec<MappedBuffer>,
 
     descriptor_pool: vk::DescriptorPool,
     descriptor_sets: Vec<vk::DescriptorSet>,
 
     pub command_pool: vk::CommandPool,
+    /// Built-in command buffers of the current swapchain, from `built_in_command_pool`.
     pub command_buffers: Vec<vk::CommandBuffer>,
+    /// Created by the first `set_protected(true)`, the built-in command buffers come from it while the
+    /// swapchain is protected.
+    protected_command_pool: Option<vk::CommandPool>,
+
+    pub sampler_cache: SamplerCache,
+    /// Objects retired while frames in flight may still use them, e.g. by swapchain recreation.
+    pub deletion_queue: DeletionQueue,
+
+    /// Drawn at the end of every frame's render pass, see `DebugOverlay::toggle`.
+    #[cfg(feature = "debug-overlay")]
+    pub debug_overlay: DebugOverlay,
 
     pub sync_objects: SyncObjects,
     pub current_frame: usize,
+    /// Set by `mark_resized`, the next `render_frame` recreates the swapchain after presenting.
+    is_framebuffer_resized: bool,
+    /// Between `acquire_frame` and `submit_frame`, the swapchain mustn't be recreated then.
+    is_frame_acquired: bool,
+    /// Set once acquire or present reported `ERROR_SURFACE_LOST_KHR`, cleared by `recreate_surface`.
+    is_surface_lost: bool,
+    /// `is_minimized` of the current frame, so the main loop and `acquire_frame` share one surface query.
+    /// Cleared by `acquire_frame`, `mark_resized` and `recreate_surface`.
+    is_minimized_cache: Cell<Option<bool>>,
 }
 
 impl render_device::RenderDevice for VkRenderDevice {
@@ -131,34 +300,261 @@ impl render_device::RenderDevice for VkRenderDevice {
 impl VkRenderDevice
 {
     pub fn new (window: &window::Window) -> VkRenderDevice {
+        VkRenderDevice::new_with_config(window, DeviceConfig::default())
+    }
+
+    pub fn new_with_config (window: &window::Window, config: DeviceConfig) -> VkRenderDevice {
+        VkRenderDevice::try_new_with_config(window, config).expect("Failed to create render device!")
+    }
+
+    /// Same as `new_with_config`, but returns why creating the instance, surface or device failed instead of panicking,
+    /// `ERROR_INCOMPATIBLE_DRIVER` when no GPU is suitable. What was created up to the failure is destroyed again.
+    pub fn try_new_with_config (window: &window::Window, config: DeviceConfig) -> Result<VkRenderDevice, vk::Result> {
+        let surface_backend = config.surface_backend.resolve(window);
+        VkRenderDevice::try_new_for_target(SurfaceTarget::Window(window), surface_backend, config)
+    }
+
+    /// Same as `try_new_with_config`, but on a `VK_EXT_headless_surface` of `width` x `height` instead of a window,
+    /// e.g. for benchmarks without a display. Nothing is shown, `config.surface_backend` is ignored.
+    /// Fails with `ERROR_EXTENSION_NOT_PRESENT` when the driver has no headless surfaces.
+    pub fn try_new_headless (width: u32, height: u32, config: DeviceConfig) -> Result<VkRenderDevice, vk::Result> {
+        VkRenderDevice::try_new_for_target(SurfaceTarget::Headless(width, height), platforms::SurfaceBackend::Headless, config)
+    }
+
+    fn try_new_for_target (
+        target: SurfaceTarget,
+        surface_backend: platforms::SurfaceBackend,
+        config: DeviceConfig,
+    ) -> Result<VkRenderDevice, vk::Result> {
         let entry = unsafe {
             ash::Entry::linked()
         };
-        let instance = VkRenderDevice::create_instance(&entry);
-        let (debug_units_loader, debug_messager) = debug::setup_debug_utils(&entry, &instance);
-        let surface = VkRenderDevice::create_surface(&entry, &instance, window);
-        let physical_device = VkRenderDevice::pick_physical_device(&instance, &surface);
-        let (device, indices) = VkRenderDevice::create_device(&instance, physical_device, &constants::VALIDATION, &surface);
+        let surface_maintenance1 = VkRenderDevice::supports_surface_maintenance1(&entry);
+        let surface_protected_capabilities = config.protected_memory
+            && VkRenderDevice::supports_surface_protected_capabilities(&entry);
+        let instance = VkRenderDevice::create_instance(
+            &entry,
+            surface_backend,
+            surface_maintenance1,
+            surface_protected_capabilities,
+            &config.instance_layers)?;
+        let (debug_units_loader, debug_messager) = debug::setup_debug_utils(&entry, &instance, &config.debug_messenger);
+        let destroy_instance = |surface: Option<&VkSurface>| unsafe {
+            if let Some(surface) = surface {
+                surface.surface_loader.destroy_surface(surface.surface, None);
+            }
+            if debug_messager != vk::DebugUtilsMessengerEXT::null() {
+                debug_units_loader.destroy_debug_utils_messenger(debug_messager, None);
+            }
+            instance.destroy_instance(None);
+        };
+
+        let surface = match VkRenderDevice::create_surface(
+            &entry,
+            &instance,
+            target,
+            surface_backend,
+            surface_maintenance1,
+            surface_protected_capabilities
+        ) {
+            Ok(surface) => surface,
+            Err(error) => {
+                destroy_instance(None);
+                return Err(error);
+            }
+        };
+        let physical_device = match VkRenderDevice::pick_physical_device(&instance, &surface) {
+            Some(physical_device) => physical_device,
+            None => {
+                destroy_instance(Some(&surface));
+                return Err(vk::Result::ERROR_INCOMPATIBLE_DRIVER);
+            }
+        };
+        let mut capabilities = DeviceCapabilities::query(
+            &instance,
+            physical_device,
+            VkRenderDevice::instance_api_version(&entry));
+        // swapchain_maintenance1 queries compatible present modes through the surface extension.
+        capabilities.swapchain_maintenance1 &= surface_maintenance1;
+        // protected queues are a cost of their own, so they're only created when asked for.
+        capabilities.protected_memory &= config.protected_memory
+            && VkRenderDevice::graphics_family_supports(&instance, physical_device, &surface, vk::QueueFlags::PROTECTED);
+        // the query accepts any family that can bind sparse memory, but the binds go through `graphics_queue`.
+        capabilities.sparse_residency_image_2d &=
+            VkRenderDevice::graphics_family_supports(&instance, physical_device, &surface, vk::QueueFlags::SPARSE_BINDING);
+
+        let device_group_devices = if config.use_device_group {
+            device_group::find_device_group(&instance, physical_device)
+        } else {
+            None
+        };
+        if let Some(physical_devices) = &device_group_devices {
+            capabilities.device_group_size = physical_devices.len() as u32;
+        }
+
+        let created_device = VkRenderDevice::create_device(
+            &instance,
+            physical_device,
+            &constants::VALIDATION,
+            &surface,
+            &capabilities,
+            &config,
+            device_group_devices.as_deref());
+        let (device, indices, graphics_queue_count) = match created_device {
+            Ok(created_device) => created_device,
+            Err(error) => {
+                destroy_instance(Some(&surface));
+                return Err(error);
+            }
+        };
+
+        let device_group = device_group_devices.and_then(|physical_devices| {
+            VkDeviceGroup::new(&instance, &device, physical_devices, &surface)
+        });
         
-        let graphics_queue = unsafe { 
-            device.get_device_queue(indices.graphics_family.unwrap(), 0)
+        let present_wait_loader = if capabilities.present_wait {
+            Some(ash::extensions::khr::PresentWait::new(&instance, &device))
+        } else {
+            None
+        };
+
+        let synchronization2_loader = if capabilities.synchronization2 {
+            Some(ash::extensions::khr::Synchronization2::new(&instance, &device))
+        } else {
+            None
+        };
+
+        let swapchain_maintenance1_fn = if capabilities.swapchain_maintenance1 {
+            Some(vk::ExtSwapchainMaintenance1Fn::load(|name| unsafe {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            }))
+        } else {
+            None
+        };
+
+        let acceleration_structure_loader = if capabilities.acceleration_structure {
+            Some(ash::extensions::khr::AccelerationStructure::new(&instance, &device))
+        } else {
+            None
+        };
+
+        let ray_tracing_pipeline_loader = if capabilities.ray_tracing {
+            Some(ash::extensions::khr::RayTracingPipeline::new(&instance, &device))
+        } else {
+            None
+        };
+
+        let mesh_shader_loader = if capabilities.mesh_shader {
+            Some(ash::extensions::ext::MeshShader::new(&instance, &device))
+        } else {
+            None
+        };
+
+        // the shading rate palette is an instance level function, so load through the instance.
+        let fragment_shading_rate_fn = if capabilities.fragment_shading_rate {
+            Some(vk::KhrFragmentShadingRateFn::load(|name| unsafe {
+                std::mem::transmute(entry.get_instance_proc_addr(instance.handle(), name.as_ptr()))
+            }))
+        } else {
+            None
+        };
+
+        let conditional_rendering_fn = if capabilities.conditional_rendering {
+            Some(vk::ExtConditionalRenderingFn::load(|name| unsafe {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            }))
+        } else {
+            None
+        };
+
+        let transform_feedback_fn = if capabilities.transform_feedback {
+            Some(vk::ExtTransformFeedbackFn::load(|name| unsafe {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            }))
+        } else {
+            None
+        };
+
+        let display_timing_fn = if capabilities.display_timing {
+            Some(vk::GoogleDisplayTimingFn::load(|name| unsafe {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            }))
+        } else {
+            None
+        };
+
+        let hdr_metadata_fn = if capabilities.hdr_metadata {
+            Some(vk::ExtHdrMetadataFn::load(|name| unsafe {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            }))
+        } else {
+            None
         };
 
-        let present_queue = unsafe { 
-            device.get_device_queue(indices.present_family.unwrap(), 0)
+        let device_fault_fn = if capabilities.device_fault {
+            Some(vk::ExtDeviceFaultFn::load(|name| unsafe {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            }))
+        } else {
+            None
         };
 
+        let graphics_queues: Vec<vk::Queue> = (0..graphics_queue_count)
+            .map(|queue_index| {
+                VkRenderDevice::get_device_queue(&device, &capabilities, indices.graphics_family.unwrap(), queue_index, true)
+            })
+            .collect();
+        let graphics_queue = graphics_queues[0];
+
+        let present_queue = VkRenderDevice::get_device_queue(
+            &device,
+            &capabilities,
+            indices.present_family.unwrap(),
+            0,
+            indices.present_family == indices.graphics_family);
+
+        let mut swapchain_support_cache = swap_chain::SwapChainSupportCache::new(true);
+        let swapchain_config = swap_chain::SwapChainConfig {
+            preferred_present_mode: None,
+            present_mode_policy: config.present_mode_policy,
+            power_saving: false,
+            frame_rate_cap: None,
+            present_mode_switching: capabilities.swapchain_maintenance1,
+            force_exclusive_sharing: config.force_exclusive_sharing,
+            present_sink: swap_chain::PresentSink::Screen,
+            preferred_format: None,
+            protected: false,
+            image_array_layers: 1,
+            extensions: config.swapchain_extensions.clone(),
+            hdr_metadata: None,
+            transparent: config.transparent,
+        };
         let swapchain = VkSpawChain::create_swapchain(
             &instance, 
             &device, 
             physical_device, 
             &surface, 
-            &indices);
-        let swapchain_image_views = swapchain.create_image_views(&device);
+            &indices,
+            device_group.as_ref(),
+            &mut swapchain_support_cache,
+            &swapchain_config,
+            vk::SwapchainKHR::null());
+        let mut swapchain = match swapchain {
+            Ok(swapchain) => swapchain,
+            Err(error) => {
+                unsafe {
+                    device.destroy_device(None);
+                }
+                destroy_instance(Some(&surface));
+                return Err(error);
+            }
+        };
+        swapchain.swapchain_image_views = swapchain.create_image_views(&device);
+        let present_ownership_transfer = VkRenderDevice::create_present_ownership_transfer(&device, &swapchain, &indices);
 
         let render_pass = VkRenderDevice::create_render_pass(
             &device, 
-            swapchain.swapchain_format);
+            &swapchain);
 
         let ubo_layout = VkRenderDevice::create_descriptor_set_layout(&device);
 
@@ -168,11 +564,10 @@ impl VkRenderDevice
             render_pass,
             ubo_layout);
 
-        let framebuffers = VkSpawChain::create_framebuffers(
+        swapchain.swapchain_framebuffers = render_target::create_framebuffers(
             &device, 
             render_pass, 
-            &swapchain_image_views, 
-            &swapchain.swapchain_extent);
+            &swapchain);
 
         let command_pool = VkRenderDevice::create_command_pool(
             &device, 
@@ -186,6 +581,7 @@ impl VkRenderDevice
                 &instance, 
                 &device, 
                 &physical_device_memory_properties, 
+                capabilities.non_coherent_atom_size,
                 command_pool, 
                 graphics_queue);
 
@@ -193,42 +589,74 @@ impl VkRenderDevice
             &instance,
             &device,
             &physical_device_memory_properties,
+            capabilities.non_coherent_atom_size,
             command_pool,
             graphics_queue,
         );
 
-        let (uniform_buffers, uniform_buffers_memory) = VkRenderDevice::create_uniform_buffers(
+        // per-image resources, sized by the images the driver created rather than the count asked for.
+        let uniform_buffers = VkRenderDevice::create_uniform_buffers(
             &device,
             &physical_device_memory_properties,
-            swapchain.swapchain_images.len()
+            capabilities.non_coherent_atom_size,
+            swapchain.image_count()
         );
 
         let descriptor_pool = VkRenderDevice::create_descriptor_pool(
             &device, 
-            swapchain.swapchain_images.len()
+            swapchain.image_count()
         );
+        let uniform_buffer_handles: Vec<vk::Buffer> = uniform_buffers.iter().map(|uniform_buffer| uniform_buffer.buffer).collect();
         let descriptor_sets = VkRenderDevice::create_descriptor_sets(
             &device,
             descriptor_pool,
             ubo_layout,
-            &uniform_buffers,
-            swapchain.swapchain_images.len(),
+            &uniform_buffer_handles,
+            swapchain.image_count(),
         );
 
+        let sampler_cache = SamplerCache::new(&device, &capabilities);
+
+        #[cfg(feature = "debug-overlay")]
+        let debug_overlay = DebugOverlay::new(
+            &commands::UploadContext {
+                instance: &instance,
+                physical_device: physical_device,
+                device: &device,
+                device_memory_properties: &physical_device_memory_properties,
+                non_coherent_atom_size: capabilities.non_coherent_atom_size,
+                command_pool: command_pool,
+                queue: graphics_queue,
+                sampler_cache: &sampler_cache,
+            },
+            render_pass,
+            swapchain.swapchain_extent,
+            swapchain.image_count());
+        #[cfg(feature = "debug-overlay")]
+        let record_overlay = |command_buffer: vk::CommandBuffer, image_index: usize| {
+            debug_overlay.cmd_draw(&device, command_buffer, image_index)
+        };
+        #[cfg(not(feature = "debug-overlay"))]
+        let record_overlay = |_: vk::CommandBuffer, _: usize| {};
+
         let command_buffers = VkRenderDevice::create_command_buffers(
             &device,
             command_pool,
             pipeline,
-            &framebuffers,
+            &swapchain.swapchain_framebuffers,
             render_pass,
             swapchain.swapchain_extent,
             vertex_buffer,
             index_buffer,
             pipeline_layout,
-            &descriptor_sets
+            &descriptor_sets,
+            &swapchain.swapchain_images,
+            present_ownership_transfer.as_ref(),
+            &record_overlay
         );
 
         let sync_ojbects = VkRenderDevice::create_sync_objects(&device);
+        let deletion_queue = DeletionQueue::new(&device);
 
         let uniform_transform = UniformBufferObject {
             model: Matrix4::<f32>::identity(),
@@ -245,20 +673,42 @@ impl VkRenderDevice
             ),
         };
 
-        VkRenderDevice {
+        let mut render_device = VkRenderDevice {
             entry: entry,
             instance: instance,
             surface: surface,
+            surface_backend: surface_backend,
             debug_utils_loader: debug_units_loader,
             debug_messager: debug_messager,
             physical_device: physical_device,
             device: device,
+            capabilities: capabilities,
 
             graphics_queue: graphics_queue,
             present_queue: present_queue,
+            graphics_queues: graphics_queues,
             indices: indices,
 
+            device_group: device_group,
+            present_wait_loader: present_wait_loader,
+            synchronization2_loader: synchronization2_loader,
+            swapchain_maintenance1_fn: swapchain_maintenance1_fn,
+            acceleration_structure_loader: acceleration_structure_loader,
+            ray_tracing_pipeline_loader: ray_tracing_pipeline_loader,
+            mesh_shader_loader: mesh_shader_loader,
+            fragment_shading_rate_fn: fragment_shading_rate_fn,
+            conditional_rendering_fn: conditional_rendering_fn,
+            transform_feedback_fn: transform_feedback_fn,
+            display_timing_fn: display_timing_fn,
+            hdr_metadata_fn: hdr_metadata_fn,
+            device_fault_fn: device_fault_fn,
+
             swapchain: swapchain,
+            retired_swapchains: vec![],
+            free_present_fences: vec![],
+            present_ownership_transfer: present_ownership_transfer,
+            swapchain_support_cache: swapchain_support_cache,
+            swapchain_config: swapchain_config,
 
             render_pass: render_pass,
             pipeline_layout: pipeline_layout,
@@ -273,24 +723,87 @@ impl VkRenderDevice
 
             uniform_transform: uniform_transform,
             uniform_buffers: uniform_buffers,
-            uniform_buffers_memory: uniform_buffers_memory,
 
             descriptor_pool: descriptor_pool,
             descriptor_sets: descriptor_sets,
 
             command_pool: command_pool,
             command_buffers: command_buffers,
+            protected_command_pool: None,
+
+            sampler_cache: sampler_cache,
+            deletion_queue: deletion_queue,
+
+            #[cfg(feature = "debug-overlay")]
+            debug_overlay: debug_overlay,
 
             sync_objects: sync_ojbects,
-            current_frame: 0
+            current_frame: 0,
+            is_framebuffer_resized: false,
+            is_frame_acquired: false,
+            is_surface_lost: false,
+            is_minimized_cache: Cell::new(None),
+        };
+
+        if let Some(sharing_mode_tuning) = &config.sharing_mode_tuning {
+            if !config.force_exclusive_sharing && render_device.has_separate_present_queue() {
+                render_device.apply_sharing_mode_tuning(sharing_mode_tuning);
+            }
         }
+
+        Ok(render_device)
+    }
+
+    pub fn entry(&self) -> &ash::Entry {
+        &self.entry
+    }
+
+    pub fn instance(&self) -> &ash::Instance {
+        &self.instance
+    }
+
+    pub fn surface(&self) -> &VkSurface {
+        &self.surface
+    }
+
+    pub fn physical_device(&self) -> vk::PhysicalDevice {
+        self.physical_device
+    }
+
+    pub fn queue_family_indices(&self) -> &QueueFamilyIndices {
+        &self.indices
+    }
+
+    pub fn debug_utils_loader(&self) -> &ash::extensions::ext::DebugUtils {
+        &self.debug_utils_loader
+    }
+
+    /// Meaningful bits of timestamps written on the graphics queue, 0 when it doesn't support timestamps.
+    pub fn timestamp_valid_bits(&self) -> u32 {
+        let queue_families = unsafe {
+            self.instance.get_physical_device_queue_family_properties(self.physical_device)
+        };
+        self.indices.graphics_family
+            .and_then(|graphics_family| queue_families.get(graphics_family as usize))
+            .map_or(0, |queue_family| queue_family.timestamp_valid_bits)
+    }
+
+    /// See `VkSpawChain::image_count`, per-image resources have to be sized by this rather than the count
+    /// the swapchain was requested with.
+    pub fn image_count(&self) -> usize {
+        self.swapchain.image_count()
+    }
+
+    /// Whether graphics and present are different queue families, `present_queue` is a queue of its own then.
+    pub fn has_separate_present_queue(&self) -> bool {
+        self.indices.graphics_family != self.indices.present_family
     }
 
     fn create_descriptor_sets(
         device: &ash::Device,
         descriptor_pool: vk::DescriptorPool,
         descriptor_set_layout: vk::DescriptorSetLayout,
-        uniforms_buffers: &Vec<vk::Buffer>,
+        uniforms_buffers: &[vk::Buffer],
         swapchain_images_size: usize,
     ) -> Vec<vk::DescriptorSet> {
         let mut layouts: Vec<vk::DescriptorSetLayout> = vec![];
@@ -366,27 +879,9 @@ impl VkRenderDevice
     }
 
     fn create_descriptor_set_layout(device: &ash::Device) -> vk::DescriptorSetLayout {
-        let ubo_layout_bindings = [vk::DescriptorSetLayoutBinding {
-            binding: 0,
-            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count: 1,
-            stage_flags: vk::ShaderStageFlags::VERTEX,
-            p_immutable_samplers: ptr::null(),
-        }];
-
-        let ubo_layout_create_info = vk::DescriptorSetLayoutCreateInfo {
-            s_type: vk::StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
-            p_next: ptr::null(),
-            flags: vk::DescriptorSetLayoutCreateFlags::empty(),
-            binding_count: ubo_layout_bindings.len() as u32,
-            p_bindings: ubo_layout_bindings.as_ptr(),
-        };
-
-        unsafe {
-            device
-                .create_descriptor_set_layout(&ubo_layout_create_info, None)
-                .expect("Failed to create Descriptor Set Layout!")
-        }
+        DescriptorSetLayoutBuilder::new()
+            .binding(0, vk::DescriptorType::UNIFORM_BUFFER, 1, vk::ShaderStageFlags::VERTEX)
+            .build(device)
     }
 
     fn create_buffer(
@@ -412,6 +907,7 @@ impl VkRenderDevice
                 .create_buffer(&buffer_create_info, None)
                 .expect("Failed to create Vertex Buffer")
         };
+        lifecycle::on_create(TrackedObject::Buffer, buffer);
 
         let mem_requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
         let memory_type = VkRenderDevice::find_memory_type(
@@ -450,101 +946,37 @@ impl VkRenderDevice
         dst_buffer: vk::Buffer,
         size: vk::DeviceSize,
     ) {
-        let allocate_info = vk::CommandBufferAllocateInfo {
-            s_type: vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
-            p_next: ptr::null(),
-            command_buffer_count: 1,
-            command_pool,
-            level: vk::CommandBufferLevel::PRIMARY,
-        };
-
-        let command_buffers = unsafe {
-            device
-                .allocate_command_buffers(&allocate_info)
-                .expect("Failed to allocate Command Buffer")
-        };
-        let command_buffer = command_buffers[0];
-
-        let begin_info = vk::CommandBufferBeginInfo {
-            s_type: vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
-            p_next: ptr::null(),
-            flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
-            p_inheritance_info: ptr::null(),
-        };
-
-        unsafe {
-            device
-                .begin_command_buffer(command_buffer, &begin_info)
-                .expect("Failed to begin Command Buffer");
-
+        commands::execute_one_time_commands(device, command_pool, submit_queue, |command_buffer| {
             let copy_regions = [vk::BufferCopy {
                 src_offset: 0,
                 dst_offset: 0,
                 size,
             }];
 
-            device.cmd_copy_buffer(command_buffer, src_buffer, dst_buffer, &copy_regions);
-
-            device
-                .end_command_buffer(command_buffer)
-                .expect("Failed to end Command Buffer");
-        }
-
-        let submit_info = [vk::SubmitInfo {
-            s_type: vk::StructureType::SUBMIT_INFO,
-            p_next: ptr::null(),
-            wait_semaphore_count: 0,
-            p_wait_semaphores: ptr::null(),
-            p_wait_dst_stage_mask: ptr::null(),
-            command_buffer_count: 1,
-            p_command_buffers: &command_buffer,
-            signal_semaphore_count: 0,
-            p_signal_semaphores: ptr::null(),
-        }];
-
-        unsafe {
-            device
-                .queue_submit(submit_queue, &submit_info, vk::Fence::null())
-                .expect("Failed to Submit Queue.");
-            device
-                .queue_wait_idle(submit_queue)
-                .expect("Failed to wait Queue idle");
-
-            device.free_command_buffers(command_pool, &command_buffers);
-        }
+            unsafe {
+                device.cmd_copy_buffer(command_buffer, src_buffer, dst_buffer, &copy_regions);
+            }
+        });
     }
 
     fn create_index_buffer(
         instance: &ash::Instance,
         device: &ash::Device,
         device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        non_coherent_atom_size: vk::DeviceSize,
         command_pool: vk::CommandPool,
         submit_queue: vk::Queue,
     ) -> (vk::Buffer, vk::DeviceMemory) {
         let buffer_size = std::mem::size_of_val(&INDICES_DATA) as vk::DeviceSize;
 
-        let (staging_buffer, staging_buffer_memory) = VkRenderDevice::create_buffer(
+        let mut staging_buffer = MappedBuffer::new(
             device,
             buffer_size,
             vk::BufferUsageFlags::TRANSFER_SRC,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
             device_memory_properties,
+            non_coherent_atom_size,
         );
-
-        unsafe {
-            let data_ptr = device
-                .map_memory(
-                    staging_buffer_memory,
-                    0,
-                    buffer_size,
-                    vk::MemoryMapFlags::empty(),
-                )
-                .expect("Failed to Map Memory") as *mut u32;
-
-            data_ptr.copy_from_nonoverlapping(INDICES_DATA.as_ptr(), INDICES_DATA.len());
-
-            device.unmap_memory(staging_buffer_memory);
-        }
+        staging_buffer.write(device, 0, &INDICES_DATA);
 
         let (index_buffer, index_buffer_memory) = VkRenderDevice::create_buffer(
             device,
@@ -558,15 +990,12 @@ impl VkRenderDevice
             device,
             submit_queue,
             command_pool,
-            staging_buffer,
+            staging_buffer.buffer,
             index_buffer,
             buffer_size,
         );
 
-        unsafe {
-            device.destroy_buffer(staging_buffer, None);
-            device.free_memory(staging_buffer_memory, None);
-        }
+        staging_buffer.destroy(device);
 
         (index_buffer, index_buffer_memory)
     }
@@ -575,31 +1004,20 @@ impl VkRenderDevice
         instance: &ash::Instance,
         device: &ash::Device,
         device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        non_coherent_atom_size: vk::DeviceSize,
         command_pool: vk::CommandPool,
         submit_queue: vk::Queue,
     ) -> (vk::Buffer, vk::DeviceMemory) {
         let buffer_size = std::mem::size_of_val(&VERTICES_DATA) as vk::DeviceSize;
 
-        let (staging_buffer, staging_buffer_memory) = VkRenderDevice::create_buffer(device,
+        let mut staging_buffer = MappedBuffer::new(
+            device,
             buffer_size,
             vk::BufferUsageFlags::TRANSFER_SRC,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            &device_memory_properties,);
-
-        unsafe {
-            let data_ptr = device
-                .map_memory(
-                    staging_buffer_memory,
-                    0,
-                    buffer_size,
-                    vk::MemoryMapFlags::empty(),
-                )
-                .expect("Failed to Map Memory") as *mut Vertex;
-
-            data_ptr.copy_from_nonoverlapping(VERTICES_DATA.as_ptr(), VERTICES_DATA.len());
-
-            device.unmap_memory(staging_buffer_memory);
-        }
+            device_memory_properties,
+            non_coherent_atom_size,
+        );
+        staging_buffer.write(device, 0, &VERTICES_DATA);
 
         let (vertex_buffer, vertex_buffer_memory) = VkRenderDevice::create_buffer(
             device,
@@ -613,58 +1031,63 @@ impl VkRenderDevice
             device,
             submit_queue,
             command_pool,
-            staging_buffer,
+            staging_buffer.buffer,
             vertex_buffer,
             buffer_size,
         );
 
-        unsafe {
-            device.destroy_buffer(staging_buffer, None);
-            device.free_memory(staging_buffer_memory, None);
-        }
+        staging_buffer.destroy(device);
 
         (vertex_buffer, vertex_buffer_memory)
     }
 
-    fn find_memory_type(
+    pub fn find_memory_type(
         type_filter: u32,
         required_properties: vk::MemoryPropertyFlags,
         mem_properties: &vk::PhysicalDeviceMemoryProperties,
     ) -> u32 {
+        VkRenderDevice::try_find_memory_type(type_filter, required_properties, mem_properties)
+            .expect("Failed to find suitable memory type!")
+    }
+
+    pub fn try_find_memory_type(
+        type_filter: u32,
+        required_properties: vk::MemoryPropertyFlags,
+        mem_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> Option<u32> {
         for (i, memory_type) in mem_properties.memory_types.iter().enumerate() {
             if (type_filter & (1 << i)) > 0
                 && memory_type.property_flags.contains(required_properties)
             {
-                return i as u32;
+                return Some(i as u32);
             }
         }
 
-        panic!("Failed to find suitable memory type!")
+        None
     }
 
     fn create_uniform_buffers(
         device: &ash::Device,
         device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        non_coherent_atom_size: vk::DeviceSize,
         swapchain_image_count: usize,
-    ) -> (Vec<vk::Buffer>, Vec<vk::DeviceMemory>) {
+    ) -> Vec<MappedBuffer> {
         let buffer_size = std::mem::size_of::<UniformBufferObject>();
 
         let mut uniform_buffers = vec![];
-        let mut uniform_buffers_memory = vec![];
 
         for _ in 0..swapchain_image_count {
-            let (uniform_buffer, uniform_buffer_memory) = VkRenderDevice::create_buffer(
+            let uniform_buffer = MappedBuffer::new(
                 device,
                 buffer_size as u64,
                 vk::BufferUsageFlags::UNIFORM_BUFFER,
-                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
                 device_memory_properties,
+                non_coherent_atom_size,
             );
             uniform_buffers.push(uniform_buffer);
-            uniform_buffers_memory.push(uniform_buffer_memory);
         }
 
-        (uniform_buffers, uniform_buffers_memory)
+        uniform_buffers
     }
 
     pub fn update_uniform_buffer(&mut self, current_image: usize, delta_time: f32) {
@@ -674,59 +1097,1340 @@ impl VkRenderDevice
 
         let ubos = [self.uniform_transform.clone()];
 
-        let buffer_size = (std::mem::size_of::<UniformBufferObject>() * ubos.len()) as u64;
+        self.uniform_buffers[current_image].write(&self.device, 0, &ubos);
+    }
+
+    pub fn acquire_next_image(&mut self, semaphore: vk::Semaphore) -> Result<(u32, bool), vk::Result> {
+        let acquire_result = match &self.device_group {
+            Some(device_group) => device_group.acquire_next_image(&self.swapchain, semaphore),
+            None => unsafe {
+                self.swapchain.swapchain_loader.acquire_next_image(
+                    self.swapchain.swapchain,
+                    u64::MAX,
+                    semaphore,
+                    vk::Fence::null(),
+                )
+            },
+        };
+
+        if let Ok((image_index, _)) = acquire_result {
+            self.swapchain.acquired_images.push(image_index);
+        }
+        acquire_result
+    }
+
+    pub fn submit_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        wait_semaphore: vk::Semaphore,
+        wait_stage: vk::PipelineStageFlags,
+        signal_semaphore: vk::Semaphore,
+        fence: vk::Fence
+    ) {
+        let mut batch = SubmitBatch::new();
+        batch
+            .wait(wait_semaphore, wait_stage)
+            .command_buffer(command_buffer)
+            .signal(signal_semaphore);
+
+        let result = self.submit_batch(&batch, fence);
+        self.expect_device(result, "Failed to execute queue submit.");
+    }
+
+    /// `None` unless `capabilities.synchronization2` is enabled.
+    pub fn synchronization2_loader(&self) -> Option<&ash::extensions::khr::Synchronization2> {
+        self.synchronization2_loader.as_ref()
+    }
+
+    /// `None` unless `capabilities.acceleration_structure` is enabled.
+    pub fn acceleration_structure_loader(&self) -> Option<&ash::extensions::khr::AccelerationStructure> {
+        self.acceleration_structure_loader.as_ref()
+    }
+
+    /// Scratch alignment for `AccelerationStructureBuilder::cmd_build`, `None` unless `capabilities.acceleration_structure` is enabled.
+    pub fn acceleration_structure_properties(&self) -> Option<vk::PhysicalDeviceAccelerationStructurePropertiesKHR> {
+        if !self.capabilities.acceleration_structure {
+            return None;
+        }
+
+        Some(unsafe { ash::extensions::khr::AccelerationStructure::get_properties(&self.instance, self.physical_device) })
+    }
+
+    /// `None` unless `capabilities.ray_tracing` is enabled.
+    pub fn ray_tracing_pipeline_loader(&self) -> Option<&ash::extensions::khr::RayTracingPipeline> {
+        self.ray_tracing_pipeline_loader.as_ref()
+    }
+
+    /// Shader group handle sizes and alignments for `ShaderBindingTable`, `None` unless `capabilities.ray_tracing` is enabled.
+    pub fn ray_tracing_pipeline_properties(&self) -> Option<vk::PhysicalDeviceRayTracingPipelinePropertiesKHR> {
+        if !self.capabilities.ray_tracing {
+            return None;
+        }
+
+        Some(unsafe { ash::extensions::khr::RayTracingPipeline::get_properties(&self.instance, self.physical_device) })
+    }
+
+    /// `None` unless `capabilities.mesh_shader` is enabled.
+    pub fn mesh_shader_loader(&self) -> Option<&ash::extensions::ext::MeshShader> {
+        self.mesh_shader_loader.as_ref()
+    }
+
+    /// Launches `group_count_x * group_count_y * group_count_z` task workgroups, or mesh workgroups without a task shader.
+    /// The bound pipeline has to be built with `GraphicsPipelineBuilder::mesh_shader`.
+    pub fn cmd_draw_mesh_tasks(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        group_count_x: u32,
+        group_count_y: u32,
+        group_count_z: u32,
+    ) {
+        let mesh_shader_loader = self.mesh_shader_loader
+            .as_ref()
+            .expect("Mesh shaders are not enabled on the device!");
+
+        unsafe {
+            mesh_shader_loader.cmd_draw_mesh_tasks(command_buffer, group_count_x, group_count_y, group_count_z);
+        }
+    }
+
+    /// Fragment sizes the device can shade at with the sample counts allowed for each, largest first.
+    /// Empty unless `capabilities.fragment_shading_rate` is enabled.
+    pub fn fragment_shading_rates(&self) -> Vec<vk::PhysicalDeviceFragmentShadingRateKHR> {
+        let fragment_shading_rate_fn = match &self.fragment_shading_rate_fn {
+            Some(fragment_shading_rate_fn) => fragment_shading_rate_fn,
+            None => return vec![],
+        };
+
+        // first call only fills in the count, the second one the rates.
+        let mut rate_count = 0;
+        unsafe {
+            (fragment_shading_rate_fn.get_physical_device_fragment_shading_rates_khr)(
+                self.physical_device,
+                &mut rate_count,
+                ptr::null_mut())
+                .result()
+                .expect("Failed to query fragment shading rates!");
+        }
+
+        let mut rates = vec![vk::PhysicalDeviceFragmentShadingRateKHR::default(); rate_count as usize];
+        unsafe {
+            (fragment_shading_rate_fn.get_physical_device_fragment_shading_rates_khr)(
+                self.physical_device,
+                &mut rate_count,
+                rates.as_mut_ptr())
+                .result()
+                .expect("Failed to query fragment shading rates!");
+        }
+        rates.truncate(rate_count as usize);
+
+        rates
+    }
+
+    /// Shading rate of the following draws, the bound pipeline has to be built with
+    /// `GraphicsPipelineBuilder::dynamic_fragment_shading_rate`.
+    /// `fragment_size` should be one of `fragment_shading_rates`, e.g. 2x2 to shade once per four pixels.
+    pub fn cmd_set_fragment_shading_rate(&self, command_buffer: vk::CommandBuffer, fragment_size: vk::Extent2D) {
+        let fragment_shading_rate_fn = self.fragment_shading_rate_fn
+            .as_ref()
+            .expect("Fragment shading rates are not enabled on the device!");
+        let combiner_ops = FRAGMENT_SHADING_RATE_KEEP_COMBINER_OPS;
+
+        unsafe {
+            (fragment_shading_rate_fn.cmd_set_fragment_shading_rate_khr)(command_buffer, &fragment_size, &combiner_ops);
+        }
+    }
+
+    /// Draws and dispatches until `cmd_end_conditional_rendering` are skipped when the `u32` at `offset` in `buffer` is zero,
+    /// e.g. written by an occlusion query copy. `buffer` needs `CONDITIONAL_RENDERING_EXT` usage.
+    pub fn cmd_begin_conditional_rendering(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+    ) {
+        assert_eq!(offset % 4, 0, "Conditional rendering offset has to be a multiple of 4");
+        let conditional_rendering_fn = self.conditional_rendering_fn
+            .as_ref()
+            .expect("Conditional rendering is not enabled on the device!");
+
+        let begin_info = vk::ConditionalRenderingBeginInfoEXT {
+            s_type: vk::StructureType::CONDITIONAL_RENDERING_BEGIN_INFO_EXT,
+            p_next: ptr::null(),
+            buffer,
+            offset,
+            flags: vk::ConditionalRenderingFlagsEXT::empty(),
+        };
+
+        unsafe {
+            (conditional_rendering_fn.cmd_begin_conditional_rendering_ext)(command_buffer, &begin_info);
+        }
+    }
+
+    pub fn cmd_end_conditional_rendering(&self, command_buffer: vk::CommandBuffer) {
+        let conditional_rendering_fn = self.conditional_rendering_fn
+            .as_ref()
+            .expect("Conditional rendering is not enabled on the device!");
+
+        unsafe {
+            (conditional_rendering_fn.cmd_end_conditional_rendering_ext)(command_buffer);
+        }
+    }
+
+    /// For `TransformFeedbackCapture`, `None` unless `capabilities.transform_feedback` is enabled.
+    pub fn transform_feedback_fn(&self) -> Option<&vk::ExtTransformFeedbackFn> {
+        self.transform_feedback_fn.as_ref()
+    }
+
+    /// Submits `batch` to the graphics queue, through `vkQueueSubmit2` when it is enabled.
+    pub fn submit_batch(&self, batch: &SubmitBatch, fence: vk::Fence) -> Result<(), vk::Result> {
+        match &self.synchronization2_loader {
+            Some(synchronization2_loader) => batch.submit2(
+                synchronization2_loader,
+                self.graphics_queue,
+                fence,
+                self.device_group.as_ref()),
+            None => batch.submit(
+                &self.device,
+                self.graphics_queue,
+                fence,
+                self.device_group.as_ref()),
+        }
+    }
+
+    /// Returns whether the swapchain is suboptimal, like `queue_present`,
+    /// and the id the present was tagged with when `capabilities.present_id` is enabled.
+    /// The id can be matched against profiling data or handed to `wait_for_present`.
+    pub fn present(&mut self, image_index: u32, wait_semaphore: vk::Semaphore) -> Result<(bool, Option<u64>), vk::Result> {
+        self.present_with_time(image_index, wait_semaphore, 0)
+    }
+
+    /// Same as `present`, but the image isn't shown before `desired_present_time`, in nanoseconds of `CLOCK_MONOTONIC`
+    /// or whatever clock the platform presents with, e.g. to show video frames at their timestamps.
+    /// Needs `capabilities.display_timing`, returns `ERROR_EXTENSION_NOT_PRESENT` otherwise.
+    /// The present is tagged with `swapchain.last_display_timing_id` afterwards, see `past_presentation_timings`.
+    pub fn present_at(
+        &mut self,
+        image_index: u32,
+        wait_semaphore: vk::Semaphore,
+        desired_present_time: u64,
+    ) -> Result<(bool, Option<u64>), vk::Result> {
+        if self.display_timing_fn.is_none() {
+            return Err(vk::Result::ERROR_EXTENSION_NOT_PRESENT);
+        }
+
+        self.present_with_time(image_index, wait_semaphore, desired_present_time)
+    }
+
+    /// Actual, earliest and requested times of presents that completed since the last call, matched by
+    /// `present_id` to `swapchain.last_display_timing_id` of their present. Only reports each present once.
+    /// Needs `capabilities.display_timing`, returns `ERROR_EXTENSION_NOT_PRESENT` otherwise.
+    pub fn past_presentation_timings(&self) -> Result<Vec<vk::PastPresentationTimingGOOGLE>, vk::Result> {
+        let display_timing_fn = self.display_timing_fn
+            .as_ref()
+            .ok_or(vk::Result::ERROR_EXTENSION_NOT_PRESENT)?;
+
+        let mut count = 0;
+        unsafe {
+            (display_timing_fn.get_past_presentation_timing_google)(
+                self.device.handle(),
+                self.swapchain.swapchain,
+                &mut count,
+                ptr::null_mut())
+                .result()?;
+        }
+
+        let mut timings = vec![vk::PastPresentationTimingGOOGLE::default(); count as usize];
+        unsafe {
+            (display_timing_fn.get_past_presentation_timing_google)(
+                self.device.handle(),
+                self.swapchain.swapchain,
+                &mut count,
+                timings.as_mut_ptr())
+                .result()?;
+        }
+        // presents can complete between the calls, they are reported next time.
+        timings.truncate(count as usize);
+
+        Ok(timings)
+    }
+
+    /// Sets `metadata` on the current swapchain and keeps it for every recreation, e.g. `swap_chain::hdr10_metadata`
+    /// for an HDR10_ST2084_EXT format picked through `set_format`. `None` stops setting it on new swapchains.
+    /// Needs `capabilities.hdr_metadata`, returns `ERROR_EXTENSION_NOT_PRESENT` otherwise.
+    pub fn set_hdr_metadata(&mut self, metadata: Option<vk::HdrMetadataEXT>) -> Result<(), vk::Result> {
+        let hdr_metadata_fn = self.hdr_metadata_fn
+            .as_ref()
+            .ok_or(vk::Result::ERROR_EXTENSION_NOT_PRESENT)?;
+
+        // without a swapchain after a lost surface it's only set once `recreate_surface` created one.
+        if let Some(metadata) = &metadata {
+            if self.swapchain.swapchain != vk::SwapchainKHR::null() {
+                self.swapchain.set_hdr_metadata(hdr_metadata_fn, &self.device, metadata);
+            }
+        }
+        self.swapchain_config.hdr_metadata = metadata;
+        Ok(())
+    }
+
+    /// Nanoseconds between two vertical blanks of the display the swapchain presents to, the step `present_at` times snap to.
+    /// Needs `capabilities.display_timing`, returns `ERROR_EXTENSION_NOT_PRESENT` otherwise.
+    pub fn refresh_cycle_duration(&self) -> Result<u64, vk::Result> {
+        let display_timing_fn = self.display_timing_fn
+            .as_ref()
+            .ok_or(vk::Result::ERROR_EXTENSION_NOT_PRESENT)?;
+
+        let mut refresh_cycle_duration = vk::RefreshCycleDurationGOOGLE::default();
+        unsafe {
+            (display_timing_fn.get_refresh_cycle_duration_google)(
+                self.device.handle(),
+                self.swapchain.swapchain,
+                &mut refresh_cycle_duration)
+                .result()?;
+        }
+
+        Ok(refresh_cycle_duration.refresh_duration)
+    }
+
+    /// `desired_present_time` 0 presents as soon as possible.
+    fn present_with_time(
+        &mut self,
+        image_index: u32,
+        wait_semaphore: vk::Semaphore,
+        desired_present_time: u64,
+    ) -> Result<(bool, Option<u64>), vk::Result> {
+        if let swap_chain::PresentSink::File { dir } = &self.swapchain_config.present_sink {
+            let dir = dir.clone();
+            self.write_swapchain_image(image_index, wait_semaphore, &dir)?;
+            self.swapchain.is_image_initialized[image_index as usize] = true;
+
+            self.swapchain.acquired_images.retain(|&acquired_image| acquired_image != image_index);
+            if self.swapchain_maintenance1_fn.is_some() {
+                self.release_swapchain_images(&[image_index]);
+                return Ok((false, None));
+            }
+            // without VK_EXT_swapchain_maintenance1 only a present or destroying the swapchain gives the image back,
+            // reporting it suboptimal gets it recreated.
+            return Ok((true, None));
+        }
+
+        let wait_semaphore = match &self.present_ownership_transfer {
+            Some(present_ownership_transfer) => present_ownership_transfer.submit_acquire(
+                &self.device,
+                self.present_queue,
+                image_index,
+                wait_semaphore)?,
+            None => wait_semaphore,
+        };
+        let wait_semaphores: Vec<vk::Semaphore> = Some(wait_semaphore)
+            .filter(|&semaphore| semaphore != vk::Semaphore::null())
+            .into_iter()
+            .collect();
+        let swapchains = [self.swapchain.swapchain];
+        let image_indices = [image_index];
+
+        let present_id = if self.capabilities.present_id {
+            self.swapchain.last_present_id += 1;
+            Some(self.swapchain.last_present_id)
+        } else {
+            None
+        };
+        let present_ids = [self.swapchain.last_present_id];
+        let mut present_id_info = vk::PresentIdKHR {
+            s_type: vk::StructureType::PRESENT_ID_KHR,
+            p_next: ptr::null(),
+            swapchain_count: present_ids.len() as u32,
+            p_present_ids: present_ids.as_ptr(),
+        };
+
+        if self.display_timing_fn.is_some() {
+            self.swapchain.last_display_timing_id = self.swapchain.last_display_timing_id.wrapping_add(1);
+        }
+        let present_times = [vk::PresentTimeGOOGLE {
+            present_id: self.swapchain.last_display_timing_id,
+            desired_present_time: desired_present_time,
+        }];
+        let mut present_times_info = vk::PresentTimesInfoGOOGLE {
+            s_type: vk::StructureType::PRESENT_TIMES_INFO_GOOGLE,
+            p_next: ptr::null(),
+            swapchain_count: present_times.len() as u32,
+            p_times: present_times.as_ptr(),
+        };
+
+        let present_modes = [self.swapchain.present_mode];
+        let mut present_mode_info = vk::SwapchainPresentModeInfoEXT {
+            s_type: vk::StructureType::SWAPCHAIN_PRESENT_MODE_INFO_EXT,
+            p_next: ptr::null(),
+            swapchain_count: present_modes.len() as u32,
+            p_present_modes: present_modes.as_ptr(),
+        };
+
+        self.swapchain.acquired_images.retain(|&acquired_image| acquired_image != image_index);
+
+        self.recycle_present_fences();
+        let present_fences = if self.swapchain_maintenance1_fn.is_some() {
+            let present_fence = self.free_present_fences
+                .pop()
+                .unwrap_or_else(|| VkFence::new(&self.device, false));
+            let fences = [present_fence.fence];
+            self.swapchain.present_fences.push(present_fence);
+            Some(fences)
+        } else {
+            None
+        };
+        let mut present_fence_info = present_fences.as_ref().map(|fences| vk::SwapchainPresentFenceInfoEXT {
+            s_type: vk::StructureType::SWAPCHAIN_PRESENT_FENCE_INFO_EXT,
+            p_next: ptr::null(),
+            swapchain_count: fences.len() as u32,
+            p_fences: fences.as_ptr(),
+        });
+
+        let mut device_group_present_info = self.device_group.as_ref().map(|device_group| device_group.present_info());
+
+        let mut present_chain: Vec<*mut vk::BaseOutStructure> = vec![];
+        if present_id.is_some() {
+            present_chain.push(&mut present_id_info as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if let Some(present_info) = &mut device_group_present_info {
+            present_chain.push(present_info as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if self.swapchain_config.present_mode_switching {
+            present_chain.push(&mut present_mode_info as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if let Some(fence_info) = &mut present_fence_info {
+            present_chain.push(fence_info as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if self.display_timing_fn.is_some() {
+            present_chain.push(&mut present_times_info as *mut _ as *mut vk::BaseOutStructure);
+        }
+
+        let mut present_info = vk::PresentInfoKHR {
+            s_type: vk::StructureType::PRESENT_INFO_KHR,
+            p_next: ptr::null(),
+            wait_semaphore_count: wait_semaphores.len() as u32,
+            p_wait_semaphores: wait_semaphores.as_ptr(),
+            swapchain_count: swapchains.len() as u32,
+            p_swapchains: swapchains.as_ptr(),
+            p_image_indices: image_indices.as_ptr(),
+            p_results: ptr::null_mut(),
+        };
+
+        let present_result = unsafe {
+            capabilities::link_p_next(&mut present_info as *mut _ as *mut vk::BaseOutStructure, &present_chain);
+
+            self.swapchain.swapchain_loader
+                .queue_present(self.present_queue, &present_info)
+        };
+
+        if let Some(device_group) = &mut self.device_group {
+            device_group.advance();
+        }
+        if present_result.is_ok() {
+            self.swapchain.is_image_initialized[image_index as usize] = true;
+        }
+
+        present_result.map(|is_suboptimal| (is_suboptimal, present_id))
+    }
+
+    /// Reads `image_index` back once `wait_semaphore` signals and writes it to `dir` as `frame_<n>.png`,
+    /// `n` being `deletion_queue.frame_index()`. Waits until the graphics queue is idle, this is a debug path.
+    /// Fails with `ERROR_FEATURE_NOT_PRESENT` when the images can't be read back: without `TRANSFER_SRC` usage,
+    /// while protected or while EXCLUSIVE between queue families. Failures to write the file are logged,
+    /// the frame is dropped then.
+    fn write_swapchain_image(&self, image_index: u32, wait_semaphore: vk::Semaphore, dir: &Path) -> Result<(), vk::Result> {
+        // the images are released to the present family at the end of each frame, so the graphics queue can't read them.
+        let can_read_back = self.swapchain.swapchain_usage.contains(vk::ImageUsageFlags::TRANSFER_SRC)
+            && self.present_ownership_transfer.is_none()
+            && !self.swapchain.is_protected;
+        if !can_read_back {
+            log::error!("PresentSink::File can't read back the swapchain images");
+            return Err(vk::Result::ERROR_FEATURE_NOT_PRESENT);
+        }
+
+        let extent = self.swapchain.swapchain_extent;
+        let image = self.swapchain.swapchain_images[image_index as usize];
+        let subresource_range = image::full_subresource_range(vk::ImageAspectFlags::COLOR);
+
+        let device_memory_properties =
+            unsafe { self.instance.get_physical_device_memory_properties(self.physical_device) };
+        let readback_buffer = MappedBuffer::new(
+            &self.device,
+            extent.width as vk::DeviceSize * extent.height as vk::DeviceSize * 4,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            &device_memory_properties,
+            self.capabilities.non_coherent_atom_size,
+        );
+
+        commands::execute_one_time_commands_after(
+            &self.device,
+            self.command_pool,
+            self.graphics_queue,
+            wait_semaphore,
+            vk::PipelineStageFlags::TRANSFER,
+            |command_buffer| {
+                image::cmd_transition_image_layout(
+                    &self.device,
+                    command_buffer,
+                    image,
+                    subresource_range,
+                    vk::ImageLayout::PRESENT_SRC_KHR,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+                image::cmd_copy_image_to_buffer(
+                    &self.device,
+                    command_buffer,
+                    image,
+                    readback_buffer.buffer,
+                    vk::ImageAspectFlags::COLOR,
+                    0,
+                    extent.width,
+                    extent.height,
+                    1);
+                image::cmd_transition_image_layout(
+                    &self.device,
+                    command_buffer,
+                    image,
+                    subresource_range,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    vk::ImageLayout::PRESENT_SRC_KHR);
+
+                let host_read_barriers = [vk::MemoryBarrier {
+                    s_type: vk::StructureType::MEMORY_BARRIER,
+                    p_next: ptr::null(),
+                    src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                    dst_access_mask: vk::AccessFlags::HOST_READ,
+                }];
+                unsafe {
+                    self.device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::PipelineStageFlags::HOST,
+                        vk::DependencyFlags::empty(),
+                        &host_read_barriers,
+                        &[],
+                        &[],
+                    );
+                }
+            });
+
+        readback_buffer.invalidate_range(&self.device, 0, readback_buffer.size);
+        let pixels = self.swapchain.readback_to_rgba8(readback_buffer.as_slice());
+        readback_buffer.destroy(&self.device);
+
+        let pixels = match pixels {
+            Some(pixels) => pixels,
+            None => {
+                log::warn!("Can't write {:?} swapchain images to a file, only 8 bit RGBA and BGRA", self.swapchain.swapchain_format);
+                return Ok(());
+            }
+        };
+
+        let path = dir.join(format!("frame_{}.png", self.deletion_queue.frame_index()));
+        let result = std::fs::create_dir_all(dir)
+            .map_err(|error| error.to_string())
+            .and_then(|_| {
+                ::image::save_buffer(&path, &pixels, extent.width, extent.height, ::image::ColorType::Rgba8)
+                    .map_err(|error| error.to_string())
+            });
+        if let Err(error) = result {
+            log::error!("Failed to write {}: {}", path.display(), error);
+        }
+        Ok(())
+    }
+
+    /// Blocks until the present tagged with `present_id` has reached the display, or `timeout` nanoseconds passed.
+    /// Needs `capabilities.present_wait`, returns `ERROR_EXTENSION_NOT_PRESENT` otherwise.
+    pub fn wait_for_present(&self, present_id: u64, timeout: u64) -> Result<(), vk::Result> {
+        match &self.present_wait_loader {
+            Some(present_wait_loader) => unsafe {
+                present_wait_loader.wait_for_present(self.swapchain.swapchain, present_id, timeout)
+            },
+            None => Err(vk::Result::ERROR_EXTENSION_NOT_PRESENT),
+        }
+    }
+
+    /// Waits until the device finished all submitted work.
+    /// Every `destroy`/`drop` in the engine assumes this happened, nothing in use by the device may be destroyed.
+    /// Returns `ERROR_DEVICE_LOST` when the device was lost while waiting.
+    pub fn wait_idle(&self) -> Result<(), vk::Result> {
+        unsafe { self.device.device_wait_idle() }
+    }
+
+    /// Regions of the window's surface on each display, see `VkSpawChain::present_rectangles`.
+    pub fn present_rectangles(&self) -> Vec<vk::Rect2D> {
+        VkSpawChain::present_rectangles(
+            &self.entry,
+            &self.instance,
+            self.physical_device,
+            &self.surface,
+            self.capabilities.api_version)
+    }
+
+    /// See `VkSpawChain::supported_swapchain_usage`, e.g. to only offer screenshots or `PresentSink::File`
+    /// when `TRANSFER_SRC` is in there.
+    pub fn supported_swapchain_usage(&self) -> vk::ImageUsageFlags {
+        VkSpawChain::supported_swapchain_usage(self.physical_device, &self.surface)
+    }
+
+    /// Whether the window's surface is 0x0, e.g. while minimized. `render_frame` skips frames then,
+    /// the main loop should wait for window events instead of rendering until it's restored.
+    /// Queried once per frame, until the next `acquire_frame` the answer is reused.
+    pub fn is_minimized(&self) -> bool {
+        // a lost surface can't be queried, the next frame reports it instead.
+        if self.is_surface_lost {
+            return false;
+        }
+        if let Some(is_minimized) = self.is_minimized_cache.get() {
+            return is_minimized;
+        }
+
+        let is_minimized = VkSpawChain::is_zero_extent(VkSpawChain::current_surface_extent(self.physical_device, &self.surface));
+        self.is_minimized_cache.set(Some(is_minimized));
+        is_minimized
+    }
+
+    /// Call on window resize events with the new inner size in physical pixels,
+    /// drivers don't have to report `OUT_OF_DATE_KHR` for every size change.
+    pub fn mark_resized(&mut self, width: u32, height: u32) {
+        self.surface.screen_width = width;
+        self.surface.screen_height = height;
+        self.is_framebuffer_resized = true;
+        self.is_minimized_cache.set(None);
+    }
+
+    /// Call when the window moved to another display, its surface formats and present modes can differ there.
+    /// The swapchain is recreated with freshly queried ones on the next frame.
+    pub fn mark_display_changed(&mut self) {
+        self.swapchain_support_cache.invalidate();
+        self.is_framebuffer_resized = true;
+    }
+
+    /// Runs one frame of the swapchain lifecycle: waits for the frame's fence, acquires an image,
+    /// lets `record` return the command buffer to draw it with, submits and presents it.
+    /// The swapchain is recreated when acquire or present report it out of date or suboptimal, or after `mark_resized`.
+    /// Returns `Ok(false)` when no image could be acquired and the frame was skipped, which includes while
+    /// `is_minimized`, and `FrameError::SurfaceLost` once the surface is gone, see `recreate_surface`.
+    pub fn render_frame<F>(&mut self, record: F) -> Result<bool, FrameError>
+    where
+        F: FnOnce(&mut VkRenderDevice, u32) -> vk::CommandBuffer,
+    {
+        let frame = match self.acquire_frame()? {
+            Some(frame) => frame,
+            None => return Ok(false),
+        };
+
+        let command_buffer = record(self, frame.image_index);
+        self.submit_frame(frame, command_buffer)
+    }
+
+    /// First half of `render_frame` for acquiring as early as possible, e.g. at the start of the frame's CPU work
+    /// so the acquire overlaps with it instead of stalling right before the submit.
+    /// Waits for the frame's fence and acquires an image, `submit_frame` has to follow before the next acquire.
+    /// Returns `Ok(None)` when no image could be acquired and the frame was skipped, which includes while
+    /// `is_minimized`, and `FrameError::SurfaceLost` once the surface is gone.
+    pub fn acquire_frame(&mut self) -> Result<Option<AcquiredFrame>, FrameError> {
+        assert!(!self.is_frame_acquired, "acquire_frame called again before submit_frame!");
+
+        if self.is_surface_lost {
+            return Err(FrameError::SurfaceLost);
+        }
+        let is_minimized = self.is_minimized();
+        self.is_minimized_cache.set(None);
+        if is_minimized {
+            return Ok(None);
+        }
+        if self.swapchain.swapchain == vk::SwapchainKHR::null() {
+            self.recreate_swapchain();
+        }
+        // nothing but the swapchain decides the window's content size on these surfaces, so the new size is used
+        // right away; this is also what fixes up the first frames when only a configure event told the real size.
+        if self.is_framebuffer_resized && self.swapchain.is_extent_from_window {
+            self.is_framebuffer_resized = false;
+            self.recreate_swapchain();
+        }
+
+        let fence_wait = self.sync_objects.inflight_fences[self.current_frame].wait(u64::MAX);
+        self.expect_device(fence_wait, "Failed to wait for Fence!");
+
+        let image_available_semaphore = self.sync_objects.image_available_semaphores[self.current_frame].semaphore;
+
+        let (image_index, is_suboptimal) = match self.acquire_next_image(image_available_semaphore) {
+            Ok(acquired) => acquired,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.is_framebuffer_resized = false;
+                self.recreate_swapchain();
+                return Ok(None);
+            }
+            Err(vk::Result::ERROR_SURFACE_LOST_KHR) => {
+                self.is_surface_lost = true;
+                return Err(FrameError::SurfaceLost);
+            }
+            Err(error) => self.expect_device(Err(error), "Failed to acquire Swap Chain Image"),
+        };
+        // only once the frame is sure to be submitted, a skipped acquire advancing the queue would destroy what
+        // the frame still running on the other slot uses.
+        self.deletion_queue.begin_frame();
+
+        #[cfg(feature = "debug-overlay")]
+        {
+            let memory_budget = self.memory_budget();
+            self.debug_overlay.update(&self.device, image_index, memory_budget);
+        }
+
+        self.is_frame_acquired = true;
+        Ok(Some(AcquiredFrame {
+            image_index: image_index,
+            frame: self.current_frame,
+            is_suboptimal: is_suboptimal,
+            image_available_semaphore: image_available_semaphore,
+        }))
+    }
+
+    /// Second half of `render_frame`: submits `command_buffer`, which has to draw `frame.image_index`,
+    /// waiting on the image's acquire at color attachment output, then presents it and moves on to the next frame.
+    /// The submit is a protected one while the swapchain is, see `set_protected`.
+    /// Returns `Ok(true)`, the frame counts as presented even when the swapchain is recreated afterwards.
+    /// `FrameError::SurfaceLost` when the present found the surface gone, the frame was still submitted.
+    pub fn submit_frame(&mut self, frame: AcquiredFrame, command_buffer: vk::CommandBuffer) -> Result<bool, FrameError> {
+        assert_eq!(frame.frame, self.current_frame, "AcquiredFrame of another frame in flight!");
+        self.is_frame_acquired = false;
+
+        let inflight_fence = &self.sync_objects.inflight_fences[frame.frame];
+        inflight_fence.reset();
+        let inflight_fence = inflight_fence.fence;
+        let render_finished_semaphore = self.sync_objects.render_finished_semaphores[frame.frame].semaphore;
+
+        let mut batch = SubmitBatch::new();
+        batch
+            .wait(frame.image_available_semaphore, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .command_buffer(command_buffer)
+            .signal(render_finished_semaphore);
+        // unprotected work must not write the protected images.
+        if self.swapchain.is_protected {
+            batch.protected();
+        }
+        let submitted = self.submit_batch(&batch, inflight_fence);
+        self.expect_device(submitted, "Failed to execute queue submit.");
+
+        let present_result = self.present(frame.image_index, render_finished_semaphore);
+        self.current_frame = (self.current_frame + 1) % global_constants::MAX_FRAMES_IN_FLIGHT;
+
+        let needs_recreate = match present_result {
+            Ok((is_present_suboptimal, _)) => frame.is_suboptimal || is_present_suboptimal,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => true,
+            Err(vk::Result::ERROR_SURFACE_LOST_KHR) => {
+                self.is_surface_lost = true;
+                return Err(FrameError::SurfaceLost);
+            }
+            Err(error) => self.expect_device(Err(error), "Failed to execute queue present"),
+        };
+
+        if needs_recreate || self.is_framebuffer_resized {
+            self.is_framebuffer_resized = false;
+            self.recreate_swapchain();
+        }
+
+        Ok(true)
+    }
+
+    /// Whether acquire or present reported the surface lost, see `FrameError::SurfaceLost`.
+    pub fn is_surface_lost(&self) -> bool {
+        self.is_surface_lost
+    }
+
+    /// Replaces a lost surface with a new one for `window`, then the swapchain and everything built on it.
+    /// Waits for the device to be idle first. Also works on a surface that isn't lost, e.g. to move to
+    /// another window. Fails with `ERROR_SURFACE_LOST_KHR` when the present queue family can't present to
+    /// the new surface, e.g. because the window is now on a display of another GPU; the old surface is
+    /// gone either way and frames keep failing with `FrameError::SurfaceLost` until a later call succeeds.
+    pub fn recreate_surface(&mut self, window: &window::Window) -> Result<(), vk::Result> {
+        assert!(!self.is_frame_acquired, "Surface recreated between acquire_frame and submit_frame!");
+        self.is_surface_lost = true;
+
+        let idle = self.wait_idle();
+        self.expect_device(idle, "Failed to wait device idle");
+        self.deletion_queue.flush();
+
+        // swapchains have to go before their surface, the retired ones included.
+        if self.swapchain.swapchain != vk::SwapchainKHR::null() {
+            self.cleanup_swapchain_resources();
+            self.swapchain.destroy_swapchain();
+            self.swapchain.swapchain = vk::SwapchainKHR::null();
+        }
+        for retired_swapchain in self.retired_swapchains.drain(..) {
+            lifecycle::on_destroy(TrackedObject::Swapchain, retired_swapchain.swapchain);
+            unsafe {
+                self.swapchain.swapchain_loader.destroy_swapchain(retired_swapchain.swapchain, None);
+            }
+        }
+        if self.surface.surface != vk::SurfaceKHR::null() {
+            unsafe {
+                self.surface.surface_loader.destroy_surface(self.surface.surface, None);
+            }
+            self.surface.surface = vk::SurfaceKHR::null();
+        }
+
+        self.surface = VkRenderDevice::create_surface(
+            &self.entry,
+            &self.instance,
+            SurfaceTarget::Window(window),
+            self.surface_backend,
+            self.surface.surface_maintenance1,
+            self.surface.surface_protected_capabilities)?;
+        self.swapchain_support_cache.invalidate();
+
+        let can_present = unsafe {
+            self.surface.surface_loader
+                .get_physical_device_surface_support(self.physical_device, self.indices.present_family.unwrap(), self.surface.surface)?
+        };
+        if !can_present {
+            log::error!("The present queue family can't present to the recreated surface");
+            return Err(vk::Result::ERROR_SURFACE_LOST_KHR);
+        }
+
+        // presents that failed left their semaphores signaled, fresh ones are simpler than tracking which.
+        self.sync_objects = VkRenderDevice::create_sync_objects(&self.device);
+        self.current_frame = 0;
+        self.is_surface_lost = false;
+        self.is_framebuffer_resized = false;
+        self.is_minimized_cache.set(None);
+
+        // while minimized the swapchain is left null, the first acquire after the restore creates it.
+        if !self.is_minimized() {
+            self.create_swapchain_resources(vk::SwapchainKHR::null());
+        }
+        log::info!("Recreated the surface");
+        Ok(())
+    }
+
+    /// What faulted after a call returned `ERROR_DEVICE_LOST`.
+    /// Needs `capabilities.device_fault`, returns `ERROR_EXTENSION_NOT_PRESENT` otherwise.
+    pub fn device_fault(&self) -> Result<debug::DeviceFaultReport, vk::Result> {
+        match &self.device_fault_fn {
+            Some(device_fault_fn) => debug::DeviceFaultReport::query(device_fault_fn, self.device.handle()),
+            None => Err(vk::Result::ERROR_EXTENSION_NOT_PRESENT),
+        }
+    }
+
+    /// Usage and budget in bytes summed over the device local heaps, the budget being how much this process
+    /// can use before allocations may start failing or evicting. `None` without `capabilities.memory_budget`.
+    pub fn memory_budget(&self) -> Option<(vk::DeviceSize, vk::DeviceSize)> {
+        if !self.capabilities.memory_budget {
+            return None;
+        }
+
+        let mut memory_budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut memory_properties2 = vk::PhysicalDeviceMemoryProperties2 {
+            s_type: vk::StructureType::PHYSICAL_DEVICE_MEMORY_PROPERTIES_2,
+            p_next: &mut memory_budget_properties as *mut _ as *mut c_void,
+            memory_properties: vk::PhysicalDeviceMemoryProperties::default(),
+        };
+        unsafe {
+            self.instance.get_physical_device_memory_properties2(self.physical_device, &mut memory_properties2);
+        }
+
+        let memory_properties = &memory_properties2.memory_properties;
+        let device_local_heaps = (0..memory_properties.memory_heap_count as usize)
+            .filter(|&heap| memory_properties.memory_heaps[heap].flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL));
+
+        Some(device_local_heaps.fold((0, 0), |(usage, budget), heap| {
+            (usage + memory_budget_properties.heap_usage[heap], budget + memory_budget_properties.heap_budget[heap])
+        }))
+    }
+
+    /// Unwraps like `expect`, but logs the `device_fault` report first when the device was lost,
+    /// with the vendor crash dump going to the working directory.
+    fn expect_device<T>(&self, result: Result<T, vk::Result>, message: &str) -> T {
+        match result {
+            Ok(value) => value,
+            Err(vk::Result::ERROR_DEVICE_LOST) => {
+                match self.device_fault() {
+                    Ok(report) => report.log(std::path::Path::new(".")),
+                    Err(error) => log::error!("Device lost, no fault info: {:?}", error),
+                }
+                panic!("{}: {:?}", message, vk::Result::ERROR_DEVICE_LOST)
+            }
+            Err(error) => panic!("{}: {:?}", message, error),
+        }
+    }
+
+    /// Format and color space pairs the surface supports, in the driver's order,
+    /// `VkSpawChain::describe_format` names them. The current one is `swapchain.swapchain_format`.
+    pub fn available_formats(&mut self) -> Vec<vk::SurfaceFormatKHR> {
+        self.swapchain_support_cache.query(self.physical_device, &self.surface).formats
+    }
+
+    /// Recreates the swapchain with `surface_format`, which stays the preferred one for later recreations.
+    /// Returns `false` without recreating when the surface doesn't support it.
+    pub fn set_format(&mut self, surface_format: vk::SurfaceFormatKHR) -> bool {
+        if !self.available_formats().contains(&surface_format) {
+            return false;
+        }
+
+        self.swapchain_config.preferred_format = Some(surface_format);
+        if surface_format.format != self.swapchain.swapchain_format
+            || surface_format.color_space != self.swapchain.swapchain_color_space {
+            self.recreate_swapchain();
+        }
+        true
+    }
+
+    /// Recreates the swapchain with protected images or back, see `SwapChainConfig::protected`.
+    /// While they're protected the built-in command buffers are recorded from a protected pool and `submit_frame`
+    /// submits protected, so the command buffers `render_frame` records have to come from `create_protected_command_pool`.
+    /// Returns `false` without recreating when `capabilities.protected_memory` isn't enabled
+    /// or the surface doesn't support protected swapchains.
+    pub fn set_protected(&mut self, protected: bool) -> bool {
+        if protected && !(self.capabilities.protected_memory && VkSpawChain::supports_protected(self.physical_device, &self.surface)) {
+            return false;
+        }
+        if protected && self.protected_command_pool.is_none() {
+            let protected_command_pool = self.create_protected_command_pool();
+            self.protected_command_pool = Some(self.expect_device(protected_command_pool, "Failed to create protected Command Pool!"));
+        }
+
+        self.swapchain_config.protected = protected;
+        if protected != self.swapchain.is_protected {
+            self.recreate_swapchain();
+        }
+        true
+    }
+
+    /// EXCLUSIVE or CONCURRENT, always EXCLUSIVE when graphics and present are the same family.
+    pub fn sharing_mode(&self) -> vk::SharingMode {
+        self.swapchain.image_sharing_mode
+    }
+
+    /// Recreates the swapchain with images of `sharing_mode`, which is kept for later recreations and overrides
+    /// what `tune_sharing_mode` chose. Returns `false` for CONCURRENT when graphics and present are the same family.
+    pub fn set_sharing_mode(&mut self, sharing_mode: vk::SharingMode) -> bool {
+        if !self.has_separate_present_queue() {
+            return sharing_mode == vk::SharingMode::EXCLUSIVE;
+        }
+
+        self.swapchain_config.force_exclusive_sharing = sharing_mode == vk::SharingMode::EXCLUSIVE;
+        if sharing_mode != self.swapchain.image_sharing_mode {
+            self.recreate_swapchain();
+        }
+        true
+    }
+
+    /// Experimental: presents `frames` frames of the built-in command buffers with EXCLUSIVE images and ownership
+    /// transfers, then as many with CONCURRENT images, and keeps the mode with the lower mean frame time.
+    /// Only tells something with a present mode that isn't capped at the refresh rate, e.g. MAILBOX or IMMEDIATE.
+    /// Returns `None` and leaves the sharing mode as it was when graphics and present are the same family,
+    /// or when a frame was skipped or failed, since the modes couldn't be compared fairly then.
+    pub fn tune_sharing_mode(&mut self, frames: u32) -> Option<swap_chain::SharingModeBenchmark> {
+        if !self.has_separate_present_queue() || frames == 0 {
+            return None;
+        }
+        let initial_sharing_mode = self.sharing_mode();
+
+        let mut frame_times = vec![];
+        for &sharing_mode in [vk::SharingMode::EXCLUSIVE, vk::SharingMode::CONCURRENT].iter() {
+            self.set_sharing_mode(sharing_mode);
+            match self.benchmark_frames(frames) {
+                Some(frame_time) => frame_times.push(frame_time),
+                None => {
+                    self.set_sharing_mode(initial_sharing_mode);
+                    return None;
+                }
+            }
+        }
+
+        let chosen = if frame_times[1] < frame_times[0] {
+            vk::SharingMode::CONCURRENT
+        } else {
+            vk::SharingMode::EXCLUSIVE
+        };
+        self.set_sharing_mode(chosen);
+
+        Some(swap_chain::SharingModeBenchmark {
+            exclusive_frame_time: frame_times[0],
+            concurrent_frame_time: frame_times[1],
+            chosen: chosen,
+        })
+    }
+
+    /// Mean time per frame over `frames` frames after a few untimed ones, `None` when one was skipped or failed.
+    fn benchmark_frames(&mut self, frames: u32) -> Option<Duration> {
+        const WARMUP_FRAMES: u32 = 5;
+
+        let mut start = Instant::now();
+        for frame in 0..WARMUP_FRAMES + frames {
+            if frame == WARMUP_FRAMES {
+                self.wait_idle().ok()?;
+                start = Instant::now();
+            }
+
+            let is_presented = self
+                .render_frame(|render_device, image_index| render_device.command_buffers[image_index as usize])
+                .ok()?;
+            if !is_presented {
+                return None;
+            }
+        }
+        self.wait_idle().ok()?;
+
+        Some(start.elapsed() / frames)
+    }
+
+    /// Sharing mode from the tuning's cache file when it has one for this device and driver,
+    /// otherwise `tune_sharing_mode` decides and the result is written back to the file.
+    fn apply_sharing_mode_tuning(&mut self, tuning: &swap_chain::SharingModeTuning) {
+        let properties = unsafe { self.instance.get_physical_device_properties(self.physical_device) };
+        let cache_key = swap_chain::sharing_mode_cache_key(&properties);
+        let cache_contents = tuning.cache_path
+            .as_ref()
+            .and_then(|cache_path| std::fs::read_to_string(cache_path).ok())
+            .unwrap_or_default();
+
+        if let Some(sharing_mode) = swap_chain::cached_sharing_mode(&cache_contents, &cache_key) {
+            log::info!("Using the cached {:?} swapchain sharing mode", sharing_mode);
+            self.set_sharing_mode(sharing_mode);
+            return;
+        }
+
+        let benchmark = match self.tune_sharing_mode(tuning.frames) {
+            Some(benchmark) => benchmark,
+            None => {
+                log::info!("Swapchain sharing mode benchmark skipped, keeping {:?}", self.sharing_mode());
+                return;
+            }
+        };
+        log::info!(
+            "Swapchain sharing mode benchmark: EXCLUSIVE {:?}, CONCURRENT {:?} per frame, using {:?}",
+            benchmark.exclusive_frame_time,
+            benchmark.concurrent_frame_time,
+            benchmark.chosen);
+
+        if let Some(cache_path) = &tuning.cache_path {
+            let cache_contents = swap_chain::with_cached_sharing_mode(&cache_contents, &cache_key, benchmark.chosen);
+            if let Err(error) = std::fs::write(cache_path, cache_contents) {
+                log::warn!("Failed to write {}: {}", cache_path.display(), error);
+            }
+        }
+    }
+
+    /// Recreates the swapchain with a transparent or opaque window, see `SwapChainConfig::transparent`.
+    /// Returns whether the window is transparent now, `false` when it was asked for but the surface fell back to opaque.
+    pub fn set_transparent(&mut self, transparent: bool) -> bool {
+        // compared to the request rather than the result, a fallback would otherwise be recreated on every call.
+        if transparent != self.swapchain_config.transparent {
+            self.swapchain_config.transparent = transparent;
+            self.recreate_swapchain();
+        }
+        self.swapchain.is_transparent()
+    }
+
+    /// Command pool on the graphics family for drawing to protected images, its command buffers have to go
+    /// through `SubmitBatch::protected` submits. Needs `capabilities.protected_memory`, returns
+    /// `ERROR_FEATURE_NOT_PRESENT` otherwise. Owned by the caller.
+    pub fn create_protected_command_pool(&self) -> Result<vk::CommandPool, vk::Result> {
+        if !self.capabilities.protected_memory {
+            return Err(vk::Result::ERROR_FEATURE_NOT_PRESENT);
+        }
+
+        let command_pool_create_info = vk::CommandPoolCreateInfo {
+            s_type: vk::StructureType::COMMAND_POOL_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::CommandPoolCreateFlags::PROTECTED | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            queue_family_index: self.indices.graphics_family.unwrap(),
+        };
+
+        unsafe { self.device.create_command_pool(&command_pool_create_info, None) }
+    }
+
+    /// Switches e.g. between FIFO and MAILBOX for a VSync toggle, starting with the next present.
+    /// Returns `false` when `mode` isn't compatible with the current swapchain, which is recreated then.
+    /// Unsupported modes fall back to the default choice, `swapchain.present_mode_selection` tells which one was used.
+    /// Also switches the policy back to `PresentModePolicy::Fixed`.
+    pub fn set_present_mode(&mut self, mode: vk::PresentModeKHR) -> bool {
+        self.swapchain_config.preferred_present_mode = Some(mode);
+        self.swapchain_config.present_mode_policy = swap_chain::PresentModePolicy::Fixed;
+
+        self.switch_present_mode(mode)
+    }
+
+    /// Power or thermal hint for `PresentModePolicy::Adaptive`, which falls back to FIFO while it's set.
+    /// Returns like `set_present_mode` when the mode changes, `true` otherwise.
+    pub fn set_power_saving(&mut self, power_saving: bool) -> bool {
+        self.swapchain_config.power_saving = power_saving;
+        self.apply_present_mode_policy()
+    }
+
+    /// Tells `PresentModePolicy::Adaptive` the application limits its frame rate, which switches it to FIFO.
+    /// Returns like `set_power_saving`.
+    pub fn set_frame_rate_cap(&mut self, frame_rate_cap: Option<u32>) -> bool {
+        self.swapchain_config.frame_rate_cap = frame_rate_cap;
+        self.apply_present_mode_policy()
+    }
+
+    fn apply_present_mode_policy(&mut self) -> bool {
+        if self.swapchain_config.present_mode_policy != swap_chain::PresentModePolicy::Adaptive {
+            return true;
+        }
+
+        // the requested mode rather than the selected one, a fallback would otherwise be recreated on every call.
+        match self.swapchain_config.requested_present_mode() {
+            Some(mode) if Some(mode) != self.swapchain.present_mode_selection.requested => self.switch_present_mode(mode),
+            _ => true,
+        }
+    }
+
+    fn switch_present_mode(&mut self, mode: vk::PresentModeKHR) -> bool {
+        if self.swapchain.compatible_present_modes.contains(&mode) {
+            self.swapchain.present_mode = mode;
+            self.swapchain.present_mode_selection.requested = Some(mode);
+            self.swapchain.present_mode_selection.selected = mode;
+            return true;
+        }
+
+        self.recreate_swapchain();
+        false
+    }
+
+    /// Hands signaled present fences back to the pool and destroys retired swapchains nothing presents from anymore.
+    fn recycle_present_fences(&mut self) {
+        let mut pending_fences = vec![];
+        for present_fence in self.swapchain.present_fences.drain(..) {
+            if present_fence.is_signaled() {
+                present_fence.reset();
+                self.free_present_fences.push(present_fence);
+            } else {
+                pending_fences.push(present_fence);
+            }
+        }
+        self.swapchain.present_fences = pending_fences;
+
+        let swapchain_loader = &self.swapchain.swapchain_loader;
+        self.retired_swapchains.retain(|retired_swapchain| {
+            if !retired_swapchain.is_done() {
+                return true;
+            }
+
+            lifecycle::on_destroy(TrackedObject::Swapchain, retired_swapchain.swapchain);
+            unsafe {
+                swapchain_loader.destroy_swapchain(retired_swapchain.swapchain, None);
+            }
+            false
+        });
+    }
+
+    /// Gives images that were acquired but won't be presented back to the presentation engine,
+    /// so a retired swapchain doesn't wait on them forever.
+    fn release_acquired_images(&mut self) {
+        if self.swapchain_maintenance1_fn.is_none() || self.swapchain.acquired_images.is_empty() {
+            return;
+        }
+
+        let acquired_images = std::mem::take(&mut self.swapchain.acquired_images);
+        self.release_swapchain_images(&acquired_images);
+    }
+
+    /// Needs `VK_EXT_swapchain_maintenance1`, the images mustn't be in use by the device anymore.
+    fn release_swapchain_images(&self, image_indices: &[u32]) {
+        let swapchain_maintenance1_fn = self.swapchain_maintenance1_fn
+            .as_ref()
+            .expect("Releasing swapchain images needs VK_EXT_swapchain_maintenance1");
+
+        let release_info = vk::ReleaseSwapchainImagesInfoEXT {
+            s_type: vk::StructureType::RELEASE_SWAPCHAIN_IMAGES_INFO_EXT,
+            p_next: ptr::null(),
+            swapchain: self.swapchain.swapchain,
+            image_index_count: image_indices.len() as u32,
+            p_image_indices: image_indices.as_ptr(),
+        };
+
+        unsafe {
+            (swapchain_maintenance1_fn.release_swapchain_images_ext)(self.device.handle(), &release_info)
+                .result()
+                .expect("Failed to release Swapchain Images.");
+        }
+    }
+
+    /// With `capabilities.swapchain_maintenance1` nothing is waited on, the old swapchain is retired
+    /// and destroyed later once its presents are done, the resources built on it go to the `deletion_queue`.
+    /// Otherwise the whole device has to go idle first.
+    /// Does nothing while `is_minimized`, the recreation is retried by the first `render_frame` after the restore.
+    pub fn recreate_swapchain(&mut self) {
+        assert!(!self.is_frame_acquired, "Swapchain recreated between acquire_frame and submit_frame!");
+        if self.is_minimized() {
+            self.is_framebuffer_resized = true;
+            return;
+        }
+        // only after `recreate_surface`, there is nothing to clean up or retire.
+        if self.swapchain.swapchain == vk::SwapchainKHR::null() {
+            self.create_swapchain_resources(vk::SwapchainKHR::null());
+            return;
+        }
+
+        let defer_destruction = self.swapchain_maintenance1_fn.is_some();
+
+        if defer_destruction {
+            // ownership acquires run on the present queue without a fence, so the deletion queue can't cover them.
+            if self.present_ownership_transfer.is_some() {
+                unsafe {
+                    self.device
+                        .queue_wait_idle(self.present_queue)
+                        .expect("Failed to wait Queue idle");
+                }
+            }
+        } else {
+            self.wait_idle().expect("Failed to wait device idle");
+        }
+
+        if defer_destruction {
+            self.retire_swapchain_resources();
+        } else {
+            self.cleanup_swapchain_resources();
+        }
 
-        unsafe {
-            let data_ptr =
-                self.device
-                    .map_memory(
-                        self.uniform_buffers_memory[current_image],
-                        0,
-                        buffer_size,
-                        vk::MemoryMapFlags::empty(),
-                    )
-                    .expect("Failed to Map Memory") as *mut UniformBufferObject;
+        let old_swapchain = if defer_destruction {
+            self.release_acquired_images();
+            self.swapchain.swapchain
+        } else {
+            self.swapchain.destroy_swapchain();
+            vk::SwapchainKHR::null()
+        };
 
-            data_ptr.copy_from_nonoverlapping(ubos.as_ptr(), ubos.len());
+        self.create_swapchain_resources(old_swapchain);
+    }
 
-            self.device
-                .unmap_memory(self.uniform_buffers_memory[current_image]);
-        }
+    /// New swapchain on the device's surface with `swapchain_config`, without image views or anything else built on it.
+    /// `recreate_swapchain` is what replaces `swapchain`, this is the part of it talking to the driver.
+    /// Fails like `VkSpawChain::create_swapchain`, e.g. while minimized.
+    pub fn create_swapchain(&mut self, old_swapchain: vk::SwapchainKHR) -> Result<VkSpawChain, vk::Result> {
+        VkSpawChain::create_swapchain(&self.instance, &self.device, self.physical_device, &self.surface, &self.indices, self.device_group.as_ref(), &mut self.swapchain_support_cache, &self.swapchain_config, old_swapchain)
     }
 
-    pub fn recreate_swapchain(&mut self) {
-        unsafe {
-            self.device
-                .device_wait_idle()
-                .expect("Failed to wait device idle")
+    /// Second half of `recreate_swapchain`, once the old swapchain's resources are cleaned up or retired.
+    /// A non-null `old_swapchain` is retired and destroyed later, a null one has to be destroyed already.
+    fn create_swapchain_resources(&mut self, old_swapchain: vk::SwapchainKHR) {
+        let new_swapchain = match self.create_swapchain(old_swapchain) {
+            Ok(new_swapchain) => new_swapchain,
+            Err(error) => {
+                // e.g. minimized since `is_minimized` was checked, the swapchain is left null like after
+                // `recreate_surface` and the first acquire after the restore creates it.
+                log::warn!("Failed to create the swapchain, retrying on the next frame: {:?}", error);
+                if old_swapchain != vk::SwapchainKHR::null() {
+                    self.retired_swapchains.push(swap_chain::RetiredSwapchain {
+                        swapchain: old_swapchain,
+                        present_fences: std::mem::take(&mut self.swapchain.present_fences),
+                    });
+                }
+                self.swapchain.swapchain = vk::SwapchainKHR::null();
+                return;
+            }
         };
+        let retired_swapchain = std::mem::replace(&mut self.swapchain, new_swapchain);
 
-        self.cleanup_swapchain_resources();
-
-        self.swapchain = VkSpawChain::create_swapchain(&self.instance, &self.device, self.physical_device, &self.surface, &self.indices);
+        if old_swapchain != vk::SwapchainKHR::null() {
+            self.retired_swapchains.push(swap_chain::RetiredSwapchain {
+                swapchain: retired_swapchain.swapchain,
+                present_fences: retired_swapchain.present_fences,
+            });
+        }
 
-        let swapchain_image_views = self.swapchain.create_image_views(&self.device);
+        self.swapchain.swapchain_image_views = self.swapchain.create_image_views(&self.device);
+        self.present_ownership_transfer = VkRenderDevice::create_present_ownership_transfer(&self.device, &self.swapchain, &self.indices);
+        if let (Some(hdr_metadata_fn), Some(hdr_metadata)) = (&self.hdr_metadata_fn, &self.swapchain_config.hdr_metadata) {
+            self.swapchain.set_hdr_metadata(hdr_metadata_fn, &self.device, hdr_metadata);
+        }
 
-        self.render_pass = VkRenderDevice::create_render_pass(&self.device, self.swapchain.swapchain_format);
+        self.render_pass = VkRenderDevice::create_render_pass(&self.device, &self.swapchain);
 
         (self.graphics_pipeline, self.pipeline_layout) = VkRenderDevice::create_graphics_pipeline(&self.device, &self.swapchain, self.render_pass, self.ubo_layout);
     
-        let framebuffers = VkSpawChain::create_framebuffers(&self.device, self.render_pass, &swapchain_image_views, &self.swapchain.swapchain_extent);
+        self.swapchain.swapchain_framebuffers = render_target::create_framebuffers(&self.device, self.render_pass, &self.swapchain);
+        self.resize_per_image_resources();
+
+        #[cfg(feature = "debug-overlay")]
+        self.debug_overlay.rebuild(
+            &self.device,
+            self.render_pass,
+            self.swapchain.swapchain_extent,
+            self.swapchain.image_count(),
+            &mut self.deletion_queue);
+        #[cfg(feature = "debug-overlay")]
+        let record_overlay = |command_buffer: vk::CommandBuffer, image_index: usize| {
+            self.debug_overlay.cmd_draw(&self.device, command_buffer, image_index)
+        };
+        #[cfg(not(feature = "debug-overlay"))]
+        let record_overlay = |_: vk::CommandBuffer, _: usize| {};
 
         self.command_buffers = VkRenderDevice::create_command_buffers(
             &self.device,
-            self.command_pool,
+            self.built_in_command_pool(),
             self.graphics_pipeline,
-            &framebuffers,
+            &self.swapchain.swapchain_framebuffers,
             self.render_pass,
             self.swapchain.swapchain_extent,
             self.vertex_buffer,
             self.index_buffer,
             self.pipeline_layout,
-            &self.descriptor_sets
+            &self.descriptor_sets,
+            &self.swapchain.swapchain_images,
+            self.present_ownership_transfer.as_ref(),
+            &record_overlay
         );
     }
 
+    /// The uniform buffers and their descriptor sets are per swapchain image, so a recreated swapchain with
+    /// a different image count needs new ones. The old ones go through the `deletion_queue`.
+    fn resize_per_image_resources(&mut self) {
+        let image_count = self.swapchain.image_count();
+        if self.uniform_buffers.len() == image_count {
+            return;
+        }
+
+        let uniform_buffers = std::mem::take(&mut self.uniform_buffers);
+        let descriptor_pool = self.descriptor_pool;
+        self.deletion_queue.enqueue(move |device| {
+            for uniform_buffer in uniform_buffers.iter() {
+                uniform_buffer.destroy(device);
+            }
+            unsafe {
+                device.destroy_descriptor_pool(descriptor_pool, None);
+            }
+        });
+
+        let physical_device_memory_properties =
+            unsafe { self.instance.get_physical_device_memory_properties(self.physical_device) };
+        self.uniform_buffers = VkRenderDevice::create_uniform_buffers(
+            &self.device,
+            &physical_device_memory_properties,
+            self.capabilities.non_coherent_atom_size,
+            image_count);
+        self.descriptor_pool = VkRenderDevice::create_descriptor_pool(&self.device, image_count);
+        let uniform_buffer_handles: Vec<vk::Buffer> =
+            self.uniform_buffers.iter().map(|uniform_buffer| uniform_buffer.buffer).collect();
+        self.descriptor_sets = VkRenderDevice::create_descriptor_sets(
+            &self.device,
+            self.descriptor_pool,
+            self.ubo_layout,
+            &uniform_buffer_handles,
+            image_count);
+    }
+
+    /// `None` unless the swapchain images are EXCLUSIVE while graphics and present are different families.
+    fn create_present_ownership_transfer(
+        device: &ash::Device,
+        swapchain: &VkSpawChain,
+        indices: &QueueFamilyIndices
+    ) -> Option<swap_chain::PresentOwnershipTransfer> {
+        let graphics_family = indices.graphics_family.unwrap();
+        let present_family = indices.present_family.unwrap();
+
+        if swapchain.image_sharing_mode != vk::SharingMode::EXCLUSIVE || graphics_family == present_family {
+            return None;
+        }
+
+        Some(swap_chain::PresentOwnershipTransfer::new(
+            device,
+            graphics_family,
+            present_family,
+            &swapchain.swapchain_images))
+    }
+
     fn create_sync_objects(device: &ash::Device) -> SyncObjects {
         let mut sync_objects = SyncObjects {
             image_available_semaphores: vec![],
@@ -734,38 +2438,14 @@ impl VkRenderDevice
             inflight_fences: vec![],
         };
 
-        let semaphore_create_info = vk::SemaphoreCreateInfo {
-            s_type: vk::StructureType::SEMAPHORE_CREATE_INFO,
-            p_next: ptr::null(),
-            flags: vk::SemaphoreCreateFlags::empty(),
-        };
-
-        let fence_create_info = vk::FenceCreateInfo {
-            s_type: vk::StructureType::FENCE_CREATE_INFO,
-            p_next: ptr::null(),
-            flags: vk::FenceCreateFlags::SIGNALED,
-        };
-
         for _ in 0..global_constants::MAX_FRAMES_IN_FLIGHT {
-            unsafe {
-                let image_available_semaphore = device
-                    .create_semaphore(&semaphore_create_info, None)
-                    .expect("Failed to create Semaphore Object!");
-                let render_finished_semaphore = device
-                    .create_semaphore(&semaphore_create_info, None)
-                    .expect("Failed to create Semaphore Object!");
-                let inflight_fence = device
-                    .create_fence(&fence_create_info, None)
-                    .expect("Failed to create Fence Object!");
-
-                sync_objects
-                    .image_available_semaphores
-                    .push(image_available_semaphore);
-                sync_objects
-                    .render_finished_semaphores
-                    .push(render_finished_semaphore);
-                sync_objects.inflight_fences.push(inflight_fence);
-            }
+            sync_objects
+                .image_available_semaphores
+                .push(VkSemaphore::new(device));
+            sync_objects
+                .render_finished_semaphores
+                .push(VkSemaphore::new(device));
+            sync_objects.inflight_fences.push(VkFence::new(device, true));
         }
 
         sync_objects
@@ -774,27 +2454,87 @@ impl VkRenderDevice
     fn create_surface(
         entry: &ash::Entry,
         instance: &ash::Instance,
-        window: &window::Window,
-    ) -> VkSurface {
-        let surface = unsafe {
-            crate::vk::platforms::create_surface(entry, instance, window)
-                .expect("Failed to create surface.")
+        target: SurfaceTarget,
+        surface_backend: platforms::SurfaceBackend,
+        surface_maintenance1: bool,
+        surface_protected_capabilities: bool,
+    ) -> Result<VkSurface, vk::Result> {
+        let (surface, (screen_width, screen_height)) = match target {
+            SurfaceTarget::Window(window) => unsafe {
+                (crate::vk::platforms::create_surface(entry, instance, window, surface_backend)?, window.inner_size())
+            },
+            SurfaceTarget::Headless(width, height) => unsafe {
+                (crate::vk::platforms::create_headless_surface(entry, instance)?, (width, height))
+            },
         };
         let surface_loader = ash::extensions::khr::Surface::new(entry, instance);
+        let surface_capabilities2_loader = if surface_maintenance1 || surface_protected_capabilities {
+            Some(ash::extensions::khr::GetSurfaceCapabilities2::new(entry, instance))
+        } else {
+            None
+        };
 
-        VkSurface {
+        Ok(VkSurface {
             surface_loader,
+            surface_capabilities2_loader,
+            surface_maintenance1,
+            surface_protected_capabilities,
             surface,
 
-            screen_width: global_constants::WINDOW_WIDTH,
-            screen_height: global_constants::WINDOW_HEIGHT,
-        }
+            screen_width,
+            screen_height,
+        })
+    }
+
+    /// Whether the instance can enable `VK_EXT_surface_maintenance1` and `VK_KHR_get_surface_capabilities2` it depends on.
+    pub fn supports_surface_maintenance1(entry: &ash::Entry) -> bool {
+        let available_extensions: HashSet<String> = entry
+            .enumerate_instance_extension_properties(None)
+            .expect("Failed to get instance extension properties.")
+            .iter()
+            .map(|extension| tools::vk_to_string(&extension.extension_name))
+            .collect();
+
+        available_extensions.contains(ash::extensions::khr::GetSurfaceCapabilities2::name().to_str().unwrap())
+            && available_extensions.contains(vk::ExtSurfaceMaintenance1Fn::name().to_str().unwrap())
+    }
+
+    /// Whether the instance can enable `VK_KHR_surface_protected_capabilities` and `VK_KHR_get_surface_capabilities2`
+    /// it depends on.
+    pub fn supports_surface_protected_capabilities(entry: &ash::Entry) -> bool {
+        let available_extensions: HashSet<String> = entry
+            .enumerate_instance_extension_properties(None)
+            .expect("Failed to get instance extension properties.")
+            .iter()
+            .map(|extension| tools::vk_to_string(&extension.extension_name))
+            .collect();
+
+        available_extensions.contains(ash::extensions::khr::GetSurfaceCapabilities2::name().to_str().unwrap())
+            && available_extensions.contains(vk::KhrSurfaceProtectedCapabilitiesFn::name().to_str().unwrap())
     }
 
-    pub fn create_instance(entry: &ash::Entry) -> ash::Instance {
-        if constants::VALIDATION.is_enable && debug::check_validation_layer_support(entry) == false {
-            panic!("Validation layers requested, but not available!");
+    /// `constants::API_VERSION`, lowered to what the loader supports. A Vulkan 1.0 loader fails
+    /// instance creation with any higher version.
+    pub fn instance_api_version(entry: &ash::Entry) -> u32 {
+        match entry.try_enumerate_instance_version() {
+            Ok(Some(loader_version)) => loader_version.min(constants::API_VERSION),
+            Ok(None) | Err(_) => vk::API_VERSION_1_0,
         }
+    }
+
+    /// `surface_maintenance1` and `surface_protected_capabilities` must only be set when
+    /// `supports_surface_maintenance1` and `supports_surface_protected_capabilities` returned `true`.
+    /// `surface_backend` has to be resolved already, see `SurfaceBackend::resolve`.
+    /// Only the installed ones of `layers` are enabled, see `DeviceConfig::instance_layers`.
+    pub fn create_instance(
+        entry: &ash::Entry,
+        surface_backend: platforms::SurfaceBackend,
+        surface_maintenance1: bool,
+        surface_protected_capabilities: bool,
+        layers: &[String]
+    ) -> Result<ash::Instance, vk::Result> {
+        let layer_names = debug::available_instance_layers(entry, layers);
+        let layer_name_pointers: Vec<*const c_char> = layer_names.iter().map(|name| name.as_ptr()).collect();
 
         let app_name = CString::new(global_constants::WINDOW_TITLE).unwrap();
         let engine_name = CString::new(global_constants::ENGINE_TITLE).unwrap();
@@ -805,35 +2545,39 @@ impl VkRenderDevice
             p_engine_name: engine_name.as_ptr(),
             application_version: global_constants::APPLICATION_VERSION,
             engine_version: global_constants::ENGINE_VERSION,
-            api_version: constants::API_VERSION
+            api_version: VkRenderDevice::instance_api_version(entry)
         };
 
-        let extension_names = platforms::required_extension_names();
+        let mut extension_names = platforms::required_extension_names(surface_backend);
+        if surface_maintenance1 || surface_protected_capabilities {
+            extension_names.push(ash::extensions::khr::GetSurfaceCapabilities2::name().as_ptr());
+        }
+        if surface_maintenance1 {
+            extension_names.push(vk::ExtSurfaceMaintenance1Fn::name().as_ptr());
+        }
+        if surface_protected_capabilities {
+            extension_names.push(vk::KhrSurfaceProtectedCapabilitiesFn::name().as_ptr());
+        }
 
         let create_info = vk::InstanceCreateInfo {
             s_type: vk::StructureType::INSTANCE_CREATE_INFO,
             p_next: ptr::null(),
             flags: vk::InstanceCreateFlags::empty(),
             p_application_info: &app_info,
-            pp_enabled_layer_names: ptr::null(),
-            enabled_layer_count: 0,
+            pp_enabled_layer_names: layer_name_pointers.as_ptr(),
+            enabled_layer_count: layer_name_pointers.len() as u32,
             pp_enabled_extension_names: extension_names.as_ptr(),
             enabled_extension_count: extension_names.len() as u32
         };
 
-        let instance: ash::Instance = unsafe {
-            entry
-            .create_instance(&create_info, None)
-            .expect("Failed to created VkInstance!")
-        };
-
-        instance
+        unsafe { entry.create_instance(&create_info, None) }
     }
 
+    /// First suitable GPU, `None` when there is none.
     pub fn pick_physical_device(
         instance: &ash::Instance,
         surface: &VkSurface
-    ) -> vk::PhysicalDevice {
+    ) -> Option<vk::PhysicalDevice> {
         let physical_devices =  unsafe {
             instance
                 .enumerate_physical_devices()
@@ -853,7 +2597,7 @@ impl VkRenderDevice
 
         println!("\n------------------\n");
 
-        result.unwrap()
+        result
     }
 
     fn is_physical_device_suitable(
@@ -933,6 +2677,17 @@ impl VkRenderDevice
             );
         }
 
+        let memory_heaps = capabilities::memory_heaps(instance, physical_device);
+        let device_local_size: vk::DeviceSize = memory_heaps
+            .iter()
+            .filter(|heap| heap.is_device_local())
+            .map(|heap| heap.size)
+            .sum();
+        println!("\tMemory Heaps: {}, device local total {}", memory_heaps.len(), capabilities::format_bytes(device_local_size));
+        for heap in memory_heaps.iter() {
+            println!("\t\t{}", heap);
+        }
+
         // there are plenty of features
         println!(
             "\tGeometry Shader support: {}",
@@ -994,6 +2749,55 @@ impl VkRenderDevice
         return required_extensions.is_empty();
     }
 
+    /// Whether the graphics family `find_queue_family` picks has all of `queue_flags`.
+    fn graphics_family_supports(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        surface: &VkSurface,
+        queue_flags: vk::QueueFlags,
+    ) -> bool {
+        let indices = VkRenderDevice::find_queue_family(instance, physical_device, surface);
+        let queue_families = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+
+        indices.graphics_family.map_or(false, |graphics_family| {
+            queue_families[graphics_family as usize].queue_flags.contains(queue_flags)
+        })
+    }
+
+    /// Graphics queues are created protected capable with `capabilities.protected_memory`.
+    fn queue_create_flags(capabilities: &DeviceCapabilities, is_graphics_family: bool) -> vk::DeviceQueueCreateFlags {
+        if capabilities.protected_memory && is_graphics_family {
+            vk::DeviceQueueCreateFlags::PROTECTED
+        } else {
+            vk::DeviceQueueCreateFlags::empty()
+        }
+    }
+
+    /// Queues created with flags can only be retrieved through `vkGetDeviceQueue2` with the same flags.
+    fn get_device_queue(
+        device: &ash::Device,
+        capabilities: &DeviceCapabilities,
+        queue_family_index: u32,
+        queue_index: u32,
+        is_graphics_family: bool
+    ) -> vk::Queue {
+        let flags = VkRenderDevice::queue_create_flags(capabilities, is_graphics_family);
+        if flags.is_empty() {
+            return unsafe { device.get_device_queue(queue_family_index, queue_index) };
+        }
+
+        let queue_info = vk::DeviceQueueInfo2 {
+            s_type: vk::StructureType::DEVICE_QUEUE_INFO_2,
+            p_next: ptr::null(),
+            flags: flags,
+            queue_family_index: queue_family_index,
+            queue_index: queue_index,
+        };
+        unsafe { device.get_device_queue2(&queue_info) }
+    }
+
+    /// Prefers one family doing both graphics and present. The present family is guaranteed to support `surface`,
+    /// when no family does `present_family` stays `None` and the device isn't suitable.
     fn find_queue_family(
         instance: &ash::Instance,
         physical_device: vk::PhysicalDevice,
@@ -1007,33 +2811,36 @@ impl VkRenderDevice
             present_family: None,
         };
 
-        let mut index = 0;
-        for queue_family in queue_families.iter() {
-            if queue_family.queue_count > 0
-                && queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS)
-            {
-                queue_family_indices.graphics_family = Some(index);
+        for (index, queue_family) in queue_families.iter().enumerate() {
+            let index = index as u32;
+            if queue_family.queue_count == 0 {
+                continue;
             }
 
+            let is_graphics_support = queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS);
+            // a failed query counts as unsupported, support can differ per family and GPU.
             let is_present_support = unsafe {
                 surface
                     .surface_loader
                     .get_physical_device_surface_support(
                         physical_device,
-                        index as u32,
+                        index,
                         surface.surface,
                     )
+                    .unwrap_or(false)
             };
 
-            if queue_family.queue_count > 0 && is_present_support.is_ok() {
+            if is_graphics_support && is_present_support {
+                queue_family_indices.graphics_family = Some(index);
                 queue_family_indices.present_family = Some(index);
-            }
-
-            if queue_family_indices.is_complete() {
                 break;
             }
-
-            index += 1;
+            if is_graphics_support && queue_family_indices.graphics_family.is_none() {
+                queue_family_indices.graphics_family = Some(index);
+            }
+            if is_present_support && queue_family_indices.present_family.is_none() {
+                queue_family_indices.present_family = Some(index);
+            }
         }
 
         queue_family_indices
@@ -1043,31 +2850,198 @@ impl VkRenderDevice
         instance: &ash::Instance,
         physical_device: vk::PhysicalDevice,
         validation: &debug::ValidationInfo,
-        surface: &VkSurface
-        ) -> (ash::Device, QueueFamilyIndices) {
+        surface: &VkSurface,
+        capabilities: &DeviceCapabilities,
+        config: &DeviceConfig,
+        device_group_devices: Option<&[vk::PhysicalDevice]>
+        ) -> Result<(ash::Device, QueueFamilyIndices, u32), vk::Result> {
         let indices = VkRenderDevice::find_queue_family(instance, physical_device, surface);
 
+        let queue_families =
+            unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+        let graphics_family = indices.graphics_family.unwrap();
+        let graphics_queue_count = config.graphics_queue_count
+            .clamp(1, queue_families[graphics_family as usize].queue_count);
+
         let mut unique_queue_families = HashSet::new();
-        unique_queue_families.insert(indices.graphics_family.unwrap());
+        unique_queue_families.insert(graphics_family);
         unique_queue_families.insert(indices.present_family.unwrap());
 
-        let queue_priorities = [1.0_f32];
+        let graphics_queue_priorities: Vec<f32> = (0..graphics_queue_count as usize)
+            .map(|index| config.graphics_queue_priorities.get(index).copied().unwrap_or(1.0).clamp(0.0, 1.0))
+            .collect();
+        let queue_priorities = [config.present_queue_priority.clamp(0.0, 1.0)];
         let mut queue_create_infos = vec![];
         for &queue_family in unique_queue_families.iter() {
+            let priorities: &[f32] = if queue_family == graphics_family {
+                &graphics_queue_priorities
+            } else {
+                &queue_priorities
+            };
+
             let queue_create_info = vk::DeviceQueueCreateInfo {
                 s_type: vk::StructureType::DEVICE_QUEUE_CREATE_INFO,
                 p_next: ptr::null(),
-                flags: vk::DeviceQueueCreateFlags::empty(),
+                flags: VkRenderDevice::queue_create_flags(capabilities, queue_family == graphics_family),
                 queue_family_index: queue_family,
-                p_queue_priorities: queue_priorities.as_ptr(),
-                queue_count: queue_priorities.len() as u32,
+                p_queue_priorities: priorities.as_ptr(),
+                queue_count: priorities.len() as u32,
             };
             queue_create_infos.push(queue_create_info);
         }
 
         let physical_device_features = vk::PhysicalDeviceFeatures {
+            sampler_anisotropy: capabilities.sampler_anisotropy as vk::Bool32,
+            geometry_shader: capabilities.geometry_shader as vk::Bool32,
+            tessellation_shader: capabilities.tessellation_shader as vk::Bool32,
+            multi_viewport: capabilities.multi_viewport as vk::Bool32,
+            depth_bias_clamp: capabilities.depth_bias_clamp as vk::Bool32,
+            image_cube_array: capabilities.image_cube_array as vk::Bool32,
+            sparse_binding: capabilities.sparse_residency_image_2d as vk::Bool32,
+            sparse_residency_image2_d: capabilities.sparse_residency_image_2d as vk::Bool32,
+            ..Default::default()
+        };
+
+        let mut vulkan12_features = vk::PhysicalDeviceVulkan12Features {
+            timeline_semaphore: capabilities.timeline_semaphore as vk::Bool32,
+            descriptor_indexing: capabilities.bindless as vk::Bool32,
+            runtime_descriptor_array: capabilities.bindless as vk::Bool32,
+            descriptor_binding_partially_bound: capabilities.bindless as vk::Bool32,
+            descriptor_binding_variable_descriptor_count: capabilities.bindless as vk::Bool32,
+            descriptor_binding_sampled_image_update_after_bind: capabilities.bindless as vk::Bool32,
+            shader_sampled_image_array_non_uniform_indexing: capabilities.bindless as vk::Bool32,
+            buffer_device_address: capabilities.buffer_device_address as vk::Bool32,
+            ..Default::default()
+        };
+        let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures {
+            dynamic_rendering: vk::TRUE,
+            ..Default::default()
+        };
+        let mut synchronization2_features = vk::PhysicalDeviceSynchronization2Features {
+            synchronization2: vk::TRUE,
+            ..Default::default()
+        };
+        let mut present_id_features = vk::PhysicalDevicePresentIdFeaturesKHR {
+            present_id: vk::TRUE,
+            ..Default::default()
+        };
+        let mut present_wait_features = vk::PhysicalDevicePresentWaitFeaturesKHR {
+            present_wait: vk::TRUE,
+            ..Default::default()
+        };
+        let mut swapchain_maintenance1_features = vk::PhysicalDeviceSwapchainMaintenance1FeaturesEXT {
+            swapchain_maintenance1: vk::TRUE,
             ..Default::default()
         };
+        let mut acceleration_structure_features = vk::PhysicalDeviceAccelerationStructureFeaturesKHR {
+            acceleration_structure: vk::TRUE,
+            ..Default::default()
+        };
+        let mut ray_tracing_pipeline_features = vk::PhysicalDeviceRayTracingPipelineFeaturesKHR {
+            ray_tracing_pipeline: vk::TRUE,
+            ..Default::default()
+        };
+        let mut transform_feedback_features = vk::PhysicalDeviceTransformFeedbackFeaturesEXT {
+            transform_feedback: vk::TRUE,
+            ..Default::default()
+        };
+        let mut conditional_rendering_features = vk::PhysicalDeviceConditionalRenderingFeaturesEXT {
+            conditional_rendering: vk::TRUE,
+            ..Default::default()
+        };
+        let mut fragment_shading_rate_features = vk::PhysicalDeviceFragmentShadingRateFeaturesKHR {
+            pipeline_fragment_shading_rate: vk::TRUE,
+            ..Default::default()
+        };
+        let mut multiview_features = vk::PhysicalDeviceMultiviewFeatures {
+            multiview: vk::TRUE,
+            ..Default::default()
+        };
+        let mut depth_clip_control_features = vk::PhysicalDeviceDepthClipControlFeaturesEXT {
+            depth_clip_control: vk::TRUE,
+            ..Default::default()
+        };
+        let mut protected_memory_features = vk::PhysicalDeviceProtectedMemoryFeatures {
+            protected_memory: vk::TRUE,
+            ..Default::default()
+        };
+        let mut device_fault_features = vk::PhysicalDeviceFaultFeaturesEXT {
+            device_fault: vk::TRUE,
+            device_fault_vendor_binary: capabilities.device_fault_vendor_binary as vk::Bool32,
+            ..Default::default()
+        };
+        let mut mesh_shader_features = vk::PhysicalDeviceMeshShaderFeaturesEXT {
+            mesh_shader: vk::TRUE,
+            task_shader: capabilities.task_shader as vk::Bool32,
+            ..Default::default()
+        };
+
+        let mut feature_chain: Vec<*mut vk::BaseOutStructure> = vec![];
+        if capabilities.api_version >= vk::API_VERSION_1_2 {
+            feature_chain.push(&mut vulkan12_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if capabilities.dynamic_rendering {
+            feature_chain.push(&mut dynamic_rendering_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if capabilities.synchronization2 {
+            feature_chain.push(&mut synchronization2_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if capabilities.present_id {
+            feature_chain.push(&mut present_id_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if capabilities.present_wait {
+            feature_chain.push(&mut present_wait_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if capabilities.swapchain_maintenance1 {
+            feature_chain.push(&mut swapchain_maintenance1_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if capabilities.acceleration_structure {
+            feature_chain.push(&mut acceleration_structure_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if capabilities.ray_tracing {
+            feature_chain.push(&mut ray_tracing_pipeline_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if capabilities.mesh_shader {
+            feature_chain.push(&mut mesh_shader_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if capabilities.fragment_shading_rate {
+            feature_chain.push(&mut fragment_shading_rate_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if capabilities.conditional_rendering {
+            feature_chain.push(&mut conditional_rendering_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if capabilities.transform_feedback {
+            feature_chain.push(&mut transform_feedback_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if capabilities.multiview {
+            feature_chain.push(&mut multiview_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if capabilities.depth_clip_control {
+            feature_chain.push(&mut depth_clip_control_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if capabilities.device_fault {
+            feature_chain.push(&mut device_fault_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if capabilities.protected_memory {
+            feature_chain.push(&mut protected_memory_features as *mut _ as *mut vk::BaseOutStructure);
+        }
+        let mut device_group_create_info = device_group_devices.map(device_group::device_create_info);
+        if let Some(create_info) = &mut device_group_create_info {
+            feature_chain.push(create_info as *mut _ as *mut vk::BaseOutStructure);
+        }
+
+        let mut physical_device_features2 = vk::PhysicalDeviceFeatures2 {
+            features: physical_device_features,
+            ..Default::default()
+        };
+        let use_features2 = capabilities.api_version >= vk::API_VERSION_1_1;
+        if use_features2 {
+            unsafe {
+                capabilities::link_p_next(
+                    &mut physical_device_features2 as *mut _ as *mut vk::BaseOutStructure,
+                    &feature_chain);
+            }
+        }
 
         let required_validation_layer_raw_names: Vec<CString> = validation
             .required_validation_layers
@@ -1080,13 +3054,20 @@ impl VkRenderDevice
             .map(|layer_name| layer_name.as_ptr())
             .collect();
 
-        let enable_extension_names = [
-            ash::extensions::khr::Swapchain::name().as_ptr(), // currently just enable the Swapchain extension.
+        let mut enable_extension_names = vec![
+            ash::extensions::khr::Swapchain::name().as_ptr(),
         ];
+        for extension_name in capabilities.enabled_extension_names() {
+            enable_extension_names.push(extension_name.as_ptr());
+        }
 
         let device_create_info = vk::DeviceCreateInfo {
-            s_type: vk::StructureType::DEVICE_QUEUE_CREATE_INFO,
-            p_next: ptr::null(),
+            s_type: vk::StructureType::DEVICE_CREATE_INFO,
+            p_next: if use_features2 {
+                &physical_device_features2 as *const _ as *const c_void
+            } else {
+                ptr::null()
+            },
             flags: vk::DeviceCreateFlags::empty(),
             queue_create_info_count: queue_create_infos.len() as u32,
             p_queue_create_infos: queue_create_infos.as_ptr(),
@@ -1102,16 +3083,18 @@ impl VkRenderDevice
             },
             enabled_extension_count: enable_extension_names.len() as u32,
             pp_enabled_extension_names: enable_extension_names.as_ptr(),
-            p_enabled_features: &physical_device_features
+            p_enabled_features: if use_features2 {
+                ptr::null()
+            } else {
+                &physical_device_features
+            }
         };
 
         let device = unsafe {
-            instance
-                .create_device(physical_device, &device_create_info, None)
-                .expect("Failed to create logical device!")
+            instance.create_device(physical_device, &device_create_info, None)?
         };
 
-        (device, indices)
+        Ok((device, indices, graphics_queue_count))
     }
 
     fn create_command_pool(
@@ -1132,17 +3115,22 @@ impl VkRenderDevice
         }
     }
 
+    /// One command buffer per framebuffer drawing the scene, `record_in_render_pass` is called with each
+    /// and the index of its image to record more draws at the end of the render pass, e.g. the debug overlay.
     pub fn create_command_buffers (
         device: &ash::Device,
         command_pool: vk::CommandPool,
         graphics_pipeline: vk::Pipeline,
-        framebuffers: &Vec<vk::Framebuffer>,
+        framebuffers: &[vk::Framebuffer],
         render_pass: vk::RenderPass,
         surface_extent: vk::Extent2D,
         vertex_buffer: vk::Buffer,
         index_buffer: vk::Buffer,
         pipeline_layout: vk::PipelineLayout,
-        descriptor_sets: &Vec<vk::DescriptorSet>
+        descriptor_sets: &[vk::DescriptorSet],
+        swapchain_images: &[vk::Image],
+        present_ownership_transfer: Option<&swap_chain::PresentOwnershipTransfer>,
+        record_in_render_pass: &dyn Fn(vk::CommandBuffer, usize)
     ) -> Vec<vk::CommandBuffer> {
         let command_buffer_allocate_info = vk::CommandBufferAllocateInfo {
             s_type: vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
@@ -1197,36 +3185,24 @@ impl VkRenderDevice
                     &render_pass_begin_info,
                     vk::SubpassContents::INLINE,
                 );
-                device.cmd_bind_pipeline(
-                    command_buffer,
-                    vk::PipelineBindPoint::GRAPHICS,
-                    graphics_pipeline,
-                );
-
-                let vertex_buffers = [vertex_buffer];
-                let offsets = [0_u64];
-                let descriptor_sets_to_bind = [descriptor_sets[i]];
+            }
 
-                device.cmd_bind_vertex_buffers(command_buffer, 0, &vertex_buffers, &offsets);
-                device.cmd_bind_index_buffer(
-                    command_buffer, 
-                    index_buffer, 
-                    0, 
-                    vk::IndexType::UINT32
-                );
-                device.cmd_bind_descriptor_sets(
-                    command_buffer, 
-                    vk::PipelineBindPoint::GRAPHICS, 
-                    pipeline_layout, 
-                    0, 
-                    &descriptor_sets_to_bind, 
-                    &[]
-                );
+            DrawCommand::new(device, command_buffer)
+                .pipeline(graphics_pipeline, pipeline_layout)
+                .bind_vertex(vertex_buffer)
+                .bind_index(index_buffer, vk::IndexType::UINT32)
+                .descriptor_set(0, descriptor_sets[i])
+                .indexed(INDICES_DATA.len() as u32);
 
-                device.cmd_draw_indexed(command_buffer, INDICES_DATA.len() as u32, 1, 0, 0, 0);
+            record_in_render_pass(command_buffer, i);
 
+            unsafe {
                 device.cmd_end_render_pass(command_buffer);
 
+                if let Some(present_ownership_transfer) = present_ownership_transfer {
+                    present_ownership_transfer.cmd_release(device, command_buffer, swapchain_images[i]);
+                }
+
                 device
                     .end_command_buffer(command_buffer)
                     .expect("Failed to record Command Buffer at Ending!");
@@ -1236,61 +3212,24 @@ impl VkRenderDevice
         command_buffers
     }
 
-    fn create_render_pass(
+    pub fn create_render_pass(
         device: &ash::Device,
-        surface_format: vk::Format
+        target: &dyn RenderTarget
     ) -> vk::RenderPass {
-        let color_attachment = vk::AttachmentDescription {
-            flags: vk::AttachmentDescriptionFlags::empty(),
-            format: surface_format,
-            samples: vk::SampleCountFlags::TYPE_1,
-            load_op: vk::AttachmentLoadOp::CLEAR,
-            store_op: vk::AttachmentStoreOp::STORE,
-            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
-            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
-            initial_layout: vk::ImageLayout::UNDEFINED,
-            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
-        };
-
-        let color_attachment_ref = vk::AttachmentReference {
-            attachment: 0,
-            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        };
-
-        let subpass = vk::SubpassDescription {
-            flags: vk::SubpassDescriptionFlags::empty(),
-            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
-            input_attachment_count: 0,
-            p_input_attachments: ptr::null(),
-            color_attachment_count: 1,
-            p_color_attachments: &color_attachment_ref,
-            p_resolve_attachments: ptr::null(),
-            p_depth_stencil_attachment: ptr::null(),
-            preserve_attachment_count: 0,
-            p_preserve_attachments: ptr::null(),
-        };
-
-        let render_pass_attachments = [color_attachment];
-
-        let renderpass_create_info = vk::RenderPassCreateInfo {
-            s_type: vk::StructureType::RENDER_PASS_CREATE_INFO,
-            flags: vk::RenderPassCreateFlags::empty(),
-            p_next: ptr::null(),
-            attachment_count: render_pass_attachments.len() as u32,
-            p_attachments: render_pass_attachments.as_ptr(),
-            subpass_count: 1,
-            p_subpasses: &subpass,
-            dependency_count: 0,
-            p_dependencies: ptr::null(),
-        };
-
-        unsafe {
-            device
-                .create_render_pass(&renderpass_create_info, None)
-                .expect("Failed to create render pass!")
-        }
+        VkRenderDevice::create_render_pass_with_load(device, target, AttachmentLoad::Clear)
+    }
 
-    } 
+    /// Same as `create_render_pass` with the color attachment loaded as `load`, e.g. `AttachmentLoad::Preserve`
+    /// for a compositing or UI pass drawing over what an earlier pass left in `target`.
+    pub fn create_render_pass_with_load(
+        device: &ash::Device,
+        target: &dyn RenderTarget,
+        load: AttachmentLoad
+    ) -> vk::RenderPass {
+        RenderPassBuilder::new()
+            .color_attachment_load(target.format(), load, target.final_layout())
+            .build(device)
+    }
 
     fn create_graphics_pipeline(
         device: &ash::Device,
@@ -1306,151 +3245,9 @@ impl VkRenderDevice
             device, 
             VkRenderDevice::read_shader_code(Path::new("shaders/spv/21-shader-ubo.frag.spv")));
 
-        let main_function_name = CString::new("main").unwrap();
-
-        let shader_stages = [
-            vk::PipelineShaderStageCreateInfo {
-                // Vertex Shader
-                s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
-                p_next: ptr::null(),
-                flags: vk::PipelineShaderStageCreateFlags::empty(),
-                module: vert_shader_module,
-                p_name: main_function_name.as_ptr(),
-                p_specialization_info: ptr::null(),
-                stage: vk::ShaderStageFlags::VERTEX,
-            },
-            vk::PipelineShaderStageCreateInfo {
-                // Fragment Shader
-                s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
-                p_next: ptr::null(),
-                flags: vk::PipelineShaderStageCreateFlags::empty(),
-                module: frag_shader_module,
-                p_name: main_function_name.as_ptr(),
-                p_specialization_info: ptr::null(),
-                stage: vk::ShaderStageFlags::FRAGMENT,
-            },
-        ];
-
         let binding_description = Vertex::get_binding_descriptions();
         let attribute_description = Vertex::get_attribute_descriptions();
 
-        let vertex_input_state_create_info = vk::PipelineVertexInputStateCreateInfo {
-            s_type: vk::StructureType::PIPELINE_VERTEX_INPUT_STATE_CREATE_INFO,
-            p_next: ptr::null(),
-            flags: vk::PipelineVertexInputStateCreateFlags::empty(),
-            vertex_attribute_description_count: attribute_description.len() as u32,
-            p_vertex_attribute_descriptions: attribute_description.as_ptr(),
-            vertex_binding_description_count: binding_description.len() as u32,
-            p_vertex_binding_descriptions: binding_description.as_ptr(),
-        };
-
-        let vertex_input_assembly_state_create_info = vk::PipelineInputAssemblyStateCreateInfo {
-            s_type: vk::StructureType::PIPELINE_INPUT_ASSEMBLY_STATE_CREATE_INFO,
-            flags: vk::PipelineInputAssemblyStateCreateFlags::empty(),
-            p_next: ptr::null(),
-            primitive_restart_enable: vk::FALSE,
-            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
-        };
-
-        let viewports = [vk::Viewport {
-            x: 0.0,
-            y: 0.0,
-            width: swap_chain.swapchain_extent.width as f32,
-            height: swap_chain.swapchain_extent.height as f32,
-            min_depth: 0.0,
-            max_depth: 1.0,
-        }];
-
-        let scissors = [vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: swap_chain.swapchain_extent,
-        }];
-
-        let viewport_state_create_info = vk::PipelineViewportStateCreateInfo {
-            s_type: vk::StructureType::PIPELINE_VIEWPORT_STATE_CREATE_INFO,
-            p_next: ptr::null(),
-            flags: vk::PipelineViewportStateCreateFlags::empty(),
-            scissor_count: scissors.len() as u32,
-            p_scissors: scissors.as_ptr(),
-            viewport_count: viewports.len() as u32,
-            p_viewports: viewports.as_ptr(),
-        };
-
-        let rasterization_state_create_info = vk::PipelineRasterizationStateCreateInfo {
-            s_type: vk::StructureType::PIPELINE_RASTERIZATION_STATE_CREATE_INFO,
-            p_next: ptr::null(),
-            flags: vk::PipelineRasterizationStateCreateFlags::empty(),
-            depth_clamp_enable: vk::FALSE,
-            cull_mode: vk::CullModeFlags::BACK,
-            front_face: vk::FrontFace::CLOCKWISE,
-            line_width: 1.0,
-            polygon_mode: vk::PolygonMode::FILL,
-            rasterizer_discard_enable: vk::FALSE,
-            depth_bias_clamp: 0.0,
-            depth_bias_constant_factor: 0.0,
-            depth_bias_enable: vk::FALSE,
-            depth_bias_slope_factor: 0.0,
-        };
-
-        let multisample_state_create_info = vk::PipelineMultisampleStateCreateInfo {
-            s_type: vk::StructureType::PIPELINE_MULTISAMPLE_STATE_CREATE_INFO,
-            flags: vk::PipelineMultisampleStateCreateFlags::empty(),
-            p_next: ptr::null(),
-            rasterization_samples: vk::SampleCountFlags::TYPE_1,
-            sample_shading_enable: vk::FALSE,
-            min_sample_shading: 0.0,
-            p_sample_mask: ptr::null(),
-            alpha_to_one_enable: vk::FALSE,
-            alpha_to_coverage_enable: vk::FALSE,
-        };
-
-        let stencil_state = vk::StencilOpState {
-            fail_op: vk::StencilOp::KEEP,
-            pass_op: vk::StencilOp::KEEP,
-            depth_fail_op: vk::StencilOp::KEEP,
-            compare_op: vk::CompareOp::ALWAYS,
-            compare_mask: 0,
-            write_mask: 0,
-            reference: 0,
-        };
-
-        let depth_stencil_state_create_info = vk::PipelineDepthStencilStateCreateInfo {
-            s_type: vk::StructureType::PIPELINE_DEPTH_STENCIL_STATE_CREATE_INFO,
-            p_next: ptr::null(),
-            flags: vk::PipelineDepthStencilStateCreateFlags::empty(),
-            depth_test_enable: vk::FALSE,
-            depth_write_enable: vk::FALSE,
-            depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
-            depth_bounds_test_enable: vk::FALSE,
-            stencil_test_enable: vk::FALSE,
-            front: stencil_state,
-            back: stencil_state,
-            max_depth_bounds: 1.0,
-            min_depth_bounds: 0.0,
-        };
-
-        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
-            blend_enable: vk::FALSE,
-            color_write_mask: vk::ColorComponentFlags::RGBA,
-            src_color_blend_factor: vk::BlendFactor::ONE,
-            dst_color_blend_factor: vk::BlendFactor::ZERO,
-            color_blend_op: vk::BlendOp::ADD,
-            src_alpha_blend_factor: vk::BlendFactor::ONE,
-            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-            alpha_blend_op: vk::BlendOp::ADD,
-        }];
-
-        let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
-            s_type: vk::StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO,
-            p_next: ptr::null(),
-            flags: vk::PipelineColorBlendStateCreateFlags::empty(),
-            logic_op_enable: vk::FALSE,
-            logic_op: vk::LogicOp::COPY,
-            attachment_count: color_blend_attachment_states.len() as u32,
-            p_attachments: color_blend_attachment_states.as_ptr(),
-            blend_constants: [0.0, 0.0, 0.0, 0.0],
-        };
-
         //                leaving the dynamic statue unconfigurated right now
         //                let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
         //                let dynamic_state_info = vk::PipelineDynamicStateCreateInfo {
@@ -1479,40 +3276,18 @@ impl VkRenderDevice
                 .expect("Failed to create pipeline layout!")
         };
 
-        let graphic_pipeline_create_infos = [vk::GraphicsPipelineCreateInfo {
-            s_type: vk::StructureType::GRAPHICS_PIPELINE_CREATE_INFO,
-            p_next: ptr::null(),
-            flags: vk::PipelineCreateFlags::empty(),
-            stage_count: shader_stages.len() as u32,
-            p_stages: shader_stages.as_ptr(),
-            p_vertex_input_state: &vertex_input_state_create_info,
-            p_input_assembly_state: &vertex_input_assembly_state_create_info,
-            p_tessellation_state: ptr::null(),
-            p_viewport_state: &viewport_state_create_info,
-            p_rasterization_state: &rasterization_state_create_info,
-            p_multisample_state: &multisample_state_create_info,
-            p_depth_stencil_state: &depth_stencil_state_create_info,
-            p_color_blend_state: &color_blend_state,
-            p_dynamic_state: ptr::null(),
-            layout: pipeline_layout,
-            render_pass: render_pass,
-            subpass: 0,
-            base_pipeline_handle: vk::Pipeline::null(),
-            base_pipeline_index: -1,
-        }];
-
-        let graphics_pipelines = unsafe {
-            device
-                .create_graphics_pipelines(vk::PipelineCache::null(), &graphic_pipeline_create_infos, None)
-                .expect("Failed to create graphics pipeline")
-        }; 
+        let graphics_pipeline = GraphicsPipelineBuilder::new(swap_chain.swapchain_extent)
+            .shader_stage(vk::ShaderStageFlags::VERTEX, vert_shader_module)
+            .shader_stage(vk::ShaderStageFlags::FRAGMENT, frag_shader_module)
+            .vertex_input(&binding_description, &attribute_description)
+            .build(device, pipeline_layout, render_pass, 0);
 
         unsafe {
             device.destroy_shader_module(vert_shader_module, None);
             device.destroy_shader_module(frag_shader_module, None);
         }
 
-        (graphics_pipelines[0], pipeline_layout)
+        (graphics_pipeline, pipeline_layout)
     }
 
     fn create_shader_module(device: &ash::Device, code: Vec<u8>) -> vk::ShaderModule {
@@ -1532,20 +3307,25 @@ impl VkRenderDevice
     }
 
     fn read_shader_code(shader_path: &Path) -> Vec<u8> {
-        use std::fs::File;
-        use std::io::Read;
-
-        let spv_file = File::open(shader_path)
-            .expect(&format!("Failed to find spv file at {:?}", shader_path));
-        let bytes_code: Vec<u8> = spv_file.bytes().filter_map(|byte| byte.ok()).collect();
+        tools::read_shader_code(shader_path)
+    }
 
-        bytes_code
+    /// Pool the built-in command buffers of the current swapchain come from.
+    fn built_in_command_pool(&self) -> vk::CommandPool {
+        match self.protected_command_pool {
+            Some(protected_command_pool) if self.swapchain.is_protected => protected_command_pool,
+            _ => self.command_pool,
+        }
     }
 
-    fn cleanup_swapchain_resources(&self) {
+    fn cleanup_swapchain_resources(&mut self) {
+        if let Some(mut present_ownership_transfer) = self.present_ownership_transfer.take() {
+            present_ownership_transfer.destroy(&self.device);
+        }
+
         unsafe {
             self.device
-                .free_command_buffers(self.command_pool, &self.command_buffers);
+                .free_command_buffers(self.built_in_command_pool(), &self.command_buffers);
 
             self.swapchain.cleanup_swapchain(&self.device);
 
@@ -1553,26 +3333,86 @@ impl VkRenderDevice
 
             self.device.destroy_render_pass(self.render_pass, None);
 
-            self.device.destroy_pipeline(self.graphics_pipeline, None);        
-
-            self.swapchain.destroy_swapchain();
+            lifecycle::on_destroy(TrackedObject::Pipeline, self.graphics_pipeline);
+            self.device.destroy_pipeline(self.graphics_pipeline, None);
         };
     }
 
-    pub fn drop(&self) {
+    /// Same as `cleanup_swapchain_resources` but through the `deletion_queue`, for recreating without waiting idle.
+    fn retire_swapchain_resources(&mut self) {
+        if let Some(mut present_ownership_transfer) = self.present_ownership_transfer.take() {
+            present_ownership_transfer.destroy(&self.device);
+        }
+
+        let command_pool = self.built_in_command_pool();
+        let command_buffers = std::mem::take(&mut self.command_buffers);
+        self.deletion_queue.enqueue(move |device| unsafe {
+            device.free_command_buffers(command_pool, &command_buffers);
+        });
+
+        self.deletion_queue.enqueue_framebuffers(std::mem::take(&mut self.swapchain.swapchain_framebuffers));
+        self.deletion_queue.enqueue_image_views(std::mem::take(&mut self.swapchain.swapchain_image_views));
+
+        let pipeline_layout = self.pipeline_layout;
+        let render_pass = self.render_pass;
+        self.deletion_queue.enqueue(move |device| unsafe {
+            device.destroy_pipeline_layout(pipeline_layout, None);
+            device.destroy_render_pass(render_pass, None);
+        });
+        self.deletion_queue.enqueue_pipeline(self.graphics_pipeline);
+    }
+
+    /// Waits for the device to be idle before destroying anything.
+    pub fn destroy(&mut self) {
+        // a lost device has nothing in flight anymore, so teardown still goes on.
+        if let Err(error) = self.wait_idle() {
+            log::error!("Failed to wait device idle before teardown: {:?}", error);
+        }
+
+        // the wrappers destroy themselves, this only has to happen before the device goes.
+        self.sync_objects.image_available_semaphores.clear();
+        self.sync_objects.render_finished_semaphores.clear();
+        self.sync_objects.inflight_fences.clear();
+
+        for retired_swapchain in self.retired_swapchains.drain(..) {
+            lifecycle::on_destroy(TrackedObject::Swapchain, retired_swapchain.swapchain);
+            unsafe {
+                self.swapchain.swapchain_loader.destroy_swapchain(retired_swapchain.swapchain, None);
+            }
+        }
+        self.swapchain.present_fences.clear();
+        self.free_present_fences.clear();
+        self.sampler_cache.clear();
+        self.deletion_queue.flush();
+        #[cfg(feature = "debug-overlay")]
+        self.debug_overlay.destroy(&self.device);
+
         unsafe {
-            for i in 0..global_constants::MAX_FRAMES_IN_FLIGHT {
-                self.device
-                    .destroy_semaphore(self.sync_objects.image_available_semaphores[i], None);
-                self.device
-                    .destroy_semaphore(self.sync_objects.render_finished_semaphores[i], None);
-                self.device.destroy_fence(self.sync_objects.inflight_fences[i], None);
+            // null after a `recreate_surface` that failed or was minimized, its resources are gone already.
+            if self.swapchain.swapchain != vk::SwapchainKHR::null() {
+                self.cleanup_swapchain_resources();
+                self.swapchain.destroy_swapchain();
             }
 
-            self.cleanup_swapchain_resources();
+            for uniform_buffer in self.uniform_buffers.iter() {
+                uniform_buffer.destroy(&self.device);
+            }
+            self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+            self.device.destroy_descriptor_set_layout(self.ubo_layout, None);
+
+            lifecycle::on_destroy(TrackedObject::Buffer, self.vertex_buffer);
+            self.device.destroy_buffer(self.vertex_buffer, None);
+            self.device.free_memory(self.vertex_buffer_memory, None);
+            lifecycle::on_destroy(TrackedObject::Buffer, self.index_buffer);
+            self.device.destroy_buffer(self.index_buffer, None);
+            self.device.free_memory(self.index_buffer_memory, None);
 
             self.device.destroy_command_pool(self.command_pool, None);
+            if let Some(protected_command_pool) = self.protected_command_pool {
+                self.device.destroy_command_pool(protected_command_pool, None);
+            }
 
+            lifecycle::report_leaks();
             self.device.destroy_device(None);
             self.surface.surface_loader.destroy_surface(self.surface.surface, None);
 
@@ -1588,8 +3428,16 @@ impl VkRenderDevice
 
 pub struct VkSurface {
     pub surface_loader: ash::extensions::khr::Surface,
+    /// Only loaded when the instance enabled `VK_EXT_surface_maintenance1` or `VK_KHR_surface_protected_capabilities`.
+    pub surface_capabilities2_loader: Option<ash::extensions::khr::GetSurfaceCapabilities2>,
+    /// The instance enabled `VK_EXT_surface_maintenance1`.
+    pub surface_maintenance1: bool,
+    /// The instance enabled `VK_KHR_surface_protected_capabilities`, see `VkSpawChain::supports_protected`.
+    pub surface_protected_capabilities: bool,
     pub surface: vk::SurfaceKHR,
 
+    /// Last known size of the window, what the swapchain extent follows on surfaces that leave it to the swapchain,
+    /// e.g. on Wayland. 0 while the size isn't known yet.
     pub screen_width: u32,
     pub screen_height: u32,
 }
\ No newline at end of file
@@ -0,0 +1,451 @@
+use ash::vk;
+
+use std::ptr;
+
+use std::os::raw::c_void;
+
+use crate::vk::image;
+
+/// Color, input and depth/stencil references of one subpass.
+type SubpassAttachmentRefs = (Vec<vk::AttachmentReference>, Vec<vk::AttachmentReference>, Option<vk::AttachmentReference>);
+
+/// Attachment indices one subpass of a `RenderPassBuilder` uses, e.g. a G-buffer write or a lighting pass
+/// reading that G-buffer back through input attachments.
+#[derive(Clone, Debug, Default)]
+pub struct Subpass {
+    color_attachments: Vec<u32>,
+    input_attachments: Vec<u32>,
+    depth_stencil_attachment: Option<u32>,
+    preserve_attachments: Vec<u32>,
+}
+
+impl Subpass {
+    pub fn new() -> Subpass {
+        Subpass::default()
+    }
+
+    /// Written as the next color output, `location` in the fragment shader is the call order.
+    pub fn color(mut self, attachment: u32) -> Subpass {
+        self.color_attachments.push(attachment);
+        self
+    }
+
+    /// Read as the next `subpassInput`, `input_attachment_index` in the shader is the call order.
+    pub fn input(mut self, attachment: u32) -> Subpass {
+        self.input_attachments.push(attachment);
+        self
+    }
+
+    pub fn depth_stencil(mut self, attachment: u32) -> Subpass {
+        self.depth_stencil_attachment = Some(attachment);
+        self
+    }
+
+    /// Not used by this subpass, but its contents have to survive it for a later one.
+    pub fn preserve(mut self, attachment: u32) -> Subpass {
+        self.preserve_attachments.push(attachment);
+        self
+    }
+
+    pub fn input_attachments(&self) -> &[u32] {
+        &self.input_attachments
+    }
+
+    fn writes(&self, attachment: u32) -> bool {
+        self.color_attachments.contains(&attachment) || self.depth_stencil_attachment == Some(attachment)
+    }
+}
+
+/// What a color attachment starts a render pass with, see `RenderPassBuilder::color_attachment_load`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AttachmentLoad {
+    /// Cleared to the render pass's clear value, the previous contents are discarded.
+    #[default]
+    Clear,
+    /// The previous contents are kept, e.g. for UI drawn over the result of an earlier pass.
+    Preserve,
+    /// Undefined contents, for passes that overwrite every pixel anyway.
+    DontCare,
+}
+
+impl AttachmentLoad {
+    pub fn load_op(&self) -> vk::AttachmentLoadOp {
+        match self {
+            AttachmentLoad::Clear => vk::AttachmentLoadOp::CLEAR,
+            AttachmentLoad::Preserve => vk::AttachmentLoadOp::LOAD,
+            AttachmentLoad::DontCare => vk::AttachmentLoadOp::DONT_CARE,
+        }
+    }
+
+    /// Preserved contents are only kept through a transition from the layout they're actually in,
+    /// `previous_layout`; everything else can start from `UNDEFINED`.
+    pub fn initial_layout(&self, previous_layout: vk::ImageLayout) -> vk::ImageLayout {
+        match self {
+            AttachmentLoad::Preserve => previous_layout,
+            AttachmentLoad::Clear | AttachmentLoad::DontCare => vk::ImageLayout::UNDEFINED,
+        }
+    }
+}
+
+/// Collects the attachments and subpasses of a render pass, then creates the `vk::RenderPass`.
+/// Color attachments come first in attachment order, the depth/stencil attachment is appended after them,
+/// so with `n` color attachments the depth attachment has index `n`.
+/// Without any `subpass` a single subpass writing every attachment is created.
+#[derive(Default)]
+pub struct RenderPassBuilder {
+    color_attachments: Vec<vk::AttachmentDescription>,
+    depth_stencil_attachment: Option<vk::AttachmentDescription>,
+    subpasses: Vec<Subpass>,
+    dependencies: Vec<vk::SubpassDependency>,
+    /// Views every subpass renders, 0 without multiview.
+    view_mask: u32,
+    correlation_mask: u32,
+}
+
+impl RenderPassBuilder {
+    pub fn new() -> RenderPassBuilder {
+        RenderPassBuilder::default()
+    }
+
+    pub fn color_attachment(
+        mut self,
+        format: vk::Format,
+        load_op: vk::AttachmentLoadOp,
+        store_op: vk::AttachmentStoreOp,
+        initial_layout: vk::ImageLayout,
+        final_layout: vk::ImageLayout
+    ) -> RenderPassBuilder {
+        self.color_attachments.push(vk::AttachmentDescription {
+            flags: vk::AttachmentDescriptionFlags::empty(),
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op,
+            store_op,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout,
+            final_layout,
+        });
+        self
+    }
+
+    /// Stored color attachment ending in `final_layout`, loaded as `load` says. Preserving expects the image
+    /// in `final_layout` at the start too, which is where an earlier pass over the same target left it.
+    pub fn color_attachment_load(
+        self,
+        format: vk::Format,
+        load: AttachmentLoad,
+        final_layout: vk::ImageLayout
+    ) -> RenderPassBuilder {
+        self.color_attachment(
+            format,
+            load.load_op(),
+            vk::AttachmentStoreOp::STORE,
+            load.initial_layout(final_layout),
+            final_layout)
+    }
+
+    /// Depth only use of the attachment, the stencil aspect of a depth-stencil format is left undefined.
+    pub fn depth_attachment(
+        self,
+        format: vk::Format,
+        load_op: vk::AttachmentLoadOp,
+        store_op: vk::AttachmentStoreOp,
+        initial_layout: vk::ImageLayout,
+        final_layout: vk::ImageLayout
+    ) -> RenderPassBuilder {
+        self.depth_stencil_attachment(
+            format,
+            load_op,
+            store_op,
+            vk::AttachmentLoadOp::DONT_CARE,
+            vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout,
+            final_layout)
+    }
+
+    /// The stencil ops only apply to formats with a stencil component, e.g. `D24_UNORM_S8_UINT`,
+    /// they are ignored for depth only formats.
+    pub fn depth_stencil_attachment(
+        mut self,
+        format: vk::Format,
+        load_op: vk::AttachmentLoadOp,
+        store_op: vk::AttachmentStoreOp,
+        stencil_load_op: vk::AttachmentLoadOp,
+        stencil_store_op: vk::AttachmentStoreOp,
+        initial_layout: vk::ImageLayout,
+        final_layout: vk::ImageLayout
+    ) -> RenderPassBuilder {
+        let has_stencil = image::has_stencil_component(format);
+
+        self.depth_stencil_attachment = Some(vk::AttachmentDescription {
+            flags: vk::AttachmentDescriptionFlags::empty(),
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op,
+            store_op,
+            stencil_load_op: if has_stencil { stencil_load_op } else { vk::AttachmentLoadOp::DONT_CARE },
+            stencil_store_op: if has_stencil { stencil_store_op } else { vk::AttachmentStoreOp::DONT_CARE },
+            initial_layout,
+            final_layout,
+        });
+        self
+    }
+
+    /// Subpasses run in the order they're added, the index for the pipelines of this one is the call order.
+    /// Reading an attachment an earlier subpass wrote adds the (by region) dependency between them.
+    pub fn subpass(mut self, subpass: Subpass) -> RenderPassBuilder {
+        self.subpasses.push(subpass);
+        self
+    }
+
+    /// Needs `capabilities.multiview`. Every draw renders once per bit of `view_mask` to the array layer of that bit,
+    /// shaders tell the views apart by `gl_ViewIndex`. The framebuffer needs layered attachments and a single layer.
+    /// `correlation_mask` marks views that look at about the same thing, e.g. both eyes, so they can be rendered together.
+    pub fn multiview(mut self, view_mask: u32, correlation_mask: u32) -> RenderPassBuilder {
+        assert!(view_mask != 0, "Multiview needs at least one view");
+        assert!(
+            correlation_mask & !view_mask == 0,
+            "Correlation mask {:#b} has views outside the view mask {:#b}", correlation_mask, view_mask);
+
+        self.view_mask = view_mask;
+        self.correlation_mask = correlation_mask;
+        self
+    }
+
+    pub fn dependency(mut self, dependency: vk::SubpassDependency) -> RenderPassBuilder {
+        self.dependencies.push(dependency);
+        self
+    }
+
+    /// Subpasses as they will be created, the implicit one if none were added.
+    pub fn subpasses(&self) -> Vec<Subpass> {
+        if !self.subpasses.is_empty() {
+            return self.subpasses.clone();
+        }
+
+        let (_, depth_stencil_index) = self.attachments();
+        let subpass = (0..self.color_attachments.len() as u32).fold(Subpass::new(), Subpass::color);
+        vec![match depth_stencil_index {
+            Some(depth_stencil_index) => subpass.depth_stencil(depth_stencil_index),
+            None => subpass,
+        }]
+    }
+
+    /// Input attachment references of `subpass` in `input_attachment_index` order,
+    /// with the layout the attachment is read in.
+    pub fn input_attachment_refs(&self, subpass: &Subpass) -> Vec<vk::AttachmentReference> {
+        let (_, depth_stencil_index) = self.attachments();
+        input_attachment_refs(subpass, depth_stencil_index)
+    }
+
+    /// Attachment descriptions in attachment order, and the index the depth/stencil attachment ended up at.
+    fn attachments(&self) -> (Vec<vk::AttachmentDescription>, Option<u32>) {
+        let mut attachments = self.color_attachments.clone();
+        let depth_stencil_index = self.depth_stencil_attachment.map(|depth_stencil_attachment| {
+            attachments.push(depth_stencil_attachment);
+            (attachments.len() - 1) as u32
+        });
+
+        (attachments, depth_stencil_index)
+    }
+
+    /// Framebuffer-local dependencies from the last subpass writing an attachment to each subpass reading it.
+    fn input_dependencies(subpasses: &[Subpass], depth_stencil_index: Option<u32>) -> Vec<vk::SubpassDependency> {
+        let mut dependencies: Vec<vk::SubpassDependency> = vec![];
+
+        for (dst_subpass, subpass) in subpasses.iter().enumerate() {
+            for &attachment in subpass.input_attachments.iter() {
+                let src_subpass = match subpasses[..dst_subpass].iter().rposition(|earlier| earlier.writes(attachment)) {
+                    Some(src_subpass) => src_subpass as u32,
+                    None => continue,
+                };
+                let (src_stage_mask, src_access_mask) = if depth_stencil_index == Some(attachment) {
+                    (
+                        vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    )
+                } else {
+                    (vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                };
+
+                match dependencies
+                    .iter_mut()
+                    .find(|dependency| dependency.src_subpass == src_subpass && dependency.dst_subpass == dst_subpass as u32)
+                {
+                    Some(dependency) => {
+                        dependency.src_stage_mask |= src_stage_mask;
+                        dependency.src_access_mask |= src_access_mask;
+                    }
+                    None => dependencies.push(vk::SubpassDependency {
+                        src_subpass,
+                        dst_subpass: dst_subpass as u32,
+                        src_stage_mask,
+                        dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                        src_access_mask,
+                        dst_access_mask: vk::AccessFlags::INPUT_ATTACHMENT_READ,
+                        dependency_flags: vk::DependencyFlags::BY_REGION,
+                    }),
+                }
+            }
+        }
+
+        dependencies
+    }
+
+    pub fn build(&self, device: &ash::Device) -> vk::RenderPass {
+        let (attachments, depth_stencil_index) = self.attachments();
+
+        let subpasses = self.subpasses();
+        for subpass in subpasses.iter() {
+            let used_attachments = subpass.color_attachments
+                .iter()
+                .chain(subpass.input_attachments.iter())
+                .chain(subpass.depth_stencil_attachment.iter())
+                .chain(subpass.preserve_attachments.iter());
+            for &attachment in used_attachments {
+                assert!(
+                    (attachment as usize) < attachments.len(),
+                    "Subpass uses attachment {} which isn't declared", attachment);
+            }
+        }
+
+        // the references have to stay where they are until the render pass is created,
+        // so they are all collected before any pointer into them is taken.
+        let attachment_refs: Vec<SubpassAttachmentRefs> = subpasses
+            .iter()
+            .map(|subpass| {
+                // a depth attachment that is also read as input in the same subpass can only be depth tested
+                let reads_depth = depth_stencil_index.is_some_and(|index| subpass.input_attachments.contains(&index));
+                let depth_stencil_layout = if reads_depth {
+                    vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+                } else {
+                    vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+                };
+
+                let color_refs = subpass.color_attachments
+                    .iter()
+                    .map(|&attachment| vk::AttachmentReference {
+                        attachment,
+                        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    })
+                    .collect();
+                let input_refs = input_attachment_refs(subpass, depth_stencil_index);
+                let depth_stencil_ref = subpass.depth_stencil_attachment.map(|attachment| vk::AttachmentReference {
+                    attachment,
+                    layout: depth_stencil_layout,
+                });
+
+                (color_refs, input_refs, depth_stencil_ref)
+            })
+            .collect();
+
+        let subpass_descriptions: Vec<vk::SubpassDescription> = subpasses
+            .iter()
+            .zip(attachment_refs.iter())
+            .map(|(subpass, (color_refs, input_refs, depth_stencil_ref))| vk::SubpassDescription {
+                flags: vk::SubpassDescriptionFlags::empty(),
+                pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+                input_attachment_count: input_refs.len() as u32,
+                p_input_attachments: if input_refs.is_empty() { ptr::null() } else { input_refs.as_ptr() },
+                color_attachment_count: color_refs.len() as u32,
+                p_color_attachments: if color_refs.is_empty() { ptr::null() } else { color_refs.as_ptr() },
+                p_resolve_attachments: ptr::null(),
+                p_depth_stencil_attachment: match depth_stencil_ref {
+                    Some(attachment_ref) => attachment_ref,
+                    None => ptr::null(),
+                },
+                preserve_attachment_count: subpass.preserve_attachments.len() as u32,
+                p_preserve_attachments: if subpass.preserve_attachments.is_empty() {
+                    ptr::null()
+                } else {
+                    subpass.preserve_attachments.as_ptr()
+                },
+            })
+            .collect();
+
+        let mut dependencies = RenderPassBuilder::input_dependencies(&subpasses, depth_stencil_index);
+        dependencies.extend_from_slice(&self.dependencies);
+
+        let view_masks = vec![self.view_mask; subpass_descriptions.len()];
+        let correlation_masks = [self.correlation_mask];
+        let multiview_create_info = vk::RenderPassMultiviewCreateInfo {
+            s_type: vk::StructureType::RENDER_PASS_MULTIVIEW_CREATE_INFO,
+            p_next: ptr::null(),
+            subpass_count: view_masks.len() as u32,
+            p_view_masks: view_masks.as_ptr(),
+            dependency_count: 0,
+            p_view_offsets: ptr::null(),
+            correlation_mask_count: if self.correlation_mask != 0 { 1 } else { 0 },
+            p_correlation_masks: correlation_masks.as_ptr(),
+        };
+
+        let renderpass_create_info = vk::RenderPassCreateInfo {
+            s_type: vk::StructureType::RENDER_PASS_CREATE_INFO,
+            flags: vk::RenderPassCreateFlags::empty(),
+            p_next: if self.view_mask != 0 {
+                &multiview_create_info as *const _ as *const c_void
+            } else {
+                ptr::null()
+            },
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            subpass_count: subpass_descriptions.len() as u32,
+            p_subpasses: subpass_descriptions.as_ptr(),
+            dependency_count: dependencies.len() as u32,
+            p_dependencies: if dependencies.is_empty() { ptr::null() } else { dependencies.as_ptr() },
+        };
+
+        unsafe {
+            device
+                .create_render_pass(&renderpass_create_info, None)
+                .expect("Failed to create render pass!")
+        }
+    }
+}
+
+fn input_attachment_refs(subpass: &Subpass, depth_stencil_index: Option<u32>) -> Vec<vk::AttachmentReference> {
+    subpass.input_attachments
+        .iter()
+        .map(|&attachment| vk::AttachmentReference {
+            attachment,
+            layout: if depth_stencil_index == Some(attachment) {
+                vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+            } else {
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_attachment_comes_after_colors_declared_later() {
+        let builder = RenderPassBuilder::new()
+            .depth_attachment(
+                vk::Format::D32_SFLOAT,
+                vk::AttachmentLoadOp::CLEAR,
+                vk::AttachmentStoreOp::DONT_CARE,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .color_attachment_load(vk::Format::R8G8B8A8_UNORM, AttachmentLoad::Clear, vk::ImageLayout::PRESENT_SRC_KHR)
+            .color_attachment_load(vk::Format::R16G16B16A16_SFLOAT, AttachmentLoad::Clear, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        let (attachments, depth_stencil_index) = builder.attachments();
+        assert_eq!(depth_stencil_index, Some(2));
+        assert_eq!(attachments[2].format, vk::Format::D32_SFLOAT);
+        assert_eq!(builder.subpasses()[0].depth_stencil_attachment, Some(2));
+
+        let lighting = Subpass::new().input(0).input(2);
+        let input_layouts: Vec<vk::ImageLayout> = builder.input_attachment_refs(&lighting)
+            .iter()
+            .map(|input_ref| input_ref.layout)
+            .collect();
+        assert_eq!(
+            input_layouts,
+            [vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL]);
+    }
+}
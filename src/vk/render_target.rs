@@ -0,0 +1,185 @@
+use ash::vk;
+
+use std::ptr;
+
+use crate::vk::image;
+use crate::vk::lifecycle::{self, TrackedObject};
+use crate::vk::swap_chain::VkSpawChain;
+
+/// Anything the renderer can draw into: the window swapchain or an offscreen set of images.
+pub trait RenderTarget {
+    fn extent(&self) -> vk::Extent2D;
+    fn format(&self) -> vk::Format;
+    fn image_view(&self, index: usize) -> vk::ImageView;
+    fn image_count(&self) -> usize;
+
+    /// Layout the color attachment must end the render pass in.
+    fn final_layout(&self) -> vk::ImageLayout;
+}
+
+impl RenderTarget for VkSpawChain {
+    fn extent(&self) -> vk::Extent2D {
+        self.swapchain_extent
+    }
+
+    fn format(&self) -> vk::Format {
+        self.swapchain_format
+    }
+
+    fn image_view(&self, index: usize) -> vk::ImageView {
+        self.swapchain_image_views[index]
+    }
+
+    fn image_count(&self) -> usize {
+        self.swapchain_image_views.len()
+    }
+
+    fn final_layout(&self) -> vk::ImageLayout {
+        vk::ImageLayout::PRESENT_SRC_KHR
+    }
+}
+
+/// Color images owned by the engine instead of the presentation engine.
+/// They end each render pass ready to be sampled.
+pub struct OffscreenTarget {
+    pub images: Vec<vk::Image>,
+    pub images_memory: Vec<vk::DeviceMemory>,
+    pub image_views: Vec<vk::ImageView>,
+
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+}
+
+impl OffscreenTarget {
+    pub fn new(
+        device: &ash::Device,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        image_count: usize
+    ) -> OffscreenTarget {
+        let mut images = vec![];
+        let mut images_memory = vec![];
+        let mut image_views = vec![];
+
+        for _ in 0..image_count {
+            let image_create_info = vk::ImageCreateInfo {
+                s_type: vk::StructureType::IMAGE_CREATE_INFO,
+                p_next: ptr::null(),
+                flags: vk::ImageCreateFlags::empty(),
+                image_type: vk::ImageType::TYPE_2D,
+                format,
+                extent: vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1,
+                },
+                mip_levels: 1,
+                array_layers: 1,
+                samples: vk::SampleCountFlags::TYPE_1,
+                tiling: vk::ImageTiling::OPTIMAL,
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC,
+                sharing_mode: vk::SharingMode::EXCLUSIVE,
+                queue_family_index_count: 0,
+                p_queue_family_indices: ptr::null(),
+                initial_layout: vk::ImageLayout::UNDEFINED,
+            };
+
+            let (image, image_memory) = image::create_image(
+                device,
+                &image_create_info,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                device_memory_properties);
+
+            let image_view = image::create_image_view(
+                device,
+                image,
+                vk::ImageViewType::TYPE_2D,
+                format,
+                vk::ImageAspectFlags::COLOR);
+
+            images.push(image);
+            images_memory.push(image_memory);
+            image_views.push(image_view);
+        }
+
+        OffscreenTarget {
+            images: images,
+            images_memory: images_memory,
+            image_views: image_views,
+            format: format,
+            extent: extent,
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            for i in 0..self.images.len() {
+                device.destroy_image_view(self.image_views[i], None);
+                lifecycle::on_destroy(TrackedObject::Image, self.images[i]);
+                device.destroy_image(self.images[i], None);
+                device.free_memory(self.images_memory[i], None);
+            }
+        }
+    }
+}
+
+impl RenderTarget for OffscreenTarget {
+    fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    fn image_view(&self, index: usize) -> vk::ImageView {
+        self.image_views[index]
+    }
+
+    fn image_count(&self) -> usize {
+        self.image_views.len()
+    }
+
+    fn final_layout(&self) -> vk::ImageLayout {
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+    }
+}
+
+/// One framebuffer per image of `target`, all sharing `render_pass`.
+pub fn create_framebuffers(
+    device: &ash::Device,
+    render_pass: vk::RenderPass,
+    target: &dyn RenderTarget
+) -> Vec<vk::Framebuffer> {
+    let mut framebuffers = vec![];
+    let extent = target.extent();
+
+    for i in 0..target.image_count() {
+        let attachments = [target.image_view(i)];
+
+        let framebuffer_create_info = vk::FramebufferCreateInfo {
+            s_type: vk::StructureType::FRAMEBUFFER_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::FramebufferCreateFlags::empty(),
+            render_pass,
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            width: extent.width,
+            height: extent.height,
+            layers: 1,
+        };
+
+        let framebuffer = unsafe {
+            device
+                .create_framebuffer(&framebuffer_create_info, None)
+                .expect("Failed to create Framebuffer!")
+        };
+
+        framebuffers.push(framebuffer);
+    }
+
+    framebuffers
+}
@@ -0,0 +1,158 @@
+use ash::vk;
+
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+
+/// `eRENDERDOC_API_Version_1_1_2`, the oldest API with every entry point used here.
+const API_VERSION_1_1_2: c_int = 10102;
+
+type GetApiFn = unsafe extern "C" fn(version: c_int, out_api: *mut *mut c_void) -> c_int;
+type StartFrameCaptureFn = unsafe extern "C" fn(device: *const c_void, window: *const c_void);
+type IsFrameCapturingFn = unsafe extern "C" fn() -> u32;
+type EndFrameCaptureFn = unsafe extern "C" fn(device: *const c_void, window: *const c_void) -> u32;
+type TriggerCaptureFn = unsafe extern "C" fn();
+
+/// `RENDERDOC_API_1_1_2` from `renderdoc_app.h`, only the entries used here are typed,
+/// the rest are placeholders keeping the offsets right.
+#[repr(C)]
+struct RenderDocApi {
+    get_api_version: *const c_void,
+    set_capture_option_u32: *const c_void,
+    set_capture_option_f32: *const c_void,
+    get_capture_option_u32: *const c_void,
+    get_capture_option_f32: *const c_void,
+    set_focus_toggle_keys: *const c_void,
+    set_capture_keys: *const c_void,
+    get_overlay_bits: *const c_void,
+    mask_overlay_bits: *const c_void,
+    remove_hooks: *const c_void,
+    unload_crash_handler: *const c_void,
+    set_capture_file_path_template: *const c_void,
+    get_capture_file_path_template: *const c_void,
+    get_num_captures: *const c_void,
+    get_capture: *const c_void,
+    trigger_capture: TriggerCaptureFn,
+    is_target_control_connected: *const c_void,
+    launch_replay_ui: *const c_void,
+    set_active_window: *const c_void,
+    start_frame_capture: StartFrameCaptureFn,
+    is_frame_capturing: IsFrameCapturingFn,
+    end_frame_capture: EndFrameCaptureFn,
+}
+
+/// RenderDoc's in-application API, for captures of exactly the frames the code asks for.
+/// Only finds RenderDoc when the process was launched or injected by it, every call is a no-op otherwise,
+/// so it's safe to leave in dev builds. Captures cover every window and the device of the instance
+/// passed to `new`.
+pub struct RenderDoc {
+    // keeps renderdoc's module referenced for as long as `api` points into it.
+    _library: Option<libloading::Library>,
+    api: *const RenderDocApi,
+    device: *const c_void,
+}
+
+impl RenderDoc {
+    pub fn new(instance: &ash::Instance) -> RenderDoc {
+        let (library, api) = match RenderDoc::load_api() {
+            Some((library, api)) => (Some(library), api),
+            None => (None, ptr::null()),
+        };
+
+        RenderDoc {
+            _library: library,
+            api: api,
+            device: RenderDoc::device_pointer(instance.handle()),
+        }
+    }
+
+    /// Whether the process runs under RenderDoc, everything else does nothing when it doesn't.
+    pub fn is_attached(&self) -> bool {
+        !self.api.is_null()
+    }
+
+    /// Starts capturing everything submitted from here on, until `end_capture`.
+    pub fn start_capture(&self) {
+        if let Some(api) = self.api() {
+            unsafe {
+                (api.start_frame_capture)(self.device, ptr::null());
+            }
+        }
+    }
+
+    /// Ends the capture started by `start_capture`, returns whether one was written.
+    pub fn end_capture(&self) -> bool {
+        match self.api() {
+            Some(api) => unsafe { (api.end_frame_capture)(self.device, ptr::null()) == 1 },
+            None => false,
+        }
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        match self.api() {
+            Some(api) => unsafe { (api.is_frame_capturing)() == 1 },
+            None => false,
+        }
+    }
+
+    /// Captures the next presented frame, same as the capture key.
+    pub fn trigger_capture(&self) {
+        if let Some(api) = self.api() {
+            unsafe {
+                (api.trigger_capture)();
+            }
+        }
+    }
+
+    fn api(&self) -> Option<&RenderDocApi> {
+        unsafe { self.api.as_ref() }
+    }
+
+    /// `RENDERDOC_DEVICEPOINTER_FROM_VKINSTANCE`, the dispatch table pointer the instance handle points to.
+    fn device_pointer(instance: vk::Instance) -> *const c_void {
+        use ash::vk::Handle;
+
+        unsafe { *(instance.as_raw() as *const *const c_void) }
+    }
+
+    /// Only looks for an already loaded module, loading RenderDoc after the instance exists would hook too late.
+    fn load_api() -> Option<(libloading::Library, *const RenderDocApi)> {
+        let library = RenderDoc::loaded_library()?;
+
+        let mut api: *mut c_void = ptr::null_mut();
+        let is_loaded = unsafe {
+            let get_api: libloading::Symbol<GetApiFn> = library.get(b"RENDERDOC_GetAPI\0").ok()?;
+            get_api(API_VERSION_1_1_2, &mut api) == 1
+        };
+        if !is_loaded || api.is_null() {
+            log::warn!("RenderDoc is loaded but doesn't provide API 1.1.2");
+            return None;
+        }
+
+        log::info!("RenderDoc attached, in-application captures enabled");
+        Some((library, api as *const RenderDocApi))
+    }
+
+    #[cfg(target_os = "windows")]
+    fn loaded_library() -> Option<libloading::Library> {
+        libloading::os::windows::Library::open_already_loaded("renderdoc.dll")
+            .ok()
+            .map(libloading::Library::from)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn loaded_library() -> Option<libloading::Library> {
+        // glibc's RTLD_NOLOAD, libloading doesn't export it.
+        const RTLD_NOLOAD: c_int = 0x4;
+
+        unsafe {
+            libloading::os::unix::Library::open(Some("librenderdoc.so"), libloading::os::unix::RTLD_NOW | RTLD_NOLOAD)
+                .ok()
+                .map(libloading::Library::from)
+        }
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+    fn loaded_library() -> Option<libloading::Library> {
+        None
+    }
+}
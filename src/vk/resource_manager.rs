@@ -0,0 +1,268 @@
+use ash::vk;
+
+use std::marker::PhantomData;
+
+use crate::vk::buffer::VkBuffer;
+use crate::vk::deletion_queue::DeletionQueue;
+use crate::vk::sampler::SamplerCache;
+use crate::vk::texture::VkTexture;
+
+/// Index into one of the `ResourceManager` pools, plus the generation of the slot it was handed out for.
+/// A handle of a removed resource stays stale even when its slot is reused.
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    marker: PhantomData<fn() -> T>,
+}
+
+// derived impls would require `T` to implement the traits as well.
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "Handle({}v{})", self.index, self.generation)
+    }
+}
+
+pub type BufferHandle = Handle<VkBuffer>;
+pub type TextureHandle = Handle<VkTexture>;
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+/// Slots are reused in LIFO order, bumping their generation every time they are freed.
+struct Pool<T> {
+    slots: Vec<Slot<T>>,
+    free_slots: Vec<u32>,
+}
+
+impl<T> Pool<T> {
+    fn new() -> Pool<T> {
+        Pool {
+            slots: vec![],
+            free_slots: vec![],
+        }
+    }
+
+    fn insert(&mut self, value: T) -> Handle<T> {
+        let index = match self.free_slots.pop() {
+            Some(index) => {
+                self.slots[index as usize].value = Some(value);
+                index
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: Some(value),
+                });
+                (self.slots.len() - 1) as u32
+            }
+        };
+
+        Handle {
+            index: index,
+            generation: self.slots[index as usize].generation,
+            marker: PhantomData,
+        }
+    }
+
+    fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_ref())
+    }
+
+    fn remove(&mut self, handle: Handle<T>) -> Option<T> {
+        let slot = self.slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)?;
+        let value = slot.value.take()?;
+
+        // a slot whose generation ran out is retired instead of risking an old handle becoming valid again.
+        slot.generation = slot.generation.wrapping_add(1);
+        if slot.generation != 0 {
+            self.free_slots.push(handle.index);
+        }
+
+        Some(value)
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = (Handle<T>, &mut T)> + '_ {
+        self.slots.iter_mut().enumerate().filter_map(|(index, slot)| {
+            let generation = slot.generation;
+            slot.value.as_mut().map(|value| {
+                let handle = Handle {
+                    index: index as u32,
+                    generation: generation,
+                    marker: PhantomData,
+                };
+                (handle, value)
+            })
+        })
+    }
+
+    fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.free_slots.clear();
+        self.slots.drain(..).filter_map(|slot| slot.value)
+    }
+}
+
+/// Owns buffers and textures and hands out typed handles to them instead of raw Vulkan handles,
+/// so a lookup through a handle of a destroyed resource returns `None` instead of a dangling handle.
+/// Destroys whatever is left on drop, which has to happen once the device is idle.
+/// Keeps a clone of the device which has to outlive it.
+pub struct ResourceManager {
+    device: ash::Device,
+    buffers: Pool<VkBuffer>,
+    textures: Pool<VkTexture>,
+}
+
+impl ResourceManager {
+    pub fn new(device: &ash::Device) -> ResourceManager {
+        ResourceManager {
+            device: device.clone(),
+            buffers: Pool::new(),
+            textures: Pool::new(),
+        }
+    }
+
+    pub fn add_buffer(&mut self, buffer: VkBuffer) -> BufferHandle {
+        self.buffers.insert(buffer)
+    }
+
+    pub fn buffer(&self, handle: BufferHandle) -> Option<&VkBuffer> {
+        self.buffers.get(handle)
+    }
+
+    /// Raw handle for recording commands, `None` for a stale handle.
+    pub fn raw_buffer(&self, handle: BufferHandle) -> Option<vk::Buffer> {
+        self.buffer(handle).map(|buffer| buffer.buffer)
+    }
+
+    /// The handle goes stale right away, the buffer itself is destroyed through `deletion_queue`
+    /// once the frames in flight that could still use it are done. Returns false for a stale handle.
+    pub fn destroy_buffer(&mut self, handle: BufferHandle, deletion_queue: &mut DeletionQueue) -> bool {
+        match self.buffers.remove(handle) {
+            Some(buffer) => {
+                deletion_queue.enqueue(move |device| buffer.destroy(device));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn add_texture(&mut self, texture: VkTexture) -> TextureHandle {
+        self.textures.insert(texture)
+    }
+
+    pub fn texture(&self, handle: TextureHandle) -> Option<&VkTexture> {
+        self.textures.get(handle)
+    }
+
+    /// Points every texture at the sampler of the current `SamplerCache::quality`, after `set_quality`.
+    /// Returns the textures whose sampler changed, the descriptors sampling them have to be rewritten.
+    pub fn refresh_texture_samplers(&mut self, sampler_cache: &SamplerCache) -> Vec<TextureHandle> {
+        self.textures
+            .iter_mut()
+            .filter_map(|(handle, texture)| texture.refresh_sampler(sampler_cache).then_some(handle))
+            .collect()
+    }
+
+    /// Same as `destroy_buffer`, the texture is destroyed through `deletion_queue`.
+    pub fn destroy_texture(&mut self, handle: TextureHandle, deletion_queue: &mut DeletionQueue) -> bool {
+        match self.textures.remove(handle) {
+            Some(texture) => {
+                deletion_queue.enqueue(move |device| texture.destroy(device));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Drop for ResourceManager {
+    fn drop(&mut self) {
+        for buffer in self.buffers.drain() {
+            buffer.destroy(&self.device);
+        }
+        for texture in self.textures.drain() {
+            texture.destroy(&self.device);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_handle_returns_none_after_slot_reuse() {
+        let mut pool: Pool<u32> = Pool::new();
+
+        let first = pool.insert(1);
+        assert_eq!(pool.get(first), Some(&1));
+        assert_eq!(pool.remove(first), Some(1));
+        assert_eq!(pool.get(first), None);
+
+        let second = pool.insert(2);
+        assert_eq!(second.index, first.index);
+        assert_ne!(second, first);
+        assert_eq!(pool.get(first), None);
+        assert_eq!(pool.get(second), Some(&2));
+    }
+
+    #[test]
+    fn removing_twice_only_removes_once() {
+        let mut pool: Pool<u32> = Pool::new();
+
+        let handle = pool.insert(1);
+        let other = pool.insert(2);
+        assert_eq!(pool.remove(handle), Some(1));
+        assert_eq!(pool.remove(handle), None);
+        assert_eq!(pool.get(other), Some(&2));
+        assert_eq!(pool.free_slots.len(), 1);
+    }
+
+    #[test]
+    fn iteration_skips_free_slots_with_current_handles() {
+        let mut pool: Pool<u32> = Pool::new();
+
+        let removed = pool.insert(1);
+        let kept = pool.insert(2);
+        pool.remove(removed);
+        let reused = pool.insert(3);
+
+        let handles: Vec<(Handle<u32>, u32)> = pool.iter_mut().map(|(handle, &mut value)| (handle, value)).collect();
+        assert_eq!(handles, vec![(reused, 3), (kept, 2)]);
+    }
+
+    #[test]
+    fn exhausted_generation_retires_slot() {
+        let mut pool: Pool<u32> = Pool::new();
+
+        let handle = pool.insert(1);
+        pool.slots[handle.index as usize].generation = u32::MAX;
+        let handle = Handle { generation: u32::MAX, ..handle };
+        assert_eq!(pool.remove(handle), Some(1));
+
+        let next = pool.insert(2);
+        assert_ne!(next.index, handle.index);
+    }
+}
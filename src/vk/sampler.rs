@@ -0,0 +1,259 @@
+use ash::vk;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::ptr;
+
+use crate::vk::capabilities::DeviceCapabilities;
+
+/// Floats compared by their bits, so a config can be a hash key.
+type SamplerKey = ([i32; 6], [u32; 3], Option<u32>, Option<i32>, i32, bool);
+
+/// Everything a `vk::Sampler` is created from, used as the `SamplerCache` key.
+#[derive(Clone, Copy, Debug)]
+pub struct SamplerConfig {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+    pub address_mode_w: vk::SamplerAddressMode,
+    pub mip_lod_bias: f32,
+    /// `None` disables anisotropic filtering, `Some` needs `capabilities.sampler_anisotropy`.
+    pub max_anisotropy: Option<f32>,
+    /// `Some` makes it a comparison sampler, e.g. for shadow maps.
+    pub compare_op: Option<vk::CompareOp>,
+    pub min_lod: f32,
+    pub max_lod: f32,
+    pub border_color: vk::BorderColor,
+    pub unnormalized_coordinates: bool,
+}
+
+impl SamplerConfig {
+    /// Trilinear filtering over every mip level with the same addressing on all axes.
+    pub fn linear(address_mode: vk::SamplerAddressMode) -> SamplerConfig {
+        SamplerConfig {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            mip_lod_bias: 0.0,
+            max_anisotropy: None,
+            compare_op: None,
+            min_lod: 0.0,
+            max_lod: vk::LOD_CLAMP_NONE,
+            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+            unnormalized_coordinates: false,
+        }
+    }
+
+    /// `self` with the anisotropy, mip LOD bias and mipmap mode of `quality`.
+    /// Anisotropy is clamped to `max_supported_anisotropy`, `None` when the device can't filter anisotropically.
+    pub fn with_quality(self, quality: TextureQuality, max_supported_anisotropy: Option<f32>) -> SamplerConfig {
+        let max_anisotropy = match (quality.max_anisotropy(), max_supported_anisotropy) {
+            (Some(max_anisotropy), Some(limit)) => Some(max_anisotropy.min(limit)),
+            _ => None,
+        };
+
+        SamplerConfig {
+            mipmap_mode: quality.mipmap_mode(),
+            mip_lod_bias: quality.mip_lod_bias(),
+            max_anisotropy: max_anisotropy,
+            ..self
+        }
+    }
+
+    fn key(&self) -> SamplerKey {
+        (
+            [
+                self.mag_filter.as_raw(),
+                self.min_filter.as_raw(),
+                self.mipmap_mode.as_raw(),
+                self.address_mode_u.as_raw(),
+                self.address_mode_v.as_raw(),
+                self.address_mode_w.as_raw(),
+            ],
+            [
+                self.mip_lod_bias.to_bits(),
+                self.min_lod.to_bits(),
+                self.max_lod.to_bits(),
+            ],
+            self.max_anisotropy.map(f32::to_bits),
+            self.compare_op.map(|compare_op| compare_op.as_raw()),
+            self.border_color.as_raw(),
+            self.unnormalized_coordinates,
+        )
+    }
+}
+
+impl PartialEq for SamplerConfig {
+    fn eq(&self, other: &SamplerConfig) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for SamplerConfig {}
+
+impl Hash for SamplerConfig {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// Texture quality setting applied to every sampler asked for through `SamplerCache::get_or_create_for_quality`,
+/// the way a settings menu exposes it. Lower tiers trade sharpness for texture bandwidth.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextureQuality {
+    /// Bilinear filtering without anisotropy and a blurrier mip selection.
+    Low,
+    Medium,
+    #[default]
+    High,
+    Ultra,
+}
+
+impl TextureQuality {
+    /// Anisotropy level before clamping to the device limit, `None` disables anisotropic filtering.
+    pub fn max_anisotropy(&self) -> Option<f32> {
+        match self {
+            TextureQuality::Low => None,
+            TextureQuality::Medium => Some(4.0),
+            TextureQuality::High => Some(8.0),
+            TextureQuality::Ultra => Some(16.0),
+        }
+    }
+
+    /// Positive values pick smaller mips earlier.
+    pub fn mip_lod_bias(&self) -> f32 {
+        match self {
+            TextureQuality::Low => 1.0,
+            TextureQuality::Medium => 0.5,
+            TextureQuality::High | TextureQuality::Ultra => 0.0,
+        }
+    }
+
+    pub fn mipmap_mode(&self) -> vk::SamplerMipmapMode {
+        match self {
+            TextureQuality::Low => vk::SamplerMipmapMode::NEAREST,
+            _ => vk::SamplerMipmapMode::LINEAR,
+        }
+    }
+}
+
+/// Shares one `vk::Sampler` between everything sampling with the same config,
+/// so texture heavy scenes stay below `maxSamplerAllocationCount`.
+/// The cache owns the samplers and destroys them on drop, keeps a clone of the device which has to outlive it.
+pub struct SamplerCache {
+    device: ash::Device,
+    samplers: RefCell<HashMap<SamplerConfig, vk::Sampler>>,
+
+    quality: TextureQuality,
+    max_supported_anisotropy: Option<f32>,
+}
+
+impl SamplerCache {
+    pub fn new(device: &ash::Device, capabilities: &DeviceCapabilities) -> SamplerCache {
+        let max_supported_anisotropy = if capabilities.sampler_anisotropy {
+            Some(capabilities.max_sampler_anisotropy)
+        } else {
+            None
+        };
+
+        SamplerCache {
+            device: device.clone(),
+            samplers: RefCell::new(HashMap::new()),
+
+            quality: TextureQuality::default(),
+            max_supported_anisotropy: max_supported_anisotropy,
+        }
+    }
+
+    pub fn quality(&self) -> TextureQuality {
+        self.quality
+    }
+
+    /// Samplers asked for afterwards get the new tier, existing textures switch to it through
+    /// `ResourceManager::refresh_texture_samplers` or `VkTexture::refresh_sampler`, after which the descriptors
+    /// using them have to be rewritten. The samplers of the previous tier stay cached until `clear`,
+    /// since frames in flight may still use them, and switching back reuses them.
+    pub fn set_quality(&mut self, quality: TextureQuality) {
+        self.quality = quality;
+    }
+
+    /// `get_or_create` with `config` adjusted to the current `quality`, for samplers of regular textures.
+    pub fn get_or_create_for_quality(&self, config: &SamplerConfig) -> vk::Sampler {
+        self.get_or_create(&config.with_quality(self.quality, self.max_supported_anisotropy))
+    }
+
+    /// Only creates a sampler the first time `config` is asked for.
+    pub fn get_or_create(&self, config: &SamplerConfig) -> vk::Sampler {
+        let mut samplers = self.samplers.borrow_mut();
+        let device = &self.device;
+
+        *samplers
+            .entry(*config)
+            .or_insert_with(|| SamplerCache::create_sampler(device, config))
+    }
+
+    /// Destroys every sampler, none of them may be in use by the device anymore.
+    pub fn clear(&self) {
+        for (_, sampler) in self.samplers.borrow_mut().drain() {
+            unsafe {
+                self.device.destroy_sampler(sampler, None);
+            }
+        }
+    }
+
+    fn create_sampler(device: &ash::Device, config: &SamplerConfig) -> vk::Sampler {
+        let sampler_create_info = vk::SamplerCreateInfo {
+            s_type: vk::StructureType::SAMPLER_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::SamplerCreateFlags::empty(),
+            mag_filter: config.mag_filter,
+            min_filter: config.min_filter,
+            mipmap_mode: config.mipmap_mode,
+            address_mode_u: config.address_mode_u,
+            address_mode_v: config.address_mode_v,
+            address_mode_w: config.address_mode_w,
+            mip_lod_bias: config.mip_lod_bias,
+            anisotropy_enable: config.max_anisotropy.is_some() as vk::Bool32,
+            max_anisotropy: config.max_anisotropy.unwrap_or(1.0),
+            compare_enable: config.compare_op.is_some() as vk::Bool32,
+            compare_op: config.compare_op.unwrap_or(vk::CompareOp::ALWAYS),
+            min_lod: config.min_lod,
+            max_lod: config.max_lod,
+            border_color: config.border_color,
+            unnormalized_coordinates: config.unnormalized_coordinates as vk::Bool32,
+        };
+
+        unsafe {
+            device
+                .create_sampler(&sampler_create_info, None)
+                .expect("Failed to create Sampler!")
+        }
+    }
+}
+
+impl Drop for SamplerCache {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quality_anisotropy_is_clamped_to_the_device_limit() {
+        let config = SamplerConfig::linear(vk::SamplerAddressMode::REPEAT);
+
+        assert_eq!(config.with_quality(TextureQuality::Ultra, Some(8.0)).max_anisotropy, Some(8.0));
+        assert_eq!(config.with_quality(TextureQuality::Medium, Some(8.0)).max_anisotropy, Some(4.0));
+        assert_eq!(config.with_quality(TextureQuality::Ultra, None).max_anisotropy, None);
+        assert_eq!(config.with_quality(TextureQuality::Low, Some(16.0)).max_anisotropy, None);
+    }
+}
@@ -0,0 +1,323 @@
+use ash::vk;
+use notify::Watcher;
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::ptr;
+use std::sync::mpsc;
+
+use crate::utility::tools;
+use crate::vk::lifecycle::{self, TrackedObject};
+
+/// Index of a shader watched by a `ShaderReloader`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShaderHandle(usize);
+
+/// Index of a pipeline rebuilt by a `ShaderReloader`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PipelineHandle(usize);
+
+/// Builds a pipeline from the current modules of its shaders, in the order they were passed to `add_pipeline`.
+pub type PipelineBuildFn = Box<dyn Fn(&ash::Device, &[vk::ShaderModule]) -> vk::Pipeline>;
+
+struct WatchedShader {
+    /// Canonical, so it compares equal to the paths in watcher events.
+    path: PathBuf,
+    module: vk::ShaderModule,
+}
+
+struct WatchedPipeline {
+    shaders: Vec<ShaderHandle>,
+    build: PipelineBuildFn,
+    pipeline: vk::Pipeline,
+}
+
+/// Dev only reloading of shaders whose file changed on disk, together with the pipelines built from them.
+/// `.spv` files are loaded as they are, GLSL sources are compiled with `glslc` first, same as `compile_shaders.py`.
+/// Owns the modules and pipelines and destroys them on drop, keeps a clone of the device which has to outlive it.
+pub struct ShaderReloader {
+    device: ash::Device,
+    shaders: Vec<WatchedShader>,
+    pipelines: Vec<WatchedPipeline>,
+
+    // directories rather than files, editors often save by replacing the file which drops a watch on it.
+    watcher: notify::RecommendedWatcher,
+    watched_directories: HashSet<PathBuf>,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+}
+
+impl ShaderReloader {
+    pub fn new(device: &ash::Device) -> ShaderReloader {
+        let (sender, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(sender).expect("Failed to create shader file watcher");
+
+        ShaderReloader {
+            device: device.clone(),
+            shaders: vec![],
+            pipelines: vec![],
+            watcher: watcher,
+            watched_directories: HashSet::new(),
+            events: events,
+        }
+    }
+
+    /// Loads the shader at `path`, panics when the first load fails since there is nothing to fall back to.
+    pub fn watch(&mut self, path: &Path) -> ShaderHandle {
+        let module = load_module(&self.device, path)
+            .unwrap_or_else(|error| panic!("Failed to load shader: {}", error));
+        let path = std::fs::canonicalize(path).expect("Failed to resolve shader path");
+
+        let directory = path.parent().expect("Shader path has no parent directory").to_path_buf();
+        if !self.watched_directories.contains(&directory) {
+            self.watcher
+                .watch(&directory, notify::RecursiveMode::NonRecursive)
+                .expect("Failed to watch shader directory");
+            self.watched_directories.insert(directory);
+        }
+
+        self.shaders.push(WatchedShader {
+            path: path,
+            module: module,
+        });
+
+        ShaderHandle(self.shaders.len() - 1)
+    }
+
+    pub fn module(&self, shader: ShaderHandle) -> vk::ShaderModule {
+        self.shaders[shader.0].module
+    }
+
+    /// Builds the pipeline right away and again every time one of `shaders` is reloaded.
+    pub fn add_pipeline(&mut self, shaders: &[ShaderHandle], build: PipelineBuildFn) -> PipelineHandle {
+        let modules: Vec<vk::ShaderModule> = shaders.iter().map(|&shader| self.module(shader)).collect();
+        let pipeline = build(&self.device, &modules);
+
+        self.pipelines.push(WatchedPipeline {
+            shaders: shaders.to_vec(),
+            build: build,
+            pipeline: pipeline,
+        });
+
+        PipelineHandle(self.pipelines.len() - 1)
+    }
+
+    /// Changes after each reload, so it has to be fetched again before every bind.
+    pub fn pipeline(&self, pipeline: PipelineHandle) -> vk::Pipeline {
+        self.pipelines[pipeline.0].pipeline
+    }
+
+    /// Reloads the shaders the watcher reported as changed since the last poll and rebuilds the pipelines
+    /// using them, after waiting for the device to be idle. Meant to be called once per frame from the main loop.
+    /// A shader that fails to load or compile keeps its old module, the error is logged.
+    /// Returns whether anything was reloaded.
+    pub fn poll_reloads(&mut self) -> bool {
+        let events: Vec<notify::Event> = self.events
+            .try_iter()
+            .filter_map(|event| event.map_err(|error| log::error!("Shader watcher failed: {}", error)).ok())
+            .collect();
+        let shader_paths: Vec<&Path> = self.shaders.iter().map(|shader| shader.path.as_path()).collect();
+        let changed = changed_shaders(&shader_paths, &events);
+        if changed.is_empty() {
+            return false;
+        }
+
+        let loads: Vec<(usize, Result<vk::ShaderModule, String>)> = changed
+            .into_iter()
+            .map(|index| (index, load_module(&self.device, &self.shaders[index].path)))
+            .collect();
+        let mut modules: Vec<vk::ShaderModule> = self.shaders.iter().map(|shader| shader.module).collect();
+        let (reloaded, replaced_modules) = replace_modules(&mut modules, loads);
+        if reloaded.is_empty() {
+            return false;
+        }
+
+        unsafe {
+            self.device
+                .device_wait_idle()
+                .expect("Failed to wait device idle!");
+            for &module in replaced_modules.iter() {
+                self.device.destroy_shader_module(module, None);
+            }
+        }
+
+        for (shader, &module) in self.shaders.iter_mut().zip(modules.iter()) {
+            shader.module = module;
+        }
+        for &index in reloaded.iter() {
+            log::info!("Reloaded shader {}", self.shaders[index].path.display());
+        }
+
+        for pipeline in self.pipelines.iter_mut() {
+            let is_affected = pipeline.shaders.iter().any(|shader| reloaded.contains(&shader.0));
+            if !is_affected {
+                continue;
+            }
+
+            let modules: Vec<vk::ShaderModule> = pipeline.shaders
+                .iter()
+                .map(|shader| self.shaders[shader.0].module)
+                .collect();
+            let rebuilt_pipeline = (pipeline.build)(&self.device, &modules);
+
+            lifecycle::on_destroy(TrackedObject::Pipeline, pipeline.pipeline);
+            unsafe {
+                self.device.destroy_pipeline(pipeline.pipeline, None);
+            }
+            pipeline.pipeline = rebuilt_pipeline;
+        }
+
+        true
+    }
+}
+
+impl Drop for ShaderReloader {
+    fn drop(&mut self) {
+        unsafe {
+            for pipeline in self.pipelines.iter() {
+                lifecycle::on_destroy(TrackedObject::Pipeline, pipeline.pipeline);
+                self.device.destroy_pipeline(pipeline.pipeline, None);
+            }
+            for shader in self.shaders.iter() {
+                self.device.destroy_shader_module(shader.module, None);
+            }
+        }
+    }
+}
+
+/// Indices of `shader_paths` that were written, created or renamed into place by any of `events`, each once.
+fn changed_shaders(shader_paths: &[&Path], events: &[notify::Event]) -> Vec<usize> {
+    let mut changed: Vec<usize> = vec![];
+
+    for event in events.iter() {
+        if !matches!(event.kind, notify::EventKind::Create(_) | notify::EventKind::Modify(_)) {
+            continue;
+        }
+
+        for path in event.paths.iter() {
+            if let Some(index) = shader_paths.iter().position(|shader_path| shader_path == path) {
+                if !changed.contains(&index) {
+                    changed.push(index);
+                }
+            }
+        }
+    }
+
+    changed
+}
+
+/// Puts every successfully loaded module into `modules`, a shader whose load failed keeps its old module
+/// and its error is logged. Returns the reloaded indices and the replaced modules, which still need destroying.
+fn replace_modules(
+    modules: &mut [vk::ShaderModule],
+    loads: Vec<(usize, Result<vk::ShaderModule, String>)>,
+) -> (Vec<usize>, Vec<vk::ShaderModule>) {
+    let mut reloaded: Vec<usize> = vec![];
+    let mut replaced_modules: Vec<vk::ShaderModule> = vec![];
+
+    for (index, load) in loads {
+        match load {
+            Ok(module) => {
+                replaced_modules.push(modules[index]);
+                modules[index] = module;
+                reloaded.push(index);
+            }
+            Err(error) => log::error!("Failed to reload shader: {}", error),
+        }
+    }
+
+    (reloaded, replaced_modules)
+}
+
+fn load_module(device: &ash::Device, path: &Path) -> Result<vk::ShaderModule, String> {
+    let spv_path = if path.extension().is_some_and(|extension| extension == "spv") {
+        path.to_path_buf()
+    } else {
+        compile_glsl(path)?
+    };
+    let words = tools::read_spv(&spv_path).map_err(|error| format!("Failed to read {}: {}", spv_path.display(), error))?;
+
+    let shader_module_create_info = vk::ShaderModuleCreateInfo {
+        s_type: vk::StructureType::SHADER_MODULE_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::ShaderModuleCreateFlags::empty(),
+        code_size: words.len() * std::mem::size_of::<u32>(),
+        p_code: words.as_ptr(),
+    };
+
+    unsafe {
+        device
+            .create_shader_module(&shader_module_create_info, None)
+            .map_err(|error| format!("Failed to create shader module from {}: {}", path.display(), error))
+    }
+}
+
+/// Compiles into the temp directory and returns the path of the `.spv` output.
+fn compile_glsl(path: &Path) -> Result<PathBuf, String> {
+    let output_path = std::env::temp_dir().join(format!(
+        "{}.reload.spv",
+        path.file_name().unwrap_or_default().to_string_lossy()));
+
+    let output = Command::new("glslc")
+        .arg(path)
+        .arg("-o")
+        .arg(&output_path)
+        .output()
+        .map_err(|error| format!("Failed to run glslc: {}", error))?;
+    if !output.status.success() {
+        return Err(format!("Failed to compile {}:\n{}", path.display(), String::from_utf8_lossy(&output.stderr)));
+    }
+
+    Ok(output_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ash::vk::Handle;
+    use notify::event::{AccessKind, CreateKind, DataChange, EventKind, ModifyKind, RemoveKind};
+
+    fn event(kind: EventKind, path: &str) -> notify::Event {
+        notify::Event::new(kind).add_path(PathBuf::from(path))
+    }
+
+    #[test]
+    fn writes_and_replacements_of_watched_shaders_are_changes() {
+        let paths = [Path::new("/shaders/a.frag"), Path::new("/shaders/b.vert")];
+        let events = [
+            event(EventKind::Modify(ModifyKind::Data(DataChange::Content)), "/shaders/b.vert"),
+            event(EventKind::Modify(ModifyKind::Data(DataChange::Any)), "/shaders/b.vert"),
+            event(EventKind::Create(CreateKind::File), "/shaders/a.frag"),
+            event(EventKind::Modify(ModifyKind::Data(DataChange::Content)), "/shaders/unrelated.frag"),
+        ];
+
+        assert_eq!(changed_shaders(&paths, &events), vec![1, 0]);
+    }
+
+    #[test]
+    fn reads_and_removals_are_not_changes() {
+        let paths = [Path::new("/shaders/a.frag")];
+        let events = [
+            event(EventKind::Access(AccessKind::Any), "/shaders/a.frag"),
+            event(EventKind::Remove(RemoveKind::File), "/shaders/a.frag"),
+        ];
+
+        assert!(changed_shaders(&paths, &events).is_empty());
+    }
+
+    #[test]
+    fn failed_reload_keeps_the_old_module() {
+        let old_modules = [vk::ShaderModule::from_raw(1), vk::ShaderModule::from_raw(2)];
+        let reloaded_module = vk::ShaderModule::from_raw(3);
+        let mut modules = old_modules;
+
+        let (reloaded, replaced_modules) = replace_modules(
+            &mut modules,
+            vec![(0, Err(String::from("syntax error"))), (1, Ok(reloaded_module))]);
+
+        assert_eq!(modules, [old_modules[0], reloaded_module]);
+        assert_eq!(reloaded, vec![1]);
+        assert_eq!(replaced_modules, vec![old_modules[1]]);
+    }
+}
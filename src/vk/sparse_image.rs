@@ -0,0 +1,312 @@
+use ash::vk;
+
+use std::ptr;
+
+use crate::vk::capabilities::DeviceCapabilities;
+use crate::vk::render_device::VkRenderDevice;
+use crate::vk::lifecycle::{self, TrackedObject};
+use crate::vk::sync::VkFence;
+
+/// 2D image with sparse residency, memory is only backed for the tiles that were bound,
+/// e.g. for streaming the visible parts of a huge texture.
+/// Needs `capabilities.sparse_residency_image_2d`, the binds go through the graphics queue or another queue
+/// of a family with `SPARSE_BINDING`.
+pub struct VkSparseImage {
+    device: ash::Device,
+    pub image: vk::Image,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    /// Texels covered by one tile, every tile is backed by `tile_size` bytes.
+    pub tile_extent: vk::Extent3D,
+    pub tile_size: vk::DeviceSize,
+
+    memory_type_index: u32,
+    /// Memory of bound tiles by tile coordinate.
+    tile_memory: Vec<((u32, u32), vk::DeviceMemory)>,
+    /// Small mips that don't fill a tile live in the mip tail, always bound as a whole.
+    mip_tail_memory: Option<vk::DeviceMemory>,
+}
+
+impl VkSparseImage {
+    /// `queue` is what binds go through here and in the later calls, from the family `queue_family_index`.
+    /// Fails with `ERROR_FEATURE_NOT_PRESENT` without `capabilities.sparse_residency_image_2d` or when that family
+    /// can't bind sparse memory, and with `ERROR_FORMAT_NOT_SUPPORTED` when `format` can't be used for a sparse
+    /// image with `usage`.
+    pub fn new(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &ash::Device,
+        capabilities: &DeviceCapabilities,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        queue: vk::Queue,
+        queue_family_index: u32,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        usage: vk::ImageUsageFlags,
+    ) -> Result<VkSparseImage, vk::Result> {
+        if !capabilities.sparse_residency_image_2d {
+            return Err(vk::Result::ERROR_FEATURE_NOT_PRESENT);
+        }
+        let queue_families = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+        let can_bind_sparse = queue_families
+            .get(queue_family_index as usize)
+            .map_or(false, |queue_family| queue_family.queue_flags.contains(vk::QueueFlags::SPARSE_BINDING));
+        if !can_bind_sparse {
+            return Err(vk::Result::ERROR_FEATURE_NOT_PRESENT);
+        }
+
+        let format_properties = unsafe {
+            instance.get_physical_device_sparse_image_format_properties(
+                physical_device,
+                format,
+                vk::ImageType::TYPE_2D,
+                vk::SampleCountFlags::TYPE_1,
+                usage,
+                vk::ImageTiling::OPTIMAL,
+            )
+        };
+        if format_properties.is_empty() {
+            return Err(vk::Result::ERROR_FORMAT_NOT_SUPPORTED);
+        }
+
+        let image_create_info = vk::ImageCreateInfo {
+            s_type: vk::StructureType::IMAGE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::ImageCreateFlags::SPARSE_BINDING | vk::ImageCreateFlags::SPARSE_RESIDENCY,
+            image_type: vk::ImageType::TYPE_2D,
+            format,
+            extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            mip_levels: 1,
+            array_layers: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            queue_family_index_count: 0,
+            p_queue_family_indices: ptr::null(),
+            initial_layout: vk::ImageLayout::UNDEFINED,
+        };
+
+        let image = unsafe {
+            device
+                .create_image(&image_create_info, None)
+                .expect("Failed to create sparse Image!")
+        };
+        lifecycle::on_create(TrackedObject::Image, image);
+
+        // sparse images have no memory bound to them, the requirements give the tile size and memory types.
+        let memory_requirements = unsafe { device.get_image_memory_requirements(image) };
+        let sparse_requirements = unsafe { device.get_image_sparse_memory_requirements(image) };
+        let color_requirements = sparse_requirements
+            .iter()
+            .find(|requirements| requirements.format_properties.aspect_mask.contains(vk::ImageAspectFlags::COLOR))
+            .copied()
+            .expect("Failed to get sparse memory requirements of the color aspect!");
+
+        let memory_type_index = VkRenderDevice::find_memory_type(
+            memory_requirements.memory_type_bits,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            device_memory_properties,
+        );
+
+        let mut sparse_image = VkSparseImage {
+            device: device.clone(),
+            image: image,
+            format: format,
+            extent: extent,
+            tile_extent: color_requirements.format_properties.image_granularity,
+            tile_size: memory_requirements.alignment,
+
+            memory_type_index: memory_type_index,
+            tile_memory: vec![],
+            mip_tail_memory: None,
+        };
+
+        if color_requirements.image_mip_tail_first_lod < 1 {
+            sparse_image.bind_mip_tail(queue, &color_requirements);
+        }
+
+        Ok(sparse_image)
+    }
+
+    /// Tiles along x and y, the last ones may stick out of the image.
+    pub fn tile_count(&self) -> (u32, u32) {
+        (
+            self.extent.width.div_ceil(self.tile_extent.width),
+            self.extent.height.div_ceil(self.tile_extent.height),
+        )
+    }
+
+    pub fn is_tile_bound(&self, tile: (u32, u32)) -> bool {
+        self.tile_memory.iter().any(|&(bound_tile, _)| bound_tile == tile)
+    }
+
+    /// Backs `tiles` with fresh memory, waits until the bind is done. Already bound tiles are skipped.
+    /// `queue` has to be of a family with `SPARSE_BINDING`, like the one passed to `new`.
+    pub fn bind_tiles(&mut self, queue: vk::Queue, tiles: &[(u32, u32)]) {
+        let (tile_count_x, tile_count_y) = self.tile_count();
+        let mut image_binds = vec![];
+
+        for &tile in tiles.iter() {
+            assert!(tile.0 < tile_count_x && tile.1 < tile_count_y, "Tile {:?} is outside of the image", tile);
+            if self.is_tile_bound(tile) {
+                continue;
+            }
+
+            let memory = self.allocate_tile_memory(self.tile_size);
+            self.tile_memory.push((tile, memory));
+            image_binds.push(self.tile_bind(tile, memory));
+        }
+
+        self.bind_image(queue, &image_binds);
+    }
+
+    /// Backs every tile, the proof that the whole image works sparsely.
+    pub fn bind_all(&mut self, queue: vk::Queue) {
+        let (tile_count_x, tile_count_y) = self.tile_count();
+        let tiles: Vec<(u32, u32)> = (0..tile_count_y)
+            .flat_map(|y| (0..tile_count_x).map(move |x| (x, y)))
+            .collect();
+
+        self.bind_tiles(queue, &tiles);
+    }
+
+    /// Unbinds `tiles` and frees their memory, the device must not be using them anymore.
+    pub fn unbind_tiles(&mut self, queue: vk::Queue, tiles: &[(u32, u32)]) {
+        let (unbound_tiles, bound_tiles): (Vec<_>, Vec<_>) = self.tile_memory
+            .drain(..)
+            .partition(|(bound_tile, _)| tiles.contains(bound_tile));
+        self.tile_memory = bound_tiles;
+
+        let image_binds: Vec<vk::SparseImageMemoryBind> = unbound_tiles
+            .iter()
+            .map(|&(tile, _)| self.tile_bind(tile, vk::DeviceMemory::null()))
+            .collect();
+
+        self.bind_image(queue, &image_binds);
+        for (_, memory) in unbound_tiles {
+            unsafe {
+                self.device.free_memory(memory, None);
+            }
+        }
+    }
+
+    /// The device must not be using the image anymore.
+    pub fn destroy(&mut self) {
+        unsafe {
+            lifecycle::on_destroy(TrackedObject::Image, self.image);
+            self.device.destroy_image(self.image, None);
+            for &(_, memory) in self.tile_memory.iter() {
+                self.device.free_memory(memory, None);
+            }
+            if let Some(memory) = self.mip_tail_memory {
+                self.device.free_memory(memory, None);
+            }
+        }
+
+        self.tile_memory.clear();
+        self.mip_tail_memory = None;
+    }
+
+    fn tile_bind(&self, tile: (u32, u32), memory: vk::DeviceMemory) -> vk::SparseImageMemoryBind {
+        let offset = vk::Offset3D {
+            x: (tile.0 * self.tile_extent.width) as i32,
+            y: (tile.1 * self.tile_extent.height) as i32,
+            z: 0,
+        };
+
+        vk::SparseImageMemoryBind {
+            subresource: vk::ImageSubresource {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                array_layer: 0,
+            },
+            offset,
+            // edge tiles are cut off at the image size.
+            extent: vk::Extent3D {
+                width: self.tile_extent.width.min(self.extent.width - offset.x as u32),
+                height: self.tile_extent.height.min(self.extent.height - offset.y as u32),
+                depth: 1,
+            },
+            memory,
+            memory_offset: 0,
+            flags: vk::SparseMemoryBindFlags::empty(),
+        }
+    }
+
+    fn bind_mip_tail(&mut self, queue: vk::Queue, requirements: &vk::SparseImageMemoryRequirements) {
+        let memory = self.allocate_tile_memory(requirements.image_mip_tail_size);
+        self.mip_tail_memory = Some(memory);
+
+        let opaque_binds = [vk::SparseMemoryBind {
+            resource_offset: requirements.image_mip_tail_offset,
+            size: requirements.image_mip_tail_size,
+            memory,
+            memory_offset: 0,
+            flags: vk::SparseMemoryBindFlags::empty(),
+        }];
+        let opaque_bind_info = vk::SparseImageOpaqueMemoryBindInfo {
+            image: self.image,
+            bind_count: opaque_binds.len() as u32,
+            p_binds: opaque_binds.as_ptr(),
+        };
+        let bind_info = vk::BindSparseInfo {
+            image_opaque_bind_count: 1,
+            p_image_opaque_binds: &opaque_bind_info,
+            ..Default::default()
+        };
+
+        self.submit_bind(queue, &bind_info);
+    }
+
+    fn bind_image(&self, queue: vk::Queue, image_binds: &[vk::SparseImageMemoryBind]) {
+        if image_binds.is_empty() {
+            return;
+        }
+
+        let image_bind_info = vk::SparseImageMemoryBindInfo {
+            image: self.image,
+            bind_count: image_binds.len() as u32,
+            p_binds: image_binds.as_ptr(),
+        };
+        let bind_info = vk::BindSparseInfo {
+            image_bind_count: 1,
+            p_image_binds: &image_bind_info,
+            ..Default::default()
+        };
+
+        self.submit_bind(queue, &bind_info);
+    }
+
+    fn submit_bind(&self, queue: vk::Queue, bind_info: &vk::BindSparseInfo) {
+        let fence = VkFence::new(&self.device, false);
+
+        unsafe {
+            self.device
+                .queue_bind_sparse(queue, std::slice::from_ref(bind_info), fence.fence)
+                .expect("Failed to bind sparse memory!");
+        }
+        fence
+            .wait(u64::MAX)
+            .expect("Failed to wait for Fence!");
+    }
+
+    fn allocate_tile_memory(&self, size: vk::DeviceSize) -> vk::DeviceMemory {
+        let allocate_info = vk::MemoryAllocateInfo {
+            s_type: vk::StructureType::MEMORY_ALLOCATE_INFO,
+            p_next: ptr::null(),
+            allocation_size: size,
+            memory_type_index: self.memory_type_index,
+        };
+
+        unsafe {
+            self.device
+                .allocate_memory(&allocate_info, None)
+                .expect("Failed to allocate sparse tile memory!")
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use ash::vk;
+
+use std::ptr;
+use std::os::raw::c_void;
+
+use crate::vk::device_group::VkDeviceGroup;
+
+#[derive(Default)]
+struct PendingSubmit {
+    wait_semaphores: Vec<vk::Semaphore>,
+    wait_stages: Vec<vk::PipelineStageFlags>,
+    command_buffers: Vec<vk::CommandBuffer>,
+    signal_semaphores: Vec<vk::Semaphore>,
+    protected: bool,
+}
+
+/// Collects several submits and hands them to the queue with a single `queue_submit`.
+/// `wait`, `command_buffer` and `signal` go into the current submit, `next_submit` starts a new one.
+pub struct SubmitBatch {
+    submits: Vec<PendingSubmit>,
+}
+
+impl Default for SubmitBatch {
+    fn default() -> SubmitBatch {
+        SubmitBatch::new()
+    }
+}
+
+impl SubmitBatch {
+    pub fn new() -> SubmitBatch {
+        SubmitBatch {
+            submits: vec![PendingSubmit::default()],
+        }
+    }
+
+    pub fn next_submit(&mut self) -> &mut SubmitBatch {
+        self.submits.push(PendingSubmit::default());
+        self
+    }
+
+    pub fn wait(&mut self, semaphore: vk::Semaphore, stage: vk::PipelineStageFlags) -> &mut SubmitBatch {
+        let submit = self.current();
+        submit.wait_semaphores.push(semaphore);
+        submit.wait_stages.push(stage);
+        self
+    }
+
+    pub fn command_buffer(&mut self, command_buffer: vk::CommandBuffer) -> &mut SubmitBatch {
+        self.current().command_buffers.push(command_buffer);
+        self
+    }
+
+    pub fn signal(&mut self, semaphore: vk::Semaphore) -> &mut SubmitBatch {
+        self.current().signal_semaphores.push(semaphore);
+        self
+    }
+
+    /// Makes the current submit a protected one, needs `capabilities.protected_memory`.
+    /// Its command buffers have to come from `VkRenderDevice::create_protected_command_pool`.
+    pub fn protected(&mut self) -> &mut SubmitBatch {
+        self.current().protected = true;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.submits.iter().all(|submit| {
+            submit.command_buffers.is_empty()
+                && submit.wait_semaphores.is_empty()
+                && submit.signal_semaphores.is_empty()
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.submits.clear();
+        self.submits.push(PendingSubmit::default());
+    }
+
+    /// Issues every submit with one `queue_submit`. `fence` is signaled once all of them completed.
+    /// With a device group every submit runs on its current device.
+    pub fn submit(
+        &self,
+        device: &ash::Device,
+        queue: vk::Queue,
+        fence: vk::Fence,
+        device_group: Option<&VkDeviceGroup>
+    ) -> Result<(), vk::Result> {
+        let submits = self.non_empty_submits();
+
+        // device indices and masks have to outlive the submit infos pointing to them.
+        let device_group_indices: Vec<(Vec<u32>, Vec<u32>, Vec<u32>)> = match device_group {
+            Some(device_group) => submits
+                .iter()
+                .map(|submit| (
+                    vec![device_group.current_device_index(); submit.wait_semaphores.len()],
+                    vec![device_group.current_device_mask(); submit.command_buffers.len()],
+                    vec![device_group.current_device_index(); submit.signal_semaphores.len()],
+                ))
+                .collect(),
+            None => vec![],
+        };
+
+        let device_group_submit_infos: Vec<vk::DeviceGroupSubmitInfo> = device_group_indices
+            .iter()
+            .map(|(wait_indices, command_buffer_masks, signal_indices)| vk::DeviceGroupSubmitInfo {
+                s_type: vk::StructureType::DEVICE_GROUP_SUBMIT_INFO,
+                p_next: ptr::null(),
+                wait_semaphore_count: wait_indices.len() as u32,
+                p_wait_semaphore_device_indices: wait_indices.as_ptr(),
+                command_buffer_count: command_buffer_masks.len() as u32,
+                p_command_buffer_device_masks: command_buffer_masks.as_ptr(),
+                signal_semaphore_count: signal_indices.len() as u32,
+                p_signal_semaphore_device_indices: signal_indices.as_ptr(),
+            })
+            .collect();
+
+        let device_group_p_next = |i: usize| match device_group_submit_infos.get(i) {
+            Some(submit_info) => submit_info as *const _ as *const c_void,
+            None => ptr::null(),
+        };
+        let protected_submit_infos: Vec<vk::ProtectedSubmitInfo> = (0..submits.len())
+            .map(|i| vk::ProtectedSubmitInfo {
+                s_type: vk::StructureType::PROTECTED_SUBMIT_INFO,
+                p_next: device_group_p_next(i),
+                protected_submit: vk::TRUE,
+            })
+            .collect();
+
+        let submit_infos: Vec<vk::SubmitInfo> = submits
+            .iter()
+            .enumerate()
+            .map(|(i, submit)| vk::SubmitInfo {
+                s_type: vk::StructureType::SUBMIT_INFO,
+                p_next: if submit.protected {
+                    &protected_submit_infos[i] as *const _ as *const c_void
+                } else {
+                    device_group_p_next(i)
+                },
+                wait_semaphore_count: submit.wait_semaphores.len() as u32,
+                p_wait_semaphores: submit.wait_semaphores.as_ptr(),
+                p_wait_dst_stage_mask: submit.wait_stages.as_ptr(),
+                command_buffer_count: submit.command_buffers.len() as u32,
+                p_command_buffers: submit.command_buffers.as_ptr(),
+                signal_semaphore_count: submit.signal_semaphores.len() as u32,
+                p_signal_semaphores: submit.signal_semaphores.as_ptr(),
+            })
+            .collect();
+
+        unsafe { device.queue_submit(queue, &submit_infos, fence) }
+    }
+
+    /// Same as `submit` but through `vkQueueSubmit2`, needs `capabilities.synchronization2`.
+    pub fn submit2(
+        &self,
+        synchronization2_loader: &ash::extensions::khr::Synchronization2,
+        queue: vk::Queue,
+        fence: vk::Fence,
+        device_group: Option<&VkDeviceGroup>
+    ) -> Result<(), vk::Result> {
+        let submits = self.non_empty_submits();
+
+        let (device_index, device_mask) = match device_group {
+            Some(device_group) => (device_group.current_device_index(), device_group.current_device_mask()),
+            None => (0, 0),
+        };
+
+        let semaphore_info = |semaphore: vk::Semaphore, stage: vk::PipelineStageFlags2| vk::SemaphoreSubmitInfo {
+            s_type: vk::StructureType::SEMAPHORE_SUBMIT_INFO,
+            p_next: ptr::null(),
+            semaphore,
+            value: 0,
+            stage_mask: stage,
+            device_index,
+        };
+
+        let wait_infos: Vec<Vec<vk::SemaphoreSubmitInfo>> = submits
+            .iter()
+            .map(|submit| submit.wait_semaphores
+                .iter()
+                .zip(submit.wait_stages.iter())
+                .map(|(&semaphore, &stage)| {
+                    // the legacy stage bits have the same values in `PipelineStageFlags2`.
+                    semaphore_info(semaphore, vk::PipelineStageFlags2::from_raw(stage.as_raw() as u64))
+                })
+                .collect())
+            .collect();
+
+        let signal_infos: Vec<Vec<vk::SemaphoreSubmitInfo>> = submits
+            .iter()
+            .map(|submit| submit.signal_semaphores
+                .iter()
+                .map(|&semaphore| semaphore_info(semaphore, vk::PipelineStageFlags2::ALL_COMMANDS))
+                .collect())
+            .collect();
+
+        let command_buffer_infos: Vec<Vec<vk::CommandBufferSubmitInfo>> = submits
+            .iter()
+            .map(|submit| submit.command_buffers
+                .iter()
+                .map(|&command_buffer| vk::CommandBufferSubmitInfo {
+                    s_type: vk::StructureType::COMMAND_BUFFER_SUBMIT_INFO,
+                    p_next: ptr::null(),
+                    command_buffer,
+                    device_mask,
+                })
+                .collect())
+            .collect();
+
+        let submit_infos: Vec<vk::SubmitInfo2> = (0..submits.len())
+            .map(|i| vk::SubmitInfo2 {
+                s_type: vk::StructureType::SUBMIT_INFO_2,
+                p_next: ptr::null(),
+                flags: if submits[i].protected {
+                    vk::SubmitFlags::PROTECTED
+                } else {
+                    vk::SubmitFlags::empty()
+                },
+                wait_semaphore_info_count: wait_infos[i].len() as u32,
+                p_wait_semaphore_infos: wait_infos[i].as_ptr(),
+                command_buffer_info_count: command_buffer_infos[i].len() as u32,
+                p_command_buffer_infos: command_buffer_infos[i].as_ptr(),
+                signal_semaphore_info_count: signal_infos[i].len() as u32,
+                p_signal_semaphore_infos: signal_infos[i].as_ptr(),
+            })
+            .collect();
+
+        unsafe { synchronization2_loader.queue_submit2(queue, &submit_infos, fence) }
+    }
+
+    fn current(&mut self) -> &mut PendingSubmit {
+        self.submits.last_mut().unwrap()
+    }
+
+    fn non_empty_submits(&self) -> Vec<&PendingSubmit> {
+        self.submits
+            .iter()
+            .filter(|submit| {
+                !submit.command_buffers.is_empty()
+                    || !submit.wait_semaphores.is_empty()
+                    || !submit.signal_semaphores.is_empty()
+            })
+            .collect()
+    }
+}
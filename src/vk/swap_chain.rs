@@ -2,22 +2,31 @@ use ash::vk;
 use ash::vk::SurfaceFormatKHR;
 use ash::vk::ValidationCacheCreateInfoEXT;
 use std::ffi::CString;
+use std::fmt;
 use std::marker::PhantomData;
+use std::path::PathBuf;
 use std::ptr;
+use std::time::Duration;
 
 use ash;
 
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_void};
 
 use crate::vk::constants;
 use crate::utility::constants as global_constants;
 use crate::vk::debug;
+use crate::vk::image;
+use crate::vk::lifecycle::{self, TrackedObject};
+use crate::vk::render_pass::AttachmentLoad;
+use crate::vk::capabilities;
 use crate::utility::tools;
 
 use crate::vk::render_device;
 use crate::rhi::window;
 
 use super::render_device::VkSurface;
+use super::device_group::VkDeviceGroup;
+use super::sync::{VkFence, VkSemaphore};
 
 pub struct VkSpawChain {
     pub swapchain_loader: ash::extensions::khr::Swapchain,
@@ -25,10 +34,351 @@ pub struct VkSpawChain {
 
     pub swapchain_images: Vec<vk::Image>,
     pub swapchain_format: vk::Format,
+    pub swapchain_color_space: vk::ColorSpaceKHR,
     pub swapchain_extent: vk::Extent2D,
+    /// See `SwapChainConfig::image_array_layers`.
+    pub image_array_layers: u32,
+    /// The surface left the extent to the swapchain, so it was taken from `VkSurface::screen_width` and `screen_height`.
+    pub is_extent_from_window: bool,
+    pub swapchain_usage: vk::ImageUsageFlags,
+    /// Created with `SwapchainCreateFlagsKHR::PROTECTED`, see `SwapChainConfig::protected`.
+    pub is_protected: bool,
+    /// Picked by `choose_composite_alpha` from what the surface supports, see `SwapChainConfig::transparent`.
+    pub composite_alpha: vk::CompositeAlphaFlagsKHR,
+    /// EXCLUSIVE across two queue families needs a `PresentOwnershipTransfer` before every present.
+    pub image_sharing_mode: vk::SharingMode,
+
+    /// Mode the next present uses. Only changes without recreation through `VkRenderDevice::set_present_mode`.
+    pub present_mode: vk::PresentModeKHR,
+    /// How `present_mode` was picked from `SwapChainConfig::requested_present_mode`.
+    pub present_mode_selection: PresentModeSelection,
+    /// Modes `present_mode` can be switched between at present time, just `present_mode`
+    /// itself unless `capabilities.swapchain_maintenance1` is enabled.
+    pub compatible_present_modes: Vec<vk::PresentModeKHR>,
 
     pub swapchain_image_views:  Vec<vk::ImageView>,
     pub swapchain_framebuffers: Vec<vk::Framebuffer>,
+
+    /// Present ids have to increase per swapchain, 0 means nothing was tagged yet.
+    pub last_present_id: u64,
+    /// Same for the ids of `VK_GOOGLE_display_timing`, which every present gets with `capabilities.display_timing`.
+    pub last_display_timing_id: u32,
+
+    /// Images acquired but not presented yet, released by hand when the swapchain is retired.
+    pub acquired_images: Vec<u32>,
+    /// Per image, whether it was presented once already and so is in PRESENT_SRC_KHR when acquired again,
+    /// until then its layout is UNDEFINED. Set by `VkRenderDevice::present` once the present went through.
+    pub is_image_initialized: Vec<bool>,
+    /// Signaled once the presentation engine is done with a present, only with `capabilities.swapchain_maintenance1`.
+    pub present_fences: Vec<VkFence>,
+}
+
+/// Old swapchain after a recreation whose images may still be used by the presentation engine.
+/// It can be destroyed once every fence of its presents is signaled.
+pub struct RetiredSwapchain {
+    pub swapchain: vk::SwapchainKHR,
+    pub present_fences: Vec<VkFence>,
+}
+
+impl RetiredSwapchain {
+    pub fn is_done(&self) -> bool {
+        self.present_fences.iter().all(|fence| fence.is_signaled())
+    }
+}
+
+/// How the swapchain format stores color, i.e. who is responsible for the gamma curve.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwapChainColorEncoding {
+    /// `*_SRGB` formats, the hardware encodes gamma on write.
+    Srgb,
+    /// `*_UNORM` formats, values are stored as written.
+    Unorm,
+    /// Floating point formats (scRGB / HDR), values stay linear.
+    Float,
+}
+
+impl SwapChainColorEncoding {
+    pub fn from_format(format: vk::Format) -> SwapChainColorEncoding {
+        match format {
+            vk::Format::R8_SRGB
+            | vk::Format::R8G8_SRGB
+            | vk::Format::R8G8B8_SRGB
+            | vk::Format::B8G8R8_SRGB
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::B8G8R8A8_SRGB
+            | vk::Format::A8B8G8R8_SRGB_PACK32 => SwapChainColorEncoding::Srgb,
+            vk::Format::R16G16B16A16_SFLOAT
+            | vk::Format::R32G32B32A32_SFLOAT
+            | vk::Format::B10G11R11_UFLOAT_PACK32 => SwapChainColorEncoding::Float,
+            _ => SwapChainColorEncoding::Unorm,
+        }
+    }
+
+    /// Whether fragment shaders writing to this target should output linear color.
+    /// `false` means the shader has to apply gamma itself.
+    pub const fn shader_outputs_linear(&self) -> bool {
+        match self {
+            SwapChainColorEncoding::Srgb | SwapChainColorEncoding::Float => true,
+            SwapChainColorEncoding::Unorm => false,
+        }
+    }
+}
+
+/// Outcome of picking a present mode, so e.g. a settings UI can grey out modes the surface doesn't offer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PresentModeSelection {
+    pub requested: Option<vk::PresentModeKHR>,
+    pub selected: vk::PresentModeKHR,
+    /// Every mode the surface supports.
+    pub available: Vec<vk::PresentModeKHR>,
+}
+
+impl PresentModeSelection {
+    /// A mode was requested but the surface doesn't support it, e.g. IMMEDIATE for tearing without vsync.
+    pub fn is_fallback(&self) -> bool {
+        match self.requested {
+            Some(requested) => requested != self.selected,
+            None => false,
+        }
+    }
+
+    pub fn is_available(&self, present_mode: vk::PresentModeKHR) -> bool {
+        self.available.contains(&present_mode)
+    }
+}
+
+/// Whether the present mode follows `SwapChainConfig::preferred_present_mode` or adapts to power and pacing needs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PresentModePolicy {
+    #[default]
+    Fixed,
+    /// MAILBOX, unless `power_saving` is set or `frame_rate_cap` is configured, then FIFO.
+    /// MAILBOX renders as fast as the GPU can, which keeps e.g. integrated GPUs running hot for frames nobody sees.
+    Adaptive,
+}
+
+/// Where presented frames end up.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum PresentSink {
+    #[default]
+    Screen,
+    /// Debug path that reads every frame back and writes it to `dir` as `frame_<n>.png` instead of presenting it,
+    /// to tell rendering bugs apart from presentation bugs or to compare against golden images.
+    /// Needs `TRANSFER_SRC` in `VkSpawChain::swapchain_usage`, presents fail with `ERROR_FEATURE_NOT_PRESENT` otherwise.
+    /// Without `capabilities.swapchain_maintenance1` the swapchain is recreated after every frame,
+    /// since only presenting or destroying it gives the image back then.
+    File { dir: PathBuf },
+}
+
+/// Startup benchmark of EXCLUSIVE images with ownership transfers against CONCURRENT images, for when graphics
+/// and present are different families. Experimental, see `VkRenderDevice::tune_sharing_mode`.
+#[derive(Clone, Debug)]
+pub struct SharingModeTuning {
+    /// Frames presented with each sharing mode.
+    pub frames: u32,
+    /// File keeping the decision per device and driver version, so later startups skip the benchmark.
+    pub cache_path: Option<PathBuf>,
+}
+
+impl Default for SharingModeTuning {
+    fn default() -> SharingModeTuning {
+        SharingModeTuning {
+            frames: 120,
+            cache_path: None,
+        }
+    }
+}
+
+/// Mean frame time with each sharing mode and the one that was kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SharingModeBenchmark {
+    pub exclusive_frame_time: Duration,
+    pub concurrent_frame_time: Duration,
+    pub chosen: vk::SharingMode,
+}
+
+/// Key of a device and its driver in a `SharingModeTuning::cache_path` file, a driver update benchmarks again.
+pub fn sharing_mode_cache_key(properties: &vk::PhysicalDeviceProperties) -> String {
+    format!("{:04x}:{:04x}:{}", properties.vendor_id, properties.device_id, properties.driver_version)
+}
+
+/// Sharing mode stored for `key` in the contents of a cache file, one `<key> EXCLUSIVE|CONCURRENT` line per device.
+pub fn cached_sharing_mode(contents: &str, key: &str) -> Option<vk::SharingMode> {
+    contents.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        if parts.next() != Some(key) {
+            return None;
+        }
+
+        match parts.next() {
+            Some("EXCLUSIVE") => Some(vk::SharingMode::EXCLUSIVE),
+            Some("CONCURRENT") => Some(vk::SharingMode::CONCURRENT),
+            _ => None,
+        }
+    })
+}
+
+/// `contents` with the line of `key` replaced by `sharing_mode`, or added at the end, other devices are kept.
+pub fn with_cached_sharing_mode(contents: &str, key: &str, sharing_mode: vk::SharingMode) -> String {
+    let mode_name = if sharing_mode == vk::SharingMode::CONCURRENT { "CONCURRENT" } else { "EXCLUSIVE" };
+
+    let mut lines: Vec<String> = contents
+        .lines()
+        .filter(|line| line.split_whitespace().next() != Some(key))
+        .map(|line| line.to_string())
+        .collect();
+    lines.push(format!("{} {}", key, mode_name));
+
+    lines.join("\n") + "\n"
+}
+
+/// Extension structs chained into `SwapchainCreateInfoKHR::p_next` on every creation, behind the ones the engine
+/// adds itself (device group, present modes), e.g. `SwapchainDisplayNativeHdrCreateInfoAMD` or
+/// `SurfaceFullScreenExclusiveInfoEXT`.
+/// Each struct is copied in with its own `p_next` cleared, the chain is linked again for every creation,
+/// so push every struct of a chain on its own.
+#[derive(Clone, Default)]
+pub struct SwapChainExtensions {
+    /// Bytes of each struct, in words so they're aligned for any Vulkan struct.
+    structures: Vec<Box<[u64]>>,
+}
+
+/// Swapchain create info extensions `create_swapchain` adds on its own, from the device group and
+/// `present_mode_switching`.
+const ENGINE_CHAINED_STRUCTURE_TYPES: [vk::StructureType; 2] = [
+    vk::StructureType::DEVICE_GROUP_SWAPCHAIN_CREATE_INFO_KHR,
+    vk::StructureType::SWAPCHAIN_PRESENT_MODES_CREATE_INFO_EXT,
+];
+
+impl SwapChainExtensions {
+    pub fn new() -> SwapChainExtensions {
+        SwapChainExtensions::default()
+    }
+
+    /// Panics for the structs the engine chains itself, `SwapchainPresentModesCreateInfoEXT` and
+    /// `DeviceGroupSwapchainCreateInfoKHR`, a second one of them in the chain is invalid.
+    ///
+    /// # Safety
+    ///
+    /// Whatever `structure` points to isn't copied, e.g. the formats behind `ImageFormatListCreateInfo::p_view_formats`:
+    /// it has to stay valid for as long as the config is used to create swapchains, which includes every recreation.
+    /// The extension `structure` belongs to has to be enabled on the device.
+    pub unsafe fn push<T>(mut self, structure: T) -> SwapChainExtensions
+    where
+        T: vk::ExtendsSwapchainCreateInfoKHR + Copy + 'static,
+    {
+        assert!(std::mem::align_of::<T>() <= std::mem::align_of::<u64>(), "Swapchain extension struct is overaligned!");
+
+        let size = std::mem::size_of::<T>();
+        let mut storage = vec![0u64; size.div_ceil(8)].into_boxed_slice();
+        ptr::copy_nonoverlapping(&structure as *const T as *const u8, storage.as_mut_ptr() as *mut u8, size);
+        let header = storage.as_mut_ptr() as *mut vk::BaseOutStructure;
+        assert!(
+            !ENGINE_CHAINED_STRUCTURE_TYPES.contains(&(*header).s_type),
+            "{:?} is chained by the engine already", (*header).s_type);
+        (*header).p_next = ptr::null_mut();
+
+        self.structures.push(storage);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.structures.is_empty()
+    }
+
+    /// Structure types in the order they're chained.
+    pub fn structure_types(&self) -> Vec<vk::StructureType> {
+        self.structures
+            .iter()
+            .map(|storage| unsafe { (*(storage.as_ptr() as *const vk::BaseOutStructure)).s_type })
+            .collect()
+    }
+}
+
+impl fmt::Debug for SwapChainExtensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.structure_types()).finish()
+    }
+}
+
+/// Metadata of HDR10 content mastered on a display with BT.2020 primaries and a D65 white point,
+/// luminances are in nits. The light levels are the brightest pixel and the brightest frame average of the content.
+pub fn hdr10_metadata(
+    max_luminance: f32,
+    min_luminance: f32,
+    max_content_light_level: f32,
+    max_frame_average_light_level: f32
+) -> vk::HdrMetadataEXT {
+    vk::HdrMetadataEXT {
+        s_type: vk::StructureType::HDR_METADATA_EXT,
+        p_next: ptr::null(),
+        display_primary_red: vk::XYColorEXT { x: 0.708, y: 0.292 },
+        display_primary_green: vk::XYColorEXT { x: 0.170, y: 0.797 },
+        display_primary_blue: vk::XYColorEXT { x: 0.131, y: 0.046 },
+        white_point: vk::XYColorEXT { x: 0.3127, y: 0.3290 },
+        max_luminance: max_luminance,
+        min_luminance: min_luminance,
+        max_content_light_level: max_content_light_level,
+        max_frame_average_light_level: max_frame_average_light_level,
+    }
+}
+
+/// Choices the application makes for the swapchain, kept between recreations.
+#[derive(Clone, Debug)]
+pub struct SwapChainConfig {
+    /// Used when the surface supports it, otherwise MAILBOX is preferred. Ignored with `PresentModePolicy::Adaptive`.
+    pub preferred_present_mode: Option<vk::PresentModeKHR>,
+    pub present_mode_policy: PresentModePolicy,
+    /// Power or thermal hint, e.g. from running on battery.
+    pub power_saving: bool,
+    /// Frames per second the application limits itself to, if it does.
+    pub frame_rate_cap: Option<u32>,
+    /// Declare every present mode compatible with the chosen one at creation, so they can be switched per present.
+    /// Needs `capabilities.swapchain_maintenance1`.
+    pub present_mode_switching: bool,
+    /// Keep images EXCLUSIVE even when graphics and present are different families,
+    /// ownership is moved over with barriers instead of paying for CONCURRENT access. Ignored for `protected` images.
+    pub force_exclusive_sharing: bool,
+    pub present_sink: PresentSink,
+    /// Used when the surface supports it, otherwise B8G8R8A8_SRGB with SRGB_NONLINEAR is preferred.
+    /// The supported ones are listed by `VkRenderDevice::available_formats`.
+    pub preferred_format: Option<vk::SurfaceFormatKHR>,
+    /// Protected images for content that mustn't be read back, e.g. DRM video. Only used when the surface
+    /// `supports_protected` and the device enabled `capabilities.protected_memory`, see `VkRenderDevice::set_protected`.
+    /// Everything drawing to them has to be a protected submit from a protected command pool.
+    pub protected: bool,
+    /// Layers of every image, e.g. 2 for a stereo swapchain with an eye per layer, at most the surface's
+    /// `max_image_array_layers`. With more than one the image views are 2D arrays, meant for a multiview
+    /// render pass with a view per layer (`RenderPassBuilder::multiview`); a plain render pass only draws layer 0.
+    pub image_array_layers: u32,
+    /// Chained into the create info of every swapchain, see `SwapChainExtensions::push` for what has to outlive them.
+    pub extensions: SwapChainExtensions,
+    /// Set on every swapchain right after its creation, see `VkRenderDevice::set_hdr_metadata`.
+    pub hdr_metadata: Option<vk::HdrMetadataEXT>,
+    /// Blend the window with what's behind it by its alpha, e.g. for an overlay. Falls back to an opaque window
+    /// with a warning when the surface can't, `VkSpawChain::is_transparent` tells what was achieved.
+    /// The window itself has to be created transparent too.
+    pub transparent: bool,
+}
+
+impl Default for SwapChainConfig {
+    fn default() -> SwapChainConfig {
+        SwapChainConfig {
+            preferred_present_mode: None,
+            present_mode_policy: PresentModePolicy::default(),
+            power_saving: false,
+            frame_rate_cap: None,
+            present_mode_switching: false,
+            force_exclusive_sharing: false,
+            present_sink: PresentSink::default(),
+            preferred_format: None,
+            protected: false,
+            image_array_layers: 1,
+            extensions: SwapChainExtensions::new(),
+            hdr_metadata: None,
+            transparent: false,
+        }
+    }
 }
 
 pub struct SwapChainSupportDetail {
@@ -37,30 +387,136 @@ pub struct SwapChainSupportDetail {
     pub present_modes: Vec<vk::PresentModeKHR>,
 }
 
+impl SwapChainConfig {
+    /// Mode asked for when the swapchain is created, `None` leaves the choice to `choose_swapchain_present_mode`.
+    pub fn requested_present_mode(&self) -> Option<vk::PresentModeKHR> {
+        match self.present_mode_policy {
+            PresentModePolicy::Fixed => self.preferred_present_mode,
+            // a capped frame rate gains nothing from MAILBOX, FIFO paces it without the extra GPU work.
+            PresentModePolicy::Adaptive if self.power_saving || self.frame_rate_cap.is_some() => Some(vk::PresentModeKHR::FIFO),
+            PresentModePolicy::Adaptive => Some(vk::PresentModeKHR::MAILBOX),
+        }
+    }
+}
+
+/// Keeps surface formats and present modes between swapchain recreations.
+/// Capabilities are always re-queried since the current extent changes on every resize.
+/// Caching is safe as long as the surface stays on the same display; call `invalidate()`
+/// when the window moves to another display, and before using the cache with a new surface.
+pub struct SwapChainSupportCache {
+    pub enabled: bool,
+
+    formats: Option<Vec<vk::SurfaceFormatKHR>>,
+    present_modes: Option<Vec<vk::PresentModeKHR>>,
+}
+
+impl SwapChainSupportCache {
+    pub fn new(enabled: bool) -> SwapChainSupportCache {
+        SwapChainSupportCache {
+            enabled: enabled,
+            formats: None,
+            present_modes: None,
+        }
+    }
+
+    pub fn query(
+        &mut self,
+        physical_device: vk::PhysicalDevice,
+        surface: &render_device::VkSurface
+    ) -> SwapChainSupportDetail {
+        if !self.enabled {
+            return VkSpawChain::query_swapchain_support(physical_device, surface);
+        }
+
+        let formats = self.formats
+            .get_or_insert_with(|| VkSpawChain::query_surface_formats(physical_device, surface))
+            .clone();
+        let present_modes = self.present_modes
+            .get_or_insert_with(|| VkSpawChain::query_surface_present_modes(physical_device, surface))
+            .clone();
+
+        SwapChainSupportDetail {
+            capabilities: VkSpawChain::query_surface_capabilities(physical_device, surface),
+            formats: formats,
+            present_modes: present_modes,
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.formats = None;
+        self.present_modes = None;
+    }
+}
+
 impl VkSpawChain {
+    /// Fails with `ERROR_OUT_OF_DATE_KHR` while the surface is 0x0, e.g. because the window got minimized,
+    /// see `current_surface_extent`.
     pub fn create_swapchain(
         instance: &ash::Instance,
         device: &ash::Device,
         physical_device: vk::PhysicalDevice,
         surface: &render_device::VkSurface,
-        queue_family: &render_device::QueueFamilyIndices
-    ) -> VkSpawChain {
-        let swapchain_support = VkSpawChain::query_swapchain_support(physical_device, &surface);
-
-        let surface_format = VkSpawChain::choose_swapchain_format(&swapchain_support.formats);
-        let present_mode = VkSpawChain::choose_swapchain_present_mode(&swapchain_support.present_modes);
-        let extent = VkSpawChain::choose_swapchain_extent(&swapchain_support.capabilities);
+        queue_family: &render_device::QueueFamilyIndices,
+        device_group: Option<&VkDeviceGroup>,
+        support_cache: &mut SwapChainSupportCache,
+        config: &SwapChainConfig,
+        old_swapchain: vk::SwapchainKHR
+    ) -> Result<VkSpawChain, vk::Result> {
+        let swapchain_support = support_cache.query(physical_device, &surface);
 
-        let image_count = swapchain_support.capabilities.min_image_count + 1;
-        let image_count = if swapchain_support.capabilities.max_image_count > 0 {
-            image_count.min(swapchain_support.capabilities.max_image_count)
+        let surface_format = VkSpawChain::choose_swapchain_format(&swapchain_support.formats, config.preferred_format)
+            .expect("Surface reports no formats to create the Swapchain with!");
+        let is_protected = config.protected && VkSpawChain::supports_protected(physical_device, surface);
+        let present_mode_selection = VkSpawChain::choose_swapchain_present_mode(
+            &swapchain_support.present_modes,
+            config.requested_present_mode());
+        if present_mode_selection.is_fallback() {
+            log::warn!(
+                "Present mode {:?} isn't supported by the surface, falling back to {:?}.",
+                present_mode_selection.requested.unwrap(),
+                present_mode_selection.selected);
         }
-        else {
-            image_count
+        let present_mode = present_mode_selection.selected;
+        let compatible_present_modes = if config.present_mode_switching {
+            VkSpawChain::query_compatible_present_modes(physical_device, surface, present_mode)
+                .into_iter()
+                .filter(|mode| swapchain_support.present_modes.contains(mode))
+                .collect()
+        } else {
+            vec![present_mode]
         };
+        let extent = VkSpawChain::choose_swapchain_extent(&swapchain_support.capabilities, surface);
+        if VkSpawChain::is_zero_extent(extent) {
+            return Err(vk::Result::ERROR_OUT_OF_DATE_KHR);
+        }
+        let max_image_array_layers = swapchain_support.capabilities.max_image_array_layers;
+        assert!(
+            config.image_array_layers >= 1 && config.image_array_layers <= max_image_array_layers,
+            "Swapchain images can have 1 to {} array layers on this surface, {} were requested!",
+            max_image_array_layers,
+            config.image_array_layers
+        );
+
+        let image_count = VkSpawChain::requested_image_count(&swapchain_support.capabilities);
+
+        let composite_alpha = VkSpawChain::choose_composite_alpha(
+            swapchain_support.capabilities.supported_composite_alpha,
+            config.transparent);
+        if config.transparent && !VkSpawChain::is_transparent_composite_alpha(composite_alpha) {
+            log::warn!(
+                "Surface doesn't support a transparent composite alpha, only {:?}, the window stays opaque",
+                swapchain_support.capabilities.supported_composite_alpha);
+        }
+
+        // TRANSFER_DST is only needed by `clear_swapchain_image` and TRANSFER_SRC by `PresentSink::File`,
+        // so they're requested opportunistically.
+        let image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | (swapchain_support.capabilities.supported_usage_flags
+                & (vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC));
 
+        // protected images stay CONCURRENT, the ownership transfer on the present queue couldn't be a protected submit.
         let (image_sharing_mode, queue_family_index_count, queue_family_indices) = 
-            if queue_family.graphics_family != queue_family.present_family {
+            if queue_family.graphics_family != queue_family.present_family && (!config.force_exclusive_sharing || is_protected) {
                 (
                     vk::SharingMode::CONCURRENT,
                     2 as u32,
@@ -73,33 +529,61 @@ impl VkSpawChain {
                 (vk::SharingMode::EXCLUSIVE, 0, vec![])
             };
 
-        let swapchain_create_info = vk::SwapchainCreateInfoKHR {
+        let mut device_group_create_info = device_group.map(|device_group| device_group.swapchain_create_info());
+        let mut present_modes_create_info = vk::SwapchainPresentModesCreateInfoEXT {
+            s_type: vk::StructureType::SWAPCHAIN_PRESENT_MODES_CREATE_INFO_EXT,
+            p_next: ptr::null(),
+            present_mode_count: compatible_present_modes.len() as u32,
+            p_present_modes: compatible_present_modes.as_ptr(),
+        };
+
+        let mut create_chain: Vec<*mut vk::BaseOutStructure> = vec![];
+        if let Some(create_info) = &mut device_group_create_info {
+            create_chain.push(create_info as *mut _ as *mut vk::BaseOutStructure);
+        }
+        if config.present_mode_switching {
+            create_chain.push(&mut present_modes_create_info as *mut _ as *mut vk::BaseOutStructure);
+        }
+        // linked through a copy, so the config's structs keep their cleared `p_next` for the next creation.
+        let mut extension_structures = config.extensions.structures.clone();
+        for storage in extension_structures.iter_mut() {
+            create_chain.push(storage.as_mut_ptr() as *mut vk::BaseOutStructure);
+        }
+
+        let mut swapchain_create_info = vk::SwapchainCreateInfoKHR {
             s_type: vk::StructureType::SWAPCHAIN_CREATE_INFO_KHR,
             p_next: ptr::null(),
-            flags: vk::SwapchainCreateFlagsKHR::empty(),
+            flags: if is_protected {
+                vk::SwapchainCreateFlagsKHR::PROTECTED
+            } else {
+                vk::SwapchainCreateFlagsKHR::empty()
+            },
             surface: surface.surface,
             min_image_count: image_count,
             image_color_space: surface_format.color_space,
             image_format: surface_format.format,
             image_extent: extent,
-            image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            image_usage: image_usage,
             image_sharing_mode: image_sharing_mode,
             p_queue_family_indices: queue_family_indices.as_ptr(),
             queue_family_index_count: queue_family_index_count,
             pre_transform: swapchain_support.capabilities.current_transform,
-            composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
+            composite_alpha: composite_alpha,
             present_mode: present_mode,
             clipped: vk::TRUE,
-            old_swapchain: vk::SwapchainKHR::null(),
-            image_array_layers: 1
+            old_swapchain: old_swapchain,
+            image_array_layers: config.image_array_layers
         };
 
         let swapchain_loader = ash::extensions::khr::Swapchain::new(instance, device);
         let swapchain = unsafe {
+            capabilities::link_p_next(&mut swapchain_create_info as *mut _ as *mut vk::BaseOutStructure, &create_chain);
+
             swapchain_loader
             .create_swapchain(&swapchain_create_info, None)
             .expect("Failed to create Swapchain!")
         };
+        lifecycle::on_create(TrackedObject::Swapchain, swapchain);
 
         let swapchain_images = unsafe {
             swapchain_loader
@@ -107,85 +591,248 @@ impl VkSpawChain {
                 .expect("Failed to get Swapchain Images.")
         };
 
-        VkSpawChain {
+        // the driver may give more images than asked for, so the count logged is the real one.
+        log::info!(
+            "Created swapchain: format {:?}, color space {:?}, present mode {:?}, {} images (min {}), extent {}x{}",
+            surface_format.format,
+            surface_format.color_space,
+            present_mode,
+            swapchain_images.len(),
+            image_count,
+            extent.width,
+            extent.height);
+
+        Ok(VkSpawChain {
             swapchain_loader: swapchain_loader,
             swapchain: swapchain,
             swapchain_format: surface_format.format,
+            swapchain_color_space: surface_format.color_space,
             swapchain_extent: extent,
+            image_array_layers: config.image_array_layers,
+            is_extent_from_window: VkSpawChain::is_extent_from_window(&swapchain_support.capabilities),
+            swapchain_usage: image_usage,
+            is_protected: is_protected,
+            composite_alpha: composite_alpha,
+            image_sharing_mode: image_sharing_mode,
+            present_mode: present_mode,
+            present_mode_selection: present_mode_selection,
+            compatible_present_modes: compatible_present_modes,
+            is_image_initialized: vec![false; swapchain_images.len()],
             swapchain_images: swapchain_images,
             swapchain_framebuffers: vec![],
             swapchain_image_views: vec![],
+            last_present_id: 0,
+            last_display_timing_id: 0,
+            acquired_images: vec![],
+            present_fences: vec![],
+        })
+    }
+
+    /// Images the swapchain actually has, which is what per-image resources like command buffers, uniform
+    /// buffers or descriptor sets have to be sized by. Drivers may create more than `requested_image_count`,
+    /// and acquire can hand out any index below this count, so arrays sized by the request can be indexed
+    /// out of bounds. Can change with every recreation.
+    pub fn image_count(&self) -> usize {
+        self.swapchain_images.len()
+    }
+
+    /// One image more than the surface's minimum, so there is always one to render to while the
+    /// presentation engine holds the rest, clamped to the maximum unless that is 0 for no limit.
+    /// Only a lower bound of what the swapchain gets, see `image_count`.
+    pub fn requested_image_count(capabilities: &vk::SurfaceCapabilitiesKHR) -> u32 {
+        let image_count = capabilities.min_image_count + 1;
+        if capabilities.max_image_count > 0 {
+            image_count.min(capabilities.max_image_count)
+        } else {
+            image_count
         }
     }
 
-    pub fn create_framebuffers(
+    pub fn color_encoding(&self) -> SwapChainColorEncoding {
+        SwapChainColorEncoding::from_format(self.swapchain_format)
+    }
+
+    /// Converts tightly packed texels read back from a swapchain image to RGBA8, e.g. for writing a PNG.
+    /// Alpha is forced to opaque since the surface is composited that way as well.
+    /// `None` for formats that aren't 8 bits per channel.
+    pub fn readback_to_rgba8(&self, texels: &[u8]) -> Option<Vec<u8>> {
+        let is_bgra = match self.swapchain_format {
+            vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM => true,
+            vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM => false,
+            _ => return None,
+        };
+
+        let rgba = texels
+            .chunks_exact(4)
+            .flat_map(|texel| {
+                let (red, blue) = if is_bgra { (texel[2], texel[0]) } else { (texel[0], texel[2]) };
+                [red, texel[1], blue, u8::MAX]
+            })
+            .collect();
+
+        Some(rgba)
+    }
+
+    /// Fills the whole swapchain image with `color` without a render pass and leaves it ready to present.
+    /// Needs `TRANSFER_DST` in `swapchain_usage`, which the surface doesn't have to support,
+    /// returns `ERROR_FEATURE_NOT_PRESENT` without recording anything otherwise.
+    /// Like `cmd_begin_color_attachment` the first barrier waits at COLOR_ATTACHMENT_OUTPUT, the stage the image's
+    /// acquire semaphore is waited on in `submit_frame`, so the clear can't start before the image was released.
+    pub fn clear_swapchain_image(
+        &self,
         device: &ash::Device,
-        render_pass: vk::RenderPass,
-        image_views: &Vec<vk::ImageView>,
-        swapchain_extent: &vk::Extent2D
-    ) -> Vec<vk::Framebuffer> {
-        let mut framebuffers = vec![];
+        command_buffer: vk::CommandBuffer,
+        image_index: u32,
+        color: [f32; 4]
+    ) -> Result<(), vk::Result> {
+        if !self.swapchain_usage.contains(vk::ImageUsageFlags::TRANSFER_DST) {
+            return Err(vk::Result::ERROR_FEATURE_NOT_PRESENT);
+        }
 
-        for &image_view in image_views.iter() {
-            let attachments = [image_view];
+        let image = self.swapchain_images[image_index as usize];
+        let subresource_range = image::full_subresource_range(vk::ImageAspectFlags::COLOR);
 
-            let framebuffer_create_info = vk::FramebufferCreateInfo {
-                s_type: vk::StructureType::FRAMEBUFFER_CREATE_INFO,
-                p_next: ptr::null(),
-                flags: vk::FramebufferCreateFlags::empty(),
-                render_pass,
-                attachment_count: attachments.len() as u32,
-                p_attachments: attachments.as_ptr(),
-                width: swapchain_extent.width,
-                height: swapchain_extent.height,
-                layers: 1,
-            };
+        self.cmd_color_attachment_barrier(
+            device,
+            command_buffer,
+            image_index,
+            (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+            (vk::AccessFlags::empty(), vk::AccessFlags::TRANSFER_WRITE),
+            (vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::PipelineStageFlags::TRANSFER));
 
-            let framebuffer = unsafe {
-                device
-                    .create_framebuffer(&framebuffer_create_info, None)
-                    .expect("Failed to create Framebuffer!")
-            };
+        unsafe {
+            device.cmd_clear_color_image(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue { float32: color },
+                &[subresource_range]);
+        }
+
+        image::cmd_transition_image_layout(
+            device,
+            command_buffer,
+            image,
+            subresource_range,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::PRESENT_SRC_KHR);
+
+        Ok(())
+    }
+
+    /// Barrier before drawing to the image of `image_index` with dynamic rendering or a render pass that doesn't
+    /// transition it itself, moving it to COLOR_ATTACHMENT_OPTIMAL. Comes from UNDEFINED on first use and whenever
+    /// `load` discards the contents anyway, from PRESENT_SRC_KHR otherwise.
+    /// Waits at COLOR_ATTACHMENT_OUTPUT, the stage the image's acquire semaphore is waited on in `submit_frame`,
+    /// so the transition can't happen before the presentation engine released the image.
+    pub fn cmd_begin_color_attachment(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: u32,
+        load: AttachmentLoad
+    ) {
+        let previous_layout = if self.is_image_initialized[image_index as usize] {
+            vk::ImageLayout::PRESENT_SRC_KHR
+        } else {
+            vk::ImageLayout::UNDEFINED
+        };
+        let old_layout = load.initial_layout(previous_layout);
+        let dst_access_mask = if load == AttachmentLoad::Preserve {
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+        } else {
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+        };
+
+        self.cmd_color_attachment_barrier(
+            device,
+            command_buffer,
+            image_index,
+            (old_layout, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            (vk::AccessFlags::empty(), dst_access_mask),
+            (vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT));
+    }
+
+    /// Barrier after the last draw to the image of `image_index`, moving it from COLOR_ATTACHMENT_OPTIMAL to
+    /// PRESENT_SRC_KHR once its color writes are done. With EXCLUSIVE images across two queue families
+    /// `PresentOwnershipTransfer::cmd_release` has to follow it.
+    pub fn cmd_end_color_attachment(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, image_index: u32) {
+        self.cmd_color_attachment_barrier(
+            device,
+            command_buffer,
+            image_index,
+            (vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::ImageLayout::PRESENT_SRC_KHR),
+            (vk::AccessFlags::COLOR_ATTACHMENT_WRITE, vk::AccessFlags::empty()),
+            (vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::PipelineStageFlags::BOTTOM_OF_PIPE));
+    }
+
+    /// Layouts, access masks and stage masks are each `(src, dst)`.
+    fn cmd_color_attachment_barrier(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        image_index: u32,
+        layouts: (vk::ImageLayout, vk::ImageLayout),
+        access_masks: (vk::AccessFlags, vk::AccessFlags),
+        stage_masks: (vk::PipelineStageFlags, vk::PipelineStageFlags)
+    ) {
+        let image_barriers = [vk::ImageMemoryBarrier {
+            s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
+            p_next: ptr::null(),
+            src_access_mask: access_masks.0,
+            dst_access_mask: access_masks.1,
+            old_layout: layouts.0,
+            new_layout: layouts.1,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: self.swapchain_images[image_index as usize],
+            subresource_range: image::full_subresource_range(vk::ImageAspectFlags::COLOR),
+        }];
 
-            framebuffers.push(framebuffer);
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                stage_masks.0,
+                stage_masks.1,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &image_barriers);
         }
+    }
 
-        framebuffers
+    /// Tells the display the mastering display and content light levels of what's presented, only meaningful with an
+    /// HDR color space like HDR10_ST2084_EXT. Lasts until the swapchain is destroyed, recreations have to set it again.
+    /// `hdr_metadata_fn` is `VK_EXT_hdr_metadata` loaded from `device`, which has to have it enabled.
+    pub fn set_hdr_metadata(
+        &self,
+        hdr_metadata_fn: &vk::ExtHdrMetadataFn,
+        device: &ash::Device,
+        metadata: &vk::HdrMetadataEXT
+    ) {
+        let swapchains = [self.swapchain];
+        unsafe {
+            (hdr_metadata_fn.set_hdr_metadata_ext)(device.handle(), 1, swapchains.as_ptr(), metadata);
+        }
     }
 
     pub fn create_image_views(&self,
         device: &ash::Device
     ) -> Vec<vk::ImageView> {
         let mut swapchain_imageviews = vec![];
+        let view_type = if self.image_array_layers > 1 {
+            vk::ImageViewType::TYPE_2D_ARRAY
+        } else {
+            vk::ImageViewType::TYPE_2D
+        };
 
         for &image in self.swapchain_images.iter() {
-            let image_view_create_info = vk::ImageViewCreateInfo {
-                s_type: vk::StructureType::IMAGE_VIEW_CREATE_INFO,
-                p_next: ptr::null(),
-                flags: vk::ImageViewCreateFlags::empty(),
-                view_type: vk::ImageViewType::TYPE_2D,
-                format: self.swapchain_format,
-                components: vk::ComponentMapping {
-                    r: vk::ComponentSwizzle::IDENTITY,
-                    g: vk::ComponentSwizzle::IDENTITY,
-                    b: vk::ComponentSwizzle::IDENTITY,
-                    a: vk::ComponentSwizzle::IDENTITY,
-                },
-                subresource_range: vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                },
+            let image_view = image::create_image_view(
+                device,
                 image,
-            };
-
-            let image_view = unsafe {
-                device
-                .create_image_view(&image_view_create_info, None)
-                .expect("Failet to create image view")
-            };
+                view_type,
+                self.swapchain_format,
+                vk::ImageAspectFlags::COLOR);
 
             swapchain_imageviews.push(image_view);
         }
@@ -197,80 +844,340 @@ impl VkSpawChain {
         physical_device: vk::PhysicalDevice,
         surface: &render_device::VkSurface
     ) -> SwapChainSupportDetail {
-        let capabilities = unsafe {
+        SwapChainSupportDetail {
+            capabilities: VkSpawChain::query_surface_capabilities(physical_device, surface),
+            formats: VkSpawChain::query_surface_formats(physical_device, surface),
+            present_modes: VkSpawChain::query_surface_present_modes(physical_device, surface),
+        }
+    }
+
+    /// Regions of the surface each display scans out, e.g. both halves of a window straddling two monitors.
+    /// Empty below Vulkan 1.1, the query needs `VK_KHR_device_group` there.
+    pub fn present_rectangles(
+        entry: &ash::Entry,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        surface: &render_device::VkSurface,
+        api_version: u32
+    ) -> Vec<vk::Rect2D> {
+        if api_version < vk::API_VERSION_1_1 {
+            return vec![];
+        }
+
+        // an instance level function, so it can't come from the device loaded swapchain loader.
+        let swapchain_fn = vk::KhrSwapchainFn::load(|name| unsafe {
+            std::mem::transmute(entry.get_instance_proc_addr(instance.handle(), name.as_ptr()))
+        });
+
+        // first call only fills in the count, the second one the rectangles.
+        let mut rect_count = 0;
+        unsafe {
+            (swapchain_fn.get_physical_device_present_rectangles_khr)(
+                physical_device,
+                surface.surface,
+                &mut rect_count,
+                ptr::null_mut())
+                .result()
+                .expect("Failed to get physical device present rectangles");
+        }
+
+        let mut rects = vec![vk::Rect2D::default(); rect_count as usize];
+        unsafe {
+            (swapchain_fn.get_physical_device_present_rectangles_khr)(
+                physical_device,
+                surface.surface,
+                &mut rect_count,
+                rects.as_mut_ptr())
+                .result()
+                .expect("Failed to get physical device present rectangles");
+        }
+        rects.truncate(rect_count as usize);
+        rects
+    }
+
+    /// Usages swapchain images on `surface` can be created with, e.g. `TRANSFER_SRC` for reading frames back.
+    /// Always includes `COLOR_ATTACHMENT`. The swapchain only asks for the optional ones it uses and gets,
+    /// what it got is `swapchain_usage`.
+    pub fn supported_swapchain_usage(
+        physical_device: vk::PhysicalDevice,
+        surface: &render_device::VkSurface
+    ) -> vk::ImageUsageFlags {
+        VkSpawChain::query_surface_capabilities(physical_device, surface).supported_usage_flags
+    }
+
+    fn query_surface_capabilities(
+        physical_device: vk::PhysicalDevice,
+        surface: &render_device::VkSurface
+    ) -> vk::SurfaceCapabilitiesKHR {
+        unsafe {
             surface
             .surface_loader
             .get_physical_device_surface_capabilities(physical_device, surface.surface)
             .expect("Failed to get physical device surface capabilities")
-        };
-        let formats = unsafe {
+        }
+    }
+
+    fn query_surface_formats(
+        physical_device: vk::PhysicalDevice,
+        surface: &render_device::VkSurface
+    ) -> Vec<vk::SurfaceFormatKHR> {
+        unsafe {
             surface
                 .surface_loader
                 .get_physical_device_surface_formats(physical_device, surface.surface)
                 .expect("Failed to get defice surface formats")
+        }
+    }
+
+    /// Whether the window actually blends with what's behind it, see `SwapChainConfig::transparent`.
+    pub fn is_transparent(&self) -> bool {
+        VkSpawChain::is_transparent_composite_alpha(self.composite_alpha)
+    }
+
+    /// INHERIT leaves it to the window system, which Vulkan can't tell, so only the multiplied modes count.
+    fn is_transparent_composite_alpha(composite_alpha: vk::CompositeAlphaFlagsKHR) -> bool {
+        composite_alpha == vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED
+            || composite_alpha == vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED
+    }
+
+    /// The first supported of PRE_MULTIPLIED, POST_MULTIPLIED, INHERIT and OPAQUE when `transparent`,
+    /// otherwise of OPAQUE and INHERIT. OPAQUE when the surface reports none of them.
+    fn choose_composite_alpha(supported: vk::CompositeAlphaFlagsKHR, transparent: bool) -> vk::CompositeAlphaFlagsKHR {
+        let preferences: &[vk::CompositeAlphaFlagsKHR] = if transparent {
+            &[
+                vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+                vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+                vk::CompositeAlphaFlagsKHR::INHERIT,
+                vk::CompositeAlphaFlagsKHR::OPAQUE,
+            ]
+        } else {
+            &[
+                vk::CompositeAlphaFlagsKHR::OPAQUE,
+                vk::CompositeAlphaFlagsKHR::INHERIT,
+            ]
         };
-        let present_modes = unsafe {
+
+        preferences
+            .iter()
+            .copied()
+            .find(|&composite_alpha| supported.contains(composite_alpha))
+            .unwrap_or(vk::CompositeAlphaFlagsKHR::OPAQUE)
+    }
+
+    /// Whether protected swapchains can be created on `surface`, `false` unless the instance enabled
+    /// `VK_KHR_surface_protected_capabilities`.
+    pub fn supports_protected(physical_device: vk::PhysicalDevice, surface: &render_device::VkSurface) -> bool {
+        let surface_capabilities2_loader = match &surface.surface_capabilities2_loader {
+            Some(loader) if surface.surface_protected_capabilities => loader,
+            _ => return false,
+        };
+
+        let surface_info = vk::PhysicalDeviceSurfaceInfo2KHR {
+            s_type: vk::StructureType::PHYSICAL_DEVICE_SURFACE_INFO_2_KHR,
+            p_next: ptr::null(),
+            surface: surface.surface,
+        };
+        let mut protected_capabilities = vk::SurfaceProtectedCapabilitiesKHR::default();
+        let mut surface_capabilities = vk::SurfaceCapabilities2KHR {
+            p_next: &mut protected_capabilities as *mut _ as *mut c_void,
+            ..Default::default()
+        };
+        unsafe {
+            (surface_capabilities2_loader.fp().get_physical_device_surface_capabilities2_khr)(
+                physical_device,
+                &surface_info,
+                &mut surface_capabilities)
+                .result()
+                .expect("Failed to get physical device surface capabilities");
+        }
+
+        protected_capabilities.supports_protected == vk::TRUE
+    }
+
+    /// Present modes a swapchain created with `present_mode` can switch to, including `present_mode` itself.
+    /// Just `present_mode` when the instance didn't enable `VK_EXT_surface_maintenance1`.
+    fn query_compatible_present_modes(
+        physical_device: vk::PhysicalDevice,
+        surface: &render_device::VkSurface,
+        present_mode: vk::PresentModeKHR
+    ) -> Vec<vk::PresentModeKHR> {
+        let surface_capabilities2_loader = match &surface.surface_capabilities2_loader {
+            Some(loader) if surface.surface_maintenance1 => loader,
+            _ => return vec![present_mode],
+        };
+
+        let mut surface_present_mode = vk::SurfacePresentModeEXT {
+            s_type: vk::StructureType::SURFACE_PRESENT_MODE_EXT,
+            p_next: ptr::null_mut(),
+            present_mode: present_mode,
+        };
+        let surface_info = vk::PhysicalDeviceSurfaceInfo2KHR {
+            s_type: vk::StructureType::PHYSICAL_DEVICE_SURFACE_INFO_2_KHR,
+            p_next: &mut surface_present_mode as *mut _ as *const c_void,
+            surface: surface.surface,
+        };
+
+        // first call only fills in the count, the second one the modes.
+        let mut compatibility = vk::SurfacePresentModeCompatibilityEXT::default();
+        let mut surface_capabilities = vk::SurfaceCapabilities2KHR {
+            p_next: &mut compatibility as *mut _ as *mut c_void,
+            ..Default::default()
+        };
+        unsafe {
+            (surface_capabilities2_loader.fp().get_physical_device_surface_capabilities2_khr)(
+                physical_device,
+                &surface_info,
+                &mut surface_capabilities)
+                .result()
+                .expect("Failed to get physical device surface capabilities");
+        }
+
+        let mut present_modes = vec![vk::PresentModeKHR::default(); compatibility.present_mode_count as usize];
+        compatibility.p_present_modes = present_modes.as_mut_ptr();
+        surface_capabilities.p_next = &mut compatibility as *mut _ as *mut c_void;
+        unsafe {
+            (surface_capabilities2_loader.fp().get_physical_device_surface_capabilities2_khr)(
+                physical_device,
+                &surface_info,
+                &mut surface_capabilities)
+                .result()
+                .expect("Failed to get physical device surface capabilities");
+        }
+        present_modes.truncate(compatibility.present_mode_count as usize);
+
+        if !present_modes.contains(&present_mode) {
+            present_modes.push(present_mode);
+        }
+        present_modes
+    }
+
+    fn query_surface_present_modes(
+        physical_device: vk::PhysicalDevice,
+        surface: &render_device::VkSurface
+    ) -> Vec<vk::PresentModeKHR> {
+        unsafe {
             surface
                 .surface_loader
                 .get_physical_device_surface_present_modes(physical_device, surface.surface)
                 .expect("Failed to get device surface present modes")
-        };
-
-        SwapChainSupportDetail {
-            capabilities: capabilities,
-            formats: formats,
-            present_modes: present_modes
         }
     }
 
+    /// Name of the format and color space for a settings menu, e.g. "B8G8R8A8_SRGB (SRGB_NONLINEAR)".
+    pub fn describe_format(surface_format: &vk::SurfaceFormatKHR) -> String {
+        format!("{:?} ({:?})", surface_format.format, surface_format.color_space)
+    }
+
+    /// `preferred_format` when available, then B8G8R8A8_SRGB with SRGB_NONLINEAR, then the first one.
+    /// `None` when there are no formats at all.
     fn choose_swapchain_format(
-        available_formats: &Vec<ash::vk::SurfaceFormatKHR>
-    ) -> ash::vk::SurfaceFormatKHR {
+        available_formats: &[ash::vk::SurfaceFormatKHR],
+        preferred_format: Option<ash::vk::SurfaceFormatKHR>
+    ) -> Option<ash::vk::SurfaceFormatKHR> {
+        if let Some(preferred_format) = preferred_format {
+            if available_formats.contains(&preferred_format) {
+                return Some(preferred_format);
+            }
+        }
 
         for format in available_formats.iter() {
             if format.format == ash::vk::Format::B8G8R8A8_SRGB
                 && format.color_space == ash::vk::ColorSpaceKHR::SRGB_NONLINEAR {
-                    return format.clone();
+                    return Some(*format);
                 }
         }
 
-        return available_formats.first().unwrap().clone();
+        available_formats.first().cloned()
     }
 
     fn choose_swapchain_present_mode(
-        present_modes: &Vec<ash::vk::PresentModeKHR>
-    ) -> ash::vk::PresentModeKHR {
+        present_modes: &[ash::vk::PresentModeKHR],
+        preferred_present_mode: Option<ash::vk::PresentModeKHR>
+    ) -> PresentModeSelection {
+        let selection = |selected| PresentModeSelection {
+            requested: preferred_present_mode,
+            selected: selected,
+            available: present_modes.to_vec(),
+        };
+
+        if let Some(preferred_present_mode) = preferred_present_mode {
+            if present_modes.contains(&preferred_present_mode) {
+                return selection(preferred_present_mode);
+            }
+        }
 
         for &present_mode in present_modes.iter() {
            if present_mode == ash::vk::PresentModeKHR::MAILBOX {
-                return present_mode;
+                return selection(present_mode);
            }
         }
 
-        return present_modes.first().unwrap().clone();
+        selection(*present_modes.first().unwrap())
+    }
+
+    /// Extent a swapchain created right now would get. 0x0 while the window is minimized,
+    /// no swapchain can be created then and rendering has to pause until the window is restored.
+    pub fn current_surface_extent(
+        physical_device: vk::PhysicalDevice,
+        surface: &render_device::VkSurface
+    ) -> vk::Extent2D {
+        VkSpawChain::choose_swapchain_extent(&VkSpawChain::query_surface_capabilities(physical_device, surface), surface)
+    }
+
+    pub fn is_zero_extent(extent: vk::Extent2D) -> bool {
+        extent.width == 0 || extent.height == 0
+    }
+
+    /// `u32::MAX` as current extent means the surface's size is whatever the swapchain is created with.
+    /// The spec only gives that meaning to both dimensions being `0xFFFFFFFF`, one of them alone is a defined extent.
+    fn is_extent_from_window(capabilities: &ash::vk::SurfaceCapabilitiesKHR) -> bool {
+        capabilities.current_extent.width == u32::MAX && capabilities.current_extent.height == u32::MAX
     }
 
     fn choose_swapchain_extent(
-        capabilities: &ash::vk::SurfaceCapabilitiesKHR
+        capabilities: &ash::vk::SurfaceCapabilitiesKHR,
+        surface: &render_device::VkSurface
     ) -> ash::vk::Extent2D {
+        let window_extent = vk::Extent2D {
+            width: surface.screen_width,
+            height: surface.screen_height,
+        };
+        VkSpawChain::extent_for_window(capabilities, window_extent)
+    }
 
-        if capabilities.current_extent.width != u32::max_value() || capabilities.current_extent.height != u32::max_value() {
+    /// The surface's current extent when it has one, otherwise `window_extent`, within the surface's limits.
+    fn extent_for_window(capabilities: &vk::SurfaceCapabilitiesKHR, window_extent: vk::Extent2D) -> vk::Extent2D {
+        let desired = if !VkSpawChain::is_extent_from_window(capabilities) {
+            // 0x0 while minimized is kept as it is, `is_zero_extent` has to see it to pause rendering.
+            if VkSpawChain::is_zero_extent(capabilities.current_extent) {
+                return capabilities.current_extent;
+            }
+            // some drivers still report the old window size for a moment after a fast resize,
+            // which can be past `max_image_extent` already.
             capabilities.current_extent
-        } else {
-            use num::clamp;
-
+        } else if VkSpawChain::is_zero_extent(window_extent) {
+            // the window may not know its size before the first configure event, the default size is a guess until then.
             vk::Extent2D {
-                width: clamp(
-                    global_constants::WINDOW_WIDTH,
-                    capabilities.min_image_extent.width,
-                    capabilities.max_image_extent.width,
-                ),
-                height: clamp(
-                    global_constants::WINDOW_HEIGHT,
-                    capabilities.min_image_extent.height,
-                    capabilities.max_image_extent.height,
-                ),
+                width: global_constants::WINDOW_WIDTH,
+                height: global_constants::WINDOW_HEIGHT,
             }
+        } else {
+            window_extent
+        };
+
+        VkSpawChain::clamp_extent(desired, capabilities)
+    }
+
+    /// `desired` within `min_image_extent` and `max_image_extent`, per dimension. Unlike `u32::clamp` it doesn't
+    /// panic when the minimum is above the maximum, the maximum wins for a surface reporting them the other way round.
+    pub fn clamp_extent(desired: vk::Extent2D, capabilities: &vk::SurfaceCapabilitiesKHR) -> vk::Extent2D {
+        vk::Extent2D {
+            width: desired.width
+                .max(capabilities.min_image_extent.width)
+                .min(capabilities.max_image_extent.width),
+            height: desired.height
+                .max(capabilities.min_image_extent.height)
+                .min(capabilities.max_image_extent.height),
         }
     }
 
@@ -287,8 +1194,342 @@ impl VkSpawChain {
     }
 
     pub fn destroy_swapchain(&self) {
+        lifecycle::on_destroy(TrackedObject::Swapchain, self.swapchain);
         unsafe {
             self.swapchain_loader.destroy_swapchain(self.swapchain, None);
         }
     }
-}
\ No newline at end of file
+}
+/// Moves EXCLUSIVE swapchain images from the graphics to the present queue family.
+/// The graphics command buffers end with `cmd_release`, `submit_acquire` runs the matching acquire
+/// on the present queue and the present waits on its semaphore instead.
+/// Images come back from the presentation engine with undefined contents, so the acquire side needs no transfer.
+pub struct PresentOwnershipTransfer {
+    pub graphics_family: u32,
+    pub present_family: u32,
+
+    command_pool: vk::CommandPool,
+    /// One per swapchain image, an image is only in one present at a time.
+    acquire_command_buffers: Vec<vk::CommandBuffer>,
+    acquired_semaphores: Vec<VkSemaphore>,
+}
+
+impl PresentOwnershipTransfer {
+    pub fn new(
+        device: &ash::Device,
+        graphics_family: u32,
+        present_family: u32,
+        swapchain_images: &[vk::Image]
+    ) -> PresentOwnershipTransfer {
+        let command_pool_create_info = vk::CommandPoolCreateInfo {
+            s_type: vk::StructureType::COMMAND_POOL_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::CommandPoolCreateFlags::empty(),
+            queue_family_index: present_family,
+        };
+
+        let command_pool = unsafe {
+            device
+                .create_command_pool(&command_pool_create_info, None)
+                .expect("Failed to create Command Pool!")
+        };
+
+        let command_buffer_allocate_info = vk::CommandBufferAllocateInfo {
+            s_type: vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
+            p_next: ptr::null(),
+            command_buffer_count: swapchain_images.len() as u32,
+            command_pool: command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+        };
+
+        let acquire_command_buffers = unsafe {
+            device
+                .allocate_command_buffers(&command_buffer_allocate_info)
+                .expect("Failed to allocate Command Buffers!")
+        };
+
+        let transfer = PresentOwnershipTransfer {
+            graphics_family: graphics_family,
+            present_family: present_family,
+            command_pool: command_pool,
+            acquire_command_buffers: acquire_command_buffers,
+            acquired_semaphores: swapchain_images.iter().map(|_| VkSemaphore::new(device)).collect(),
+        };
+
+        for (&command_buffer, &image) in transfer.acquire_command_buffers.iter().zip(swapchain_images.iter()) {
+            let command_buffer_begin_info = vk::CommandBufferBeginInfo {
+                s_type: vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
+                p_next: ptr::null(),
+                p_inheritance_info: ptr::null(),
+                flags: vk::CommandBufferUsageFlags::SIMULTANEOUS_USE,
+            };
+
+            unsafe {
+                device
+                    .begin_command_buffer(command_buffer, &command_buffer_begin_info)
+                    .expect("Failed to begin recording Command Buffer at beginning!");
+
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[transfer.ownership_barrier(image, vk::AccessFlags::empty())]);
+
+                device
+                    .end_command_buffer(command_buffer)
+                    .expect("Failed to record Command Buffer at Ending!");
+            }
+        }
+
+        transfer
+    }
+
+    /// Release half of the transfer, recorded after the last render pass drawing to `image`.
+    pub fn cmd_release(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, image: vk::Image) {
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[self.ownership_barrier(image, vk::AccessFlags::COLOR_ATTACHMENT_WRITE)]);
+        }
+    }
+
+    /// Submits the acquire half for `image_index` after `wait_semaphore`,
+    /// returns the semaphore the present has to wait on.
+    pub fn submit_acquire(
+        &self,
+        device: &ash::Device,
+        present_queue: vk::Queue,
+        image_index: u32,
+        wait_semaphore: vk::Semaphore
+    ) -> Result<vk::Semaphore, vk::Result> {
+        let wait_semaphores = [wait_semaphore];
+        let wait_stages = [vk::PipelineStageFlags::ALL_COMMANDS];
+        let command_buffers = [self.acquire_command_buffers[image_index as usize]];
+        let signal_semaphores = [self.acquired_semaphores[image_index as usize].semaphore];
+
+        let submit_infos = [vk::SubmitInfo {
+            s_type: vk::StructureType::SUBMIT_INFO,
+            p_next: ptr::null(),
+            wait_semaphore_count: wait_semaphores.len() as u32,
+            p_wait_semaphores: wait_semaphores.as_ptr(),
+            p_wait_dst_stage_mask: wait_stages.as_ptr(),
+            command_buffer_count: command_buffers.len() as u32,
+            p_command_buffers: command_buffers.as_ptr(),
+            signal_semaphore_count: signal_semaphores.len() as u32,
+            p_signal_semaphores: signal_semaphores.as_ptr(),
+        }];
+
+        unsafe {
+            device.queue_submit(present_queue, &submit_infos, vk::Fence::null())?;
+        }
+
+        Ok(signal_semaphores[0])
+    }
+
+    /// The present queue must not execute any acquire anymore.
+    pub fn destroy(&mut self, device: &ash::Device) {
+        self.acquired_semaphores.clear();
+
+        unsafe {
+            device.destroy_command_pool(self.command_pool, None);
+        }
+    }
+
+    fn ownership_barrier(&self, image: vk::Image, src_access_mask: vk::AccessFlags) -> vk::ImageMemoryBarrier {
+        vk::ImageMemoryBarrier {
+            s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
+            p_next: ptr::null(),
+            src_access_mask: src_access_mask,
+            dst_access_mask: vk::AccessFlags::empty(),
+            old_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            src_queue_family_index: self.graphics_family,
+            dst_queue_family_index: self.present_family,
+            image: image,
+            subresource_range: image::full_subresource_range(vk::ImageAspectFlags::COLOR),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn surface_capabilities(min_image_count: u32, max_image_count: u32) -> vk::SurfaceCapabilitiesKHR {
+        vk::SurfaceCapabilitiesKHR {
+            min_image_count: min_image_count,
+            max_image_count: max_image_count,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn requested_image_count_is_one_above_the_minimum_within_the_maximum() {
+        assert_eq!(VkSpawChain::requested_image_count(&surface_capabilities(2, 8)), 3);
+        assert_eq!(VkSpawChain::requested_image_count(&surface_capabilities(3, 3)), 3);
+        // 0 means the surface has no maximum.
+        assert_eq!(VkSpawChain::requested_image_count(&surface_capabilities(4, 0)), 5);
+    }
+
+    fn extent(width: u32, height: u32) -> vk::Extent2D {
+        vk::Extent2D {
+            width: width,
+            height: height,
+        }
+    }
+
+    fn extent_capabilities(current_extent: vk::Extent2D) -> vk::SurfaceCapabilitiesKHR {
+        vk::SurfaceCapabilitiesKHR {
+            current_extent: current_extent,
+            min_image_extent: extent(64, 64),
+            max_image_extent: extent(4096, 2160),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn defined_current_extent_is_used_as_it_is() {
+        let capabilities = extent_capabilities(extent(1920, 1080));
+
+        assert_eq!(VkSpawChain::extent_for_window(&capabilities, extent(800, 600)), extent(1920, 1080));
+        // minimized, has to stay 0x0 instead of being clamped up to the minimum.
+        let minimized = extent_capabilities(extent(0, 0));
+        assert_eq!(VkSpawChain::extent_for_window(&minimized, extent(800, 600)), extent(0, 0));
+    }
+
+    #[test]
+    fn undefined_current_extent_takes_the_window_size_within_the_limits() {
+        let capabilities = extent_capabilities(extent(u32::MAX, u32::MAX));
+
+        assert_eq!(VkSpawChain::extent_for_window(&capabilities, extent(800, 600)), extent(800, 600));
+        assert_eq!(VkSpawChain::extent_for_window(&capabilities, extent(8000, 32)), extent(4096, 64));
+        assert_eq!(
+            VkSpawChain::extent_for_window(&capabilities, extent(0, 0)),
+            extent(global_constants::WINDOW_WIDTH, global_constants::WINDOW_HEIGHT));
+    }
+
+    #[test]
+    fn mixed_current_extent_counts_as_defined() {
+        // only (0xFFFFFFFF, 0xFFFFFFFF) is the special value, a single u32::MAX dimension is clamped like any other.
+        let capabilities = extent_capabilities(extent(u32::MAX, 1080));
+
+        assert!(!VkSpawChain::is_extent_from_window(&capabilities));
+        assert_eq!(VkSpawChain::extent_for_window(&capabilities, extent(800, 600)), extent(4096, 1080));
+    }
+
+    #[test]
+    fn clamp_extent_lets_the_maximum_win_over_a_larger_minimum() {
+        let mut capabilities = extent_capabilities(extent(u32::MAX, u32::MAX));
+        capabilities.min_image_extent = extent(512, 512);
+        capabilities.max_image_extent = extent(256, 1024);
+
+        assert_eq!(VkSpawChain::clamp_extent(extent(100, 100), &capabilities), extent(256, 512));
+    }
+
+    #[test]
+    fn swapchain_extensions_keep_each_structure_without_its_p_next() {
+        let mut counter_create_info = vk::SwapchainCounterCreateInfoEXT {
+            surface_counters: vk::SurfaceCounterFlagsEXT::VBLANK,
+            ..Default::default()
+        };
+        let mut hdr_create_info = vk::SwapchainDisplayNativeHdrCreateInfoAMD::default();
+        counter_create_info.p_next = &mut hdr_create_info as *mut _ as *const c_void;
+
+        let extensions = unsafe {
+            SwapChainExtensions::new()
+                .push(counter_create_info)
+                .push(hdr_create_info)
+        };
+
+        assert_eq!(
+            extensions.structure_types(),
+            vec![
+                vk::StructureType::SWAPCHAIN_COUNTER_CREATE_INFO_EXT,
+                vk::StructureType::SWAPCHAIN_DISPLAY_NATIVE_HDR_CREATE_INFO_AMD,
+            ]);
+        let stored_counter_create_info =
+            unsafe { &*(extensions.structures[0].as_ptr() as *const vk::SwapchainCounterCreateInfoEXT) };
+        assert!(stored_counter_create_info.p_next.is_null());
+        assert_eq!(stored_counter_create_info.surface_counters, vk::SurfaceCounterFlagsEXT::VBLANK);
+    }
+
+    #[test]
+    #[should_panic(expected = "chained by the engine already")]
+    fn extensions_reject_structs_the_engine_chains() {
+        let present_modes_create_info = vk::SwapchainPresentModesCreateInfoEXT::default();
+
+        unsafe {
+            SwapChainExtensions::new().push(present_modes_create_info);
+        }
+    }
+
+    #[test]
+    fn choose_composite_alpha_degrades_from_pre_multiplied_to_opaque() {
+        let all = vk::CompositeAlphaFlagsKHR::OPAQUE
+            | vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED
+            | vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED
+            | vk::CompositeAlphaFlagsKHR::INHERIT;
+        let choose = VkSpawChain::choose_composite_alpha;
+
+        assert_eq!(choose(all, true), vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED);
+        assert_eq!(choose(all ^ vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED, true), vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED);
+        assert_eq!(
+            choose(vk::CompositeAlphaFlagsKHR::OPAQUE | vk::CompositeAlphaFlagsKHR::INHERIT, true),
+            vk::CompositeAlphaFlagsKHR::INHERIT);
+        assert_eq!(choose(vk::CompositeAlphaFlagsKHR::OPAQUE, true), vk::CompositeAlphaFlagsKHR::OPAQUE);
+        assert!(!VkSpawChain::is_transparent_composite_alpha(choose(vk::CompositeAlphaFlagsKHR::OPAQUE, true)));
+
+        assert_eq!(choose(all, false), vk::CompositeAlphaFlagsKHR::OPAQUE);
+        // e.g. Android surfaces, which only support INHERIT.
+        assert_eq!(choose(vk::CompositeAlphaFlagsKHR::INHERIT, false), vk::CompositeAlphaFlagsKHR::INHERIT);
+    }
+
+    fn surface_format(format: vk::Format, color_space: vk::ColorSpaceKHR) -> vk::SurfaceFormatKHR {
+        vk::SurfaceFormatKHR {
+            format: format,
+            color_space: color_space,
+        }
+    }
+
+    #[test]
+    fn choose_swapchain_format_prefers_srgb_over_the_first_format() {
+        let srgb = surface_format(vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR);
+        let unorm = surface_format(vk::Format::B8G8R8A8_UNORM, vk::ColorSpaceKHR::SRGB_NONLINEAR);
+        let hdr = surface_format(vk::Format::A2B10G10R10_UNORM_PACK32, vk::ColorSpaceKHR::HDR10_ST2084_EXT);
+
+        assert_eq!(VkSpawChain::choose_swapchain_format(&[unorm, srgb], None), Some(srgb));
+        // an explicit preference wins over sRGB, but only when the surface has it.
+        assert_eq!(VkSpawChain::choose_swapchain_format(&[unorm, srgb, hdr], Some(hdr)), Some(hdr));
+        assert_eq!(VkSpawChain::choose_swapchain_format(&[unorm, srgb], Some(hdr)), Some(srgb));
+    }
+
+    #[test]
+    fn choose_swapchain_format_falls_back_to_the_first_format() {
+        let unorm = surface_format(vk::Format::B8G8R8A8_UNORM, vk::ColorSpaceKHR::SRGB_NONLINEAR);
+        // sRGB format, but in another color space.
+        let srgb_extended = surface_format(vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT);
+
+        assert_eq!(VkSpawChain::choose_swapchain_format(&[unorm, srgb_extended], None), Some(unorm));
+        assert_eq!(VkSpawChain::choose_swapchain_format(&[], None), None);
+    }
+
+    #[test]
+    fn cached_sharing_mode_replaces_only_the_line_of_its_device() {
+        let contents = with_cached_sharing_mode("", "10de:2484:1", vk::SharingMode::CONCURRENT);
+        let contents = with_cached_sharing_mode(&contents, "1002:73bf:7", vk::SharingMode::CONCURRENT);
+        let contents = with_cached_sharing_mode(&contents, "10de:2484:1", vk::SharingMode::EXCLUSIVE);
+
+        assert_eq!(contents, "1002:73bf:7 CONCURRENT\n10de:2484:1 EXCLUSIVE\n");
+        assert_eq!(cached_sharing_mode(&contents, "10de:2484:1"), Some(vk::SharingMode::EXCLUSIVE));
+        assert_eq!(cached_sharing_mode(&contents, "1002:73bf:7"), Some(vk::SharingMode::CONCURRENT));
+        assert_eq!(cached_sharing_mode(&contents, "8086:4680:3"), None);
+    }
+}
@@ -0,0 +1,250 @@
+use ash::vk;
+
+use std::ptr;
+
+/// Binary semaphore destroyed on drop. Keeps a clone of the device, which has to outlive it.
+pub struct VkSemaphore {
+    device: ash::Device,
+    pub semaphore: vk::Semaphore,
+}
+
+impl VkSemaphore {
+    pub fn new(device: &ash::Device) -> VkSemaphore {
+        let semaphore_create_info = vk::SemaphoreCreateInfo {
+            s_type: vk::StructureType::SEMAPHORE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::SemaphoreCreateFlags::empty(),
+        };
+
+        let semaphore = unsafe {
+            device
+                .create_semaphore(&semaphore_create_info, None)
+                .expect("Failed to create Semaphore Object!")
+        };
+
+        VkSemaphore {
+            device: device.clone(),
+            semaphore: semaphore,
+        }
+    }
+}
+
+impl Drop for VkSemaphore {
+    /// The semaphore must not be in use by the device anymore.
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_semaphore(self.semaphore, None);
+        }
+    }
+}
+
+/// Fence destroyed on drop. Keeps a clone of the device, which has to outlive it.
+pub struct VkFence {
+    device: ash::Device,
+    pub fence: vk::Fence,
+}
+
+impl VkFence {
+    /// `signaled` creates the fence already signaled, e.g. so the first wait of a frame loop doesn't block.
+    pub fn new(device: &ash::Device, signaled: bool) -> VkFence {
+        let fence_create_info = vk::FenceCreateInfo {
+            s_type: vk::StructureType::FENCE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: if signaled {
+                vk::FenceCreateFlags::SIGNALED
+            } else {
+                vk::FenceCreateFlags::empty()
+            },
+        };
+
+        let fence = unsafe {
+            device
+                .create_fence(&fence_create_info, None)
+                .expect("Failed to create Fence Object!")
+        };
+
+        VkFence {
+            device: device.clone(),
+            fence: fence,
+        }
+    }
+
+    /// Blocks until the fence is signaled, `timeout` is in nanoseconds. Returns `TIMEOUT` when it ran out.
+    pub fn wait(&self, timeout: u64) -> Result<(), vk::Result> {
+        unsafe { self.device.wait_for_fences(&[self.fence], true, timeout) }
+    }
+
+    pub fn reset(&self) {
+        unsafe {
+            self.device
+                .reset_fences(&[self.fence])
+                .expect("Failed to reset Fence!");
+        }
+    }
+
+    pub fn is_signaled(&self) -> bool {
+        unsafe {
+            self.device
+                .get_fence_status(self.fence)
+                .expect("Failed to get Fence status!")
+        }
+    }
+}
+
+impl Drop for VkFence {
+    /// The fence must not be in use by a pending submit anymore.
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_fence(self.fence, None);
+        }
+    }
+}
+
+/// Split barrier: set at one point of a command stream and waited on later,
+/// so work recorded in between can overlap. Keeps a clone of the device, which has to outlive it.
+pub struct VkEvent {
+    device: ash::Device,
+    pub event: vk::Event,
+    /// Created with `DEVICE_ONLY`, the host can't `set`/`reset` it then.
+    pub is_device_only: bool,
+}
+
+impl VkEvent {
+    /// `device_only` needs `capabilities.synchronization2`, it lets the driver skip host visibility
+    /// but the event can then only be waited on with `cmd_wait2`.
+    pub fn new(device: &ash::Device, device_only: bool) -> VkEvent {
+        let event_create_info = vk::EventCreateInfo {
+            s_type: vk::StructureType::EVENT_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: if device_only {
+                vk::EventCreateFlags::DEVICE_ONLY
+            } else {
+                vk::EventCreateFlags::empty()
+            },
+        };
+
+        let event = unsafe {
+            device
+                .create_event(&event_create_info, None)
+                .expect("Failed to create Event Object!")
+        };
+
+        VkEvent {
+            device: device.clone(),
+            event: event,
+            is_device_only: device_only,
+        }
+    }
+
+    pub fn set(&self) {
+        assert!(!self.is_device_only, "Device only events can't be set from the host");
+        unsafe {
+            self.device
+                .set_event(self.event)
+                .expect("Failed to set Event!");
+        }
+    }
+
+    pub fn reset(&self) {
+        assert!(!self.is_device_only, "Device only events can't be reset from the host");
+        unsafe {
+            self.device
+                .reset_event(self.event)
+                .expect("Failed to reset Event!");
+        }
+    }
+
+    /// The status of a `DEVICE_ONLY` event is undefined on the host, `ERROR_FEATURE_NOT_PRESENT` is returned for those.
+    pub fn is_set(&self) -> Result<bool, vk::Result> {
+        if self.is_device_only {
+            return Err(vk::Result::ERROR_FEATURE_NOT_PRESENT);
+        }
+
+        unsafe { self.device.get_event_status(self.event) }
+    }
+
+    /// Signals the event once the `stage_mask` stages of all earlier commands completed.
+    pub fn cmd_set(&self, command_buffer: vk::CommandBuffer, stage_mask: vk::PipelineStageFlags) {
+        unsafe {
+            self.device.cmd_set_event(command_buffer, self.event, stage_mask);
+        }
+    }
+
+    pub fn cmd_reset(&self, command_buffer: vk::CommandBuffer, stage_mask: vk::PipelineStageFlags) {
+        unsafe {
+            self.device.cmd_reset_event(command_buffer, self.event, stage_mask);
+        }
+    }
+
+    /// `src_stage_mask` must match the stages the event was set with.
+    pub fn cmd_wait(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        src_stage_mask: vk::PipelineStageFlags,
+        dst_stage_mask: vk::PipelineStageFlags,
+        memory_barriers: &[vk::MemoryBarrier],
+        buffer_memory_barriers: &[vk::BufferMemoryBarrier],
+        image_memory_barriers: &[vk::ImageMemoryBarrier],
+    ) {
+        assert!(!self.is_device_only, "Device only events have to be waited on with cmd_wait2");
+        unsafe {
+            self.device.cmd_wait_events(
+                command_buffer,
+                &[self.event],
+                src_stage_mask,
+                dst_stage_mask,
+                memory_barriers,
+                buffer_memory_barriers,
+                image_memory_barriers,
+            );
+        }
+    }
+
+    /// Sync2 version of `cmd_set`, the whole dependency is declared when setting.
+    pub fn cmd_set2(
+        &self,
+        synchronization2_loader: &ash::extensions::khr::Synchronization2,
+        command_buffer: vk::CommandBuffer,
+        dependency_info: &vk::DependencyInfo,
+    ) {
+        unsafe {
+            synchronization2_loader.cmd_set_event2(command_buffer, self.event, dependency_info);
+        }
+    }
+
+    pub fn cmd_reset2(
+        &self,
+        synchronization2_loader: &ash::extensions::khr::Synchronization2,
+        command_buffer: vk::CommandBuffer,
+        stage_mask: vk::PipelineStageFlags2,
+    ) {
+        unsafe {
+            synchronization2_loader.cmd_reset_event2(command_buffer, self.event, stage_mask);
+        }
+    }
+
+    /// `dependency_info` must be the same one the event was set with in `cmd_set2`.
+    pub fn cmd_wait2(
+        &self,
+        synchronization2_loader: &ash::extensions::khr::Synchronization2,
+        command_buffer: vk::CommandBuffer,
+        dependency_info: &vk::DependencyInfo,
+    ) {
+        unsafe {
+            synchronization2_loader.cmd_wait_events2(
+                command_buffer,
+                &[self.event],
+                std::slice::from_ref(dependency_info),
+            );
+        }
+    }
+}
+
+impl Drop for VkEvent {
+    /// The event must not be used by pending command buffers anymore.
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_event(self.event, None);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use ash::vk;
+
+use std::ffi::CString;
+use std::ptr;
+
+/// Headless device on the first physical device, `None` when there is no Vulkan driver.
+pub fn create_test_device() -> Option<(ash::Entry, ash::Instance, ash::Device, vk::PhysicalDeviceMemoryProperties)> {
+    let entry = ash::Entry::linked();
+    let app_name = CString::new("PupsyEngine tests").unwrap();
+    let app_info = vk::ApplicationInfo {
+        s_type: vk::StructureType::APPLICATION_INFO,
+        p_next: ptr::null(),
+        p_application_name: app_name.as_ptr(),
+        application_version: 0,
+        p_engine_name: app_name.as_ptr(),
+        engine_version: 0,
+        api_version: vk::API_VERSION_1_0,
+    };
+    let instance_create_info = vk::InstanceCreateInfo {
+        s_type: vk::StructureType::INSTANCE_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::InstanceCreateFlags::empty(),
+        p_application_info: &app_info,
+        enabled_layer_count: 0,
+        pp_enabled_layer_names: ptr::null(),
+        enabled_extension_count: 0,
+        pp_enabled_extension_names: ptr::null(),
+    };
+    let instance = unsafe { entry.create_instance(&instance_create_info, None).ok()? };
+
+    let physical_device = unsafe { instance.enumerate_physical_devices().ok()?.first().copied()? };
+    let memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+
+    let queue_priorities = [1.0_f32];
+    let queue_create_infos = [vk::DeviceQueueCreateInfo {
+        s_type: vk::StructureType::DEVICE_QUEUE_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::DeviceQueueCreateFlags::empty(),
+        queue_family_index: 0,
+        queue_count: queue_priorities.len() as u32,
+        p_queue_priorities: queue_priorities.as_ptr(),
+    }];
+    let device_create_info = vk::DeviceCreateInfo {
+        s_type: vk::StructureType::DEVICE_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::DeviceCreateFlags::empty(),
+        queue_create_info_count: queue_create_infos.len() as u32,
+        p_queue_create_infos: queue_create_infos.as_ptr(),
+        ..Default::default()
+    };
+    let device = unsafe { instance.create_device(physical_device, &device_create_info, None).ok()? };
+
+    Some((entry, instance, device, memory_properties))
+}
@@ -0,0 +1,420 @@
+use ash::vk;
+
+use std::path::Path;
+use std::ptr;
+
+use crate::vk::buffer::MappedBuffer;
+use crate::vk::capabilities::DeviceCapabilities;
+use crate::vk::commands::UploadContext;
+use crate::vk::compressed_texture::CompressedImage;
+use crate::vk::image;
+use crate::vk::lifecycle::{self, TrackedObject};
+use crate::vk::sampler::{SamplerCache, SamplerConfig};
+
+/// Sampled image uploaded once from host data. The sampler comes from the `SamplerCache` and isn't owned by the texture.
+pub struct VkTexture {
+    pub image: vk::Image,
+    pub image_memory: vk::DeviceMemory,
+    pub image_view: vk::ImageView,
+    pub sampler: vk::Sampler,
+    /// What `sampler` is asked for with, before the `SamplerCache` quality is applied.
+    pub sampler_config: SamplerConfig,
+
+    pub format: vk::Format,
+    pub extent: vk::Extent3D,
+    pub mip_levels: u32,
+    pub array_layers: u32,
+    pub view_type: vk::ImageViewType,
+}
+
+/// Data of a single mip level of a single array layer.
+struct TextureRegion<'a> {
+    data: &'a [u8],
+    mip_level: u32,
+    array_layer: u32,
+}
+
+/// Shape of the image a texture is created with, mip and layer counts follow from `regions`.
+struct TextureDesc<'a> {
+    image_type: vk::ImageType,
+    view_type: vk::ImageViewType,
+    flags: vk::ImageCreateFlags,
+    extent: vk::Extent3D,
+    format: vk::Format,
+    regions: &'a [TextureRegion<'a>],
+    address_mode: vk::SamplerAddressMode,
+}
+
+impl VkTexture {
+    /// `data` is tightly packed `format` texels, row after row.
+    pub fn new_2d(
+        upload_context: &UploadContext,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+        data: &[u8]
+    ) -> VkTexture {
+        VkTexture::create(upload_context, &TextureDesc {
+            image_type: vk::ImageType::TYPE_2D,
+            view_type: vk::ImageViewType::TYPE_2D,
+            flags: vk::ImageCreateFlags::empty(),
+            extent: vk::Extent3D { width, height, depth: 1 },
+            format,
+            regions: &VkTexture::layer_regions(&[data]),
+            address_mode: vk::SamplerAddressMode::REPEAT,
+        })
+    }
+
+    /// Loads any image the `image` crate can decode as `R8G8B8A8_SRGB`.
+    pub fn from_file(upload_context: &UploadContext, path: &Path) -> VkTexture {
+        let image_object = ::image::open(path)
+            .expect("Failed to open texture image!")
+            .to_rgba8();
+        let (width, height) = image_object.dimensions();
+
+        VkTexture::new_2d(
+            upload_context,
+            width,
+            height,
+            vk::Format::R8G8B8A8_SRGB,
+            &image_object.into_raw())
+    }
+
+    /// Uploads the pre-compressed mip chain of a `.ktx2` or `.dds` file (BC on desktop, ASTC on mobile) as is.
+    /// When the device can't sample that format, `fallback` is loaded uncompressed instead.
+    /// Cubemap arrays need `capabilities.image_cube_array`.
+    pub fn from_compressed_file(
+        upload_context: &UploadContext,
+        capabilities: &DeviceCapabilities,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        path: &Path,
+        fallback: Option<&Path>
+    ) -> VkTexture {
+        let compressed_image = CompressedImage::load(path)
+            .unwrap_or_else(|error| panic!("Failed to load compressed texture: {}", error));
+
+        let is_format_supported = image::find_supported_format(
+            instance,
+            physical_device,
+            &[compressed_image.format],
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::SAMPLED_IMAGE,
+        ).is_some();
+
+        if !is_format_supported {
+            let fallback = fallback.unwrap_or_else(|| {
+                panic!("{:?} of {} is not supported by the device and there is no fallback", compressed_image.format, path.display())
+            });
+            log::warn!(
+                "{:?} of {} is not supported by the device, loading {} uncompressed instead.",
+                compressed_image.format, path.display(), fallback.display());
+
+            return VkTexture::from_file(upload_context, fallback);
+        }
+
+        let regions: Vec<TextureRegion> = compressed_image.levels
+            .iter()
+            .map(|level| TextureRegion {
+                data: &compressed_image.data[level.offset..level.offset + level.size],
+                mip_level: level.mip_level,
+                array_layer: level.array_layer,
+            })
+            .collect();
+
+        let (image_type, view_type, flags, address_mode) = if compressed_image.is_cubemap && compressed_image.array_layers > 6 {
+            assert!(capabilities.image_cube_array, "{} is a cubemap array, which needs imageCubeArray", path.display());
+            (vk::ImageType::TYPE_2D, vk::ImageViewType::CUBE_ARRAY, vk::ImageCreateFlags::CUBE_COMPATIBLE, vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        } else if compressed_image.is_cubemap {
+            (vk::ImageType::TYPE_2D, vk::ImageViewType::CUBE, vk::ImageCreateFlags::CUBE_COMPATIBLE, vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        } else if compressed_image.extent.depth > 1 {
+            (vk::ImageType::TYPE_3D, vk::ImageViewType::TYPE_3D, vk::ImageCreateFlags::empty(), vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        } else if compressed_image.array_layers > 1 {
+            (vk::ImageType::TYPE_2D, vk::ImageViewType::TYPE_2D_ARRAY, vk::ImageCreateFlags::empty(), vk::SamplerAddressMode::REPEAT)
+        } else {
+            (vk::ImageType::TYPE_2D, vk::ImageViewType::TYPE_2D, vk::ImageCreateFlags::empty(), vk::SamplerAddressMode::REPEAT)
+        };
+
+        VkTexture::create(upload_context, &TextureDesc {
+            image_type,
+            view_type,
+            flags,
+            extent: compressed_image.extent,
+            format: compressed_image.format,
+            regions: &regions,
+            address_mode,
+        })
+    }
+
+    /// Cubemap from six square faces in `+X, -X, +Y, -Y, +Z, -Z` order,
+    /// sampled with clamp-to-edge so the face seams don't wrap.
+    pub fn from_six_faces(
+        upload_context: &UploadContext,
+        size: u32,
+        format: vk::Format,
+        faces: [&[u8]; 6]
+    ) -> VkTexture {
+        assert!(VkTexture::layers_have_same_size(&faces), "All cubemap faces must have the same size");
+
+        VkTexture::create(upload_context, &TextureDesc {
+            image_type: vk::ImageType::TYPE_2D,
+            view_type: vk::ImageViewType::CUBE,
+            flags: vk::ImageCreateFlags::CUBE_COMPATIBLE,
+            extent: vk::Extent3D { width: size, height: size, depth: 1 },
+            format,
+            regions: &VkTexture::layer_regions(&faces),
+            address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        })
+    }
+
+    /// Volume texture, `data` holds `depth` slices of `width * height` texels, uploaded with a single copy.
+    pub fn new_3d(
+        upload_context: &UploadContext,
+        capabilities: &DeviceCapabilities,
+        width: u32,
+        height: u32,
+        depth: u32,
+        format: vk::Format,
+        data: &[u8]
+    ) -> VkTexture {
+        let max_dimension = capabilities.max_image_dimension_3d;
+        assert!(
+            width <= max_dimension && height <= max_dimension && depth <= max_dimension,
+            "3D texture {}x{}x{} exceeds maxImageDimension3D ({})",
+            width, height, depth, max_dimension
+        );
+
+        VkTexture::create(upload_context, &TextureDesc {
+            image_type: vk::ImageType::TYPE_3D,
+            view_type: vk::ImageViewType::TYPE_3D,
+            flags: vk::ImageCreateFlags::empty(),
+            extent: vk::Extent3D { width, height, depth },
+            format,
+            regions: &VkTexture::layer_regions(&[data]),
+            address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        })
+    }
+
+    /// Array texture with one layer per entry of `layers`, each uploaded into its own array layer.
+    /// Unlike a cubemap any layer count up to `maxImageArrayLayers` works.
+    pub fn new_array(
+        upload_context: &UploadContext,
+        capabilities: &DeviceCapabilities,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+        layers: &[&[u8]]
+    ) -> VkTexture {
+        assert!(!layers.is_empty(), "Array texture needs at least one layer");
+        assert!(
+            layers.len() as u32 <= capabilities.max_image_array_layers,
+            "Array texture with {} layers exceeds maxImageArrayLayers ({})",
+            layers.len(), capabilities.max_image_array_layers
+        );
+        assert!(VkTexture::layers_have_same_size(layers), "All array texture layers must have the same size");
+
+        VkTexture::create(upload_context, &TextureDesc {
+            image_type: vk::ImageType::TYPE_2D,
+            view_type: vk::ImageViewType::TYPE_2D_ARRAY,
+            flags: vk::ImageCreateFlags::empty(),
+            extent: vk::Extent3D { width, height, depth: 1 },
+            format,
+            regions: &VkTexture::layer_regions(layers),
+            address_mode: vk::SamplerAddressMode::REPEAT,
+        })
+    }
+
+    /// Fetches `sampler` again for the current `SamplerCache::quality`. Returns whether it changed,
+    /// the descriptors sampling the texture have to be rewritten then.
+    pub fn refresh_sampler(&mut self, sampler_cache: &SamplerCache) -> bool {
+        let sampler = sampler_cache.get_or_create_for_quality(&self.sampler_config);
+        let is_changed = sampler != self.sampler;
+        self.sampler = sampler;
+        is_changed
+    }
+
+    /// Extra view into some mips or layers of the texture, destroyed by the caller.
+    pub fn create_view(
+        &self,
+        device: &ash::Device,
+        view_type: vk::ImageViewType,
+        subresources: image::ImageSubresources
+    ) -> vk::ImageView {
+        image::create_subresource_image_view(
+            device,
+            self.image,
+            view_type,
+            self.format,
+            vk::ImageAspectFlags::COLOR,
+            subresources,
+            self.mip_levels,
+            self.array_layers)
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_image_view(self.image_view, None);
+            lifecycle::on_destroy(TrackedObject::Image, self.image);
+            device.destroy_image(self.image, None);
+            device.free_memory(self.image_memory, None);
+        }
+    }
+
+    fn create(upload_context: &UploadContext, desc: &TextureDesc) -> VkTexture {
+        let device = upload_context.device;
+        let mip_levels = desc.regions.iter().map(|region| region.mip_level + 1).max().unwrap_or(1);
+        let array_layers = desc.regions.iter().map(|region| region.array_layer + 1).max().unwrap_or(1);
+
+        let usage = vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED;
+        let tiling = image::choose_tiling(upload_context.instance, upload_context.physical_device, desc.format, usage);
+
+        let image_create_info = vk::ImageCreateInfo {
+            s_type: vk::StructureType::IMAGE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: desc.flags,
+            image_type: desc.image_type,
+            format: desc.format,
+            extent: desc.extent,
+            mip_levels,
+            array_layers,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            queue_family_index_count: 0,
+            p_queue_family_indices: ptr::null(),
+            initial_layout: vk::ImageLayout::UNDEFINED,
+        };
+
+        let (texture_image, texture_image_memory) = image::create_image(
+            device,
+            &image_create_info,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            upload_context.device_memory_properties);
+
+        VkTexture::upload_regions(upload_context, texture_image, desc.extent, desc.regions);
+
+        let image_view = image::create_image_view(
+            device,
+            texture_image,
+            desc.view_type,
+            desc.format,
+            vk::ImageAspectFlags::COLOR);
+
+        let sampler_config = SamplerConfig::linear(desc.address_mode);
+        let sampler = upload_context.sampler_cache.get_or_create_for_quality(&sampler_config);
+
+        VkTexture {
+            image: texture_image,
+            image_memory: texture_image_memory,
+            image_view: image_view,
+            sampler: sampler,
+            sampler_config: sampler_config,
+
+            format: desc.format,
+            extent: desc.extent,
+            mip_levels: mip_levels,
+            array_layers: array_layers,
+            view_type: desc.view_type,
+        }
+    }
+
+    /// Copies every region through one staging buffer, the barriers cover all mips and layers at once.
+    fn upload_regions(
+        upload_context: &UploadContext,
+        texture_image: vk::Image,
+        extent: vk::Extent3D,
+        regions: &[TextureRegion],
+    ) {
+        let device = upload_context.device;
+        let buffer_size: vk::DeviceSize = regions
+            .iter()
+            .map(|region| VkTexture::align_region_offset(region.data.len() as vk::DeviceSize))
+            .sum();
+
+        let mut staging_buffer = MappedBuffer::new(
+            device,
+            buffer_size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            upload_context.device_memory_properties,
+            upload_context.non_coherent_atom_size,
+        );
+
+        let mut copy_regions = vec![];
+        let mut buffer_offset = 0;
+        for region in regions.iter() {
+            staging_buffer.write(device, buffer_offset, region.data);
+
+            let mip_extent = image::mip_extent(extent, region.mip_level);
+            copy_regions.push(vk::BufferImageCopy {
+                // keeps the depth of 3D textures.
+                image_extent: mip_extent,
+                ..image::buffer_image_copy(
+                    buffer_offset,
+                    vk::ImageAspectFlags::COLOR,
+                    region.mip_level,
+                    region.array_layer,
+                    1,
+                    mip_extent.width,
+                    mip_extent.height)
+            });
+
+            buffer_offset += VkTexture::align_region_offset(region.data.len() as vk::DeviceSize);
+        }
+
+        let subresource_range = image::full_subresource_range(vk::ImageAspectFlags::COLOR);
+
+        upload_context.execute(|command_buffer| {
+            image::cmd_transition_image_layout(
+                device,
+                command_buffer,
+                texture_image,
+                subresource_range,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+
+            unsafe {
+                device.cmd_copy_buffer_to_image(
+                    command_buffer,
+                    staging_buffer.buffer,
+                    texture_image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &copy_regions,
+                );
+            }
+
+            image::cmd_transition_image_layout(
+                device,
+                command_buffer,
+                texture_image,
+                subresource_range,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        });
+
+        staging_buffer.destroy(device);
+    }
+
+    /// One region per layer, all on mip 0.
+    fn layer_regions<'a>(layers: &[&'a [u8]]) -> Vec<TextureRegion<'a>> {
+        layers
+            .iter()
+            .enumerate()
+            .map(|(layer, &data)| TextureRegion {
+                data,
+                mip_level: 0,
+                array_layer: layer as u32,
+            })
+            .collect()
+    }
+
+    /// Buffer offsets of copies must be a multiple of the texel block size,
+    /// 16 bytes covers every uncompressed format up to 128 bits and all BC and ASTC blocks.
+    fn align_region_offset(size: vk::DeviceSize) -> vk::DeviceSize {
+        size.div_ceil(16) * 16
+    }
+
+    fn layers_have_same_size(layers: &[&[u8]]) -> bool {
+        layers.iter().all(|layer| layer.len() == layers[0].len())
+    }
+}
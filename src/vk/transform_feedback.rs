@@ -0,0 +1,116 @@
+use ash::vk;
+
+use std::ptr;
+
+use crate::vk::buffer::MappedBuffer;
+
+/// Captures what the last vertex stage writes to `xfb_buffer = 0` outputs, e.g. to inspect vertex shader results.
+/// Needs `capabilities.transform_feedback`, both buffers are host visible so the capture can be read back directly.
+pub struct TransformFeedbackCapture {
+    pub buffer: MappedBuffer,
+    /// Bytes written by the last capture, written by the device in `cmd_end`.
+    pub counter_buffer: MappedBuffer,
+}
+
+impl TransformFeedbackCapture {
+    /// Captures up to `size` bytes, everything past that is dropped.
+    pub fn new(
+        device: &ash::Device,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        non_coherent_atom_size: vk::DeviceSize,
+        size: vk::DeviceSize,
+    ) -> TransformFeedbackCapture {
+        let buffer = MappedBuffer::new(
+            device,
+            size,
+            vk::BufferUsageFlags::TRANSFORM_FEEDBACK_BUFFER_EXT,
+            device_memory_properties,
+            non_coherent_atom_size,
+        );
+        let counter_buffer = MappedBuffer::new(
+            device,
+            std::mem::size_of::<u32>() as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFORM_FEEDBACK_COUNTER_BUFFER_EXT,
+            device_memory_properties,
+            non_coherent_atom_size,
+        );
+
+        TransformFeedbackCapture {
+            buffer: buffer,
+            counter_buffer: counter_buffer,
+        }
+    }
+
+    /// Binds the capture buffer and starts capturing from its start, needs to be inside a render pass.
+    pub fn cmd_begin(&self, transform_feedback_fn: &vk::ExtTransformFeedbackFn, command_buffer: vk::CommandBuffer) {
+        let buffers = [self.buffer.buffer];
+        let offsets = [0];
+        let sizes = [self.buffer.size];
+
+        unsafe {
+            (transform_feedback_fn.cmd_bind_transform_feedback_buffers_ext)(
+                command_buffer,
+                0,
+                buffers.len() as u32,
+                buffers.as_ptr(),
+                offsets.as_ptr(),
+                sizes.as_ptr());
+            // no counter buffer, so the capture doesn't resume where the last one stopped.
+            (transform_feedback_fn.cmd_begin_transform_feedback_ext)(command_buffer, 0, 0, ptr::null(), ptr::null());
+        }
+    }
+
+    /// Stops capturing and writes the captured byte count to `counter_buffer`.
+    pub fn cmd_end(&self, transform_feedback_fn: &vk::ExtTransformFeedbackFn, command_buffer: vk::CommandBuffer) {
+        let counter_buffers = [self.counter_buffer.buffer];
+        let counter_offsets = [0];
+
+        unsafe {
+            (transform_feedback_fn.cmd_end_transform_feedback_ext)(
+                command_buffer,
+                0,
+                counter_buffers.len() as u32,
+                counter_buffers.as_ptr(),
+                counter_offsets.as_ptr());
+        }
+    }
+
+    /// Makes the capture visible to `read`, recorded after the render pass with the capture ended.
+    pub fn cmd_readback_barrier(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        let memory_barrier = vk::MemoryBarrier {
+            s_type: vk::StructureType::MEMORY_BARRIER,
+            p_next: ptr::null(),
+            src_access_mask: vk::AccessFlags::TRANSFORM_FEEDBACK_WRITE_EXT
+                | vk::AccessFlags::TRANSFORM_FEEDBACK_COUNTER_WRITE_EXT,
+            dst_access_mask: vk::AccessFlags::HOST_READ,
+        };
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFORM_FEEDBACK_EXT,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    /// Captured bytes, only once the command buffer with the capture has finished.
+    pub fn read(&self, device: &ash::Device) -> Vec<u8> {
+        self.counter_buffer.invalidate_range(device, 0, self.counter_buffer.size);
+        let counter_bytes = &self.counter_buffer.as_slice()[..std::mem::size_of::<u32>()];
+        let byte_count = u32::from_ne_bytes(counter_bytes.try_into().unwrap()) as vk::DeviceSize;
+        let byte_count = byte_count.min(self.buffer.size);
+
+        self.buffer.invalidate_range(device, 0, byte_count);
+        self.buffer.as_slice()[..byte_count as usize].to_vec()
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        self.buffer.destroy(device);
+        self.counter_buffer.destroy(device);
+    }
+}
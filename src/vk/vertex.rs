@@ -2,7 +2,7 @@ use ash::vk;
 use memoffset::offset_of;
 
 #[repr(C)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub struct Vertex {
     pub pos: [f32; 2],
     pub color: [f32; 3],